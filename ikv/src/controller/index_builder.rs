@@ -3,11 +3,10 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use log::info;
 
-use crate::index::ckv::CKVIndex;
+use crate::index::ckv::{configured_num_segments, CKVIndex};
 use crate::kafka::consumer::IKVKafkaConsumer;
 use crate::kafka::processor::WritesProcessor;
 use crate::proto::generated_proto::common::IKVStoreConfig;
-use crate::proto::generated_proto::index::CKVIndexHeader;
 
 use super::index_loader;
 
@@ -40,13 +39,21 @@ impl IndexBuilder {
 
         info!("Starting index compaction.");
         // set headers - date time of data present in this index.
-        let mut header = CKVIndexHeader::new();
+        let mut header = index.read_index_header()?;
         header.base_index_epoch_millis =
             SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
         index.write_index_header(&header)?;
 
-        // in-place compaction
-        index.compact_and_close()?;
+        // in-place compaction, reshard if a different segment count is configured
+        match configured_num_segments(config)? {
+            Some(num_segments) if num_segments != index.num_segments() => {
+                info!("Resharding index into {} segments.", num_segments);
+                index.reshard_and_close(num_segments)?;
+            }
+            _ => {
+                index.compact_and_close()?;
+            }
+        }
 
         // upload to S3
         info!("Uploading base index to S3.");
@@ -0,0 +1,509 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::bail;
+use base64::Engine;
+use log::{info, warn};
+use parquet::basic::{LogicalType, Repetition, Type as PhysicalType};
+use parquet::data_type::{
+    BoolType, ByteArray, ByteArrayType, DoubleType, FloatType, Int32Type, Int64Type,
+};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+
+use crate::index::ckv::{CKVIndex, TypedValue};
+use crate::proto::generated_proto::common::{FieldType, FieldValue};
+use crate::schema::{coerce, list, vector};
+
+#[cfg(test)]
+#[path = "index_exporter_test.rs"]
+mod index_exporter_test;
+
+const PARQUET_ROW_GROUP_SIZE: usize = 64 * 1024;
+const PROGRESS_LOG_INTERVAL_DOCUMENTS: u64 = 1_000_000;
+const MAX_LOGGED_DROPPED_VALUES: u64 = 100;
+
+/// Format of an export output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON object per line, keyed by field name. Missing values are left out,
+    /// BYTES values are base64 encoded, lists and vectors are JSON arrays.
+    Jsonl,
+
+    /// Apache Parquet, one column per field, typed by the declared field type
+    /// (BYTES for fields without one). Lists and vectors are LIST columns.
+    Parquet,
+}
+
+impl ExportFormat {
+    /// Format of a file by its extension (.jsonl/.json, .parquet).
+    pub fn from_path(filepath: &str) -> anyhow::Result<Self> {
+        let extension = Path::new(filepath)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "jsonl" | "json" => Ok(ExportFormat::Jsonl),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => bail!("Cannot infer export format of file: {}", filepath),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ExportOptions {
+    // fields to export, all fields if empty. The primary key is always exported (first).
+    pub field_names: Vec<String>,
+
+    // primary keys of documents to export, all documents if None
+    pub primary_keys: Option<Vec<Vec<u8>>>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExportStats {
+    pub num_documents: u64,
+
+    // values which cannot be converted to the declared type of their
+    // field (parquet only), exported as nulls
+    pub num_dropped_values: u64,
+}
+
+/// Dumps documents of an index to a local file, ex. for audits or training sets.
+pub struct IndexExporter {}
+
+impl IndexExporter {
+    /// Writes documents of `index` to `filepath`, in the format of its extension (see ExportFormat).
+    /// Expired and deleted values are not exported. The index can take writes during the export,
+    /// which may or may not be exported.
+    pub fn export(
+        index: &CKVIndex,
+        filepath: &str,
+        options: &ExportOptions,
+    ) -> anyhow::Result<ExportStats> {
+        let format = ExportFormat::from_path(filepath)?;
+
+        let mut field_names = vec![index.primary_key_field_name()];
+        let projection = if options.field_names.is_empty() {
+            index.field_names()
+        } else {
+            options.field_names.clone()
+        };
+        for field_name in projection {
+            if !field_names.contains(&field_name) {
+                field_names.push(field_name);
+            }
+        }
+        let primary_keys: Option<Vec<&[u8]>> = options
+            .primary_keys
+            .as_ref()
+            .map(|primary_keys| primary_keys.iter().map(|pk| pk.as_slice()).collect());
+
+        info!(
+            "Exporting fields: {:?} to: {} as {:?}",
+            &field_names, filepath, format
+        );
+        let stats = match format {
+            ExportFormat::Jsonl => {
+                export_jsonl(index, filepath, &field_names, primary_keys.as_deref())?
+            }
+            ExportFormat::Parquet => {
+                export_parquet(index, filepath, &field_names, primary_keys.as_deref())?
+            }
+        };
+        info!("Export done: {:?}", &stats);
+
+        Ok(stats)
+    }
+}
+
+fn export_jsonl(
+    index: &CKVIndex,
+    filepath: &str,
+    field_names: &[String],
+    primary_keys: Option<&[&[u8]]>,
+) -> anyhow::Result<ExportStats> {
+    let mut writer = BufWriter::new(File::create(filepath)?);
+    let mut stats = ExportStats::default();
+
+    index.for_each_document(field_names, primary_keys, |_, values| {
+        let mut document = serde_json::Map::new();
+        for (field_name, value) in field_names.iter().zip(values) {
+            if let Some((field_type, data)) = value {
+                document.insert(field_name.clone(), json_value(field_type, &data)?);
+            }
+        }
+        serde_json::to_writer(&mut writer, &document)?;
+        writer.write_all(b"\n")?;
+
+        stats.num_documents += 1;
+        log_progress(&stats);
+        Ok(())
+    })?;
+
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    Ok(stats)
+}
+
+fn json_value(field_type: FieldType, data: &[u8]) -> anyhow::Result<serde_json::Value> {
+    if let Some(element_type) = list::element_type(field_type) {
+        return Ok(serde_json::Value::Array(
+            list::elements(field_type, data)?
+                .into_iter()
+                .map(|element| json_value(element_type, element))
+                .collect::<anyhow::Result<Vec<serde_json::Value>>>()?,
+        ));
+    }
+    if vector::is_vector_type(field_type) {
+        return Ok(serde_json::Value::Array(
+            vector::to_f32_elements(field_type, data)?
+                .into_iter()
+                .map(f32_json_value)
+                .collect(),
+        ));
+    }
+
+    let value = match field_type {
+        FieldType::INT32 => serde_json::Value::from(i32::from_le_bytes(data.try_into()?)),
+        FieldType::INT64 => serde_json::Value::from(i64::from_le_bytes(data.try_into()?)),
+        FieldType::FLOAT32 => f32_json_value(f32::from_le_bytes(data.try_into()?)),
+        FieldType::FLOAT64 => serde_json::Value::from(f64::from_le_bytes(data.try_into()?)),
+        FieldType::BOOLEAN => {
+            let byte: [u8; 1] = data.try_into()?;
+            serde_json::Value::Bool(byte[0] != 0)
+        }
+        FieldType::STRING => serde_json::Value::String(String::from_utf8_lossy(data).into_owned()),
+        FieldType::BYTES => {
+            serde_json::Value::String(base64::engine::general_purpose::STANDARD.encode(data))
+        }
+        _ => bail!("Cannot export field type: {:?}", field_type),
+    };
+    Ok(value)
+}
+
+/// Shortest decimal representation of the f32 (ex. 0.1 and not 0.10000000149011612).
+fn f32_json_value(value: f32) -> serde_json::Value {
+    let shortest: f64 = value.to_string().parse().unwrap_or(value as f64);
+    serde_json::Value::from(shortest)
+}
+
+fn export_parquet(
+    index: &CKVIndex,
+    filepath: &str,
+    field_names: &[String],
+    primary_keys: Option<&[&[u8]]>,
+) -> anyhow::Result<ExportStats> {
+    let mut columns = vec![];
+    let mut fields = vec![];
+    for field_name in field_names {
+        let field_type = index.get_field_type(field_name).unwrap_or(FieldType::BYTES);
+        let column = ColumnBuffer::new(field_type)?;
+        fields.push(Arc::new(column.parquet_type(field_name)?));
+        columns.push(column);
+    }
+    let schema = Arc::new(
+        Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?,
+    );
+
+    let mut writer =
+        SerializedFileWriter::new(File::create(filepath)?, schema, Default::default())?;
+    let mut stats = ExportStats::default();
+    let mut num_buffered_rows = 0;
+
+    index.for_each_document(field_names, primary_keys, |primary_key, values| {
+        for ((column, field_name), value) in columns.iter_mut().zip(field_names).zip(values) {
+            if let Err(e) = column.push(value) {
+                stats.num_dropped_values += 1;
+                if stats.num_dropped_values <= MAX_LOGGED_DROPPED_VALUES {
+                    warn!(
+                        "Exporting null for field: {} of primary-key: {:?}, error: {}",
+                        field_name, primary_key, e
+                    );
+                }
+                column.push(None)?;
+            }
+        }
+
+        num_buffered_rows += 1;
+        if num_buffered_rows == PARQUET_ROW_GROUP_SIZE {
+            write_row_group(&mut writer, &mut columns)?;
+            num_buffered_rows = 0;
+        }
+
+        stats.num_documents += 1;
+        log_progress(&stats);
+        Ok(())
+    })?;
+
+    if num_buffered_rows > 0 {
+        write_row_group(&mut writer, &mut columns)?;
+    }
+    writer.close()?;
+    Ok(stats)
+}
+
+fn write_row_group(
+    writer: &mut SerializedFileWriter<File>,
+    columns: &mut [ColumnBuffer],
+) -> anyhow::Result<()> {
+    let mut row_group = writer.next_row_group()?;
+    for column in columns.iter_mut() {
+        let mut column_writer = match row_group.next_column()? {
+            None => bail!("Parquet schema has less columns than buffered"),
+            Some(column_writer) => column_writer,
+        };
+
+        let definition_levels = Some(column.definition_levels.as_slice());
+        let repetition_levels = if column.is_list {
+            Some(column.repetition_levels.as_slice())
+        } else {
+            None
+        };
+        match &column.values {
+            ColumnValues::Boolean(values) => column_writer.typed::<BoolType>().write_batch(
+                values,
+                definition_levels,
+                repetition_levels,
+            )?,
+            ColumnValues::Int32(values) => column_writer.typed::<Int32Type>().write_batch(
+                values,
+                definition_levels,
+                repetition_levels,
+            )?,
+            ColumnValues::Int64(values) => column_writer.typed::<Int64Type>().write_batch(
+                values,
+                definition_levels,
+                repetition_levels,
+            )?,
+            ColumnValues::Float(values) => column_writer.typed::<FloatType>().write_batch(
+                values,
+                definition_levels,
+                repetition_levels,
+            )?,
+            ColumnValues::Double(values) => column_writer.typed::<DoubleType>().write_batch(
+                values,
+                definition_levels,
+                repetition_levels,
+            )?,
+            ColumnValues::ByteArray(values) => column_writer.typed::<ByteArrayType>().write_batch(
+                values,
+                definition_levels,
+                repetition_levels,
+            )?,
+        };
+        column_writer.close()?;
+        column.clear();
+    }
+    row_group.close()?;
+    Ok(())
+}
+
+fn log_progress(stats: &ExportStats) {
+    if stats
+        .num_documents
+        .is_multiple_of(PROGRESS_LOG_INTERVAL_DOCUMENTS)
+    {
+        info!("Exported {} documents.", stats.num_documents);
+    }
+}
+
+/// Parquet values of a row group.
+enum ColumnValues {
+    Boolean(Vec<bool>),
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+    ByteArray(Vec<ByteArray>),
+}
+
+/// Buffered values of a field for the rows of a row group, along with their levels.
+///
+/// Scalar fields are OPTIONAL columns: definition level 0 for null, 1 for values.
+/// List and vector fields are OPTIONAL LIST columns with REQUIRED elements: definition level
+/// 0 for null, 1 for empty lists, 2 for elements, and repetition level 0 for the first element
+/// (or null/empty list) of a row, 1 for the others.
+struct ColumnBuffer {
+    field_type: FieldType,
+
+    // scalar type of values, or of list/vector elements
+    value_type: FieldType,
+    is_list: bool,
+
+    values: ColumnValues,
+    definition_levels: Vec<i16>,
+    repetition_levels: Vec<i16>,
+}
+
+impl ColumnBuffer {
+    fn new(field_type: FieldType) -> anyhow::Result<Self> {
+        let (value_type, is_list) = if let Some(element_type) = list::element_type(field_type) {
+            (element_type, true)
+        } else if vector::is_vector_type(field_type) {
+            (FieldType::FLOAT32, true)
+        } else {
+            (field_type, false)
+        };
+
+        let values = match value_type {
+            FieldType::BOOLEAN => ColumnValues::Boolean(vec![]),
+            FieldType::INT32 => ColumnValues::Int32(vec![]),
+            FieldType::INT64 => ColumnValues::Int64(vec![]),
+            FieldType::FLOAT32 => ColumnValues::Float(vec![]),
+            FieldType::FLOAT64 => ColumnValues::Double(vec![]),
+            FieldType::STRING | FieldType::BYTES => ColumnValues::ByteArray(vec![]),
+            _ => bail!("Cannot export field type: {:?}", field_type),
+        };
+
+        Ok(Self {
+            field_type,
+            value_type,
+            is_list,
+            values,
+            definition_levels: vec![],
+            repetition_levels: vec![],
+        })
+    }
+
+    fn parquet_type(&self, field_name: &str) -> anyhow::Result<Type> {
+        let physical_type = match self.values {
+            ColumnValues::Boolean(_) => PhysicalType::BOOLEAN,
+            ColumnValues::Int32(_) => PhysicalType::INT32,
+            ColumnValues::Int64(_) => PhysicalType::INT64,
+            ColumnValues::Float(_) => PhysicalType::FLOAT,
+            ColumnValues::Double(_) => PhysicalType::DOUBLE,
+            ColumnValues::ByteArray(_) => PhysicalType::BYTE_ARRAY,
+        };
+        let logical_type = match self.value_type {
+            FieldType::STRING => Some(LogicalType::String),
+            _ => None,
+        };
+
+        if !self.is_list {
+            return Ok(Type::primitive_type_builder(field_name, physical_type)
+                .with_repetition(Repetition::OPTIONAL)
+                .with_logical_type(logical_type)
+                .build()?);
+        }
+
+        let element = Type::primitive_type_builder("element", physical_type)
+            .with_repetition(Repetition::REQUIRED)
+            .with_logical_type(logical_type)
+            .build()?;
+        let repeated = Type::group_type_builder("list")
+            .with_repetition(Repetition::REPEATED)
+            .with_fields(vec![Arc::new(element)])
+            .build()?;
+        Ok(Type::group_type_builder(field_name)
+            .with_repetition(Repetition::OPTIONAL)
+            .with_logical_type(Some(LogicalType::List))
+            .with_fields(vec![Arc::new(repeated)])
+            .build()?)
+    }
+
+    /// Buffers the value of a row, converted to the field type if it was written with another
+    /// type (see coerce()). Errors (without buffering) if it cannot be converted.
+    fn push(&mut self, value: Option<TypedValue>) -> anyhow::Result<()> {
+        let data = match value {
+            None => {
+                self.definition_levels.push(0);
+                if self.is_list {
+                    self.repetition_levels.push(0);
+                }
+                return Ok(());
+            }
+            Some((field_type, data)) if field_type == self.field_type => data,
+            Some((field_type, data)) => {
+                let mut field_value = FieldValue::new();
+                field_value.fieldType = field_type.into();
+                field_value.value = data;
+                coerce::coerce(&field_value, self.field_type)?.value
+            }
+        };
+
+        if !self.is_list {
+            self.push_value(&data)?;
+            self.definition_levels.push(1);
+            return Ok(());
+        }
+
+        let elements: Vec<Vec<u8>> = if vector::is_vector_type(self.field_type) {
+            vector::to_f32_elements(self.field_type, &data)?
+                .into_iter()
+                .map(|e| e.to_le_bytes().to_vec())
+                .collect()
+        } else {
+            list::elements(self.field_type, &data)?
+                .into_iter()
+                .map(|e| e.to_vec())
+                .collect()
+        };
+        if elements.is_empty() {
+            self.definition_levels.push(1);
+            self.repetition_levels.push(0);
+            return Ok(());
+        }
+
+        // decode all elements before buffering any
+        let num_values = self.num_values();
+        for element in elements.iter() {
+            if let Err(e) = self.push_value(element) {
+                self.truncate_values(num_values);
+                return Err(e);
+            }
+        }
+        for i in 0..elements.len() {
+            self.definition_levels.push(2);
+            self.repetition_levels.push(if i == 0 { 0 } else { 1 });
+        }
+        Ok(())
+    }
+
+    fn push_value(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        match &mut self.values {
+            ColumnValues::Boolean(values) => {
+                let byte: [u8; 1] = data.try_into()?;
+                values.push(byte[0] != 0);
+            }
+            ColumnValues::Int32(values) => values.push(i32::from_le_bytes(data.try_into()?)),
+            ColumnValues::Int64(values) => values.push(i64::from_le_bytes(data.try_into()?)),
+            ColumnValues::Float(values) => values.push(f32::from_le_bytes(data.try_into()?)),
+            ColumnValues::Double(values) => values.push(f64::from_le_bytes(data.try_into()?)),
+            ColumnValues::ByteArray(values) => values.push(ByteArray::from(data.to_vec())),
+        };
+        Ok(())
+    }
+
+    fn num_values(&self) -> usize {
+        match &self.values {
+            ColumnValues::Boolean(values) => values.len(),
+            ColumnValues::Int32(values) => values.len(),
+            ColumnValues::Int64(values) => values.len(),
+            ColumnValues::Float(values) => values.len(),
+            ColumnValues::Double(values) => values.len(),
+            ColumnValues::ByteArray(values) => values.len(),
+        }
+    }
+
+    fn truncate_values(&mut self, len: usize) {
+        match &mut self.values {
+            ColumnValues::Boolean(values) => values.truncate(len),
+            ColumnValues::Int32(values) => values.truncate(len),
+            ColumnValues::Int64(values) => values.truncate(len),
+            ColumnValues::Float(values) => values.truncate(len),
+            ColumnValues::Double(values) => values.truncate(len),
+            ColumnValues::ByteArray(values) => values.truncate(len),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.truncate_values(0);
+        self.definition_levels.clear();
+        self.repetition_levels.clear();
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::{Field, RowAccessor};
+
+use crate::controller::index_exporter::{ExportFormat, ExportOptions, IndexExporter};
+use crate::index::ckv::CKVIndex;
+use crate::proto::generated_proto::common::{FieldType, FieldValue};
+use crate::utils;
+use crate::utils::testing::string_to_field_value;
+
+fn field_value(field_type: FieldType, value: Vec<u8>) -> FieldValue {
+    let mut field_value = FieldValue::new();
+    field_value.fieldType = field_type.into();
+    field_value.value = value;
+    field_value
+}
+
+/// Documents 0..3 of utils::testing::create_document(), document 1 with
+/// list, vector and float fields, document 2 with a mistyped field3.
+fn create_index(mount_directory: &str) -> CKVIndex {
+    let ikv_config = utils::testing::setup_index_cfg(mount_directory);
+    let _ = std::fs::remove_dir_all(mount_directory);
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+
+    for i in 0..3 {
+        index
+            .upsert_field_values(&utils::testing::create_document(i))
+            .unwrap();
+    }
+
+    let mut tags = vec![];
+    for tag in ["a", "bc"] {
+        tags.extend((tag.len() as u32).to_le_bytes());
+        tags.extend(tag.as_bytes());
+    }
+    let embedding: Vec<u8> = [0.5f32, -1.0]
+        .iter()
+        .flat_map(|e| e.to_le_bytes())
+        .collect();
+    let document = HashMap::from([
+        ("field0".to_string(), string_to_field_value("field0:1")),
+        (
+            "tags".to_string(),
+            field_value(FieldType::STRING_LIST, tags),
+        ),
+        (
+            "embedding".to_string(),
+            field_value(FieldType::FLOAT32_VECTOR, embedding),
+        ),
+        (
+            "score".to_string(),
+            field_value(FieldType::FLOAT32, 0.1f32.to_le_bytes().to_vec()),
+        ),
+    ]);
+    index.upsert_field_values(&document).unwrap();
+
+    let document = HashMap::from([
+        ("field0".to_string(), string_to_field_value("field0:2")),
+        ("field3".to_string(), string_to_field_value("x")),
+    ]);
+    index.upsert_field_values(&document).unwrap();
+
+    index
+}
+
+fn read_jsonl(filepath: &str) -> Vec<serde_json::Value> {
+    let mut documents: Vec<serde_json::Value> = std::fs::read_to_string(filepath)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    documents.sort_by_key(|document| document["field0"].as_str().unwrap().to_string());
+    documents
+}
+
+#[test]
+pub fn test_export_jsonl() {
+    let mount_directory = "/tmp/index_exporter_test_test_export_jsonl";
+    let index = create_index(mount_directory);
+    let filepath = format!("{}/export.jsonl", mount_directory);
+
+    let stats = IndexExporter::export(&index, &filepath, &ExportOptions::default()).unwrap();
+    assert_eq!(stats.num_documents, 3);
+    let documents = read_jsonl(&filepath);
+    assert_eq!(
+        documents[0],
+        serde_json::json!({"field0": "field0:0", "field1": "field1:0", "field2": "ZmllbGQyOjA=", "field3": 0})
+    );
+    assert_eq!(
+        documents[1],
+        serde_json::json!({
+            "field0": "field0:1", "field1": "field1:1", "field2": "ZmllbGQyOjE=", "field3": 1,
+            "tags": ["a", "bc"], "embedding": [0.5, -1.0], "score": 0.1
+        })
+    );
+    assert_eq!(documents[2]["field3"], serde_json::json!("x"));
+
+    // projection and primary key filter, primary key is always exported
+    let options = ExportOptions {
+        field_names: vec!["tags".to_string(), "unknown".to_string()],
+        primary_keys: Some(vec![
+            b"field0:1".to_vec(),
+            b"field0:2".to_vec(),
+            b"missing".to_vec(),
+        ]),
+    };
+    let stats = IndexExporter::export(&index, &filepath, &options).unwrap();
+    assert_eq!(stats.num_documents, 2);
+    let documents = read_jsonl(&filepath);
+    assert_eq!(
+        documents,
+        vec![
+            serde_json::json!({"field0": "field0:1", "tags": ["a", "bc"]}),
+            serde_json::json!({"field0": "field0:2"}),
+        ]
+    );
+
+    index.close().unwrap();
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_export_parquet() {
+    let mount_directory = "/tmp/index_exporter_test_test_export_parquet";
+    let index = create_index(mount_directory);
+    let filepath = format!("{}/export.parquet", mount_directory);
+
+    let options = ExportOptions {
+        field_names: vec![
+            "field2".to_string(),
+            "field3".to_string(),
+            "tags".to_string(),
+            "embedding".to_string(),
+            "score".to_string(),
+        ],
+        primary_keys: None,
+    };
+    let stats = IndexExporter::export(&index, &filepath, &options).unwrap();
+    assert_eq!(stats.num_documents, 3);
+
+    // mistyped field3 of document 2
+    assert_eq!(stats.num_dropped_values, 1);
+
+    let reader = SerializedFileReader::try_from(filepath.as_str()).unwrap();
+    let columns: Vec<String> = reader
+        .metadata()
+        .file_metadata()
+        .schema_descr()
+        .root_schema()
+        .get_fields()
+        .iter()
+        .map(|field| field.name().to_string())
+        .collect();
+    assert_eq!(
+        columns,
+        vec!["field0", "field2", "field3", "tags", "embedding", "score"]
+    );
+
+    let mut rows: Vec<_> = reader.into_iter().map(|row| row.unwrap()).collect();
+    rows.sort_by_key(|row| row.get_string(0).unwrap().clone());
+    assert_eq!(rows.len(), 3);
+
+    assert_eq!(rows[0].get_bytes(1).unwrap().data(), b"field2:0");
+    assert_eq!(rows[0].get_int(2).unwrap(), 0);
+    assert!(rows[0].get_list(3).is_err());
+
+    assert_eq!(rows[1].get_int(2).unwrap(), 1);
+    let tags: Vec<&Field> = rows[1].get_list(3).unwrap().elements().iter().collect();
+    assert_eq!(
+        tags,
+        vec![&Field::Str("a".to_string()), &Field::Str("bc".to_string())]
+    );
+    let embedding: Vec<&Field> = rows[1].get_list(4).unwrap().elements().iter().collect();
+    assert_eq!(embedding, vec![&Field::Float(0.5), &Field::Float(-1.0)]);
+    assert_eq!(rows[1].get_float(5).unwrap(), 0.1);
+
+    assert!(rows[2].get_int(2).is_err());
+
+    index.close().unwrap();
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_export_format_from_path() {
+    assert_eq!(
+        ExportFormat::from_path("/data/dump.jsonl").unwrap(),
+        ExportFormat::Jsonl
+    );
+    assert_eq!(
+        ExportFormat::from_path("dump.Parquet").unwrap(),
+        ExportFormat::Parquet
+    );
+    assert!(ExportFormat::from_path("dump.csv").is_err());
+}
@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail};
+use log::{info, warn};
+use parquet::file::serialized_reader::SerializedFileReader;
+use parquet::record::Field;
+use protobuf::Enum;
+
+use crate::index::ckv::{configured_field_values, CKVIndex};
+use crate::index::offset_store::OffsetStore;
+use crate::kafka::consumer;
+use crate::proto::generated_proto::common::{FieldType, FieldValue, IKVStoreConfig};
+use crate::proto::generated_proto::index::KafkaOffsetStoreEntry;
+use crate::schema::{coerce, list, vector};
+
+use super::index_loader;
+
+#[cfg(test)]
+#[path = "index_importer_test.rs"]
+mod index_importer_test;
+
+const PROGRESS_LOG_INTERVAL_ROWS: u64 = 1_000_000;
+const MAX_LOGGED_INVALID_ROWS: u64 = 100;
+
+/// Format of an import input file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// One JSON object per line, keyed by column name.
+    Jsonl,
+
+    /// Comma separated values, with a header row of column names.
+    /// Cells of list and vector columns are JSON arrays.
+    Csv,
+
+    /// Apache Parquet, columns are top level fields.
+    Parquet,
+}
+
+impl ImportFormat {
+    /// Format of a file by its extension (.jsonl/.json, .csv, .parquet).
+    pub fn from_path(filepath: &str) -> anyhow::Result<Self> {
+        let extension = Path::new(filepath)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "jsonl" | "json" => Ok(ImportFormat::Jsonl),
+            "csv" => Ok(ImportFormat::Csv),
+            "parquet" => Ok(ImportFormat::Parquet),
+            _ => bail!("Cannot infer import format of file: {}", filepath),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ImportStats {
+    pub num_files: u64,
+    pub num_rows: u64,
+
+    // rows skipped with "import_skip_invalid_rows"
+    pub num_invalid_rows: u64,
+}
+
+/// Cell of an input row, before conversion to the declared field type.
+#[derive(Debug)]
+enum Cell {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+    List(Vec<Cell>),
+
+    // values without a field type (ex. nested objects), only fail rows if mapped to a field
+    Unsupported(String),
+}
+
+type Row = Vec<(String, Cell)>;
+
+/// Row of an input file along with its 1-based row number (line number for JSONL).
+type NumberedRow = (u64, anyhow::Result<Row>);
+
+/// Offline builder of a base index from local files, bypassing the write stream.
+pub struct IndexImporter {}
+
+impl IndexImporter {
+    // NOTE: callers must cleanup their working directories
+
+    /// Imports files of "import_filepaths" (comma separated) as per "import_field_types"
+    /// (format: "field1:string,field2:int32_list", see FieldType), and uploads the
+    /// resulting base index to S3.
+    ///
+    /// The end of the write stream is fetched before importing, readers only consume
+    /// events written after it on top of the imported index. Files must hold all
+    /// documents written before the import.
+    pub fn import_and_export(config: &IKVStoreConfig) -> anyhow::Result<()> {
+        let filepaths: Vec<String> = config
+            .stringConfigs
+            .get("import_filepaths")
+            .ok_or(anyhow!("import_filepaths is a required import config"))?
+            .split(',')
+            .map(|filepath| filepath.trim().to_string())
+            .filter(|filepath| !filepath.is_empty())
+            .collect();
+        let field_types = configured_import_field_types(config)?;
+        let stream_offset = consumer::fetch_high_watermark_offset(config)?;
+        info!("Importing as of write stream offset: {:?}", &stream_offset);
+
+        Self::import(config, &filepaths, &field_types, vec![stream_offset])?;
+
+        info!("Uploading base index to S3.");
+        index_loader::upload_index(config)?;
+
+        info!("Base index import and upload successful.");
+        Ok(())
+    }
+
+    /// Builds a new base index (at the configured mount directory) from rows of `filepaths`.
+    /// Columns are mapped to fields of type `field_types`, other columns are ignored, and
+    /// null/missing cells leave the field unset. The primary key column is required.
+    ///
+    /// The index is compacted and stamped with the current time, ready to be published
+    /// with index_loader::upload_index(). It holds `stream_offsets`, readers consume the
+    /// write stream on top of it from there.
+    ///
+    /// Invalid rows fail the import, unless booleanConfigs "import_skip_invalid_rows" is set.
+    pub fn import(
+        config: &IKVStoreConfig,
+        filepaths: &[String],
+        field_types: &HashMap<String, FieldType>,
+        stream_offsets: Vec<KafkaOffsetStoreEntry>,
+    ) -> anyhow::Result<ImportStats> {
+        let primary_key = config
+            .stringConfigs
+            .get("primary_key_field_name")
+            .ok_or(anyhow!("primary_key is a required client-specified config"))?;
+        if !field_types.contains_key(primary_key) {
+            bail!("Missing field type of primary key: {}", primary_key);
+        }
+        if let Some((field_name, _)) = field_types
+            .iter()
+            .find(|(_, field_type)| **field_type == FieldType::UNKNOWN)
+        {
+            bail!("Missing field type of field: {}", field_name);
+        }
+        let skip_invalid_rows = config
+            .booleanConfigs
+            .get("import_skip_invalid_rows")
+            .copied()
+            .unwrap_or(false);
+
+        // formats are checked upfront, before any (long-running) import
+        let inputs = filepaths
+            .iter()
+            .map(|filepath| Ok((filepath, ImportFormat::from_path(filepath)?)))
+            .collect::<anyhow::Result<Vec<(&String, ImportFormat)>>>()?;
+
+        if !CKVIndex::index_not_present(config)? {
+            bail!("Cannot import into an existing index, delete it first");
+        }
+        let index = CKVIndex::open_or_create(config)?;
+
+        let mut stats = ImportStats::default();
+        for (filepath, format) in inputs {
+            info!("Importing rows from: {} as {:?}", filepath, format);
+            stats.num_files += 1;
+
+            for (row_number, row) in read_rows(filepath, format)? {
+                let result = row.and_then(|row| {
+                    let document = to_document(row, primary_key, field_types)?;
+                    index.upsert_field_values(&document)
+                });
+                if let Err(e) = result {
+                    if !skip_invalid_rows {
+                        bail!("{}: invalid row: {}, error: {}", filepath, row_number, e);
+                    }
+                    stats.num_invalid_rows += 1;
+                    if stats.num_invalid_rows <= MAX_LOGGED_INVALID_ROWS {
+                        warn!(
+                            "{}: skipping invalid row: {}, error: {}",
+                            filepath, row_number, e
+                        );
+                    }
+                    continue;
+                }
+
+                stats.num_rows += 1;
+                if stats.num_rows % PROGRESS_LOG_INTERVAL_ROWS == 0 {
+                    info!("Imported {} rows.", stats.num_rows);
+                }
+            }
+        }
+        info!("Import done: {:?}", &stats);
+
+        // set headers - date time of data present in this index.
+        let mut header = index.read_index_header()?;
+        header.base_index_epoch_millis =
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        index.write_index_header(&header)?;
+
+        let mount_directory = crate::utils::paths::get_index_mount_directory_fqn(config)?;
+        OffsetStore::open_or_create(mount_directory)?.write_entries(stream_offsets)?;
+
+        info!("Starting index compaction.");
+        index.compact_and_close()?;
+
+        Ok(stats)
+    }
+}
+
+fn configured_import_field_types(
+    config: &IKVStoreConfig,
+) -> anyhow::Result<HashMap<String, FieldType>> {
+    let field_types = configured_field_values(config, "import_field_types")?
+        .ok_or(anyhow!("import_field_types is a required import config"))?;
+
+    let mut field_name_to_type = HashMap::new();
+    for (field_name, field_type) in field_types {
+        let field_type = FieldType::from_str(&field_type.to_uppercase())
+            .filter(|field_type| *field_type != FieldType::UNKNOWN)
+            .ok_or(anyhow!(
+                "import_field_types unsupported field type: {}",
+                field_type
+            ))?;
+        field_name_to_type.insert(field_name, field_type);
+    }
+
+    Ok(field_name_to_type)
+}
+
+fn read_rows(
+    filepath: &str,
+    format: ImportFormat,
+) -> anyhow::Result<Box<dyn Iterator<Item = NumberedRow>>> {
+    let rows: Box<dyn Iterator<Item = anyhow::Result<Row>>> = match format {
+        ImportFormat::Jsonl => {
+            // numbered before skipping blank lines, so that errors point to their line
+            let reader = BufReader::new(File::open(filepath)?);
+            return Ok(Box::new(reader.lines().zip(1..).filter_map(
+                |(line, line_number)| match line {
+                    Err(e) => Some((line_number, Err(e.into()))),
+                    Ok(line) if line.trim().is_empty() => None,
+                    Ok(line) => Some((line_number, json_row(&line))),
+                },
+            )));
+        }
+        ImportFormat::Csv => {
+            let mut reader = csv::Reader::from_path(filepath)?;
+            let columns = reader.headers()?.clone();
+            Box::new(reader.into_records().map(move |record| {
+                let record = record?;
+                Ok(columns
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(column, text)| {
+                        let cell = if text.is_empty() {
+                            Cell::Null
+                        } else {
+                            Cell::Text(text.to_string())
+                        };
+                        (column.to_string(), cell)
+                    })
+                    .collect())
+            }))
+        }
+        ImportFormat::Parquet => {
+            let reader = SerializedFileReader::try_from(filepath)?;
+            Box::new(reader.into_iter().map(|row| {
+                row?.get_column_iter()
+                    .map(|(column, field)| Ok((column.to_string(), parquet_cell(field)?)))
+                    .collect()
+            }))
+        }
+    };
+
+    Ok(Box::new((1..).zip(rows)))
+}
+
+fn json_row(line: &str) -> anyhow::Result<Row> {
+    match serde_json::from_str(line)? {
+        serde_json::Value::Object(object) => object
+            .into_iter()
+            .map(|(column, value)| Ok((column, json_cell(value)?)))
+            .collect(),
+        _ => bail!("Not a JSON object"),
+    }
+}
+
+fn json_cell(value: serde_json::Value) -> anyhow::Result<Cell> {
+    let cell = match value {
+        serde_json::Value::Null => Cell::Null,
+        serde_json::Value::Bool(b) => Cell::Bool(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Cell::Int(i),
+            None => Cell::Float(n.as_f64().ok_or(anyhow!("Unsupported number: {}", n))?),
+        },
+        serde_json::Value::String(s) => Cell::Text(s),
+        serde_json::Value::Array(elements) => Cell::List(
+            elements
+                .into_iter()
+                .map(json_cell)
+                .collect::<anyhow::Result<Vec<Cell>>>()?,
+        ),
+        serde_json::Value::Object(_) => Cell::Unsupported("nested JSON object".to_string()),
+    };
+    Ok(cell)
+}
+
+fn parquet_cell(field: &Field) -> anyhow::Result<Cell> {
+    let cell = match field {
+        Field::Null => Cell::Null,
+        Field::Bool(b) => Cell::Bool(*b),
+        Field::Byte(i) => Cell::Int(*i as i64),
+        Field::Short(i) => Cell::Int(*i as i64),
+        Field::Int(i) => Cell::Int(*i as i64),
+        Field::Long(i) => Cell::Int(*i),
+        Field::UByte(i) => Cell::Int(*i as i64),
+        Field::UShort(i) => Cell::Int(*i as i64),
+        Field::UInt(i) => Cell::Int(*i as i64),
+        Field::ULong(i) => Cell::Int(i64::try_from(*i)?),
+        Field::Float(f) => Cell::Float(*f as f64),
+        Field::Double(f) => Cell::Float(*f),
+        Field::Str(s) => Cell::Text(s.clone()),
+        Field::Bytes(b) => Cell::Bytes(b.data().to_vec()),
+        Field::ListInternal(elements) => Cell::List(
+            elements
+                .elements()
+                .iter()
+                .map(parquet_cell)
+                .collect::<anyhow::Result<Vec<Cell>>>()?,
+        ),
+        _ => Cell::Unsupported(format!("parquet value: {}", field)),
+    };
+    Ok(cell)
+}
+
+fn to_document(
+    row: Row,
+    primary_key: &str,
+    field_types: &HashMap<String, FieldType>,
+) -> anyhow::Result<HashMap<String, FieldValue>> {
+    let mut document = HashMap::new();
+    for (column, cell) in row {
+        let field_type = match field_types.get(&column) {
+            None => continue,
+            Some(field_type) => *field_type,
+        };
+        if let Some(field_value) =
+            to_field_value(cell, field_type).map_err(|e| anyhow!("column: {}, {}", column, e))?
+        {
+            document.insert(column, field_value);
+        }
+    }
+
+    if !document.contains_key(primary_key) {
+        bail!("Missing primary key: {}", primary_key);
+    }
+    Ok(document)
+}
+
+/// Converts a cell to `field_type`, None for null cells.
+fn to_field_value(cell: Cell, field_type: FieldType) -> anyhow::Result<Option<FieldValue>> {
+    let data = if let Some(element_type) = list::element_type(field_type) {
+        let mut elements = vec![];
+        for element in list_elements(cell)? {
+            match scalar_value(element, element_type)? {
+                None => bail!("Unsupported null list element"),
+                Some(value) => elements.push(value),
+            }
+        }
+        list::encode(field_type, &elements)?
+    } else if vector::is_vector_type(field_type) {
+        let mut elements = vec![];
+        for element in list_elements(cell)? {
+            match scalar_value(element, FieldType::FLOAT32)? {
+                None => bail!("Unsupported null vector element"),
+                Some(value) => elements.push(f32::from_le_bytes(
+                    value.as_slice().try_into().expect("4 byte float"),
+                )),
+            }
+        }
+        if elements.is_empty() {
+            return Ok(None);
+        }
+        vector::from_f32_elements(field_type, &elements)?
+    } else {
+        match scalar_value(cell, field_type)? {
+            None => return Ok(None),
+            Some(data) => data,
+        }
+    };
+
+    let mut field_value = FieldValue::new();
+    field_value.fieldType = field_type.into();
+    field_value.value = data;
+    Ok(Some(field_value))
+}
+
+/// Elements of a list cell, text cells (ex. CSV) are parsed as JSON arrays.
+fn list_elements(cell: Cell) -> anyhow::Result<Vec<Cell>> {
+    match cell {
+        Cell::Null => Ok(vec![]),
+        Cell::List(elements) => Ok(elements),
+        Cell::Text(text) => match json_cell(serde_json::from_str(&text)?)? {
+            Cell::List(elements) => Ok(elements),
+            _ => bail!("Not a JSON array: {}", text),
+        },
+        cell => bail!("Not a list: {:?}", cell),
+    }
+}
+
+/// Encoded value of a scalar cell converted to `field_type` (see coerce()), None for null cells.
+fn scalar_value(cell: Cell, field_type: FieldType) -> anyhow::Result<Option<Vec<u8>>> {
+    let (from, data) = match cell {
+        Cell::Null => return Ok(None),
+        Cell::Bool(b) => (FieldType::BOOLEAN, vec![b as u8]),
+        Cell::Int(i) => (FieldType::INT64, i.to_le_bytes().to_vec()),
+        Cell::Float(f) => (FieldType::FLOAT64, f.to_le_bytes().to_vec()),
+        Cell::Text(s) => (FieldType::STRING, s.into_bytes()),
+        Cell::Bytes(b) => (FieldType::BYTES, b),
+        Cell::List(_) => bail!("Cannot convert a list to: {:?}", field_type),
+        Cell::Unsupported(description) => bail!("Unsupported {}", description),
+    };
+
+    let mut value = FieldValue::new();
+    value.fieldType = from.into();
+    value.value = data;
+    Ok(Some(coerce::coerce(&value, field_type)?.value))
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::Arc;
+
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+
+use crate::controller::index_importer::{
+    configured_import_field_types, ImportFormat, IndexImporter,
+};
+use crate::index::ckv::CKVIndex;
+use crate::index::offset_store::OffsetStore;
+use crate::proto::generated_proto::common::FieldType;
+use crate::proto::generated_proto::index::KafkaOffsetStoreEntry;
+use crate::utils;
+
+fn field_types() -> HashMap<String, FieldType> {
+    HashMap::from([
+        ("field0".to_string(), FieldType::STRING),
+        ("age".to_string(), FieldType::INT32),
+        ("score".to_string(), FieldType::FLOAT64),
+        ("tags".to_string(), FieldType::STRING_LIST),
+        ("embedding".to_string(), FieldType::FLOAT16_VECTOR),
+    ])
+}
+
+fn string_list(elements: &[&str]) -> Vec<u8> {
+    let mut result = vec![];
+    for element in elements {
+        result.extend((element.len() as u32).to_le_bytes());
+        result.extend(element.as_bytes());
+    }
+    result
+}
+
+fn write_parquet(filepath: &str) {
+    let schema = Arc::new(
+        parse_message_type(
+            "message schema {
+                REQUIRED BYTE_ARRAY field0 (UTF8);
+                OPTIONAL INT64 age;
+                OPTIONAL DOUBLE score;
+            }",
+        )
+        .unwrap(),
+    );
+    let file = File::create(filepath).unwrap();
+    let mut writer = SerializedFileWriter::new(file, schema, Default::default()).unwrap();
+    let mut row_group = writer.next_row_group().unwrap();
+
+    let mut column = row_group.next_column().unwrap().unwrap();
+    column
+        .typed::<ByteArrayType>()
+        .write_batch(&[ByteArray::from("p1"), ByteArray::from("p2")], None, None)
+        .unwrap();
+    column.close().unwrap();
+
+    // age of p2 is null
+    let mut column = row_group.next_column().unwrap().unwrap();
+    column
+        .typed::<Int64Type>()
+        .write_batch(&[51], Some(&[1, 0]), None)
+        .unwrap();
+    column.close().unwrap();
+
+    let mut column = row_group.next_column().unwrap().unwrap();
+    column
+        .typed::<DoubleType>()
+        .write_batch(&[0.5, 1.5], Some(&[1, 1]), None)
+        .unwrap();
+    column.close().unwrap();
+
+    row_group.close().unwrap();
+    writer.close().unwrap();
+}
+
+#[test]
+pub fn test_import_formats() {
+    let mount_directory = "/tmp/index_importer_test_test_import_formats";
+    let ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    std::fs::create_dir_all(&mount_directory).unwrap();
+
+    let jsonl_filepath = format!("{}/rows.jsonl", mount_directory);
+    std::fs::write(
+        &jsonl_filepath,
+        r#"{"field0": "j1", "age": 31, "score": 2, "tags": ["a", "bc"], "embedding": [1.5, -2], "other": {"x": 1}}
+
+{"field0": "j2", "age": null, "tags": []}
+"#,
+    )
+    .unwrap();
+    let csv_filepath = format!("{}/rows.csv", mount_directory);
+    std::fs::write(
+        &csv_filepath,
+        "field0,age,score,tags,other\nc1,41,0.25,\"[\"\"d\"\"]\",x\nc2,,,,\n",
+    )
+    .unwrap();
+    let parquet_filepath = format!("{}/rows.parquet", mount_directory);
+    write_parquet(&parquet_filepath);
+
+    let filepaths = vec![
+        jsonl_filepath.clone(),
+        csv_filepath.clone(),
+        parquet_filepath.clone(),
+    ];
+    let mut stream_offset = KafkaOffsetStoreEntry::new();
+    stream_offset.topic = "topic".to_string();
+    stream_offset.partition = 0;
+    stream_offset.offset = 42;
+    let stats = IndexImporter::import(
+        &ikv_config,
+        &filepaths,
+        &field_types(),
+        vec![stream_offset.clone()],
+    )
+    .unwrap();
+    assert_eq!(stats.num_files, 3);
+    assert_eq!(stats.num_rows, 6);
+    assert_eq!(stats.num_invalid_rows, 0);
+
+    // importing again requires a fresh index
+    assert!(IndexImporter::import(&ikv_config, &filepaths, &field_types(), vec![]).is_err());
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert!(index.read_index_header().unwrap().base_index_epoch_millis > 0);
+
+    // readers consume the write stream from the offset of the import
+    let offset_store = OffsetStore::open_or_create(
+        utils::paths::get_index_mount_directory_fqn(&ikv_config).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        offset_store.read_all_offsets().unwrap(),
+        vec![stream_offset]
+    );
+
+    // jsonl
+    assert_eq!(
+        index.get_field_value(b"j1", "age").unwrap(),
+        31i32.to_le_bytes()
+    );
+    assert_eq!(
+        index.get_field_value(b"j1", "score").unwrap(),
+        2f64.to_le_bytes()
+    );
+    assert_eq!(
+        index.get_field_value(b"j1", "tags").unwrap(),
+        string_list(&["a", "bc"])
+    );
+    assert_eq!(
+        index.get_field_value(b"j1", "embedding").unwrap(),
+        [0x3e00u16.to_le_bytes(), 0xc000u16.to_le_bytes()].concat()
+    );
+    assert_eq!(index.get_field_value(b"j1", "other"), None);
+    assert_eq!(index.get_field_value(b"j2", "age"), None);
+    assert_eq!(
+        index.get_field_value(b"j2", "tags").unwrap(),
+        Vec::<u8>::new()
+    );
+
+    // csv
+    assert_eq!(
+        index.get_field_value(b"c1", "age").unwrap(),
+        41i32.to_le_bytes()
+    );
+    assert_eq!(
+        index.get_field_value(b"c1", "score").unwrap(),
+        0.25f64.to_le_bytes()
+    );
+    assert_eq!(
+        index.get_field_value(b"c1", "tags").unwrap(),
+        string_list(&["d"])
+    );
+    assert_eq!(index.get_field_value(b"c2", "age"), None);
+    assert_eq!(index.get_field_value(b"c2", "field0").unwrap(), b"c2");
+
+    // parquet
+    assert_eq!(
+        index.get_field_value(b"p1", "age").unwrap(),
+        51i32.to_le_bytes()
+    );
+    assert_eq!(index.get_field_value(b"p2", "age"), None);
+    assert_eq!(
+        index.get_field_value(b"p2", "score").unwrap(),
+        1.5f64.to_le_bytes()
+    );
+    index.close().unwrap();
+
+    let report = CKVIndex::verify_index(&ikv_config).unwrap();
+    assert!(report.is_valid(), "{}", report.problems_summary());
+    assert_eq!(report.num_documents, 6);
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_import_invalid_rows() {
+    let mount_directory = "/tmp/index_importer_test_test_import_invalid_rows";
+    let mut ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    std::fs::create_dir_all(&mount_directory).unwrap();
+
+    // missing primary key, unparsable and out of range ages
+    let jsonl_filepath = format!("{}/rows.jsonl", mount_directory);
+    std::fs::write(
+        &jsonl_filepath,
+        r#"{"field0": "j1", "age": 1}
+
+{"age": 2}
+{"field0": "j3", "age": "three"}
+{"field0": "j4", "age": 5000000000}
+not json
+{"field0": "j6", "age": 6}
+"#,
+    )
+    .unwrap();
+    let filepaths = vec![jsonl_filepath];
+
+    let error = IndexImporter::import(&ikv_config, &filepaths, &field_types(), vec![]).unwrap_err();
+    // blank lines are skipped, but counted in line numbers
+    assert!(error.to_string().contains("invalid row: 3"), "{}", error);
+    CKVIndex::delete_all(&ikv_config).unwrap();
+
+    // unknown formats and missing primary key types fail upfront
+    assert!(IndexImporter::import(
+        &ikv_config,
+        &["rows.txt".to_string()],
+        &field_types(),
+        vec![]
+    )
+    .is_err());
+    let mut no_primary_key = field_types();
+    no_primary_key.remove("field0");
+    assert!(IndexImporter::import(&ikv_config, &filepaths, &no_primary_key, vec![]).is_err());
+    assert!(CKVIndex::index_not_present(&ikv_config).unwrap());
+
+    ikv_config
+        .booleanConfigs
+        .insert("import_skip_invalid_rows".to_string(), true);
+    let stats = IndexImporter::import(&ikv_config, &filepaths, &field_types(), vec![]).unwrap();
+    assert_eq!(stats.num_rows, 2);
+    assert_eq!(stats.num_invalid_rows, 4);
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert_eq!(
+        index.get_field_value(b"j6", "age").unwrap(),
+        6i32.to_le_bytes()
+    );
+    assert_eq!(index.get_field_value(b"j3", "field0"), None);
+    index.close().unwrap();
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_import_format_from_path() {
+    assert_eq!(
+        ImportFormat::from_path("/data/part-0.JSONL").unwrap(),
+        ImportFormat::Jsonl
+    );
+    assert_eq!(
+        ImportFormat::from_path("part-0.csv").unwrap(),
+        ImportFormat::Csv
+    );
+    assert_eq!(
+        ImportFormat::from_path("part-0.parquet").unwrap(),
+        ImportFormat::Parquet
+    );
+    assert!(ImportFormat::from_path("part-0").is_err());
+}
+
+#[test]
+pub fn test_configured_import_field_types() {
+    let mut ikv_config = utils::testing::setup_index_cfg("/tmp/index_importer_test");
+    assert!(configured_import_field_types(&ikv_config).is_err());
+
+    ikv_config.stringConfigs.insert(
+        "import_field_types".to_string(),
+        "field0:string, age:INT32,tags:string_list".to_string(),
+    );
+    let field_types = configured_import_field_types(&ikv_config).unwrap();
+    assert_eq!(field_types.len(), 3);
+    assert_eq!(field_types.get("age"), Some(&FieldType::INT32));
+    assert_eq!(field_types.get("tags"), Some(&FieldType::STRING_LIST));
+
+    ikv_config
+        .stringConfigs
+        .insert("import_field_types".to_string(), "age:int128".to_string());
+    assert!(configured_import_field_types(&ikv_config).is_err());
+    ikv_config
+        .stringConfigs
+        .insert("import_field_types".to_string(), "age:unknown".to_string());
+    assert!(configured_import_field_types(&ikv_config).is_err());
+}
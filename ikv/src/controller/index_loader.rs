@@ -12,7 +12,7 @@ use aws_sdk_s3::primitives::ByteStream;
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use log::info;
+use log::{info, warn};
 use tar::Archive;
 
 use crate::index::ckv::CKVIndex;
@@ -21,6 +21,11 @@ use crate::utils;
 
 const REFRESH_BASE_INDEX_AGE_MILLIS: u128 = 7 * 24 * 60 * 60 * 1000; // 7 days
 
+// Index level files of a base index, uploaded as a separate part along with each segment
+// (key: <base-index-key>/<part>) so that repairs download only what they restore.
+const INDEX_FILES_PART: &str = "index_files";
+const INDEX_FILES: [&str; 3] = ["header", "schema", "kafka_offsets"];
+
 #[tokio::main(flavor = "current_thread")]
 pub async fn load_index(config: &IKVStoreConfig) -> anyhow::Result<()> {
     let working_mount_directory = crate::utils::paths::get_working_mount_directory_fqn(config)?;
@@ -30,7 +35,10 @@ pub async fn load_index(config: &IKVStoreConfig) -> anyhow::Result<()> {
     std::fs::create_dir_all(&working_mount_directory)?;
     std::fs::create_dir_all(&index_mount_directory)?;
 
-    if base_index_download_required(config).await? {
+    // before checks, which would find an index half switched by resharding
+    CKVIndex::complete_reshard(config)?;
+
+    if base_index_download_required(&working_mount_directory, config).await? {
         info!("Removing existing base index on disk.");
         CKVIndex::delete_all(config)?;
 
@@ -44,20 +52,40 @@ pub async fn load_index(config: &IKVStoreConfig) -> anyhow::Result<()> {
 
 /// New index downloaded when:
 /// 1) No index is present on disk (ex. bootstrapping new hardware)
-/// 2) Index is corrupt/invalida
+/// 2) Index is corrupt/invalid, and cannot be repaired (see repair_index())
 /// 3) Base index age is old and we should refresh it.
-async fn base_index_download_required(config: &IKVStoreConfig) -> anyhow::Result<bool> {
+async fn base_index_download_required(
+    working_mount_directory: &str,
+    config: &IKVStoreConfig,
+) -> anyhow::Result<bool> {
     if CKVIndex::index_not_present(config)? {
         info!("No base index present in mount directory, needs download.");
         return Ok(true);
     }
 
-    if let Err(e) = CKVIndex::is_valid_index(config) {
+    // full verification reads the whole index, opt-in since it slows down every open
+    let report = if full_index_verification_enabled(config) {
+        CKVIndex::verify_index(config)?
+    } else {
+        CKVIndex::verify_index_structure(config)?
+    };
+    if !report.is_valid() {
         info!(
-            "Base index found in inconsistent state: {}, needs download.",
-            e
+            "Base index failed verification: {}",
+            report.problems_summary()
         );
-        return Ok(true);
+        if !index_repair_enabled(config) {
+            info!("Index repair is not enabled, needs download.");
+            return Ok(true);
+        }
+
+        match repair_index(working_mount_directory, config).await {
+            Ok(()) => info!("Repaired base index."),
+            Err(e) => {
+                warn!("Cannot repair base index: {}, needs download.", e);
+                return Ok(true);
+            }
+        }
     }
 
     // Check for download based on age.
@@ -82,6 +110,150 @@ async fn base_index_download_required(config: &IKVStoreConfig) -> anyhow::Result
     return Ok(false);
 }
 
+fn full_index_verification_enabled(config: &IKVStoreConfig) -> bool {
+    config
+        .booleanConfigs
+        .get("enable_full_index_verification")
+        .copied()
+        .unwrap_or(false)
+}
+
+fn index_repair_enabled(config: &IKVStoreConfig) -> bool {
+    config
+        .booleanConfigs
+        .get("enable_index_repair")
+        .copied()
+        .unwrap_or(false)
+}
+
+/// Repairs an index which failed verification, keeping its valid segments.
+/// Segments are first repaired locally (see CKVIndex::repair_segments()), ones which are still
+/// broken are restored from the latest base index and caught up by replaying events since
+/// then (see CKVIndex::restore_segments()). Only the broken segments and index level files
+/// needed for checks are downloaded, from parts uploaded along with the base index.
+async fn repair_index(
+    working_mount_directory: &str,
+    config: &IKVStoreConfig,
+) -> anyhow::Result<()> {
+    let broken_segment_ids = CKVIndex::repair_segments(config)?;
+    if broken_segment_ids.is_empty() {
+        return Ok(());
+    }
+    info!(
+        "Restoring segments: {:?} from base index.",
+        &broken_segment_ids
+    );
+
+    let (key, _) = find_latest_base_index(config)
+        .await?
+        .ok_or(anyhow!("No base index to restore segments from"))?;
+
+    let bucket_name = config
+        .stringConfigs
+        .get("base_index_s3_bucket_name")
+        .ok_or(anyhow!(
+            "base_index_s3_bucket_name is a required gateway-specified config"
+        ))?
+        .to_string();
+
+    // clear leftovers of previous attempts
+    let tarball_index_filename = format!("{}/base_index.tar.gz", working_mount_directory);
+    if Path::new(&tarball_index_filename).exists() {
+        std::fs::remove_file(&tarball_index_filename)?;
+    }
+    let repair_directory = format!("{}/repair", working_mount_directory);
+    if Path::new(&repair_directory).exists() {
+        std::fs::remove_dir_all(&repair_directory)?;
+    }
+
+    let aws_config = aws_config::defaults(BehaviorVersion::latest())
+        .no_credentials()
+        .region("us-west-2")
+        .load()
+        .await;
+    let s3_client = S3Client::new(&aws_config);
+
+    // download index level files and broken segments only
+    let mut parts = vec![INDEX_FILES_PART.to_string()];
+    for index_id in broken_segment_ids.iter() {
+        parts.push(segment_part(*index_id));
+    }
+    for part in parts.iter() {
+        let part_key = format!("{}/{}", &key, part);
+        if let Err(e) = download_from_s3(
+            &s3_client,
+            config,
+            &bucket_name,
+            &part_key,
+            &tarball_index_filename,
+        )
+        .await
+        {
+            // ex. base index uploaded without parts
+            warn!(
+                "Cannot download base index part: {}, error: {}, downloading whole base index.",
+                &part_key, e
+            );
+            if Path::new(&tarball_index_filename).exists() {
+                std::fs::remove_file(&tarball_index_filename)?;
+            }
+            if Path::new(&repair_directory).exists() {
+                std::fs::remove_dir_all(&repair_directory)?;
+            }
+            download_whole_base_index_for_repair(
+                &s3_client,
+                config,
+                &bucket_name,
+                &key,
+                &tarball_index_filename,
+                &repair_directory,
+                &broken_segment_ids,
+            )
+            .await?;
+            break;
+        }
+        unpack_tarball(&tarball_index_filename, &repair_directory)?;
+        std::fs::remove_file(&tarball_index_filename)?;
+    }
+
+    CKVIndex::restore_segments(
+        config,
+        &format!("{}/base_index", repair_directory),
+        &broken_segment_ids,
+    )?;
+    std::fs::remove_dir_all(repair_directory)?;
+
+    Ok(())
+}
+
+/// Downloads a base index in full, and unpacks index level files and segments `index_ids`.
+async fn download_whole_base_index_for_repair(
+    s3_client: &S3Client,
+    config: &IKVStoreConfig,
+    bucket_name: &str,
+    key: &str,
+    tarball_index_filename: &str,
+    repair_directory: &str,
+    index_ids: &[usize],
+) -> anyhow::Result<()> {
+    download_from_s3(s3_client, config, bucket_name, key, tarball_index_filename).await?;
+
+    let mut paths: Vec<String> = INDEX_FILES
+        .iter()
+        .map(|filename| format!("base_index/{}", filename))
+        .collect();
+    for index_id in index_ids.iter() {
+        paths.push(format!("base_index/index/segment_{}", index_id));
+    }
+    unpack_tarball_paths(tarball_index_filename, repair_directory, &paths)?;
+    std::fs::remove_file(tarball_index_filename)?;
+    Ok(())
+}
+
+fn segment_part(index_id: usize) -> String {
+    format!("segment_{}", index_id)
+}
+
 fn local_base_index_epoch_millis(config: &IKVStoreConfig) -> anyhow::Result<Option<u128>> {
     let ckv_index = CKVIndex::open_or_create(config)?;
     let header = ckv_index.read_index_header()?;
@@ -104,6 +276,13 @@ pub async fn upload_index(config: &IKVStoreConfig) -> anyhow::Result<()> {
     if let Err(e) = CKVIndex::is_valid_index(config) {
         bail!("Cannot upload bad index, error: {}", e);
     }
+    let report = CKVIndex::verify_index(config)?;
+    if !report.is_valid() {
+        bail!(
+            "Cannot upload bad index, verification failed: {}",
+            report.problems_summary()
+        );
+    }
 
     // upload as base index
     orchestrate_index_upload(&working_mount_directory, &index_mount_directory, config).await
@@ -168,8 +347,60 @@ async fn orchestrate_index_upload(
     let base_index_s3_key = format!("{}/{}/{}/{}", &account_id, &store_name, partition, &epoch);
 
     // upload!
+    upload_to_s3(
+        &client,
+        config,
+        &bucket_name,
+        &base_index_s3_key,
+        &tarball_index_filename,
+    )
+    .await?;
+
+    // Remove tarball
+    std::fs::remove_file(&tarball_index_filename)?;
+
+    // upload parts for repairs, after the whole base index (see repair_index())
+    let mut parts = vec![(
+        INDEX_FILES_PART.to_string(),
+        INDEX_FILES
+            .iter()
+            .map(|filename| filename.to_string())
+            .collect(),
+    )];
+    for entry in std::fs::read_dir(format!("{}/index", index_mount_directory))? {
+        let filename = entry?.file_name().to_string_lossy().to_string();
+        if let Some(index_id) = filename
+            .strip_prefix("segment_")
+            .and_then(|index_id| index_id.parse::<usize>().ok())
+        {
+            parts.push((segment_part(index_id), vec![format!("index/{}", filename)]));
+        }
+    }
+    for (part, paths) in parts {
+        pack_tarball_paths(index_mount_directory, &paths, &tarball_index_filename)?;
+        upload_to_s3(
+            &client,
+            config,
+            &bucket_name,
+            &format!("{}/{}", &base_index_s3_key, part),
+            &tarball_index_filename,
+        )
+        .await?;
+        std::fs::remove_file(&tarball_index_filename)?;
+    }
+
+    Ok(())
+}
+
+async fn upload_to_s3(
+    client: &S3Client,
+    config: &IKVStoreConfig,
+    bucket: &str,
+    key: &str,
+    source: &str,
+) -> anyhow::Result<()> {
     let sse_key_objects = utils::encryption::sse_key_and_digest(config)?;
-    let body = ByteStream::from_path(Path::new(&tarball_index_filename)).await?;
+    let body = ByteStream::from_path(Path::new(source)).await?;
 
     client
         .put_object()
@@ -177,14 +408,11 @@ async fn orchestrate_index_upload(
         .sse_customer_algorithm(aws_sdk_s3::types::ServerSideEncryption::Aes256.as_str())
         .sse_customer_key(sse_key_objects.0)
         .sse_customer_key_md5(sse_key_objects.1)
-        .bucket(bucket_name)
-        .key(base_index_s3_key)
+        .bucket(bucket)
+        .key(key)
         .body(body)
         .send()
         .await?;
-
-    // Remove tarball
-    std::fs::remove_file(tarball_index_filename)?;
     Ok(())
 }
 
@@ -244,6 +472,10 @@ async fn find_latest_base_index(config: &IKVStoreConfig) -> anyhow::Result<Optio
             // key format: <account_id>/<storename>/<partition>/<epoch>
             if let Some(key) = object.key() {
                 let key_parts = key.split('/').collect::<Vec<&str>>();
+                if key_parts.len() > 4 {
+                    // part of a base index, see orchestrate_index_upload()
+                    continue;
+                }
 
                 let epoch = key_parts.get(3).ok_or(anyhow!(
                     "malformed base index key: {}, expecting epoch",
@@ -372,6 +604,31 @@ fn unpack_tarball(input_filepath: &str, destination_dir: &str) -> anyhow::Result
     Ok(())
 }
 
+/// Unpacks only entries at (or under) `paths` of a tarball.
+fn unpack_tarball_paths(
+    input_filepath: &str,
+    destination_dir: &str,
+    paths: &[String],
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(destination_dir)?;
+    let file = OpenOptions::new().read(true).open(input_filepath)?;
+    let tar = GzDecoder::new(file);
+    let mut archive = Archive::new(tar);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.to_string_lossy().to_string();
+        let entry_path = entry_path.trim_end_matches('/');
+        if paths
+            .iter()
+            .any(|path| entry_path == path || entry_path.starts_with(&format!("{}/", path)))
+        {
+            entry.unpack_in(destination_dir)?;
+        }
+    }
+
+    Ok(())
+}
+
 // input_dir: directory to tarball (ex. the index directory)
 // output_filepath: of the the tarball file
 fn pack_tarball(input_dir: &str, output_filepath: &str) -> anyhow::Result<()> {
@@ -385,3 +642,29 @@ fn pack_tarball(input_dir: &str, output_filepath: &str) -> anyhow::Result<()> {
     tar.append_dir_all("base_index", input_dir)?;
     Ok(())
 }
+
+/// Same as pack_tarball(), with only files or directories at `paths` (relative to `input_dir`).
+fn pack_tarball_paths(
+    input_dir: &str,
+    paths: &[String],
+    output_filepath: &str,
+) -> anyhow::Result<()> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(output_filepath)?;
+    let enc = GzEncoder::new(file, Compression::default());
+    let mut tar = tar::Builder::new(enc);
+    for path in paths {
+        let input_path = Path::new(input_dir).join(path);
+        let tar_path = format!("base_index/{}", path);
+        if input_path.is_dir() {
+            tar.append_dir_all(tar_path, input_path)?;
+        } else {
+            tar.append_path_with_name(input_path, tar_path)?;
+        }
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
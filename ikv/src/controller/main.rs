@@ -1,8 +1,10 @@
 use std::sync::Arc;
 
 use log::info;
+use tokio::runtime::Runtime;
 
-use crate::index::ckv::CKVIndex;
+use crate::index::ckv::{configured_warmup_field_names, CKVIndex};
+use crate::index::online_compactor::OnlineCompactor;
 use crate::kafka::consumer::IKVKafkaConsumer;
 use crate::kafka::processor::WritesProcessor;
 use crate::kafka::producer::IKVKafkaProducer;
@@ -20,10 +22,29 @@ pub struct ReadController {
     index: Arc<CKVIndex>,
     processor: Arc<WritesProcessor>,
     kafka_consumer: IKVKafkaConsumer,
+    online_compactor: Option<OnlineCompactor>,
 }
 
 impl ReadController {
     pub fn open(config: &IKVStoreConfig) -> anyhow::Result<Self> {
+        Self::open_with_consumer(config, |processor| IKVKafkaConsumer::new(config, processor))
+    }
+
+    /// Same as open(), with write events consumed on a runtime shared with
+    /// other controllers, see ReaderRegistry.
+    pub fn open_with_consumer_runtime(
+        config: &IKVStoreConfig,
+        consumer_runtime: Arc<Runtime>,
+    ) -> anyhow::Result<Self> {
+        Self::open_with_consumer(config, |processor| {
+            IKVKafkaConsumer::with_runtime(config, processor, consumer_runtime)
+        })
+    }
+
+    fn open_with_consumer<F>(config: &IKVStoreConfig, new_consumer: F) -> anyhow::Result<Self>
+    where
+        F: FnOnce(Arc<WritesProcessor>) -> anyhow::Result<IKVKafkaConsumer>,
+    {
         // fetch server configs and override|merge with client supplied configs
         // let config = Controller::merge_with_server_config(client_supplied_config)?;
 
@@ -33,7 +54,7 @@ impl ReadController {
 
         // Initialize kafka consumer
         let processor = Arc::new(WritesProcessor::new(index.clone()));
-        let kafka_consumer = IKVKafkaConsumer::new(&config, processor.clone())?;
+        let kafka_consumer = new_consumer(processor.clone())?;
 
         // Start write event consumption
         // Blocks till pending events are consumed
@@ -41,10 +62,35 @@ impl ReadController {
 
         kafka_consumer.run_in_background()?;
 
+        // Fault hot fields into the page cache (if configured)
+        index.warmup(&configured_warmup_field_names(config))?;
+
+        // Start online compaction (if enabled)
+        let online_compactor = OnlineCompactor::start(&config, index.clone())?;
+
+        Ok(ReadController {
+            index,
+            processor,
+            kafka_consumer,
+            online_compactor,
+        })
+    }
+
+    /// Opens the local index only, write events are not consumed.
+    #[cfg(test)]
+    pub(crate) fn open_without_consumption(
+        config: &IKVStoreConfig,
+        consumer_runtime: Arc<Runtime>,
+    ) -> anyhow::Result<Self> {
+        let index = Arc::new(CKVIndex::open_or_create(config)?);
+        let processor = Arc::new(WritesProcessor::new(index.clone()));
+        let kafka_consumer =
+            IKVKafkaConsumer::with_runtime(config, processor.clone(), consumer_runtime)?;
         Ok(ReadController {
             index,
             processor,
             kafka_consumer,
+            online_compactor: None,
         })
     }
 
@@ -78,6 +124,9 @@ impl ReadController {
 
     pub fn close(self) -> anyhow::Result<()> {
         self.kafka_consumer.stop();
+        if let Some(online_compactor) = self.online_compactor {
+            online_compactor.stop();
+        }
         info!("Closing IKV Reader Client, Bye Bye.");
         Ok(())
     }
@@ -1,3 +1,7 @@
 pub mod index_builder;
+pub mod index_exporter;
+pub mod index_importer;
 pub mod index_loader;
 pub mod main;
+pub mod partitioned_reader;
+pub mod registry;
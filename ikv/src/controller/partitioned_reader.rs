@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use log::info;
+use protobuf::Enum;
+use tokio::runtime::Runtime;
+
+use crate::index::ckv::CKVIndex;
+use crate::kafka::consumer::consumer_runtime;
+use crate::kafka::partitioner::{self, Partitioner};
+use crate::proto::generated_proto::common::{FieldType, FieldValue, IKVStoreConfig};
+
+use super::main::ReadController;
+
+#[cfg(test)]
+#[path = "partitioned_reader_test.rs"]
+mod partitioned_reader_test;
+
+// Upper bound on threads consuming write events of all partitions.
+const MAX_CONSUMER_THREADS: usize = 4;
+
+// Size prefix of missing values, see CKVIndex::batch_get_field_values().
+const NONE_SIZE: [u8; 4] = (-1i32).to_le_bytes();
+
+/// Reader of all partitions of a store (or a configured subset), each consumed into
+/// its own index (at mount-dir/store/partition). Reads are routed to the partition
+/// which the producer writes a document to.
+pub struct PartitionedReader {
+    consumer_runtime: Arc<Runtime>,
+    num_partitions: i32,
+    partitioner: Partitioner,
+
+    // type of serialized primary keys, with the kafka default partitioner
+    primary_key_type: Option<FieldType>,
+
+    // partition -> controller, of served partitions
+    controllers: BTreeMap<i32, ReadController>,
+}
+
+impl PartitionedReader {
+    /// Opens indexes of partitions in "partitions" (format: "0,1,5"), or of all
+    /// "num_kafka_partitions" partitions. The "partition" config is ignored.
+    /// With the kafka default partitioner, "primary_key_type" is required to route reads.
+    pub fn open(config: &IKVStoreConfig) -> anyhow::Result<Self> {
+        Self::open_with(config, ReadController::open_with_consumer_runtime)
+    }
+
+    fn open_with<F>(config: &IKVStoreConfig, open_partition: F) -> anyhow::Result<Self>
+    where
+        F: Fn(&IKVStoreConfig, Arc<Runtime>) -> anyhow::Result<ReadController>,
+    {
+        let num_partitions = configured_num_partitions(config)?;
+        let partitions = configured_partitions(config, num_partitions)?;
+        let partitioner = Partitioner::from_config(config)?;
+        let primary_key_type = match partitioner {
+            Partitioner::Ikv => None,
+            Partitioner::KafkaDefault => Some(configured_primary_key_type(config)?),
+        };
+
+        let num_consumer_threads = partitions.len().min(MAX_CONSUMER_THREADS);
+        let consumer_runtime = Arc::new(consumer_runtime(num_consumer_threads)?);
+
+        let mut reader = PartitionedReader {
+            consumer_runtime,
+            num_partitions,
+            partitioner,
+            primary_key_type,
+            controllers: BTreeMap::new(),
+        };
+        for partition in partitions {
+            let mut partition_config = config.clone();
+            partition_config
+                .intConfigs
+                .insert("partition".to_string(), partition as i64);
+
+            // blocks till pending write events of the partition are consumed
+            match open_partition(&partition_config, reader.consumer_runtime.clone()) {
+                Ok(controller) => {
+                    reader.controllers.insert(partition, controller);
+                }
+                Err(e) => {
+                    let _ = reader.close();
+                    bail!("Cannot open partition: {}, error: {}", partition, e);
+                }
+            }
+        }
+
+        info!(
+            "Opened partitions: {:?} of {}",
+            reader.controllers.keys().collect::<Vec<_>>(),
+            num_partitions
+        );
+        Ok(reader)
+    }
+
+    /// Partition of a document, see Partitioner.
+    pub fn partition(&self, primary_key: &[u8]) -> Option<i32> {
+        let field_value = match self.primary_key_type {
+            None => return partitioner::partition(primary_key, self.num_partitions).ok(),
+            Some(primary_key_type) => {
+                // kafka default partitioner hashes the serialized primary key
+                let mut field_value = FieldValue::new();
+                field_value.fieldType = primary_key_type.into();
+                field_value.value = primary_key.to_vec();
+                field_value
+            }
+        };
+        self.partitioner
+            .document_partition(&field_value, self.num_partitions)
+            .ok()
+    }
+
+    /// Atomic reference to the index of a partition, None if it is not served.
+    pub fn index_ref(&self, partition: i32) -> Option<Arc<CKVIndex>> {
+        self.controllers
+            .get(&partition)
+            .map(|controller| controller.index_ref())
+    }
+
+    /// See CKVIndex::get_field_value(), None for documents of partitions which are not served.
+    pub fn get_field_value(&self, primary_key: &[u8], field_name: &str) -> Option<Vec<u8>> {
+        self.index_ref(self.partition(primary_key)?)?
+            .get_field_value(primary_key, field_name)
+    }
+
+    /// See CKVIndex::batch_get_field_values(), values of documents of partitions
+    /// which are not served are missing.
+    pub fn batch_get_field_values(
+        &self,
+        primary_keys: Vec<&[u8]>,
+        field_names: Vec<&str>,
+    ) -> Vec<u8> {
+        let mut result = vec![];
+        for primary_key in primary_keys {
+            match self
+                .partition(primary_key)
+                .and_then(|partition| self.index_ref(partition))
+            {
+                None => {
+                    for _ in 0..field_names.len() {
+                        result.extend(NONE_SIZE);
+                    }
+                }
+                Some(index) => result
+                    .extend(index.batch_get_field_values(vec![primary_key], field_names.clone())),
+            }
+        }
+        result
+    }
+
+    /// Stops consuming write events, and closes all partitions.
+    pub fn close(self) -> anyhow::Result<()> {
+        for (partition, controller) in self.controllers {
+            controller.close()?;
+            info!("Closed partition: {}", partition);
+        }
+
+        if let Ok(runtime) = Arc::try_unwrap(self.consumer_runtime) {
+            runtime.shutdown_timeout(Duration::from_secs(60));
+        }
+        Ok(())
+    }
+
+    /// Get reference from raw pointer.
+    pub fn from_external_handle(handle: i64) -> &'static PartitionedReader {
+        unsafe { &*(handle as *const PartitionedReader) }
+    }
+
+    /// Get boxed reference from raw pointer.
+    pub fn from_external_handle_as_boxed(handle: i64) -> Box<PartitionedReader> {
+        let boxed_reader_ptr = handle as *mut PartitionedReader;
+        unsafe { Box::from_raw(boxed_reader_ptr) }
+    }
+
+    /// Convert to raw pointer, which can be stored outside of Rust.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_external_handle(self) -> i64 {
+        let boxed_reader = Box::new(self);
+        let handle: *mut PartitionedReader = Box::into_raw(boxed_reader);
+        handle as i64
+    }
+}
+
+fn configured_num_partitions(config: &IKVStoreConfig) -> anyhow::Result<i32> {
+    let num_partitions = config
+        .intConfigs
+        .get("num_kafka_partitions")
+        .copied()
+        .ok_or(anyhow!(
+            "num_kafka_partitions is a required gateway-specified config"
+        ))?;
+    if num_partitions <= 0 || num_partitions > i32::MAX as i64 {
+        bail!("num_kafka_partitions bad value: {}", num_partitions);
+    }
+    Ok(num_partitions as i32)
+}
+
+/// Type of primary keys (FieldType name, ex. "STRING"), which the kafka default
+/// partitioner hashes along with the key.
+fn configured_primary_key_type(config: &IKVStoreConfig) -> anyhow::Result<FieldType> {
+    let primary_key_type = config.stringConfigs.get("primary_key_type").ok_or(anyhow!(
+        "primary_key_type is a required client-specified config with the kafka_default partitioner"
+    ))?;
+    FieldType::from_str(&primary_key_type.to_uppercase())
+        .filter(|field_type| *field_type != FieldType::UNKNOWN)
+        .ok_or(anyhow!(
+            "primary_key_type unsupported field type: {}",
+            primary_key_type
+        ))
+}
+
+/// Client-specified subset of partitions to serve, all partitions if not configured.
+/// Format: "0,1,5"
+fn configured_partitions(config: &IKVStoreConfig, num_partitions: i32) -> anyhow::Result<Vec<i32>> {
+    let partitions = match config.stringConfigs.get("partitions") {
+        None => return Ok((0..num_partitions).collect()),
+        Some(partitions) => partitions,
+    };
+
+    let mut result = vec![];
+    for partition in partitions
+        .split(',')
+        .map(|p| p.trim())
+        .filter(|p| !p.is_empty())
+    {
+        match partition.parse::<i32>() {
+            Ok(p) if (0..num_partitions).contains(&p) => {
+                if !result.contains(&p) {
+                    result.push(p);
+                }
+            }
+            _ => bail!("partitions bad value: {}", partition),
+        }
+    }
+    if result.is_empty() {
+        bail!("partitions bad value: {}", partitions);
+    }
+    result.sort();
+    Ok(result)
+}
@@ -0,0 +1,133 @@
+use protobuf::Message;
+
+use crate::controller::main::ReadController;
+use crate::controller::partitioned_reader::{
+    configured_num_partitions, configured_partitions, PartitionedReader,
+};
+use crate::kafka::partitioner::default_partition;
+use crate::proto::generated_proto::common::IKVStoreConfig;
+use crate::utils;
+use crate::utils::testing::PRIMARY_KEY_FIELD_NAME;
+
+#[test]
+pub fn test_configured_partitions() {
+    let mut config = IKVStoreConfig::new();
+    assert!(configured_num_partitions(&config).is_err());
+    config
+        .intConfigs
+        .insert("num_kafka_partitions".to_string(), 0);
+    assert!(configured_num_partitions(&config).is_err());
+    config
+        .intConfigs
+        .insert("num_kafka_partitions".to_string(), 4);
+    assert_eq!(configured_num_partitions(&config).unwrap(), 4);
+
+    // all partitions by default
+    assert_eq!(configured_partitions(&config, 4).unwrap(), vec![0, 1, 2, 3]);
+
+    config
+        .stringConfigs
+        .insert("partitions".to_string(), "3, 1,1".to_string());
+    assert_eq!(configured_partitions(&config, 4).unwrap(), vec![1, 3]);
+
+    for bad_value in ["4", "-1", "x", ""] {
+        config
+            .stringConfigs
+            .insert("partitions".to_string(), bad_value.to_string());
+        assert!(configured_partitions(&config, 4).is_err());
+    }
+}
+
+fn setup_partitioned_cfg(mount_directory: &str) -> IKVStoreConfig {
+    let mut config = utils::testing::setup_index_cfg(mount_directory);
+    for (key, value) in [
+        ("account_id", "account"),
+        ("account_passkey", "passkey"),
+        ("kafka_bootstrap_server", "localhost:9092"),
+        ("kafka_topic", "topic"),
+        ("primary_key_type", "string"),
+    ] {
+        config
+            .stringConfigs
+            .insert(key.to_string(), value.to_string());
+    }
+    config
+        .intConfigs
+        .insert("num_kafka_partitions".to_string(), 2);
+    config
+}
+
+#[test]
+pub fn test_routing() {
+    let mount_directory = "/tmp/partitioned_reader_test_test_routing";
+    let _ = std::fs::remove_dir_all(mount_directory);
+    let mut config = setup_partitioned_cfg(mount_directory);
+
+    let reader =
+        PartitionedReader::open_with(&config, ReadController::open_without_consumption).unwrap();
+
+    // index each document in the partition the producer writes it to
+    let mut primary_keys = vec![];
+    let mut num_documents = [0, 0];
+    for i in 0..20 {
+        let document = utils::testing::create_document(i);
+        let primary_key = document.get(PRIMARY_KEY_FIELD_NAME).unwrap();
+        let partition = default_partition(&primary_key.write_to_bytes().unwrap(), 2).unwrap();
+        assert_eq!(reader.partition(&primary_key.value), Some(partition));
+        num_documents[partition as usize] += 1;
+
+        let index = reader.index_ref(partition).unwrap();
+        index.upsert_field_values(&document).unwrap();
+        primary_keys.push(String::from_utf8(primary_key.value.clone()).unwrap());
+    }
+    assert!(num_documents[0] > 0 && num_documents[1] > 0);
+    for partition in 0..2 {
+        reader.index_ref(partition).unwrap().flush_writes().unwrap();
+    }
+
+    // reads are routed to the partition of each document
+    for primary_key in primary_keys.iter() {
+        assert_eq!(
+            reader
+                .get_field_value(primary_key.as_bytes(), PRIMARY_KEY_FIELD_NAME)
+                .unwrap(),
+            primary_key.as_bytes()
+        );
+    }
+    let result = reader.batch_get_field_values(
+        vec![primary_keys[0].as_bytes(), primary_keys[1].as_bytes()],
+        vec![PRIMARY_KEY_FIELD_NAME],
+    );
+    assert_eq!(
+        result,
+        [primary_keys[0].as_bytes(), primary_keys[1].as_bytes()]
+            .iter()
+            .flat_map(|pk| [(pk.len() as i32).to_le_bytes().to_vec(), pk.to_vec()].concat())
+            .collect::<Vec<u8>>()
+    );
+    reader.close().unwrap();
+
+    // documents of partitions which are not served are missing
+    config
+        .stringConfigs
+        .insert("partitions".to_string(), "1".to_string());
+    let reader =
+        PartitionedReader::open_with(&config, ReadController::open_without_consumption).unwrap();
+    assert!(reader.index_ref(0).is_none());
+    for primary_key in primary_keys.iter() {
+        let value = reader.get_field_value(primary_key.as_bytes(), PRIMARY_KEY_FIELD_NAME);
+        match reader.partition(primary_key.as_bytes()).unwrap() {
+            0 => assert!(value.is_none()),
+            _ => assert_eq!(value.unwrap(), primary_key.as_bytes()),
+        }
+    }
+    reader.close().unwrap();
+
+    // the kafka default partitioner needs the primary key type
+    config.stringConfigs.remove("primary_key_type");
+    assert!(
+        PartitionedReader::open_with(&config, ReadController::open_without_consumption).is_err()
+    );
+
+    let _ = std::fs::remove_dir_all(mount_directory);
+}
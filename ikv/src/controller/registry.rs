@@ -0,0 +1,183 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use log::info;
+use tokio::runtime::Runtime;
+
+use crate::index::ckv::CKVIndex;
+use crate::kafka::consumer::consumer_runtime;
+use crate::proto::generated_proto::common::IKVStoreConfig;
+
+use super::main::ReadController;
+
+#[cfg(test)]
+#[path = "registry_test.rs"]
+mod registry_test;
+
+/// Process-wide registry of read controllers of several stores (and their partitions),
+/// whose write events are consumed on a single shared runtime.
+pub struct ReaderRegistry {
+    consumer_runtime: Arc<Runtime>,
+
+    // store-name -> partition -> controller
+    stores: RwLock<HashMap<String, BTreeMap<i64, ReadController>>>,
+
+    // serializes open_store() and close_store(), reads are not blocked while
+    // a store catches up on pending writes
+    open_lock: Mutex<()>,
+}
+
+impl ReaderRegistry {
+    /// Empty registry, consuming write events on `num_consumer_threads` threads.
+    pub fn new(num_consumer_threads: usize) -> anyhow::Result<Self> {
+        if num_consumer_threads == 0 {
+            bail!("Registry requires at least one consumer thread");
+        }
+
+        Ok(ReaderRegistry {
+            consumer_runtime: Arc::new(consumer_runtime(num_consumer_threads)?),
+            stores: RwLock::new(HashMap::new()),
+            open_lock: Mutex::new(()),
+        })
+    }
+
+    /// Opens a store partition, identified by its "store_name" and "partition" configs.
+    /// See ReadController::open(). Errors if it is already open.
+    pub fn open_store(&self, config: &IKVStoreConfig) -> anyhow::Result<()> {
+        self.open_store_with(config, ReadController::open_with_consumer_runtime)
+    }
+
+    fn open_store_with<F>(&self, config: &IKVStoreConfig, open: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&IKVStoreConfig, Arc<Runtime>) -> anyhow::Result<ReadController>,
+    {
+        let (store_name, partition) = store_key(config)?;
+
+        let _guard = self.open_lock.lock().unwrap();
+        if self.index_ref(&store_name, partition).is_some() {
+            bail!(
+                "Store: {} partition: {} is already open",
+                store_name,
+                partition
+            );
+        }
+
+        // blocks till pending write events are consumed
+        let controller = open(config, self.consumer_runtime.clone())?;
+        self.stores
+            .write()
+            .unwrap()
+            .entry(store_name.clone())
+            .or_default()
+            .insert(partition, controller);
+
+        info!("Opened store: {} partition: {}", store_name, partition);
+        Ok(())
+    }
+
+    /// Stops consuming write events of a store partition, and closes it.
+    pub fn close_store(&self, store_name: &str, partition: i64) -> anyhow::Result<()> {
+        let _guard = self.open_lock.lock().unwrap();
+        let controller = {
+            let mut stores = self.stores.write().unwrap();
+            let partitions = stores
+                .get_mut(store_name)
+                .ok_or(anyhow!("Store: {} is not open", store_name))?;
+            let controller = partitions.remove(&partition).ok_or(anyhow!(
+                "Store: {} partition: {} is not open",
+                store_name,
+                partition
+            ))?;
+            if partitions.is_empty() {
+                stores.remove(store_name);
+            }
+            controller
+        };
+
+        controller.close()?;
+        info!("Closed store: {} partition: {}", store_name, partition);
+        Ok(())
+    }
+
+    /// Atomic reference to the index of a store partition, None if it is not open.
+    pub fn index_ref(&self, store_name: &str, partition: i64) -> Option<Arc<CKVIndex>> {
+        self.stores
+            .read()
+            .unwrap()
+            .get(store_name)?
+            .get(&partition)
+            .map(|controller| controller.index_ref())
+    }
+
+    /// Field value of a document of a store. Documents live in a single partition,
+    /// open partitions of the store are looked up in order.
+    pub fn get_field_value(
+        &self,
+        store_name: &str,
+        primary_key: &[u8],
+        field_name: &str,
+    ) -> Option<Vec<u8>> {
+        let indexes: Vec<Arc<CKVIndex>> = self
+            .stores
+            .read()
+            .unwrap()
+            .get(store_name)?
+            .values()
+            .map(|controller| controller.index_ref())
+            .collect();
+
+        indexes
+            .iter()
+            .find_map(|index| index.get_field_value(primary_key, field_name))
+    }
+
+    /// Closes all open stores, and stops the consumer runtime.
+    pub fn close(self) -> anyhow::Result<()> {
+        let stores = self.stores.into_inner().unwrap();
+        for (store_name, partitions) in stores {
+            for (partition, controller) in partitions {
+                controller.close()?;
+                info!("Closed store: {} partition: {}", store_name, partition);
+            }
+        }
+
+        if let Ok(runtime) = Arc::try_unwrap(self.consumer_runtime) {
+            runtime.shutdown_timeout(Duration::from_secs(60));
+        }
+        Ok(())
+    }
+
+    /// Get reference from raw pointer.
+    pub fn from_external_handle(handle: i64) -> &'static ReaderRegistry {
+        unsafe { &*(handle as *const ReaderRegistry) }
+    }
+
+    /// Get boxed reference from raw pointer.
+    pub fn from_external_handle_as_boxed(handle: i64) -> Box<ReaderRegistry> {
+        let boxed_registry_ptr = handle as *mut ReaderRegistry;
+        unsafe { Box::from_raw(boxed_registry_ptr) }
+    }
+
+    /// Convert to raw pointer, which can be stored outside of Rust.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_external_handle(self) -> i64 {
+        let boxed_registry = Box::new(self);
+        let handle: *mut ReaderRegistry = Box::into_raw(boxed_registry);
+        handle as i64
+    }
+}
+
+fn store_key(config: &IKVStoreConfig) -> anyhow::Result<(String, i64)> {
+    let store_name = config
+        .stringConfigs
+        .get("store_name")
+        .ok_or(anyhow!("store_name is a required client-specified config"))?;
+    let partition = config
+        .intConfigs
+        .get("partition")
+        .copied()
+        .ok_or(anyhow!("partition is a required client-specified config"))?;
+    Ok((store_name.to_string(), partition))
+}
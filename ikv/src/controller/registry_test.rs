@@ -0,0 +1,107 @@
+use crate::controller::main::ReadController;
+use crate::controller::registry::ReaderRegistry;
+use crate::proto::generated_proto::common::IKVStoreConfig;
+use crate::utils;
+use crate::utils::testing::{DOCFIELD1, PRIMARY_KEY_FIELD_NAME};
+
+#[test]
+pub fn test_empty_registry() {
+    assert!(ReaderRegistry::new(0).is_err());
+
+    let registry = ReaderRegistry::new(1).unwrap();
+    assert!(registry.index_ref("store", 0).is_none());
+    assert!(registry
+        .get_field_value("store", b"pkey", "field")
+        .is_none());
+    assert!(registry.close_store("store", 0).is_err());
+
+    // stores are identified by store name and partition
+    let mut config = IKVStoreConfig::new();
+    assert!(registry.open_store(&config).is_err());
+    config
+        .stringConfigs
+        .insert("store_name".to_string(), "store".to_string());
+    assert!(registry.open_store(&config).is_err());
+
+    registry.close().unwrap();
+}
+
+fn setup_store_cfg(mount_directory: &str, store_name: &str) -> IKVStoreConfig {
+    let mut config = utils::testing::setup_index_cfg(mount_directory);
+    for (key, value) in [
+        ("store_name", store_name),
+        ("account_id", "account"),
+        ("account_passkey", "passkey"),
+        ("kafka_bootstrap_server", "localhost:9092"),
+        ("kafka_topic", store_name),
+    ] {
+        config
+            .stringConfigs
+            .insert(key.to_string(), value.to_string());
+    }
+    config
+}
+
+#[test]
+pub fn test_two_stores() {
+    let mount_directory = "/tmp/registry_test_test_two_stores";
+    let _ = std::fs::remove_dir_all(mount_directory);
+
+    // both stores share a single consumer thread
+    let registry = ReaderRegistry::new(1).unwrap();
+    let store1_config = setup_store_cfg(mount_directory, "store1");
+    let store2_config = setup_store_cfg(mount_directory, "store2");
+    registry
+        .open_store_with(&store1_config, ReadController::open_without_consumption)
+        .unwrap();
+    registry
+        .open_store_with(&store2_config, ReadController::open_without_consumption)
+        .unwrap();
+    assert!(registry
+        .open_store_with(&store1_config, ReadController::open_without_consumption)
+        .is_err());
+
+    let doc0 = utils::testing::create_document(0);
+    let pkey0 = doc0.get(PRIMARY_KEY_FIELD_NAME).unwrap().value.clone();
+    let doc1 = utils::testing::create_document(1);
+    let pkey1 = doc1.get(PRIMARY_KEY_FIELD_NAME).unwrap().value.clone();
+
+    let store1_index = registry.index_ref("store1", 0).unwrap();
+    store1_index.upsert_field_values(&doc0).unwrap();
+    store1_index.flush_writes().unwrap();
+    let store2_index = registry.index_ref("store2", 0).unwrap();
+    store2_index.upsert_field_values(&doc1).unwrap();
+    store2_index.flush_writes().unwrap();
+
+    // each store serves its own documents
+    assert_eq!(
+        registry
+            .get_field_value("store1", &pkey0, DOCFIELD1)
+            .unwrap(),
+        doc0.get(DOCFIELD1).unwrap().value
+    );
+    assert!(registry
+        .get_field_value("store1", &pkey1, DOCFIELD1)
+        .is_none());
+    assert_eq!(
+        registry
+            .get_field_value("store2", &pkey1, DOCFIELD1)
+            .unwrap(),
+        doc1.get(DOCFIELD1).unwrap().value
+    );
+    assert!(registry
+        .get_field_value("store2", &pkey0, DOCFIELD1)
+        .is_none());
+
+    // closing a store leaves the other one open
+    registry.close_store("store1", 0).unwrap();
+    assert!(registry
+        .get_field_value("store1", &pkey0, DOCFIELD1)
+        .is_none());
+    assert!(registry
+        .get_field_value("store2", &pkey1, DOCFIELD1)
+        .is_some());
+
+    registry.close().unwrap();
+    let _ = std::fs::remove_dir_all(mount_directory);
+}
@@ -1,4 +1,6 @@
 use crate::controller::main::{ReadController, WriteController};
+use crate::controller::partitioned_reader::PartitionedReader;
+use crate::controller::registry::ReaderRegistry;
 use crate::proto::generated_proto::common::IKVStoreConfig;
 
 pub fn open_reader(ikv_config: &IKVStoreConfig) -> anyhow::Result<i64> {
@@ -16,6 +18,21 @@ pub fn close_reader(handle: i64) -> anyhow::Result<()> {
     boxed_controller.close()
 }
 
+pub fn open_partitioned_reader(ikv_config: &IKVStoreConfig) -> anyhow::Result<i64> {
+    // configure logging
+    crate::utils::logging::configure_logging(&ikv_config)?;
+
+    // open and catch up all partitions
+    let reader = PartitionedReader::open(&ikv_config)?;
+
+    Ok(reader.to_external_handle())
+}
+
+pub fn close_partitioned_reader(handle: i64) -> anyhow::Result<()> {
+    let boxed_reader = PartitionedReader::from_external_handle_as_boxed(handle);
+    boxed_reader.close()
+}
+
 pub fn open_writer(ikv_config: &IKVStoreConfig) -> anyhow::Result<i64> {
     // configure logging
     crate::utils::logging::configure_logging(&ikv_config)?;
@@ -30,3 +47,20 @@ pub fn close_writer(handle: i64) -> anyhow::Result<()> {
     let boxed_controller = WriteController::from_external_handle_as_boxed(handle);
     boxed_controller.close()
 }
+
+pub fn open_registry(num_consumer_threads: i32) -> anyhow::Result<i64> {
+    let registry = ReaderRegistry::new(num_consumer_threads.max(0) as usize)?;
+    Ok(registry.to_external_handle())
+}
+
+pub fn open_registry_store(handle: i64, ikv_config: &IKVStoreConfig) -> anyhow::Result<()> {
+    // configure logging
+    crate::utils::logging::configure_logging(&ikv_config)?;
+
+    ReaderRegistry::from_external_handle(handle).open_store(ikv_config)
+}
+
+pub fn close_registry(handle: i64) -> anyhow::Result<()> {
+    let boxed_registry = ReaderRegistry::from_external_handle_as_boxed(handle);
+    boxed_registry.close()
+}
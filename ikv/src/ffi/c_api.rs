@@ -3,10 +3,30 @@ use std::ffi::CStr;
 use log::error;
 use protobuf::Message;
 
+use crate::controller::index_exporter::{ExportOptions, IndexExporter};
 use crate::controller::main::ReadController;
+use crate::controller::partitioned_reader::PartitionedReader;
+use crate::controller::registry::ReaderRegistry;
 use crate::proto::generated_proto::common::IKVStoreConfig;
 
 use crate::ffi::{api, utils};
+use crate::kafka::partitioner;
+
+/// Borrows a nul-terminated C string, None if the pointer is null or the data is not utf8.
+fn c_str<'a>(input: *const libc::c_char) -> Option<&'a str> {
+    if input.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(input) }.to_str().ok()
+}
+
+/// Borrows a byte buffer, empty if the pointer is null or the length is not positive.
+fn c_bytes<'a>(input: *const libc::c_char, len: i32) -> &'a [u8] {
+    if input.is_null() || len <= 0 {
+        return &[];
+    }
+    unsafe { std::slice::from_raw_parts(input as *const u8, len as usize) }
+}
 
 #[no_mangle]
 pub extern "C" fn health_check(input: *const libc::c_char) -> i64 {
@@ -83,6 +103,137 @@ pub extern "C" fn close_index(handle: i64) {
     }
 }
 
+/// Reader of all (or configured) partitions of a store, see PartitionedReader.
+#[no_mangle]
+pub extern "C" fn open_partitioned_index(
+    config: *const libc::c_char,
+    config_len: i32,
+) -> IndexHandle {
+    let cfg_bytes = unsafe { std::slice::from_raw_parts(config as *const u8, config_len as usize) };
+
+    let ikv_config = match IKVStoreConfig::parse_from_bytes(cfg_bytes) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!(
+                "Cannot parse client_options (proto3 deser error), details: {}",
+                e.to_string()
+            );
+            return IndexHandle {
+                handle: -1,
+                status: 1,
+            };
+        }
+    };
+
+    match api::open_partitioned_reader(&ikv_config) {
+        Ok(handle) => IndexHandle { handle, status: 0 },
+        Err(e) => {
+            error!(
+                "Cannot startup IKV partitioned reader, details: {}",
+                e.to_string()
+            );
+            IndexHandle {
+                handle: -1,
+                status: 2,
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn close_partitioned_index(handle: i64) {
+    if let Err(e) = api::close_partitioned_reader(handle) {
+        error!(
+            "Cannot close partitioned reader, failed with error: {}",
+            e.to_string()
+        );
+    }
+}
+
+/// Registry of several stores, whose write events are consumed on
+/// `num_consumer_threads` shared threads. See ReaderRegistry.
+#[no_mangle]
+pub extern "C" fn open_reader_registry(num_consumer_threads: i32) -> IndexHandle {
+    match api::open_registry(num_consumer_threads) {
+        Ok(handle) => IndexHandle { handle, status: 0 },
+        Err(e) => {
+            error!(
+                "Cannot startup IKV reader registry, details: {}",
+                e.to_string()
+            );
+            IndexHandle {
+                handle: -1,
+                status: 2,
+            }
+        }
+    }
+}
+
+/// Opens a store partition in the registry, see ReaderRegistry::open_store().
+/// Returns 0 on success, 1 if configs cannot be parsed, 2 if the store cannot be opened.
+#[no_mangle]
+pub extern "C" fn registry_open_store(
+    handle: i64,
+    config: *const libc::c_char,
+    config_len: i32,
+) -> i64 {
+    let cfg_bytes = unsafe { std::slice::from_raw_parts(config as *const u8, config_len as usize) };
+
+    let ikv_config = match IKVStoreConfig::parse_from_bytes(cfg_bytes) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!(
+                "Cannot parse client_options (proto3 deser error), details: {}",
+                e.to_string()
+            );
+            return 1;
+        }
+    };
+
+    match api::open_registry_store(handle, &ikv_config) {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("Cannot open store in registry, details: {}", e.to_string());
+            2
+        }
+    }
+}
+
+/// Returns 0 on success, -1 on failure (ex. store partition is not open).
+#[no_mangle]
+pub extern "C" fn registry_close_store(
+    handle: i64,
+    store_name: *const libc::c_char,
+    partition: i64,
+) -> i32 {
+    let registry = ReaderRegistry::from_external_handle(handle);
+
+    let store_name = match c_str(store_name) {
+        Some(store_name) => store_name,
+        None => {
+            error!("Cannot close store in registry, store_name is not valid utf8");
+            return -1;
+        }
+    };
+    match registry.close_store(store_name, partition) {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("Cannot close store in registry, error: {}", e.to_string());
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn close_reader_registry(handle: i64) {
+    if let Err(e) = api::close_registry(handle) {
+        error!(
+            "Cannot close reader registry, failed with error: {}",
+            e.to_string()
+        );
+    }
+}
+
 // References:
 // https://users.rust-lang.org/t/how-to-return-byte-array-from-rust-function-to-ffi-c/18136/4
 // https://kmdouglass.github.io/posts/complex-data-types-and-the-rust-ffi/
@@ -149,6 +300,87 @@ pub extern "C" fn get_field_value(
     }
 }
 
+/// Same as get_field_value, for a partitioned reader (see open_partitioned_index).
+#[no_mangle]
+pub extern "C" fn partitioned_get_field_value(
+    handle: i64,
+    pkey: *const libc::c_char,
+    pkey_len: i32,
+    field_name: *const libc::c_char,
+) -> BytesBuffer {
+    let reader = PartitionedReader::from_external_handle(handle);
+    let primary_key = unsafe { std::slice::from_raw_parts(pkey as *const u8, pkey_len as usize) };
+
+    let field_name = match c_str(field_name) {
+        Some(field_name) => field_name,
+        None => return EMPTY_BB,
+    };
+    match reader.get_field_value(primary_key, field_name) {
+        Some(field_value) => BytesBuffer::from_bytes(field_value),
+        None => EMPTY_BB,
+    }
+}
+
+/// Same as multiget_field_values, for a partitioned reader (see open_partitioned_index).
+#[no_mangle]
+pub extern "C" fn partitioned_multiget_field_values(
+    handle: i64,
+    concat_primary_keys: *const libc::c_char,
+    concat_primary_keys_len: i32,
+    concat_field_names: *const libc::c_char,
+    concat_field_names_len: i32,
+) -> BytesBuffer {
+    let reader = PartitionedReader::from_external_handle(handle);
+
+    // parse size-prefixed primary keys
+    let concat_primary_keys = unsafe {
+        std::slice::from_raw_parts(
+            concat_primary_keys as *const u8,
+            concat_primary_keys_len as usize,
+        )
+    };
+    let primary_keys = utils::unpack_size_prefixed_bytes(concat_primary_keys);
+    if primary_keys.is_empty() {
+        return EMPTY_BB;
+    }
+
+    // parse size-prefixed field names
+    let concat_field_names = unsafe {
+        std::slice::from_raw_parts(
+            concat_field_names as *const u8,
+            concat_field_names_len as usize,
+        )
+    };
+    let field_names = utils::unpack_size_prefixed_strs(concat_field_names);
+    if field_names.is_empty() {
+        return EMPTY_BB;
+    }
+
+    BytesBuffer::from_bytes(reader.batch_get_field_values(primary_keys, field_names))
+}
+
+/// Same as get_field_value, for a store opened in a registry (see open_reader_registry).
+#[no_mangle]
+pub extern "C" fn registry_get_field_value(
+    handle: i64,
+    store_name: *const libc::c_char,
+    pkey: *const libc::c_char,
+    pkey_len: i32,
+    field_name: *const libc::c_char,
+) -> BytesBuffer {
+    let registry = ReaderRegistry::from_external_handle(handle);
+    let primary_key = unsafe { std::slice::from_raw_parts(pkey as *const u8, pkey_len as usize) };
+
+    let (store_name, field_name) = match (c_str(store_name), c_str(field_name)) {
+        (Some(store_name), Some(field_name)) => (store_name, field_name),
+        _ => return EMPTY_BB,
+    };
+    match registry.get_field_value(store_name, primary_key, field_name) {
+        Some(field_value) => BytesBuffer::from_bytes(field_value),
+        None => EMPTY_BB,
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn multiget_field_values(
     handle: i64,
@@ -190,6 +422,195 @@ pub extern "C" fn multiget_field_values(
     BytesBuffer::from_bytes(result)
 }
 
+/// Same as multiget_field_values, with list values trimmed to their last `last_k` elements.
+/// Returns an empty buffer for a negative `last_k`.
+#[no_mangle]
+pub extern "C" fn multiget_last_list_elements(
+    handle: i64,
+    concat_primary_keys: *const libc::c_char,
+    concat_primary_keys_len: i32,
+    concat_field_names: *const libc::c_char,
+    concat_field_names_len: i32,
+    last_k: i32,
+) -> BytesBuffer {
+    let controller = ReadController::from_external_handle(handle);
+    if last_k < 0 {
+        return EMPTY_BB;
+    }
+
+    // parse size-prefixed primary keys
+    let concat_primary_keys = unsafe {
+        std::slice::from_raw_parts(
+            concat_primary_keys as *const u8,
+            concat_primary_keys_len as usize,
+        )
+    };
+    let primary_keys = utils::unpack_size_prefixed_bytes(concat_primary_keys);
+    if primary_keys.is_empty() {
+        return EMPTY_BB;
+    }
+
+    // parse size-prefixed field names
+    let concat_field_names = unsafe {
+        std::slice::from_raw_parts(
+            concat_field_names as *const u8,
+            concat_field_names_len as usize,
+        )
+    };
+    let field_names = utils::unpack_size_prefixed_strs(concat_field_names);
+    if field_names.is_empty() {
+        return EMPTY_BB;
+    }
+
+    let result = controller.index_ref().batch_get_last_list_elements(
+        primary_keys,
+        field_names,
+        last_k as usize,
+    );
+
+    BytesBuffer::from_bytes(result)
+}
+
+/// Approximate nearest neighbors of a query vector (little-endian f32 elements),
+/// among vectors of a field with an ann index.
+/// Result format: [(size)primary_key1][(f32)distance1]...[(size)primary_keyk][(f32)distancek]
+#[no_mangle]
+pub extern "C" fn knn(
+    handle: i64,
+    field_name: *const libc::c_char,
+    query: *const libc::c_char,
+    query_len: i32,
+    k: i32,
+) -> BytesBuffer {
+    let controller = ReadController::from_external_handle(handle);
+    if k <= 0 {
+        return EMPTY_BB;
+    }
+
+    let field_name = match c_str(field_name) {
+        Some(field_name) => field_name,
+        None => {
+            error!("Cannot run knn, field_name is not valid utf8");
+            return EMPTY_BB;
+        }
+    };
+    let query = unsafe { std::slice::from_raw_parts(query as *const u8, query_len as usize) };
+    let query = utils::unpack_f32_elements(query);
+
+    match controller.index_ref().knn(field_name, &query, k as usize) {
+        Ok(neighbors) => BytesBuffer::from_bytes(utils::pack_neighbors(neighbors)),
+        Err(e) => {
+            error!("Cannot run knn, error: {}", e);
+            EMPTY_BB
+        }
+    }
+}
+
+/// Declared type of a field (FieldType enum value), 0 (UNKNOWN) for unknown or non-utf8 field names.
+#[no_mangle]
+pub extern "C" fn get_field_type(handle: i64, field_name: *const libc::c_char) -> i32 {
+    let controller = ReadController::from_external_handle(handle);
+
+    c_str(field_name)
+        .and_then(|field_name| controller.index_ref().get_field_type(field_name))
+        .map(|field_type| field_type as i32)
+        .unwrap_or(0)
+}
+
+/// Partition of a document in the topic of a store using the IKV partitioner
+/// ("partitioner": "ikv"), see kafka::partitioner::partition().
+/// Returns -1 for a bad partition count.
+#[no_mangle]
+pub extern "C" fn partition_for_key(
+    pkey: *const libc::c_char,
+    pkey_len: i32,
+    num_partitions: i32,
+) -> i32 {
+    let primary_key = unsafe { std::slice::from_raw_parts(pkey as *const u8, pkey_len as usize) };
+    partitioner::partition(primary_key, num_partitions).unwrap_or(-1)
+}
+
+/// Dumps documents to a local file (.jsonl or .parquet), see IndexExporter.
+/// Exports all fields if no field names are provided, and all documents if no primary keys are.
+/// Returns 0 on success, -1 on failure.
+#[no_mangle]
+pub extern "C" fn export_index(
+    handle: i64,
+    output_filepath: *const libc::c_char,
+    concat_field_names: *const libc::c_char,
+    concat_field_names_len: i32,
+    concat_primary_keys: *const libc::c_char,
+    concat_primary_keys_len: i32,
+) -> i32 {
+    let controller = ReadController::from_external_handle(handle);
+
+    let output_filepath = match c_str(output_filepath) {
+        Some(output_filepath) => output_filepath,
+        None => {
+            error!("Cannot export index, output_filepath is not valid utf8");
+            return -1;
+        }
+    };
+
+    // parse size-prefixed field names and primary keys
+    let concat_field_names = c_bytes(concat_field_names, concat_field_names_len);
+    let concat_primary_keys = c_bytes(concat_primary_keys, concat_primary_keys_len);
+    let primary_keys = utils::unpack_size_prefixed_bytes(concat_primary_keys);
+    let options = ExportOptions {
+        field_names: utils::unpack_size_prefixed_strs(concat_field_names)
+            .into_iter()
+            .map(|field_name| field_name.to_string())
+            .collect(),
+        primary_keys: if primary_keys.is_empty() {
+            None
+        } else {
+            Some(primary_keys.into_iter().map(|pk| pk.to_vec()).collect())
+        },
+    };
+
+    match IndexExporter::export(&controller.index_ref(), output_filepath, &options) {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("Cannot export index, error: {}", e);
+            -1
+        }
+    }
+}
+
+/// Document and value statistics (serialized IndexStats proto), see CKVIndex::index_stats().
+/// Returns an empty buffer on failure.
+#[no_mangle]
+pub extern "C" fn get_index_stats(handle: i64) -> BytesBuffer {
+    let controller = ReadController::from_external_handle(handle);
+
+    let maybe_stats = controller
+        .index_ref()
+        .index_stats()
+        .and_then(|stats| Ok(stats.write_to_bytes()?));
+    match maybe_stats {
+        Ok(stats) => BytesBuffer::from_bytes(stats),
+        Err(e) => {
+            error!("Cannot compute index stats, error: {}", e);
+            EMPTY_BB
+        }
+    }
+}
+
+/// Hit rate and size of the decoded-value cache (serialized ValueCacheStats proto),
+/// see CKVIndex::value_cache_stats().
+#[no_mangle]
+pub extern "C" fn get_value_cache_stats(handle: i64) -> BytesBuffer {
+    let controller = ReadController::from_external_handle(handle);
+
+    match controller.index_ref().value_cache_stats().write_to_bytes() {
+        Ok(stats) => BytesBuffer::from_bytes(stats),
+        Err(e) => {
+            error!("Cannot serialize value cache stats, error: {}", e);
+            EMPTY_BB
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn free_bytes_buffer(buf: BytesBuffer) {
     buf.free()
@@ -1,11 +1,15 @@
 use jni::objects::{JByteArray, JClass, JObject, JString};
-use jni::sys::{jbyteArray, jlong, jstring};
+use jni::sys::{jbyteArray, jint, jlong, jstring};
 use jni::JNIEnv;
 use protobuf::Message;
 
 use crate::controller::index_builder::IndexBuilder;
+use crate::controller::index_importer::IndexImporter;
 use crate::controller::main::{ReadController, WriteController};
+use crate::controller::partitioned_reader::PartitionedReader;
+use crate::controller::registry::ReaderRegistry;
 use crate::ffi::{api, utils};
+use crate::kafka::partitioner;
 use crate::proto::generated_proto::common::{FieldValue, IKVStoreConfig};
 use crate::proto::generated_proto::streaming::IKVDataEvent;
 
@@ -46,6 +50,31 @@ pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_buildIndex<'local>(
     }
 }
 
+// NOTE: callers must cleanup their working directories
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_importIndex<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    config: JByteArray<'local>,
+) {
+    let config = utils::jbyte_array_to_vec(&env, config).unwrap();
+    let ikv_config = IKVStoreConfig::parse_from_bytes(&config).expect("could not read configs");
+
+    // logging setup
+    if let Err(e) = crate::utils::logging::configure_logging(&ikv_config) {
+        let exception = format!("Cannot initialize logging: {}", e.to_string());
+        let _ = env.throw_new("java/lang/RuntimeException", exception);
+        return;
+    }
+
+    // import and export
+    if let Err(e) = IndexImporter::import_and_export(&ikv_config) {
+        let exception = format!("Cannot import offline index, error: {}", e.to_string());
+        let _ = env.throw_new("java/lang/RuntimeException", exception);
+        return;
+    }
+}
+
 #[no_mangle]
 pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_open<'local>(
     mut env: JNIEnv<'local>,
@@ -85,6 +114,169 @@ pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_close<'local>(
     }
 }
 
+/// Reader of all (or configured) partitions of a store, see PartitionedReader.
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_openPartitioned<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    config: JByteArray<'local>,
+) -> jlong {
+    let maybe_handle = utils::jbyte_array_to_vec(&env, config)
+        .and_then(|config| Ok(IKVStoreConfig::parse_from_bytes(&config)?))
+        .and_then(|ikv_config| api::open_partitioned_reader(&ikv_config));
+    match maybe_handle {
+        Ok(handle) => handle,
+        Err(e) => {
+            let exception = format!(
+                "Cannot open partitioned reader, failed with error: {}",
+                e.to_string()
+            );
+            let _ = env.throw_new("java/lang/RuntimeException", exception);
+            0
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_closePartitioned<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) {
+    if let Err(e) = api::close_partitioned_reader(handle) {
+        let exception = format!(
+            "Cannot close partitioned reader, failed with error: {}",
+            e.to_string()
+        );
+        let _ = env.throw_new("java/lang/RuntimeException", exception);
+    }
+}
+
+/// Same as readField, for a partitioned reader.
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_partitionedReadField<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    primary_key: JByteArray<'local>,
+    field_name: JString<'local>,
+) -> jbyteArray {
+    let reader = PartitionedReader::from_external_handle(handle);
+    let primary_key = utils::jbyte_array_to_vec(&env, primary_key).unwrap();
+    let field_name: String = env.get_string(&field_name).unwrap().into();
+
+    match reader.get_field_value(&primary_key, &field_name) {
+        Some(field_value) => utils::vec_to_jbyte_array(&env, field_value),
+        None => JObject::null().into_raw(),
+    }
+}
+
+/// Same as batchReadFields, for a partitioned reader.
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_partitionedBatchReadFields<'local>(
+    env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    primary_keys: JByteArray<'local>,
+    field_names: JByteArray<'local>,
+) -> jbyteArray {
+    let reader = PartitionedReader::from_external_handle(handle);
+
+    let primary_keys = utils::jbyte_array_to_vec(&env, primary_keys).unwrap();
+    let primary_keys = utils::unpack_size_prefixed_bytes(&primary_keys);
+
+    let field_names = utils::jbyte_array_to_vec(&env, field_names).unwrap();
+    let field_names = utils::unpack_size_prefixed_strs(&field_names);
+
+    let result = reader.batch_get_field_values(primary_keys, field_names);
+    utils::vec_to_jbyte_array(&env, result)
+}
+
+/// Registry of several stores sharing write event consumer threads, see ReaderRegistry.
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_openRegistry<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    num_consumer_threads: jint,
+) -> jlong {
+    match api::open_registry(num_consumer_threads) {
+        Ok(handle) => handle,
+        Err(e) => {
+            let exception = format!("Cannot open registry, failed with error: {}", e.to_string());
+            let _ = env.throw_new("java/lang/RuntimeException", exception);
+            return 0;
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_registryOpenStore<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    config: JByteArray<'local>,
+) {
+    let maybe_opened = utils::jbyte_array_to_vec(&env, config)
+        .and_then(|config| Ok(IKVStoreConfig::parse_from_bytes(&config)?))
+        .and_then(|ikv_config| api::open_registry_store(handle, &ikv_config));
+    if let Err(e) = maybe_opened {
+        let exception = format!("Cannot open store, failed with error: {}", e.to_string());
+        let _ = env.throw_new("java/lang/RuntimeException", exception);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_registryCloseStore<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    store_name: JString<'local>,
+    partition: jlong,
+) {
+    let registry = ReaderRegistry::from_external_handle(handle);
+    let store_name: String = env.get_string(&store_name).unwrap().into();
+    if let Err(e) = registry.close_store(&store_name, partition) {
+        let exception = format!("Cannot close store, failed with error: {}", e.to_string());
+        let _ = env.throw_new("java/lang/RuntimeException", exception);
+    }
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_closeRegistry<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) {
+    if let Err(e) = api::close_registry(handle) {
+        let exception = format!(
+            "Cannot close registry, failed with error: {}",
+            e.to_string()
+        );
+        let _ = env.throw_new("java/lang/RuntimeException", exception);
+    }
+}
+
+/// Same as readField, for a store opened in a registry.
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_registryReadField<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    store_name: JString<'local>,
+    primary_key: JByteArray<'local>,
+    field_name: JString<'local>,
+) -> jbyteArray {
+    let registry = ReaderRegistry::from_external_handle(handle);
+    let store_name: String = env.get_string(&store_name).unwrap().into();
+    let primary_key = utils::jbyte_array_to_vec(&env, primary_key).unwrap();
+    let field_name: String = env.get_string(&field_name).unwrap().into();
+
+    match registry.get_field_value(&store_name, &primary_key, &field_name) {
+        Some(field_value) => utils::vec_to_jbyte_array(&env, field_value),
+        None => JObject::null().into_raw(),
+    }
+}
+
 #[no_mangle]
 pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_readField<'local>(
     mut env: JNIEnv<'local>,
@@ -132,6 +324,151 @@ pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_batchReadFields<'loc
     utils::vec_to_jbyte_array(&env, result)
 }
 
+/// Throws for a negative `last_k`, as multiget_last_list_elements() of the C api rejects it.
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_batchReadLastListElements<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    primary_keys: JByteArray<'local>,
+    field_names: JByteArray<'local>,
+    last_k: jint,
+) -> jbyteArray {
+    let controller = ReadController::from_external_handle(handle);
+    if last_k < 0 {
+        let exception_msg = format!(
+            "Cannot read last list elements, negative last_k: {}",
+            last_k
+        );
+        let _ = env.throw_new("java/lang/RuntimeException", exception_msg);
+        return JObject::null().into_raw();
+    }
+
+    let primary_keys = utils::jbyte_array_to_vec(&env, primary_keys).unwrap();
+    let primary_keys = utils::unpack_size_prefixed_bytes(&primary_keys);
+
+    let field_names = utils::jbyte_array_to_vec(&env, field_names).unwrap();
+    let field_names = utils::unpack_size_prefixed_strs(&field_names);
+
+    let result = controller.index_ref().batch_get_last_list_elements(
+        primary_keys,
+        field_names,
+        last_k as usize,
+    );
+
+    // TODO - ensure we don't return batch response larger than i32
+    utils::vec_to_jbyte_array(&env, result)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_knn<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    field_name: JString<'local>,
+    query: JByteArray<'local>,
+    k: jint,
+) -> jbyteArray {
+    let controller = ReadController::from_external_handle(handle);
+    let field_name: String = env.get_string(&field_name).unwrap().into();
+    let query = utils::jbyte_array_to_vec(&env, query).unwrap();
+    let query = utils::unpack_f32_elements(&query);
+
+    match controller
+        .index_ref()
+        .knn(&field_name, &query, k.max(0) as usize)
+    {
+        Ok(neighbors) => utils::vec_to_jbyte_array(&env, utils::pack_neighbors(neighbors)),
+        Err(e) => {
+            let exception_msg = format!("Cannot run knn, error: {}", e);
+            let _ = env.throw_new("java/lang/RuntimeException", exception_msg);
+            JObject::null().into_raw()
+        }
+    }
+}
+
+/// Declared type of a field (FieldType enum value), 0 (UNKNOWN) for unknown fields.
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_getFieldType<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+    field_name: JString<'local>,
+) -> jint {
+    let controller = ReadController::from_external_handle(handle);
+    let field_name: String = env.get_string(&field_name).unwrap().into();
+
+    controller
+        .index_ref()
+        .get_field_type(&field_name)
+        .map(|field_type| field_type as jint)
+        .unwrap_or(0)
+}
+
+/// Document and value statistics (serialized IndexStats proto), see CKVIndex::index_stats().
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_getIndexStats<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jbyteArray {
+    let controller = ReadController::from_external_handle(handle);
+
+    let maybe_stats = controller
+        .index_ref()
+        .index_stats()
+        .and_then(|stats| Ok(stats.write_to_bytes()?));
+    match maybe_stats {
+        Ok(stats) => utils::vec_to_jbyte_array(&env, stats),
+        Err(e) => {
+            let exception_msg = format!("Cannot compute index stats, error: {}", e);
+            let _ = env.throw_new("java/lang/RuntimeException", exception_msg);
+            JObject::null().into_raw()
+        }
+    }
+}
+
+/// Hit rate and size of the decoded-value cache (serialized ValueCacheStats proto),
+/// see CKVIndex::value_cache_stats().
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_getValueCacheStats<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jbyteArray {
+    let controller = ReadController::from_external_handle(handle);
+
+    match controller.index_ref().value_cache_stats().write_to_bytes() {
+        Ok(stats) => utils::vec_to_jbyte_array(&env, stats),
+        Err(e) => {
+            let exception_msg = format!("Cannot serialize value cache stats, error: {}", e);
+            let _ = env.throw_new("java/lang/RuntimeException", exception_msg);
+            JObject::null().into_raw()
+        }
+    }
+}
+
+/// Partition of a document in the topic of a store using the IKV partitioner
+/// ("partitioner": "ikv"), see kafka::partitioner::partition().
+#[no_mangle]
+pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_partitionForKey<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    primary_key: JByteArray<'local>,
+    num_partitions: jint,
+) -> jint {
+    let maybe_partition = utils::jbyte_array_to_vec(&env, primary_key)
+        .and_then(|primary_key| partitioner::partition(&primary_key, num_partitions));
+    match maybe_partition {
+        Ok(partition) => partition,
+        Err(e) => {
+            let exception = format!("Cannot partition primary key, error: {}", e);
+            let _ = env.throw_new("java/lang/RuntimeException", exception);
+            -1
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "system" fn Java_io_inlined_clients_IKVClientJNI_openWriter<'local>(
     mut env: JNIEnv<'local>,
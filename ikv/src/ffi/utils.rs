@@ -126,6 +126,25 @@ pub fn unpack_size_prefixed_strs<'a>(input: &'a [u8]) -> Vec<&'a str> {
     result
 }
 
+/// Little-endian f32 elements to Vec<f32>, trailing partial elements are ignored.
+pub fn unpack_f32_elements(input: &[u8]) -> Vec<f32> {
+    input
+        .chunks_exact(4)
+        .map(|e| f32::from_le_bytes(e.try_into().expect("chunks are 4 bytes wide")))
+        .collect()
+}
+
+/// knn results to [(size)primary_key1][(f32)distance1]...[(size)primary_keyk][(f32)distancek]
+pub fn pack_neighbors(neighbors: Vec<(Vec<u8>, f32)>) -> Vec<u8> {
+    let mut result = Vec::new();
+    for (primary_key, distance) in neighbors {
+        result.extend((primary_key.len() as i32).to_le_bytes());
+        result.extend(primary_key);
+        result.extend(distance.to_le_bytes());
+    }
+    result
+}
+
 /// https://stackoverflow.com/questions/59707349/cast-vector-of-i8-to-vector-of-u8-in-rust
 fn vec_i8_into_u8(v: Vec<i8>) -> Vec<u8> {
     // ideally we'd use Vec::into_raw_parts, but it's unstable,
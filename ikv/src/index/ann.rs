@@ -0,0 +1,616 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::bail;
+use log::warn;
+use protobuf::Message;
+
+use crate::{
+    proto::generated_proto::index::{
+        ann_log_entry::Operation, AnnLogCommit, AnnLogDelete, AnnLogEntry, AnnLogUpsert, AnnMetric,
+        SavedAnnIndex, SavedAnnNeighbors, SavedAnnNode,
+    },
+    schema::field::FieldId,
+};
+
+#[cfg(test)]
+#[path = "ann_test.rs"]
+mod ann_test;
+
+// Max neighbors per node on upper layers, twice as many on the bottom layer.
+const MAX_NEIGHBORS: usize = 16;
+
+// Candidate list size while inserting, higher is slower but gives a better graph.
+const EF_CONSTRUCTION: usize = 100;
+
+// Min candidate list size while searching.
+const EF_SEARCH: usize = 64;
+
+// Layers are capped, only reachable with ~MAX_NEIGHBORS^-MAX_LEVEL probability anyway.
+const MAX_LEVEL: usize = 16;
+
+// Graphs are rebuilt without deleted nodes once they make up most of the graph.
+const MIN_DELETED_NODES_FOR_REBUILD: usize = 1024;
+
+// Log of changes made to the ann indexes of a segment after they were last saved.
+// Format: [i32 size][u32 crc32][AnnLogEntry]...
+const LOG_FILENAME: &str = "ann_indexes_log";
+
+/// Approximate nearest-neighbor index over vectors of one field of a segment,
+/// implemented as a HNSW graph (https://arxiv.org/abs/1603.09320).
+///
+/// Deletes only mark graph nodes, so that the graph stays connected. Upserting
+/// a primary-key deletes its previous node and inserts a new one.
+#[derive(Debug)]
+pub struct HnswIndex {
+    field_id: FieldId,
+    field_name: String,
+    metric: AnnMetric,
+
+    // 0 till the first insert
+    dimension: usize,
+
+    // node-id is the position in this list
+    nodes: Vec<Node>,
+
+    // primary-key -> node-id, for live nodes
+    live_node_ids: HashMap<Vec<u8>, u32>,
+
+    entry_point: Option<u32>,
+
+    // xorshift state, for picking node levels
+    rng_state: u64,
+}
+
+#[derive(Debug)]
+struct Node {
+    primary_key: Vec<u8>,
+    vector: Vec<f32>,
+
+    // neighbor node-ids per layer, bottom layer first
+    layers: Vec<Vec<u32>>,
+
+    deleted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f32,
+    node_id: u32,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.node_id.cmp(&other.node_id))
+    }
+}
+
+impl HnswIndex {
+    pub fn new(field_id: FieldId, field_name: &str, metric: AnnMetric) -> Self {
+        Self {
+            field_id,
+            field_name: field_name.to_string(),
+            metric,
+            dimension: 0,
+            nodes: vec![],
+            live_node_ids: HashMap::new(),
+            entry_point: None,
+            rng_state: seed(field_name),
+        }
+    }
+
+    pub fn from_saved(saved: &SavedAnnIndex) -> anyhow::Result<Self> {
+        let mut index = Self::new(
+            saved.field_id,
+            &saved.field_name,
+            saved.metric.enum_value_or_default(),
+        );
+        index.dimension = saved.dimension as usize;
+
+        for (node_id, saved_node) in saved.nodes.iter().enumerate() {
+            if saved_node.vector.len() != index.dimension {
+                bail!(
+                    "Ann index of field: {} has node with dimension: {}, expected: {}",
+                    saved.field_name,
+                    saved_node.vector.len(),
+                    index.dimension
+                );
+            }
+
+            let mut layers = Vec::with_capacity(saved_node.layers.len());
+            for saved_neighbors in saved_node.layers.iter() {
+                if saved_neighbors
+                    .node_ids
+                    .iter()
+                    .any(|id| *id as usize >= saved.nodes.len())
+                {
+                    bail!(
+                        "Ann index of field: {} has out of range neighbors",
+                        saved.field_name
+                    );
+                }
+                layers.push(saved_neighbors.node_ids.clone());
+            }
+            if layers.is_empty() {
+                bail!(
+                    "Ann index of field: {} has node without layers",
+                    saved.field_name
+                );
+            }
+
+            if !saved_node.deleted {
+                index
+                    .live_node_ids
+                    .insert(saved_node.primary_key.clone(), node_id as u32);
+            }
+            index.nodes.push(Node {
+                primary_key: saved_node.primary_key.clone(),
+                vector: saved_node.vector.clone(),
+                layers,
+                deleted: saved_node.deleted,
+            });
+        }
+
+        if saved.entry_point >= 0 {
+            if saved.entry_point as usize >= index.nodes.len() {
+                bail!(
+                    "Ann index of field: {} has out of range entry point",
+                    saved.field_name
+                );
+            }
+            index.entry_point = Some(saved.entry_point as u32);
+        }
+
+        Ok(index)
+    }
+
+    pub fn to_saved(&self) -> SavedAnnIndex {
+        let mut saved = SavedAnnIndex::new();
+        saved.field_id = self.field_id;
+        saved.field_name = self.field_name.clone();
+        saved.metric = self.metric.into();
+        saved.dimension = self.dimension as u32;
+        saved.entry_point = self.entry_point.map(|id| id as i64).unwrap_or(-1);
+
+        for node in self.nodes.iter() {
+            let mut saved_node = SavedAnnNode::new();
+            saved_node.primary_key = node.primary_key.clone();
+            saved_node.vector = node.vector.clone();
+            for neighbors in node.layers.iter() {
+                let mut saved_neighbors = SavedAnnNeighbors::new();
+                saved_neighbors.node_ids = neighbors.clone();
+                saved_node.layers.push(saved_neighbors);
+            }
+            saved_node.deleted = node.deleted;
+            saved.nodes.push(saved_node);
+        }
+
+        saved
+    }
+
+    pub fn field_name(&self) -> &str {
+        &self.field_name
+    }
+
+    pub fn metric(&self) -> AnnMetric {
+        self.metric
+    }
+
+    /// Inserts (or replaces) the vector of a primary-key.
+    pub fn insert(&mut self, primary_key: &[u8], vector: Vec<f32>) {
+        if self.dimension != vector.len() {
+            if self.dimension != 0 {
+                // schema enforces dimension, so the field was dropped and recreated
+                warn!(
+                    "Resetting ann index of field: {}, dimension changed from: {} to: {}",
+                    self.field_name,
+                    self.dimension,
+                    vector.len()
+                );
+                self.clear();
+            }
+            self.dimension = vector.len();
+        }
+
+        self.delete(primary_key);
+
+        let node_id = self.nodes.len() as u32;
+        let level = self.random_level();
+        self.nodes.push(Node {
+            primary_key: primary_key.to_vec(),
+            vector,
+            layers: vec![vec![]; level + 1],
+            deleted: false,
+        });
+        self.live_node_ids.insert(primary_key.to_vec(), node_id);
+
+        let entry_point = match self.entry_point {
+            None => {
+                self.entry_point = Some(node_id);
+                return;
+            }
+            Some(entry_point) => entry_point,
+        };
+
+        let query = self.nodes[node_id as usize].vector.clone();
+        let top_level = self.nodes[entry_point as usize].layers.len() - 1;
+
+        // greedy descent through layers above the new node
+        let mut entry_points = vec![entry_point];
+        for layer in (level + 1..=top_level).rev() {
+            let nearest = self.search_layer(&query, &entry_points, 1, layer);
+            entry_points = vec![nearest[0].node_id];
+        }
+
+        // connect to nearest nodes on each layer of the new node
+        for layer in (0..=std::cmp::min(level, top_level)).rev() {
+            let nearest = self.search_layer(&query, &entry_points, EF_CONSTRUCTION, layer);
+            let max_neighbors = max_neighbors(layer);
+
+            let neighbors: Vec<u32> = nearest
+                .iter()
+                .take(max_neighbors)
+                .map(|c| c.node_id)
+                .collect();
+            for neighbor in neighbors.iter().copied() {
+                self.nodes[neighbor as usize].layers[layer].push(node_id);
+                if self.nodes[neighbor as usize].layers[layer].len() > max_neighbors {
+                    self.prune_neighbors(neighbor, layer, max_neighbors);
+                }
+            }
+            self.nodes[node_id as usize].layers[layer] = neighbors;
+
+            entry_points = nearest.iter().map(|c| c.node_id).collect();
+        }
+
+        if level > top_level {
+            self.entry_point = Some(node_id);
+        }
+    }
+
+    /// Applies a logged upsert or delete of this index, see append_log().
+    pub fn apply_log_entry(&mut self, entry: &AnnLogEntry) {
+        match entry.operation.as_ref() {
+            Some(Operation::Upsert(upsert)) if upsert.field_id == self.field_id => {
+                self.insert(&upsert.primary_key, upsert.vector.clone())
+            }
+            Some(Operation::Delete(delete)) if delete.field_id == self.field_id => {
+                self.delete(&delete.primary_key)
+            }
+            _ => {}
+        }
+    }
+
+    /// Marks the node of a primary-key deleted, if any.
+    pub fn delete(&mut self, primary_key: &[u8]) {
+        if let Some(node_id) = self.live_node_ids.remove(primary_key) {
+            self.nodes[node_id as usize].deleted = true;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.dimension = 0;
+        self.nodes.clear();
+        self.live_node_ids.clear();
+        self.entry_point = None;
+    }
+
+    /// True if deleted nodes make up most of the graph, see rebuild().
+    pub fn needs_rebuild(&self) -> bool {
+        let num_deleted = self.nodes.len() - self.live_node_ids.len();
+        num_deleted >= MIN_DELETED_NODES_FOR_REBUILD && num_deleted > self.live_node_ids.len()
+    }
+
+    /// Rebuilds the graph from live nodes only.
+    pub fn rebuild(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.clear();
+        for node in nodes.into_iter().filter(|node| !node.deleted) {
+            self.insert(&node.primary_key, node.vector);
+        }
+    }
+
+    /// Approximate nearest live nodes to `query`, closest first, as (primary-key, distance).
+    /// Can return more than `k` results, so that callers can filter some out.
+    pub fn search(&self, query: &[f32], k: usize) -> anyhow::Result<Vec<(&[u8], f32)>> {
+        let entry_point = match self.entry_point {
+            None => return Ok(vec![]),
+            Some(entry_point) => entry_point,
+        };
+        if query.len() != self.dimension {
+            bail!(
+                "Query vector has dimension: {}, field: {} has dimension: {}",
+                query.len(),
+                self.field_name,
+                self.dimension
+            );
+        }
+
+        let top_level = self.nodes[entry_point as usize].layers.len() - 1;
+        let mut entry_points = vec![entry_point];
+        for layer in (1..=top_level).rev() {
+            let nearest = self.search_layer(query, &entry_points, 1, layer);
+            entry_points = vec![nearest[0].node_id];
+        }
+
+        let ef = std::cmp::max(k, EF_SEARCH);
+        let nearest = self.search_layer(query, &entry_points, ef, 0);
+        Ok(nearest
+            .into_iter()
+            .map(|c| (&self.nodes[c.node_id as usize], c.distance))
+            .filter(|(node, _)| !node.deleted)
+            .map(|(node, distance)| (node.primary_key.as_slice(), distance))
+            .collect())
+    }
+
+    /// Best-first search on one layer, returns up to `ef` nearest nodes, closest first.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry_points: &[u32],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<u32> = HashSet::new();
+        let mut candidates: BinaryHeap<Reverse<Candidate>> = BinaryHeap::new();
+        let mut nearest: BinaryHeap<Candidate> = BinaryHeap::new();
+
+        for node_id in entry_points.iter().copied() {
+            if visited.insert(node_id) {
+                let candidate = Candidate {
+                    distance: self.distance_to(query, node_id),
+                    node_id,
+                };
+                candidates.push(Reverse(candidate));
+                nearest.push(candidate);
+            }
+        }
+        while nearest.len() > ef {
+            nearest.pop();
+        }
+
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            let furthest = nearest.peek().expect("nearest has entry points");
+            if nearest.len() >= ef && candidate.distance > furthest.distance {
+                break;
+            }
+
+            let layers = &self.nodes[candidate.node_id as usize].layers;
+            let neighbors = match layers.get(layer) {
+                None => continue,
+                Some(neighbors) => neighbors,
+            };
+            for neighbor in neighbors.iter().copied() {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+
+                let distance = self.distance_to(query, neighbor);
+                let furthest = nearest.peek().expect("nearest has entry points");
+                if nearest.len() < ef || distance < furthest.distance {
+                    let candidate = Candidate {
+                        distance,
+                        node_id: neighbor,
+                    };
+                    candidates.push(Reverse(candidate));
+                    nearest.push(candidate);
+                    if nearest.len() > ef {
+                        nearest.pop();
+                    }
+                }
+            }
+        }
+
+        nearest.into_sorted_vec()
+    }
+
+    /// Keeps the `max_neighbors` nearest neighbors of a node on a layer.
+    fn prune_neighbors(&mut self, node_id: u32, layer: usize, max_neighbors: usize) {
+        let node = &self.nodes[node_id as usize];
+        let mut neighbors: Vec<Candidate> = node.layers[layer]
+            .iter()
+            .map(|neighbor| Candidate {
+                distance: self.distance_to(&node.vector, *neighbor),
+                node_id: *neighbor,
+            })
+            .collect();
+        neighbors.sort();
+        neighbors.truncate(max_neighbors);
+
+        self.nodes[node_id as usize].layers[layer] =
+            neighbors.into_iter().map(|c| c.node_id).collect();
+    }
+
+    fn distance_to(&self, query: &[f32], node_id: u32) -> f32 {
+        distance(self.metric, query, &self.nodes[node_id as usize].vector)
+    }
+
+    /// Level of a new node, exponentially decaying probability per level.
+    fn random_level(&mut self) -> usize {
+        // xorshift64
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+
+        // uniform in (0, 1]
+        let uniform = ((self.rng_state >> 11) + 1) as f64 / (1u64 << 53) as f64;
+        let level = -uniform.ln() / (MAX_NEIGHBORS as f64).ln();
+        std::cmp::min(level as usize, MAX_LEVEL)
+    }
+}
+
+fn max_neighbors(layer: usize) -> usize {
+    if layer == 0 {
+        2 * MAX_NEIGHBORS
+    } else {
+        MAX_NEIGHBORS
+    }
+}
+
+/// Distance between two vectors of the same dimension, lower is closer.
+pub fn distance(metric: AnnMetric, a: &[f32], b: &[f32]) -> f32 {
+    match metric {
+        AnnMetric::L2 => a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum(),
+        AnnMetric::COSINE => {
+            let mut dot = 0.0;
+            let mut norm_a = 0.0;
+            let mut norm_b = 0.0;
+            for (x, y) in a.iter().zip(b) {
+                dot += x * y;
+                norm_a += x * x;
+                norm_b += y * y;
+            }
+            if norm_a == 0.0 || norm_b == 0.0 {
+                return 1.0;
+            }
+            1.0 - dot / (norm_a.sqrt() * norm_b.sqrt())
+        }
+        AnnMetric::DOT_PRODUCT => -a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>(),
+    }
+}
+
+/// Deterministic non-zero rng seed.
+fn seed(field_name: &str) -> u64 {
+    let hash = fxhash::hash64(field_name.as_bytes());
+    if hash == 0 {
+        0x9e3779b97f4a7c15
+    } else {
+        hash
+    }
+}
+
+pub fn upsert_log_entry(field_id: FieldId, primary_key: &[u8], vector: &[f32]) -> AnnLogEntry {
+    let mut upsert = AnnLogUpsert::new();
+    upsert.field_id = field_id;
+    upsert.primary_key = primary_key.to_vec();
+    upsert.vector = vector.to_vec();
+    let mut entry = AnnLogEntry::new();
+    entry.operation = Some(Operation::Upsert(upsert));
+    entry
+}
+
+pub fn delete_log_entry(field_id: FieldId, primary_key: &[u8]) -> AnnLogEntry {
+    let mut delete = AnnLogDelete::new();
+    delete.field_id = field_id;
+    delete.primary_key = primary_key.to_vec();
+    let mut entry = AnnLogEntry::new();
+    entry.operation = Some(Operation::Delete(delete));
+    entry
+}
+
+/// Appends changes to the ann index log of a segment, followed by a commit entry
+/// with the segment's mmap write offset. Returns the size of the log.
+pub fn append_log(
+    mount_directory: &str,
+    entries: &[AnnLogEntry],
+    mmap_write_offset: u64,
+) -> io::Result<u64> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_filename(mount_directory))?;
+    let mut writer = BufWriter::new(file);
+
+    let mut commit = AnnLogCommit::new();
+    commit.mmap_write_offset = mmap_write_offset;
+    let mut commit_entry = AnnLogEntry::new();
+    commit_entry.operation = Some(Operation::Commit(commit));
+
+    for entry in entries.iter().chain(std::iter::once(&commit_entry)) {
+        let bytes = entry.write_to_bytes()?;
+        writer.write_all(&(bytes.len() as i32).to_le_bytes())?;
+        writer.write_all(&crc32fast::hash(&bytes).to_le_bytes())?;
+        writer.write_all(&bytes)?;
+    }
+    writer.flush()?;
+
+    Ok(writer.get_ref().metadata()?.len())
+}
+
+/// Applies committed changes of the ann index log of a segment in order, see append_log().
+/// Returns the mmap write offset of the last commit, None if nothing is committed.
+/// Replay stops at the first partially written or corrupt entry, and the log
+/// is truncated to its last commit.
+pub fn replay_log<F>(mount_directory: &str, mut apply: F) -> anyhow::Result<Option<u64>>
+where
+    F: FnMut(&AnnLogEntry),
+{
+    let filename = log_filename(mount_directory);
+    if !Path::new(&filename).exists() {
+        return Ok(None);
+    }
+
+    let mut contents = vec![];
+    File::open(&filename)?.read_to_end(&mut contents)?;
+
+    let mut position = 0;
+    let mut committed_len = 0;
+    let mut mmap_write_offset = None;
+    let mut uncommitted = vec![];
+    while position + 8 <= contents.len() {
+        let size = i32::from_le_bytes(contents[position..position + 4].try_into()?);
+        let checksum = u32::from_le_bytes(contents[position + 4..position + 8].try_into()?);
+        let end = position + 8 + size.max(0) as usize;
+        if size < 0 || end > contents.len() {
+            break;
+        }
+        let bytes = &contents[position + 8..end];
+        if checksum != crc32fast::hash(bytes) {
+            warn!("Found ann index log entry with checksum mismatch");
+            break;
+        }
+        let entry = AnnLogEntry::parse_from_bytes(bytes)?;
+        position = end;
+
+        match entry.operation.as_ref() {
+            Some(Operation::Commit(commit)) => {
+                uncommitted.drain(..).for_each(|entry| apply(&entry));
+                mmap_write_offset = Some(commit.mmap_write_offset);
+                committed_len = position;
+            }
+            _ => uncommitted.push(entry),
+        }
+    }
+
+    if committed_len < contents.len() {
+        warn!(
+            "Truncating ann index log of segment: {} from {} to {} bytes, dropping uncommitted tail",
+            mount_directory,
+            contents.len(),
+            committed_len
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(&filename)?
+            .set_len(committed_len as u64)?;
+    }
+
+    Ok(mmap_write_offset)
+}
+
+/// Drops the ann index log of a segment, ex. once indexes are saved in full.
+pub fn remove_log(mount_directory: &str) -> io::Result<()> {
+    match std::fs::remove_file(log_filename(mount_directory)) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn log_filename(mount_directory: &str) -> String {
+    format!("{}/{}", mount_directory, LOG_FILENAME)
+}
@@ -0,0 +1,192 @@
+use std::fs::OpenOptions;
+
+use crate::index::ann::{
+    append_log, delete_log_entry, distance, remove_log, replay_log, upsert_log_entry, HnswIndex,
+};
+use crate::proto::generated_proto::index::AnnMetric;
+
+const DIMENSION: usize = 8;
+
+/// Deterministic pseudo-random vectors.
+fn create_vectors(count: usize) -> Vec<Vec<f32>> {
+    let mut state: u64 = 42;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % 2000) as f32 / 1000.0 - 1.0
+    };
+
+    (0..count)
+        .map(|_| (0..DIMENSION).map(|_| next()).collect())
+        .collect()
+}
+
+fn primary_key(i: usize) -> Vec<u8> {
+    format!("pk:{}", i).into_bytes()
+}
+
+fn brute_force_knn(vectors: &[Vec<f32>], query: &[f32], k: usize) -> Vec<Vec<u8>> {
+    let mut distances: Vec<(usize, f32)> = vectors
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (i, distance(AnnMetric::L2, query, v)))
+        .collect();
+    distances.sort_by(|a, b| a.1.total_cmp(&b.1));
+    distances
+        .into_iter()
+        .take(k)
+        .map(|(i, _)| primary_key(i))
+        .collect()
+}
+
+#[test]
+pub fn test_recall() {
+    let vectors = create_vectors(1000);
+    let mut index = HnswIndex::new(1, "embedding", AnnMetric::L2);
+    for (i, vector) in vectors.iter().enumerate() {
+        index.insert(&primary_key(i), vector.clone());
+    }
+
+    // compare with exact nearest neighbors
+    let k = 10;
+    let mut found = 0;
+    for query in create_vectors(1050).iter().skip(1000) {
+        let expected = brute_force_knn(&vectors, query, k);
+        let result = index.search(query, k).unwrap();
+        assert!(result.len() >= k);
+        assert!(result.windows(2).all(|w| w[0].1 <= w[1].1));
+        found += result
+            .iter()
+            .take(k)
+            .filter(|(pk, _)| expected.iter().any(|e| e == pk))
+            .count();
+    }
+    let recall = found as f64 / (50 * k) as f64;
+    assert!(recall > 0.9, "recall: {}", recall);
+
+    // wrong query dimension
+    assert!(index.search(&[0.0; 3], k).is_err());
+}
+
+#[test]
+pub fn test_upsert_and_delete() {
+    let mut index = HnswIndex::new(1, "embedding", AnnMetric::L2);
+    assert!(index.search(&[0.0; DIMENSION], 1).unwrap().is_empty());
+
+    let vectors = create_vectors(100);
+    for (i, vector) in vectors.iter().enumerate() {
+        index.insert(&primary_key(i), vector.clone());
+    }
+
+    // exact match is nearest
+    let result = index.search(&vectors[7], 1).unwrap();
+    assert_eq!(result[0].0, primary_key(7));
+    assert_eq!(result[0].1, 0.0);
+
+    // replaced vector, old one is not returned
+    index.insert(&primary_key(7), vectors[8].clone());
+    let result = index.search(&vectors[7], 200).unwrap();
+    assert_ne!(result[0].0, primary_key(7));
+    assert_eq!(
+        result
+            .iter()
+            .filter(|(pk, _)| *pk == primary_key(7))
+            .count(),
+        1
+    );
+
+    // deleted
+    index.delete(&primary_key(8));
+    let result = index.search(&vectors[8], 200).unwrap();
+    assert!(result.iter().all(|(pk, _)| *pk != primary_key(8)));
+    assert_eq!(result.len(), 99);
+}
+
+#[test]
+pub fn test_save_and_rebuild() {
+    let vectors = create_vectors(1500);
+    let mut index = HnswIndex::new(3, "embedding", AnnMetric::COSINE);
+    for (i, vector) in vectors.iter().enumerate() {
+        index.insert(&primary_key(i), vector.clone());
+    }
+    for i in 0..1200 {
+        index.delete(&primary_key(i));
+    }
+
+    // round trip
+    let saved = index.to_saved();
+    let loaded = HnswIndex::from_saved(&saved).unwrap();
+    assert_eq!(loaded.field_name(), "embedding");
+    assert_eq!(loaded.metric(), AnnMetric::COSINE);
+    assert_eq!(
+        loaded.search(&vectors[1300], 5).unwrap(),
+        index.search(&vectors[1300], 5).unwrap()
+    );
+
+    // most nodes are deleted
+    assert!(index.needs_rebuild());
+    index.rebuild();
+    assert!(!index.needs_rebuild());
+    assert_eq!(index.to_saved().nodes.len(), 300);
+    let result = index.search(&vectors[1300], 1).unwrap();
+    assert_eq!(result[0].0, primary_key(1300));
+
+    // corrupt
+    let mut saved = index.to_saved();
+    saved.nodes[0].layers[0].node_ids.push(5000);
+    assert!(HnswIndex::from_saved(&saved).is_err());
+}
+
+#[test]
+pub fn test_log() {
+    let mount_directory = "/tmp/ann_test_test_log";
+    let _ = std::fs::remove_dir_all(mount_directory);
+    std::fs::create_dir_all(mount_directory).unwrap();
+    let log_filename = format!("{}/ann_indexes_log", mount_directory);
+
+    let vectors = create_vectors(10);
+    let mut index = HnswIndex::new(3, "embedding", AnnMetric::L2);
+    assert_eq!(replay_log(mount_directory, |_| {}).unwrap(), None);
+
+    // changes of other fields are not applied
+    append_log(
+        mount_directory,
+        &[
+            upsert_log_entry(3, &primary_key(0), &vectors[0]),
+            upsert_log_entry(3, &primary_key(1), &vectors[1]),
+            upsert_log_entry(4, &primary_key(2), &vectors[2]),
+        ],
+        100,
+    )
+    .unwrap();
+    let log_size = append_log(
+        mount_directory,
+        &[delete_log_entry(3, &primary_key(0))],
+        200,
+    )
+    .unwrap();
+    assert_eq!(
+        replay_log(mount_directory, |entry| index.apply_log_entry(entry)).unwrap(),
+        Some(200)
+    );
+    let result = index.search(&vectors[0], 3).unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].0, primary_key(1));
+
+    // uncommitted and torn entries are dropped
+    let mut file = OpenOptions::new().append(true).open(&log_filename).unwrap();
+    std::io::Write::write_all(&mut file, &[1, 2, 3]).unwrap();
+    let mut index = HnswIndex::new(3, "embedding", AnnMetric::L2);
+    assert_eq!(
+        replay_log(mount_directory, |entry| index.apply_log_entry(entry)).unwrap(),
+        Some(200)
+    );
+    assert_eq!(std::fs::metadata(&log_filename).unwrap().len(), log_size);
+
+    remove_log(mount_directory).unwrap();
+    remove_log(mount_directory).unwrap();
+    assert_eq!(replay_log(mount_directory, |_| {}).unwrap(), None);
+
+    let _ = std::fs::remove_dir_all(mount_directory);
+}
@@ -2,22 +2,37 @@ use crate::{
     proto::generated_proto::{
         common::FieldValue,
         common::{FieldType, IKVStoreConfig},
-        index::CKVIndexHeader,
+        index::{
+            AnnMetric, CKVIndexHeader, CompressionCodec, FieldStats, IndexStats, RepairCatchUp,
+            ValueCacheStats,
+        },
+        streaming::EventHeader,
     },
-    schema::field::FieldId,
+    schema::{coerce, field::FieldId, list},
 };
 use anyhow::{anyhow, bail};
-use log::info;
+use log::{info, warn};
+use protobuf::Message;
 
 use super::{
-    ckv_segment::CKVIndexSegment, header::HeaderStore, offset_store::OffsetStore,
-    schema_store::CKVIndexSchema, stats::CompactionStats,
+    ckv_segment::{now_millis, CKVIndexSegment, PageCachePolicy, ValueOptions},
+    header::HeaderStore,
+    metadata_file::MetadataFile,
+    offset_store::OffsetStore,
+    schema_store::CKVIndexSchema,
+    stats::{CompactionStats, SegmentDocumentStats},
+    verification::{VerificationProblem, VerificationReport},
 };
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{HashMap, HashSet},
     fs::{self},
     path::Path,
-    sync::RwLock,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex, RwLock,
+    },
+    time::Instant,
 };
 
 #[cfg(test)]
@@ -28,7 +43,33 @@ mod ckv_test;
 #[path = "compaction_test.rs"]
 mod compaction_test;
 
-const NUM_SEGMENTS: usize = 16;
+// Field-id of the primary-key, see CKVIndexSchema.
+const PRIMARY_KEY_FIELD_ID: FieldId = 0;
+
+/// Field value as stored, along with its type.
+pub type TypedValue = (FieldType, Vec<u8>);
+
+// Segment count for new indexes when not configured, and for
+// indexes built before the count was saved in the index header.
+const DEFAULT_NUM_SEGMENTS: usize = 16;
+
+// Lock shards of each segment's value cache, when not configured.
+const DEFAULT_VALUE_CACHE_NUM_SHARDS: usize = 8;
+
+// Documents read before handing them to a for_each_document() visitor.
+const FOR_EACH_DOCUMENT_BATCH_SIZE: usize = 1024;
+
+// Documents copied per acquisition of a segment's read lock, during online compaction.
+const COMPACTION_BATCH_SIZE: usize = 1024;
+
+// Staged index directory of a resharding, switched in once complete.
+const RESHARDED_INDEX_DIRECTORY: &str = "resharded_index";
+
+// Index directory replaced by a resharding, deleted once the new one is switched in.
+const PRE_RESHARD_INDEX_DIRECTORY: &str = "pre_reshard_index";
+
+// Header of a resharded index, present once its staged segments are complete.
+const RESHARD_FILENAME: &str = "reshard";
 
 /// Memmap based row-oriented key-value index.
 #[derive(Debug)]
@@ -45,6 +86,28 @@ pub struct CKVIndex {
     schema: RwLock<CKVIndexSchema>,
 
     header_store: HeaderStore,
+
+    // skip writes older (by source timestamp) than the stored field value or delete
+    last_writer_wins: bool,
+
+    // field-name -> distance metric, for vector fields with ann indexes
+    ann_metrics: HashMap<String, AnnMetric>,
+
+    // handling of writes which don't match the declared type of their field
+    field_type_policy: FieldTypePolicy,
+
+    // writes with mismatched types (warn policy), and fields already warned about
+    num_mismatched_type_writes: AtomicU64,
+    mismatched_type_fields: Mutex<HashSet<String>>,
+
+    // segments restored by repair which are catching up, see applies_replayed_event()
+    repair_catch_up: Mutex<Option<RepairCatchUp>>,
+
+    // madvise/mlock settings of segment mmap files
+    page_cache_policy: PageCachePolicy,
+
+    // fields stored in their own column files, see CKVIndexSegment::set_column_field_ids()
+    column_group_fields: HashSet<String>,
 }
 
 impl CKVIndex {
@@ -53,6 +116,7 @@ impl CKVIndex {
 
         // create mount directory if it does not exist
         fs::create_dir_all(&mount_directory)?;
+        complete_reshard(&mount_directory)?;
 
         // open_or_create schema
         let primary_key = config
@@ -60,29 +124,101 @@ impl CKVIndex {
             .get("primary_key_field_name")
             .ok_or(anyhow!("primary_key is a required client-specified config"))?;
 
-        let schema = CKVIndexSchema::open_or_create(&mount_directory, primary_key.clone())?;
+        let mut schema = CKVIndexSchema::open_or_create(&mount_directory, primary_key.clone())?;
+        if let Some(field_name_to_codec) = configured_compression_codecs(config)? {
+            schema.set_compression_codecs(field_name_to_codec)?;
+        }
+        if let Some(field_name_to_ttl_millis) = configured_ttls(config)? {
+            schema.set_ttls(field_name_to_ttl_millis)?;
+        }
+
+        // index headers, holds the segment count
+        let index_present = Path::new(&format!("{}/index", &mount_directory)).exists();
+        let header_store = HeaderStore::open_or_create(&mount_directory)?;
+        let mut header = header_store.read_header()?;
+        if header.num_segments == 0 {
+            header.num_segments = if index_present {
+                // index built before segment count was persisted
+                DEFAULT_NUM_SEGMENTS as u32
+            } else {
+                configured_num_segments(config)?.unwrap_or(DEFAULT_NUM_SEGMENTS) as u32
+            };
+            header_store.write_header(&header)?;
+        }
+
+        let num_segments = header.num_segments as usize;
+        if let Some(configured) = configured_num_segments(config)? {
+            if configured != num_segments {
+                info!(
+                    "Configured num_index_segments: {} differs from index segment count: {}, index needs resharding to apply it",
+                    configured, num_segments
+                );
+            }
+        }
 
         // open_or_create index segments
-        let mut segments = Vec::with_capacity(NUM_SEGMENTS);
-        for index_id in 0..NUM_SEGMENTS {
-            let segment_mount_directory = format!("{}/index/segment_{}", mount_directory, index_id);
+        let mut segments = open_or_create_segments(&mount_directory, num_segments)?;
+
+        // ann indexes of known fields, others are enabled on first write
+        let ann_metrics = configured_ann_metrics(config)?.unwrap_or_default();
+        for (field_name, metric) in ann_metrics.iter() {
+            if let Some(field_id) = schema.fetch_id_by_name(field_name) {
+                for segment in segments.iter_mut() {
+                    segment
+                        .get_mut()
+                        .unwrap()
+                        .enable_ann_index(field_id, field_name, *metric)?;
+                }
+            }
+        }
+
+        let page_cache_policy = configured_page_cache_policy(config)?;
+        let column_group_fields = configured_column_group_fields(config);
+        let column_field_ids = column_field_ids(&schema, &column_group_fields);
+        for segment in segments.iter_mut() {
+            let segment = segment.get_mut().unwrap();
+            segment.set_page_cache_policy(page_cache_policy);
+            segment.set_column_field_ids(column_field_ids.clone());
+        }
 
-            // TODO: this needs to be parallelized, can take time for large indexes
-            let segment = CKVIndexSegment::open_or_create(&segment_mount_directory)?;
-            segments.push(RwLock::new(segment));
+        // value cache budget is split evenly across segments
+        if let Some((capacity_bytes, num_shards)) = configured_value_cache(config)? {
+            for segment in segments.iter_mut() {
+                segment
+                    .get_mut()
+                    .unwrap()
+                    .enable_value_cache(capacity_bytes / num_segments as u64, num_shards);
+            }
         }
 
         // open_or_create kafka store, done to initialize correctly
         let _ = OffsetStore::open_or_create(mount_directory.to_string())?;
 
-        // index headers
-        let header_store = HeaderStore::open_or_create(&mount_directory)?;
+        let last_writer_wins = config
+            .booleanConfigs
+            .get("last_writer_wins")
+            .copied()
+            .unwrap_or(false);
+        let field_type_policy = configured_field_type_policy(config)?;
+
+        let repair_catch_up = match MetadataFile::new(&mount_directory, "repair_catch_up").read()? {
+            None => None,
+            Some(bytes) => Some(RepairCatchUp::parse_from_bytes(&bytes)?),
+        };
 
         Ok(Self {
             mount_directory,
             segments,
             schema: RwLock::new(schema),
             header_store,
+            last_writer_wins,
+            ann_metrics,
+            field_type_policy,
+            num_mismatched_type_writes: AtomicU64::new(0),
+            mismatched_type_fields: Mutex::new(HashSet::new()),
+            repair_catch_up: Mutex::new(repair_catch_up),
+            page_cache_policy,
+            column_group_fields,
         })
     }
 
@@ -119,8 +255,12 @@ impl CKVIndex {
             bail!("IKVIndex root path does not exist at: {}", &index_path);
         }
 
+        // check if headers are valid
+        HeaderStore::is_valid_index(&mount_directory)?;
+        let header = HeaderStore::open_or_create(&mount_directory)?.read_header()?;
+
         // check if all segments are valid
-        for index_id in 0..NUM_SEGMENTS {
+        for index_id in 0..saved_num_segments(&header) {
             let segment_mount_directory = format!("{}/index/segment_{}", mount_directory, index_id);
             CKVIndexSegment::is_valid_segment(&segment_mount_directory)?;
         }
@@ -131,21 +271,304 @@ impl CKVIndex {
         // check if kafka offset store is valid
         OffsetStore::is_valid_index(&mount_directory)?;
 
-        // check if headers are valid
-        HeaderStore::is_valid_index(&mount_directory)?;
-
         // valid index!
         Ok(())
     }
 
+    /// Deep verification of the index on disk, without modifying it. Unlike
+    /// is_valid_index(), reads every segment and value referenced by the offset
+    /// tables, and cross-checks field ids with the schema. Errors only for bad
+    /// configs, problems with the index are listed in the report.
+    pub fn verify_index(config: &IKVStoreConfig) -> anyhow::Result<VerificationReport> {
+        Self::verify(config, true)
+    }
+
+    /// Structural verification of the index on disk, cheap enough to run on every open.
+    /// Same as verify_index() for index level files, but only checks that segment files
+    /// are present and that their metadata is consistent with file sizes, values are not read.
+    pub fn verify_index_structure(config: &IKVStoreConfig) -> anyhow::Result<VerificationReport> {
+        Self::verify(config, false)
+    }
+
+    fn verify(config: &IKVStoreConfig, verify_values: bool) -> anyhow::Result<VerificationReport> {
+        let mount_directory = crate::utils::paths::get_index_mount_directory_fqn(config)?;
+        let primary_key = config
+            .stringConfigs
+            .get("primary_key_field_name")
+            .ok_or(anyhow!("primary_key is a required client-specified config"))?;
+
+        let mut report = VerificationReport::default();
+        let bad_index_file = |name: &str, error: anyhow::Error| VerificationProblem::BadIndexFile {
+            name: name.to_string(),
+            error: error.to_string(),
+        };
+
+        // index level files must be present, also ensures they are not created below
+        let index_files_present = if Path::new(&format!("{}/index", &mount_directory)).exists() {
+            HeaderStore::is_valid_index(&mount_directory)
+                .and(CKVIndexSchema::is_valid_index(&mount_directory))
+                .and(OffsetStore::is_valid_index(&mount_directory))
+        } else {
+            Err(anyhow!("index root path does not exist"))
+        };
+        if let Err(e) = index_files_present {
+            report.problems.push(bad_index_file("files", e));
+            return Ok(report);
+        }
+
+        let header = match HeaderStore::open_or_create(&mount_directory)
+            .and_then(|header_store| header_store.read_header())
+        {
+            Ok(header) => header,
+            Err(e) => {
+                report.problems.push(bad_index_file("header", e));
+                return Ok(report);
+            }
+        };
+
+        let schema = match CKVIndexSchema::open_or_create(&mount_directory, primary_key.clone())
+            .and_then(|schema| schema.verify().map(|_| schema))
+        {
+            Ok(schema) => schema,
+            Err(e) => {
+                report.problems.push(bad_index_file("schema", e));
+                return Ok(report);
+            }
+        };
+
+        if let Err(e) = OffsetStore::open_or_create(mount_directory.clone())
+            .map_err(anyhow::Error::from)
+            .and_then(|offset_store| offset_store.read_all_offsets())
+        {
+            report.problems.push(bad_index_file("kafka offsets", e));
+        }
+
+        let num_segments = saved_num_segments(&header);
+        let field_ids = schema.field_ids();
+        for index_id in 0..num_segments {
+            let segment_mount_directory = format!("{}/index/segment_{}", mount_directory, index_id);
+            CKVIndexSegment::verify_segment(
+                &segment_mount_directory,
+                index_id,
+                num_segments,
+                schema.field_id_counter(),
+                &field_ids,
+                verify_values,
+                &mut report,
+            );
+        }
+
+        Ok(report)
+    }
+
+    /// Repairs segments which fail verification in place, see CKVIndexSegment::repair_segment().
+    /// Returns ids of segments which are still broken, to be restored from the base index
+    /// (see restore_segments()). Errors if index level files are broken.
+    pub fn repair_segments(config: &IKVStoreConfig) -> anyhow::Result<Vec<usize>> {
+        let mount_directory = crate::utils::paths::get_index_mount_directory_fqn(config)?;
+        let report = Self::verify_index(config)?;
+        if report.problems.iter().any(|p| p.segment().is_none()) {
+            bail!(
+                "Cannot repair index level files: {}",
+                report.problems_summary()
+            );
+        }
+
+        let header = HeaderStore::open_or_create(&mount_directory)?.read_header()?;
+        let num_segments = saved_num_segments(&header);
+        let index_ids = broken_segment_ids(&mount_directory, num_segments, &report);
+        if index_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        for index_id in index_ids {
+            let segment_mount_directory = format!("{}/index/segment_{}", mount_directory, index_id);
+            if let Err(e) = CKVIndexSegment::repair_segment(&segment_mount_directory) {
+                warn!(
+                    "Cannot repair segment: {}, error: {}",
+                    &segment_mount_directory, e
+                );
+            }
+        }
+
+        let report = Self::verify_index(config)?;
+        Ok(broken_segment_ids(&mount_directory, num_segments, &report))
+    }
+
+    /// Replaces segments with the ones of a base index (unpacked at `base_index_directory`),
+    /// and starts catching them up: stream offsets are rewound to the ones of the base index,
+    /// and replayed events up to the current offsets only apply to the replaced segments
+    /// (see applies_replayed_event()). Errors if the base index is not compatible.
+    pub fn restore_segments(
+        config: &IKVStoreConfig,
+        base_index_directory: &str,
+        index_ids: &[usize],
+    ) -> anyhow::Result<()> {
+        let mount_directory = crate::utils::paths::get_index_mount_directory_fqn(config)?;
+        let primary_key = config
+            .stringConfigs
+            .get("primary_key_field_name")
+            .ok_or(anyhow!("primary_key is a required client-specified config"))?;
+
+        // same segment count, and field ids
+        HeaderStore::is_valid_index(base_index_directory)?;
+        CKVIndexSchema::is_valid_index(base_index_directory)?;
+        let num_segments =
+            saved_num_segments(&HeaderStore::open_or_create(&mount_directory)?.read_header()?);
+        let base_num_segments =
+            saved_num_segments(&HeaderStore::open_or_create(base_index_directory)?.read_header()?);
+        if num_segments != base_num_segments {
+            bail!(
+                "Base index has {} segments, index has {}",
+                base_num_segments,
+                num_segments
+            );
+        }
+        let schema = CKVIndexSchema::open_or_create(&mount_directory, primary_key.clone())?;
+        let base_schema =
+            CKVIndexSchema::open_or_create(base_index_directory, primary_key.clone())?;
+        if !schema.has_compatible_field_ids(&base_schema) {
+            bail!("Base index schema has incompatible field ids");
+        }
+
+        // base segments must be valid
+        let mut report = VerificationReport::default();
+        for &index_id in index_ids {
+            CKVIndexSegment::verify_segment(
+                &format!("{}/index/segment_{}", base_index_directory, index_id),
+                index_id,
+                num_segments,
+                base_schema.field_id_counter(),
+                &base_schema.field_ids(),
+                true,
+                &mut report,
+            );
+        }
+        if !report.is_valid() {
+            bail!(
+                "Base index segments are broken: {}",
+                report.problems_summary()
+            );
+        }
+
+        // catch up with events since the base index, written before rewinding offsets
+        // since an interrupted restore is retried with rewound offsets
+        let offset_store = OffsetStore::open_or_create(mount_directory.clone())?;
+        let offsets = offset_store.read_all_offsets()?;
+        let base_offsets =
+            OffsetStore::open_or_create(base_index_directory.to_string())?.read_all_offsets()?;
+        let catch_up_file = MetadataFile::new(&mount_directory, "repair_catch_up");
+        let mut catch_up = match catch_up_file.read()? {
+            None => RepairCatchUp::new(),
+            Some(bytes) => RepairCatchUp::parse_from_bytes(&bytes)?,
+        };
+        for &index_id in index_ids {
+            if !catch_up.segment_ids.contains(&(index_id as u32)) {
+                catch_up.segment_ids.push(index_id as u32);
+            }
+        }
+        for entry in offsets.iter() {
+            match catch_up
+                .until_offsets
+                .iter_mut()
+                .find(|e| e.topic == entry.topic && e.partition == entry.partition)
+            {
+                Some(until) => until.offset = until.offset.max(entry.offset),
+                None => catch_up.until_offsets.push(entry.clone()),
+            }
+        }
+        catch_up_file.write(&catch_up.write_to_bytes()?)?;
+
+        // rewind offsets, replay from the start if the base index has none
+        let rewound_offsets = offsets
+            .into_iter()
+            .filter_map(|mut entry| {
+                let base_entry = base_offsets
+                    .iter()
+                    .find(|e| e.topic == entry.topic && e.partition == entry.partition)?;
+                entry.offset = entry.offset.min(base_entry.offset);
+                Some(entry)
+            })
+            .collect();
+        offset_store.write_entries(rewound_offsets)?;
+
+        // swap in base segments
+        for &index_id in index_ids {
+            let segment_mount_directory = format!("{}/index/segment_{}", mount_directory, index_id);
+            if Path::new(&segment_mount_directory).exists() {
+                fs::remove_dir_all(&segment_mount_directory)?;
+            }
+            fs::rename(
+                format!("{}/index/segment_{}", base_index_directory, index_id),
+                &segment_mount_directory,
+            )?;
+            info!(
+                "Restored segment: {} from base index",
+                &segment_mount_directory
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Whether an event (at a stream offset) applies to a document. While segments restored by
+    /// repair catch up, replayed events only apply to their documents, since other segments have
+    /// already applied them. `document` is None for schema level events (ex. dropped fields).
+    pub fn applies_replayed_event(
+        &self,
+        topic: &str,
+        partition: i32,
+        offset: i64,
+        document: Option<&HashMap<String, FieldValue>>,
+    ) -> anyhow::Result<bool> {
+        let mut maybe_catch_up = self.repair_catch_up.lock().unwrap();
+        let catch_up = match maybe_catch_up.as_ref() {
+            None => return Ok(true),
+            Some(catch_up) => catch_up,
+        };
+
+        let caught_up = catch_up
+            .until_offsets
+            .iter()
+            .find(|e| e.topic == topic && e.partition == partition)
+            .is_none_or(|until| offset >= until.offset);
+        if caught_up {
+            info!(
+                "Restored segments: {:?} caught up at offset: {}",
+                &catch_up.segment_ids, offset
+            );
+            MetadataFile::new(&self.mount_directory, "repair_catch_up").delete()?;
+            *maybe_catch_up = None;
+            return Ok(true);
+        }
+
+        let document = match document {
+            None => return Ok(false),
+            Some(document) => document,
+        };
+        Ok(match self.extract_primary_key(document)? {
+            None => false,
+            Some(primary_key) => {
+                let index_id = segment_id(&primary_key, self.segments.len()) as u32;
+                catch_up.segment_ids.contains(&index_id)
+            }
+        })
+    }
+
     /// Clears out all index structures from disk.
     pub fn delete_all(config: &IKVStoreConfig) -> anyhow::Result<()> {
         let mount_directory = crate::utils::paths::get_index_mount_directory_fqn(config)?;
 
-        let index_path = format!("{}/index", &mount_directory);
-        if Path::new(&index_path).exists() {
-            std::fs::remove_dir_all(&index_path)?;
+        for index_path in [
+            format!("{}/index", &mount_directory),
+            format!("{}/{}", &mount_directory, RESHARDED_INDEX_DIRECTORY),
+            format!("{}/{}", &mount_directory, PRE_RESHARD_INDEX_DIRECTORY),
+        ] {
+            if Path::new(&index_path).exists() {
+                std::fs::remove_dir_all(&index_path)?;
+            }
         }
+        MetadataFile::new(&mount_directory, RESHARD_FILENAME).delete()?;
 
         CKVIndexSchema::delete_all(&mount_directory)?;
         OffsetStore::delete_all(&mount_directory)?;
@@ -153,9 +576,17 @@ impl CKVIndex {
         Ok(())
     }
 
+    /// Number of segments the index is split into.
+    pub fn num_segments(&self) -> usize {
+        self.segments.len()
+    }
+
     pub fn compact_and_close(mut self) -> anyhow::Result<(CompactionStats, CompactionStats)> {
+        let num_segments = self.segments.len();
+
         // schema compaction, get field id mapping
         let new_fid_to_old_fid = self.schema.write().unwrap().compact()?;
+        let column_field_ids = self.column_field_ids();
 
         // loop over existing segments, copy-to-compact, and close both
         let mut pre_compaction_stats: Vec<CompactionStats> = vec![];
@@ -176,6 +607,7 @@ impl CKVIndex {
                 &self.mount_directory, segment_id
             );
             let mut compacted_segment = CKVIndexSegment::open_or_create(&segment_mount_directory)?;
+            compacted_segment.set_column_field_ids(column_field_ids.clone());
 
             segment.copy_to_compact(&mut compacted_segment, &new_fid_to_old_fid)?;
 
@@ -189,7 +621,7 @@ impl CKVIndex {
         }
 
         // swap directories
-        for i in 0..NUM_SEGMENTS {
+        for i in 0..num_segments {
             // clear mount-dir/store/partition/index/segment-N
             let segment_mount_directory = format!("{}/index/segment_{}", &self.mount_directory, i);
             std::fs::remove_dir_all(&segment_mount_directory)?;
@@ -208,6 +640,269 @@ impl CKVIndex {
         Ok((pre_stats, post_stats))
     }
 
+    /// Compaction stats of a single segment, can be used to decide
+    /// whether it needs online compaction.
+    pub fn segment_compaction_stats(&self, index_id: usize) -> anyhow::Result<CompactionStats> {
+        self.segments[index_id].read().unwrap().compaction_stats()
+    }
+
+    /// Live documents per segment, present values and their bytes per field, and
+    /// garbage bytes in the mmap files. Segments are read one at a time.
+    pub fn index_stats(&self) -> anyhow::Result<IndexStats> {
+        let (field_names, field_ids) = {
+            let schema = self.schema.read().unwrap();
+            (schema.field_names(), schema.field_ids())
+        };
+
+        let mut segment_stats: Vec<SegmentDocumentStats> = Vec::with_capacity(self.segments.len());
+        for segment in self.segments.iter() {
+            segment_stats.push(segment.read().unwrap().document_stats(&field_ids)?);
+        }
+
+        let mut field_ids: Vec<FieldId> = field_ids.into_iter().collect();
+        field_ids.sort();
+
+        let mut index_stats = IndexStats::new();
+        for (field_name, field_id) in field_names.into_iter().zip(field_ids) {
+            let mut field_stats = FieldStats::new();
+            field_stats.field_name = field_name;
+            for stats in segment_stats.iter() {
+                field_stats.num_values += stats.field_num_values[field_id as usize];
+                field_stats.value_bytes += stats.field_value_bytes[field_id as usize];
+            }
+            if field_stats.num_values > 0 {
+                field_stats.avg_value_bytes =
+                    field_stats.value_bytes as f64 / field_stats.num_values as f64;
+            }
+            index_stats.field_stats.push(field_stats);
+        }
+        for stats in segment_stats.iter() {
+            index_stats.segment_num_documents.push(stats.num_documents);
+            index_stats.mmap_used_bytes += stats.mmap_used_bytes;
+            index_stats.mmap_dead_bytes +=
+                stats.mmap_used_bytes.saturating_sub(stats.mmap_live_bytes);
+        }
+        index_stats.num_mismatched_type_writes =
+            self.num_mismatched_type_writes.load(Ordering::Relaxed);
+
+        Ok(index_stats)
+    }
+
+    /// Hit rate and size of the decoded-value cache, summed over segments.
+    /// All zeros if the cache is not enabled.
+    pub fn value_cache_stats(&self) -> ValueCacheStats {
+        let mut cache_stats = ValueCacheStats::new();
+        for segment in self.segments.iter() {
+            if let Some(stats) = segment.read().unwrap().value_cache_stats() {
+                cache_stats.hits += stats.hits;
+                cache_stats.misses += stats.misses;
+                cache_stats.evictions += stats.evictions;
+                cache_stats.num_entries += stats.num_entries;
+                cache_stats.size_bytes += stats.size_bytes;
+            }
+        }
+        if cache_stats.hits + cache_stats.misses > 0 {
+            cache_stats.hit_rate =
+                cache_stats.hits as f64 / (cache_stats.hits + cache_stats.misses) as f64;
+        }
+        cache_stats
+    }
+
+    /// Faults values of `field_names` into the page cache, so that first reads
+    /// of hot fields don't block on disk. Unknown fields are ignored.
+    /// Returns the number of value bytes touched.
+    pub fn warmup(&self, field_names: &[String]) -> anyhow::Result<u64> {
+        let start = Instant::now();
+        let field_ids: HashSet<FieldId> = {
+            let schema = self.schema.read().unwrap();
+            field_names
+                .iter()
+                .filter_map(|field_name| schema.fetch_id_by_name(field_name))
+                .collect()
+        };
+        if field_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let mut touched_bytes = 0;
+        for segment in self.segments.iter() {
+            touched_bytes += segment.read().unwrap().warmup(&field_ids);
+        }
+        info!(
+            "Warmed up fields: {:?} ({} bytes) in {} ms",
+            field_names,
+            touched_bytes,
+            start.elapsed().as_millis()
+        );
+        Ok(touched_bytes)
+    }
+
+    /// Online compaction of a single segment, while the index is serving reads and writes.
+    /// Live documents are copied to `compacted_segment_N` in batches under the segment's
+    /// read lock, then documents written to during the copy are copied again and the
+    /// compacted segment is swapped in under its write lock.
+    pub fn compact_segment(&self, index_id: usize) -> anyhow::Result<()> {
+        let segment_mount_directory =
+            format!("{}/index/segment_{}", &self.mount_directory, index_id);
+        let compacted_segment_mount_directory = format!(
+            "{}/index/compacted_segment_{}",
+            &self.mount_directory, index_id
+        );
+        let stale_segment_mount_directory =
+            format!("{}/index/stale_segment_{}", &self.mount_directory, index_id);
+
+        // clear leftovers from any previously failed attempt
+        if Path::new(&compacted_segment_mount_directory).exists() {
+            std::fs::remove_dir_all(&compacted_segment_mount_directory)?;
+        }
+        let mut compacted_segment =
+            CKVIndexSegment::open_or_create(&compacted_segment_mount_directory)?;
+        compacted_segment.set_column_field_ids(self.column_field_ids());
+        compacted_segment.set_page_cache_policy(self.page_cache_policy);
+
+        // documents written to from here on are copied again before the swap
+        let primary_keys = {
+            let mut segment = self.segments[index_id].write().unwrap();
+            segment.track_written_keys();
+            segment.primary_keys()
+        };
+
+        // field ids are not changed, dropped fields are not copied. Fields created
+        // after this point are only found in documents which are copied again.
+        let new_fid_to_old_fid = self.schema.read().unwrap().uncompacted_fid_mapping();
+        let copied = primary_keys
+            .chunks(COMPACTION_BATCH_SIZE)
+            .try_for_each(|batch| {
+                self.segments[index_id]
+                    .read()
+                    .unwrap()
+                    .copy_documents_by_key(
+                        batch,
+                        std::slice::from_mut(&mut compacted_segment),
+                        &new_fid_to_old_fid,
+                    )
+            });
+        if let Err(e) = copied {
+            self.segments[index_id].write().unwrap().take_written_keys();
+            compacted_segment.close()?;
+            std::fs::remove_dir_all(&compacted_segment_mount_directory)?;
+            return Err(e);
+        }
+
+        let mut segment = self.segments[index_id].write().unwrap();
+        let written_keys = segment.take_written_keys();
+        let new_fid_to_old_fid = self.schema.read().unwrap().uncompacted_fid_mapping();
+        compacted_segment.set_column_field_ids(self.column_field_ids());
+        segment.copy_documents_by_key(
+            written_keys.iter(),
+            std::slice::from_mut(&mut compacted_segment),
+            &new_fid_to_old_fid,
+        )?;
+
+        // swap directories, see open_or_create_segment() for crash recovery
+        segment.rename(&stale_segment_mount_directory)?;
+        compacted_segment.rename(&segment_mount_directory)?;
+        let mut stale_segment = std::mem::replace(&mut *segment, compacted_segment);
+        segment.take_ann_indexes(&mut stale_segment);
+        segment.take_value_cache(&mut stale_segment);
+        let post_stats = segment.compaction_stats()?;
+        drop(segment);
+
+        stale_segment.close()?;
+        std::fs::remove_dir_all(&stale_segment_mount_directory)?;
+
+        info!(
+            "Online compaction of index segment: {} done, copied {} documents written to during compaction again, post-compaction stats: {:?}",
+            index_id,
+            written_keys.len(),
+            &post_stats
+        );
+        Ok(())
+    }
+
+    /// Completes a resharding interrupted by a crash (see reshard_and_close()), if any.
+    pub fn complete_reshard(config: &IKVStoreConfig) -> anyhow::Result<()> {
+        let mount_directory = crate::utils::paths::get_index_mount_directory_fqn(config)?;
+        complete_reshard(&mount_directory)
+    }
+
+    /// Rewrites the index into `num_segments` segments (compacting them as well),
+    /// and saves the new segment count in the index header.
+    ///
+    /// Resharded segments are staged in a sibling of the index directory, which is
+    /// switched in only once all of them are written. Old segments are deleted last,
+    /// a crash at any point leaves either the old or the new index.
+    pub fn reshard_and_close(
+        mut self,
+        num_segments: usize,
+    ) -> anyhow::Result<(CompactionStats, CompactionStats)> {
+        if num_segments == 0 {
+            bail!("Cannot reshard index to zero segments");
+        }
+        let prev_num_segments = self.segments.len();
+
+        // schema compaction, get field id mapping
+        let new_fid_to_old_fid = self.schema.write().unwrap().compact()?;
+        let column_field_ids = self.column_field_ids();
+
+        // resharded segments, clear leftovers from any previously failed attempt
+        let resharded_index_directory =
+            format!("{}/{}", &self.mount_directory, RESHARDED_INDEX_DIRECTORY);
+        if Path::new(&resharded_index_directory).exists() {
+            std::fs::remove_dir_all(&resharded_index_directory)?;
+        }
+        let mut resharded_segments = Vec::with_capacity(num_segments);
+        for segment_id in 0..num_segments {
+            let segment_mount_directory =
+                format!("{}/segment_{}", &resharded_index_directory, segment_id);
+            let mut resharded_segment = CKVIndexSegment::open_or_create(&segment_mount_directory)?;
+            resharded_segment.set_column_field_ids(column_field_ids.clone());
+            resharded_segments.push(resharded_segment);
+        }
+
+        // loop over existing segments, copy-to-reshard, and close
+        let mut pre_compaction_stats: Vec<CompactionStats> = vec![];
+        for (segment_id, segment) in self.segments.drain(..).enumerate() {
+            info!(
+                "Starting resharding of index segment: {} into {} segments",
+                segment_id, num_segments
+            );
+
+            let mut segment = segment.into_inner().unwrap();
+            segment.copy_to_reshard(&mut resharded_segments, &new_fid_to_old_fid)?;
+            pre_compaction_stats.push(segment.compaction_stats()?);
+            segment.close()?;
+        }
+
+        let mut post_compaction_stats: Vec<CompactionStats> = vec![];
+        for segment in resharded_segments.drain(..) {
+            post_compaction_stats.push(segment.compaction_stats()?);
+            segment.close()?;
+        }
+
+        // commit: the new header is saved along with the staged segments,
+        // from here on an interrupted resharding is completed on open
+        let mut header = self.header_store.read_header()?;
+        header.num_segments = num_segments as u32;
+        MetadataFile::new(&self.mount_directory, RESHARD_FILENAME)
+            .write(&header.write_to_bytes()?)?;
+
+        // swap directories and persist new segment count
+        complete_reshard(&self.mount_directory)?;
+
+        // log compaction statistics
+        let pre_stats = CompactionStats::aggregate(&pre_compaction_stats);
+        let post_stats = CompactionStats::aggregate(&post_compaction_stats);
+        info!(
+            "Resharded index from {} to {} segments",
+            prev_num_segments, num_segments
+        );
+        info!("Pre-resharding stats: {:?}", &pre_stats);
+        info!("Post-resharding stats: {:?}", &post_stats);
+
+        Ok((pre_stats, post_stats))
+    }
+
     /// Fetch field value for a primary key.
     pub fn get_field_value(&self, primary_key: &[u8], field_name: &str) -> Option<Vec<u8>> {
         let field_id: FieldId;
@@ -216,7 +911,7 @@ impl CKVIndex {
             field_id = schema.fetch_id_by_name(field_name)?;
         }
 
-        let index_id: usize = fxhash::hash(primary_key) % NUM_SEGMENTS;
+        let index_id = segment_id(primary_key, self.segments.len());
         let ckv_segment = self.segments[index_id].read().unwrap();
 
         ckv_segment.read_field(primary_key, field_id)
@@ -230,6 +925,26 @@ impl CKVIndex {
         &self,
         primary_keys: Vec<&[u8]>,
         field_names: Vec<&str>,
+    ) -> Vec<u8> {
+        self.batch_read_fields(primary_keys, field_names, None)
+    }
+
+    /// Same as batch_get_field_values(), with list values trimmed to
+    /// their last (newest) `last_k` elements. Other values are read whole.
+    pub fn batch_get_last_list_elements(
+        &self,
+        primary_keys: Vec<&[u8]>,
+        field_names: Vec<&str>,
+        last_k: usize,
+    ) -> Vec<u8> {
+        self.batch_read_fields(primary_keys, field_names, Some(last_k))
+    }
+
+    fn batch_read_fields(
+        &self,
+        primary_keys: Vec<&[u8]>,
+        field_names: Vec<&str>,
+        last_k: Option<usize>,
     ) -> Vec<u8> {
         let capacity = primary_keys.len() * field_names.len() * 16;
         if capacity == 0 {
@@ -250,24 +965,143 @@ impl CKVIndex {
         }
 
         // holds read acquired locks, released when we exit function scope
-        let mut acquired_ckv_segments = Vec::with_capacity(NUM_SEGMENTS);
-        for _ in 0..NUM_SEGMENTS {
+        let mut acquired_ckv_segments = Vec::with_capacity(self.segments.len());
+        for _ in 0..self.segments.len() {
             acquired_ckv_segments.push(None);
         }
 
         for primary_key in primary_keys.iter() {
-            let index_id: usize = fxhash::hash(primary_key) % NUM_SEGMENTS;
+            let index_id = segment_id(primary_key, self.segments.len());
             if acquired_ckv_segments[index_id].is_none() {
                 acquired_ckv_segments[index_id] = Some(self.segments[index_id].read().unwrap());
             }
 
             let ckv_segment = acquired_ckv_segments[index_id].as_ref().unwrap();
-            ckv_segment.read_fields(primary_key, &field_ids, &mut result);
+            ckv_segment.read_fields(primary_key, &field_ids, last_k, &mut result);
         }
 
         result
     }
 
+    /// Declared type of a field, i.e. type of its first written value.
+    /// None for unknown fields.
+    pub fn get_field_type(&self, field_name: &str) -> Option<FieldType> {
+        self.schema.read().unwrap().fetch_type_by_name(field_name)
+    }
+
+    pub fn primary_key_field_name(&self) -> String {
+        self.schema
+            .read()
+            .unwrap()
+            .primary_key_field_name()
+            .to_string()
+    }
+
+    /// Names of all fields, ordered by field id (primary key first).
+    pub fn field_names(&self) -> Vec<String> {
+        self.schema.read().unwrap().field_names()
+    }
+
+    /// Visits documents with their values (and types) of `field_names`, unknown fields have
+    /// no values. Visits all documents, or the existing ones of `primary_keys` if provided.
+    /// Documents are read in batches, i.e. writes during the visit may or may not be seen.
+    pub fn for_each_document<F>(
+        &self,
+        field_names: &[String],
+        primary_keys: Option<&[&[u8]]>,
+        mut visit: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(&[u8], Vec<Option<TypedValue>>) -> anyhow::Result<()>,
+    {
+        let field_ids: Vec<Option<FieldId>> = {
+            let schema = self.schema.read().unwrap();
+            field_names
+                .iter()
+                .map(|field_name| schema.fetch_id_by_name(field_name))
+                .collect()
+        };
+
+        // Documents are read in batches under the segment lock, and visited after
+        // releasing it, so that slow visitors (ex. file writers) do not block writes.
+        match primary_keys {
+            None => {
+                for segment in self.segments.iter() {
+                    let segment_primary_keys = segment.read().unwrap().primary_keys();
+                    for batch in segment_primary_keys.chunks(FOR_EACH_DOCUMENT_BATCH_SIZE) {
+                        let batch: Vec<&[u8]> = batch.iter().map(|pk| pk.as_slice()).collect();
+                        self.visit_documents(&batch, &field_ids, &mut visit)?;
+                    }
+                }
+            }
+            Some(primary_keys) => {
+                for batch in primary_keys.chunks(FOR_EACH_DOCUMENT_BATCH_SIZE) {
+                    self.visit_documents(batch, &field_ids, &mut visit)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit_documents<F>(
+        &self,
+        primary_keys: &[&[u8]],
+        field_ids: &[Option<FieldId>],
+        visit: &mut F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(&[u8], Vec<Option<TypedValue>>) -> anyhow::Result<()>,
+    {
+        let mut documents = Vec::with_capacity(primary_keys.len());
+        for primary_key in primary_keys.iter().copied() {
+            let index_id = segment_id(primary_key, self.segments.len());
+            let maybe_values = self.segments[index_id]
+                .read()
+                .unwrap()
+                .read_document(primary_key, field_ids);
+            if let Some(values) = maybe_values {
+                documents.push((primary_key, values));
+            }
+        }
+
+        for (primary_key, values) in documents {
+            visit(primary_key, values)?;
+        }
+        Ok(())
+    }
+
+    /// Approximate `k` nearest neighbors of `query` among vectors of a field with an
+    /// ann index (see "field_ann_indexes" config), as (primary-key, distance) closest first.
+    pub fn knn(
+        &self,
+        field_name: &str,
+        query: &[f32],
+        k: usize,
+    ) -> anyhow::Result<Vec<(Vec<u8>, f32)>> {
+        if !self.ann_metrics.contains_key(field_name) {
+            bail!("Field: {} does not have an ann index", field_name);
+        }
+        if k == 0 {
+            return Ok(vec![]);
+        }
+
+        let field_id = match self.schema.read().unwrap().fetch_id_by_name(field_name) {
+            None => return Ok(vec![]),
+            Some(field_id) => field_id,
+        };
+
+        let mut result = vec![];
+        for segment in self.segments.iter() {
+            let segment = segment.read().unwrap();
+            result.extend(segment.ann_search(field_id, query, k)?);
+        }
+
+        result.sort_by(|a, b| a.1.total_cmp(&b.1));
+        result.truncate(k);
+        Ok(result)
+    }
+
     /// Write APIs
     /// 1. upsert multiple fields for a document
     /// 2. delete multiple fields for a document
@@ -299,12 +1133,22 @@ impl CKVIndex {
     pub fn upsert_field_values(
         &self,
         document: &HashMap<String, FieldValue>,
+    ) -> anyhow::Result<()> {
+        self.upsert_field_values_with_options(document, &WriteOptions::default())
+    }
+
+    pub fn upsert_field_values_with_options(
+        &self,
+        document: &HashMap<String, FieldValue>,
+        write_options: &WriteOptions,
     ) -> anyhow::Result<()> {
         if document.is_empty() {
             return Ok(());
         }
 
-        // upsert schema
+        // check field types, upsert schema
+        let document = self.enforce_field_types(document)?;
+        let document = document.as_ref();
         self.upsert_schema(document)?;
 
         // extract primary key
@@ -316,41 +1160,344 @@ impl CKVIndex {
         }
 
         // flatten to vectors
-        let mut field_ids = Vec::with_capacity(document.len());
-        let mut values = Vec::with_capacity(document.len());
-        {
-            let schema = self.schema.read().unwrap();
-            for (field_name, field_value) in document.iter() {
-                // Filter out unknown field types
-                if field_value.fieldType.enum_value_or_default() == FieldType::UNKNOWN {
-                    continue;
+        let (mut field_ids, mut values, mut value_options) =
+            self.flatten_document(document, write_options);
+        if field_ids.is_empty() {
+            // can occur when only unknown field types were inserted
+            return Ok(());
+        }
+        let ann_fields = self.ann_fields(document);
+
+        let index_id = segment_id(&primary_key, self.segments.len());
+        let mut ckv_index_segment = self.segments[index_id].write().unwrap();
+        for (field_id, field_name, metric) in ann_fields {
+            ckv_index_segment.enable_ann_index(field_id, field_name, metric)?;
+        }
+
+        // last-writer-wins: skip fields with newer writes
+        self.skip_older_writes(
+            &ckv_index_segment,
+            &primary_key,
+            write_options,
+            &mut field_ids,
+            &mut values,
+            &mut value_options,
+        );
+
+        ckv_index_segment.upsert_document(&primary_key, &field_ids, &values, &value_options)?;
+        Ok(())
+    }
+
+    /// Adds deltas to numeric fields of a document, fields without a value are
+    /// initialized to their delta. See IncrementDocumentFieldsEvent.
+    pub fn increment_field_values_with_options(
+        &self,
+        document: &HashMap<String, FieldValue>,
+        write_options: &WriteOptions,
+    ) -> anyhow::Result<()> {
+        if document.is_empty() {
+            return Ok(());
+        }
+
+        // check field types, upsert schema
+        let document = self.enforce_field_types(document)?;
+        let document = document.as_ref();
+        self.upsert_schema(document)?;
+
+        // extract primary key
+        let primary_key = self
+            .extract_primary_key(document)?
+            .ok_or(anyhow!("Cannot increment with missing primary-key"))?;
+        if primary_key.len() > u16::MAX as usize {
+            bail!("primary_key larger than 64KB is unsupported");
+        }
+
+        // flatten to vectors
+        let (field_ids, deltas, delta_options) = self.flatten_document(document, write_options);
+
+        for i in 0..field_ids.len() {
+            if field_ids[i] == PRIMARY_KEY_FIELD_ID {
+                continue;
+            }
+            match deltas[i].fieldType.enum_value_or_default() {
+                FieldType::INT32 | FieldType::INT64 | FieldType::FLOAT32 | FieldType::FLOAT64 => {}
+                field_type => bail!("Cannot increment field of type: {:?}", field_type),
+            }
+        }
+
+        let index_id = segment_id(&primary_key, self.segments.len());
+        let mut ckv_index_segment = self.segments[index_id].write().unwrap();
+        if let Some((partition, offset)) = write_options.event_offset {
+            if ckv_index_segment.is_event_applied(partition, offset) {
+                // replayed event
+                return Ok(());
+            }
+        }
+
+        // compute all new values before writing any, so that a failing field
+        // does not leave the document partially incremented
+        let mut incremented_field_ids = Vec::with_capacity(field_ids.len());
+        let mut incremented_values = Vec::with_capacity(field_ids.len());
+        let mut incremented_value_options = Vec::with_capacity(field_ids.len());
+        let mut init_field_ids = Vec::with_capacity(field_ids.len());
+        let mut init_values = Vec::with_capacity(field_ids.len());
+        let mut init_value_options = Vec::with_capacity(field_ids.len());
+        for i in 0..field_ids.len() {
+            if field_ids[i] == PRIMARY_KEY_FIELD_ID {
+                continue;
+            }
+
+            match ckv_index_segment.incremented_value(&primary_key, field_ids[i], deltas[i])? {
+                Some((incremented_value, options)) => {
+                    incremented_field_ids.push(field_ids[i]);
+                    incremented_values.push(incremented_value);
+                    incremented_value_options.push(options);
                 }
+                None => {
+                    init_field_ids.push(field_ids[i]);
+                    init_values.push(deltas[i]);
+                    init_value_options.push(delta_options[i]);
+                }
+            }
+        }
 
-                field_ids.push(
-                    schema
-                        .fetch_id_by_name(field_name)
-                        .expect("upsert_schema ensures schema is known"),
-                );
-                values.push(field_value);
+        // last-writer-wins: skip initializing fields with newer deletes
+        self.skip_older_writes(
+            &ckv_index_segment,
+            &primary_key,
+            write_options,
+            &mut init_field_ids,
+            &mut init_values,
+            &mut init_value_options,
+        );
+
+        // write primary-key along with initialized fields, for new documents
+        if !init_field_ids.is_empty()
+            && ckv_index_segment
+                .read_field(&primary_key, PRIMARY_KEY_FIELD_ID)
+                .is_none()
+        {
+            if let Some(i) = field_ids.iter().position(|id| *id == PRIMARY_KEY_FIELD_ID) {
+                init_field_ids.push(field_ids[i]);
+                init_values.push(deltas[i]);
+                init_value_options.push(delta_options[i]);
             }
         }
 
-        if field_ids.is_empty() {
-            // can occur when only unknown field types were inserted
+        // single write of initialized and incremented fields
+        init_field_ids.extend(incremented_field_ids);
+        init_values.extend(incremented_values.iter());
+        init_value_options.extend(incremented_value_options);
+        if !init_field_ids.is_empty() {
+            ckv_index_segment.upsert_document(
+                &primary_key,
+                &init_field_ids,
+                &init_values,
+                &init_value_options,
+            )?;
+        }
+
+        if let Some((partition, offset)) = write_options.event_offset {
+            ckv_index_segment.mark_event_applied(partition, offset);
+        }
+        Ok(())
+    }
+
+    /// Appends elements to list fields of a document, trimming lists to their last
+    /// `max_length` elements (unbounded if 0). See AppendDocumentFieldsEvent.
+    pub fn append_field_values_with_options(
+        &self,
+        document: &HashMap<String, FieldValue>,
+        max_length: usize,
+        write_options: &WriteOptions,
+    ) -> anyhow::Result<()> {
+        if document.is_empty() {
             return Ok(());
         }
 
-        let index_id = fxhash::hash(&primary_key) % NUM_SEGMENTS;
+        // check field types, upsert schema
+        let document = self.enforce_field_types(document)?;
+        let document = document.as_ref();
+        self.upsert_schema(document)?;
+
+        // extract primary key
+        let primary_key = self
+            .extract_primary_key(document)?
+            .ok_or(anyhow!("Cannot append with missing primary-key"))?;
+        if primary_key.len() > u16::MAX as usize {
+            bail!("primary_key larger than 64KB is unsupported");
+        }
+
+        // flatten to vectors
+        let (mut field_ids, mut values, mut value_options) =
+            self.flatten_document(document, write_options);
+
+        for i in 0..field_ids.len() {
+            if field_ids[i] == PRIMARY_KEY_FIELD_ID {
+                continue;
+            }
+            let field_type = values[i].fieldType.enum_value_or_default();
+            if !list::is_list_type(field_type) {
+                bail!("Cannot append to field of type: {:?}", field_type);
+            }
+        }
+
+        let index_id = segment_id(&primary_key, self.segments.len());
         let mut ckv_index_segment = self.segments[index_id].write().unwrap();
-        ckv_index_segment.upsert_document(&primary_key, &field_ids, &values)?;
+        if let Some((partition, offset)) = write_options.event_offset {
+            if ckv_index_segment.is_event_applied(partition, offset) {
+                // replayed event
+                return Ok(());
+            }
+        }
+
+        // last-writer-wins: skip fields with newer writes
+        self.skip_older_writes(
+            &ckv_index_segment,
+            &primary_key,
+            write_options,
+            &mut field_ids,
+            &mut values,
+            &mut value_options,
+        );
+
+        // compute all new lists before writing any, so that a failing field
+        // does not leave the document partially appended
+        let mut appended_field_ids = Vec::with_capacity(field_ids.len());
+        let mut appended_values = Vec::with_capacity(field_ids.len());
+        let mut appended_value_options = Vec::with_capacity(field_ids.len());
+        for i in 0..field_ids.len() {
+            if field_ids[i] == PRIMARY_KEY_FIELD_ID {
+                continue;
+            }
+            appended_values.push(ckv_index_segment.appended_value(
+                &primary_key,
+                field_ids[i],
+                values[i],
+                max_length,
+            )?);
+            appended_field_ids.push(field_ids[i]);
+            appended_value_options.push(value_options[i]);
+        }
+
+        if !appended_field_ids.is_empty() {
+            // write primary-key along with appended fields, for new documents
+            if ckv_index_segment
+                .read_field(&primary_key, PRIMARY_KEY_FIELD_ID)
+                .is_none()
+            {
+                if let Some(i) = field_ids.iter().position(|id| *id == PRIMARY_KEY_FIELD_ID) {
+                    appended_field_ids.push(field_ids[i]);
+                    appended_values.push(values[i].clone());
+                    appended_value_options.push(value_options[i]);
+                }
+            }
+
+            ckv_index_segment.upsert_document(
+                &primary_key,
+                &appended_field_ids,
+                &appended_values,
+                &appended_value_options,
+            )?;
+        }
+
+        if let Some((partition, offset)) = write_options.event_offset {
+            ckv_index_segment.mark_event_applied(partition, offset);
+        }
         Ok(())
     }
 
+    /// Fields of a document which have ann indexes, as (field-id, field-name, metric).
+    fn ann_fields(
+        &self,
+        document: &HashMap<String, FieldValue>,
+    ) -> Vec<(FieldId, &str, AnnMetric)> {
+        if self.ann_metrics.is_empty() {
+            return vec![];
+        }
+
+        let schema = self.schema.read().unwrap();
+        self.ann_metrics
+            .iter()
+            .filter(|(field_name, _)| document.contains_key(*field_name))
+            .filter_map(|(field_name, metric)| {
+                schema
+                    .fetch_id_by_name(field_name)
+                    .map(|field_id| (field_id, field_name.as_str(), *metric))
+            })
+            .collect()
+    }
+
+    /// Flattens a document to field-ids, values and their storage options.
+    /// Fields must be known to the schema, fields with unknown types are skipped.
+    fn flatten_document<'a>(
+        &self,
+        document: &'a HashMap<String, FieldValue>,
+        write_options: &WriteOptions,
+    ) -> (Vec<FieldId>, Vec<&'a FieldValue>, Vec<ValueOptions>) {
+        let mut field_ids = Vec::with_capacity(document.len());
+        let mut values = Vec::with_capacity(document.len());
+        let mut value_options = Vec::with_capacity(document.len());
+
+        let schema = self.schema.read().unwrap();
+        for (field_name, field_value) in document.iter() {
+            // Filter out unknown field types
+            if field_value.fieldType.enum_value_or_default() == FieldType::UNKNOWN {
+                continue;
+            }
+
+            field_ids.push(
+                schema
+                    .fetch_id_by_name(field_name)
+                    .expect("upsert_schema ensures schema is known"),
+            );
+            values.push(field_value);
+            value_options.push(ValueOptions {
+                codec: schema.fetch_codec_by_name(field_name),
+                expires_at_millis: write_options
+                    .expires_at_millis(schema.fetch_ttl_millis_by_name(field_name)),
+                timestamp_millis: self.write_timestamp_millis(write_options),
+            });
+        }
+
+        (field_ids, values, value_options)
+    }
+
+    /// Last-writer-wins: drops fields which have newer writes (or deletes) than `write_options`.
+    fn skip_older_writes(
+        &self,
+        ckv_index_segment: &CKVIndexSegment,
+        primary_key: &[u8],
+        write_options: &WriteOptions,
+        field_ids: &mut Vec<FieldId>,
+        values: &mut Vec<&FieldValue>,
+        value_options: &mut Vec<ValueOptions>,
+    ) {
+        let timestamp_millis = self.write_timestamp_millis(write_options);
+        if timestamp_millis == 0 {
+            return;
+        }
+
+        let mut i = 0;
+        while i < field_ids.len() {
+            if ckv_index_segment.write_timestamp_millis(primary_key, field_ids[i])
+                > timestamp_millis
+            {
+                field_ids.swap_remove(i);
+                values.swap_remove(i);
+                value_options.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
     // Note: deleting pkey from document is ok.
-    pub fn delete_field_values(
+    pub fn delete_field_values_with_options(
         &self,
         document: &HashMap<String, FieldValue>,
         field_names: &[String],
+        write_options: &WriteOptions,
     ) -> anyhow::Result<()> {
         if document.is_empty() || field_names.is_empty() {
             return Ok(());
@@ -374,15 +1521,30 @@ impl CKVIndex {
             }
         }
 
-        let index_id = fxhash::hash(&primary_key) % NUM_SEGMENTS;
+        let index_id = segment_id(&primary_key, self.segments.len());
         let mut ckv_index_segment = self.segments[index_id].write().unwrap();
+
+        let timestamp_millis = self.write_timestamp_millis(write_options);
+        if timestamp_millis != 0 {
+            return Self::tombstone_field_values(
+                &mut ckv_index_segment,
+                &primary_key,
+                &field_ids,
+                timestamp_millis,
+            );
+        }
+
         ckv_index_segment.delete_field_values(&primary_key, &field_ids)?;
 
         Ok(())
     }
 
     /// Delete a document, given its primary key.
-    pub fn delete_document(&self, document: &HashMap<String, FieldValue>) -> anyhow::Result<()> {
+    pub fn delete_document_with_options(
+        &self,
+        document: &HashMap<String, FieldValue>,
+        write_options: &WriteOptions,
+    ) -> anyhow::Result<()> {
         if document.is_empty() {
             return Ok(());
         }
@@ -394,13 +1556,60 @@ impl CKVIndex {
             .extract_primary_key(document)?
             .ok_or(anyhow!("Cannot delete with missing primary-key"))?;
 
-        let index_id = fxhash::hash(&primary_key) % NUM_SEGMENTS;
+        let index_id = segment_id(&primary_key, self.segments.len());
+
+        // last-writer-wins: delete all fields known to the schema, remembering the timestamp
+        let timestamp_millis = self.write_timestamp_millis(write_options);
+        if timestamp_millis != 0 {
+            let field_ids: Vec<FieldId> = self
+                .schema
+                .read()
+                .unwrap()
+                .uncompacted_fid_mapping()
+                .into_iter()
+                .filter(|field_id| *field_id != FieldId::MAX)
+                .collect();
+            let mut ckv_index_segment = self.segments[index_id].write().unwrap();
+            return Self::tombstone_field_values(
+                &mut ckv_index_segment,
+                &primary_key,
+                &field_ids,
+                timestamp_millis,
+            );
+        }
+
         let mut ckv_index_segment = self.segments[index_id].write().unwrap();
         ckv_index_segment.delete_document(&primary_key)?;
 
         Ok(())
     }
 
+    /// Timestamp to save with (and compare against for) a write in last-writer-wins mode.
+    /// 0 when not in this mode, or for writes without a source timestamp which are always applied.
+    fn write_timestamp_millis(&self, write_options: &WriteOptions) -> u64 {
+        if !self.last_writer_wins {
+            return 0;
+        }
+        write_options.source_timestamp_millis
+    }
+
+    /// Deletes fields, skipping ones with newer writes.
+    fn tombstone_field_values(
+        ckv_index_segment: &mut CKVIndexSegment,
+        primary_key: &[u8],
+        field_ids: &[FieldId],
+        timestamp_millis: u64,
+    ) -> anyhow::Result<()> {
+        let field_ids: Vec<FieldId> = field_ids
+            .iter()
+            .copied()
+            .filter(|field_id| {
+                ckv_index_segment.write_timestamp_millis(primary_key, *field_id) <= timestamp_millis
+            })
+            .collect();
+        ckv_index_segment.tombstone_field_values(primary_key, &field_ids, timestamp_millis)
+    }
+
     /// Drops provided fields (exact names or prefixes). Ignores attempt to drop primary-key.
     pub fn drop_fields(
         &self,
@@ -428,6 +1637,73 @@ impl CKVIndex {
         schema.hard_delete_all_fields()
     }
 
+    /// Checks types of field values against the declared types of their fields,
+    /// as per the field type policy. Returns the document to write, which has
+    /// coerced values with the "coerce" policy.
+    fn enforce_field_types<'a>(
+        &self,
+        document: &'a HashMap<String, FieldValue>,
+    ) -> anyhow::Result<Cow<'a, HashMap<String, FieldValue>>> {
+        let schema = self.schema.read().unwrap();
+
+        let mut coerced_document: Option<HashMap<String, FieldValue>> = None;
+        for (field_name, field_value) in document.iter() {
+            let field_type = field_value.fieldType.enum_value_or_default();
+            let declared_type = match schema.fetch_type_by_name(field_name) {
+                None => continue,
+                Some(declared_type) => declared_type,
+            };
+            if field_type == declared_type || field_type == FieldType::UNKNOWN {
+                continue;
+            }
+
+            match self.field_type_policy {
+                FieldTypePolicy::Warn => {
+                    // warn once per field, later writes are only counted (see index_stats())
+                    self.num_mismatched_type_writes
+                        .fetch_add(1, Ordering::Relaxed);
+                    if self
+                        .mismatched_type_fields
+                        .lock()
+                        .unwrap()
+                        .insert(field_name.clone())
+                    {
+                        warn!(
+                            "Field: {} is declared as {:?}, writing value of type: {:?}, further mismatched writes are counted in index stats",
+                            field_name, declared_type, field_type
+                        );
+                    }
+                }
+                FieldTypePolicy::Reject => bail!(
+                    "Field: {} is declared as {:?}, cannot write value of type: {:?}",
+                    field_name,
+                    declared_type,
+                    field_type
+                ),
+                FieldTypePolicy::Coerce => {
+                    let coerced_value =
+                        coerce::coerce(field_value, declared_type).map_err(|e| {
+                            anyhow!(
+                                "Field: {} is declared as {:?}, cannot coerce value of type: {:?}: {}",
+                                field_name,
+                                declared_type,
+                                field_type,
+                                e
+                            )
+                        })?;
+                    coerced_document
+                        .get_or_insert_with(|| document.clone())
+                        .insert(field_name.clone(), coerced_value);
+                }
+            }
+        }
+
+        Ok(match coerced_document {
+            None => Cow::Borrowed(document),
+            Some(coerced_document) => Cow::Owned(coerced_document),
+        })
+    }
+
     fn upsert_schema(&self, document: &HashMap<String, FieldValue>) -> anyhow::Result<()> {
         let mut needs_update = false;
         {
@@ -438,6 +1714,9 @@ impl CKVIndex {
                     break;
                 }
             }
+            if schema.check_vector_fields(document)? || schema.has_undeclared_types(document) {
+                needs_update = true;
+            }
         }
 
         if needs_update {
@@ -445,9 +1724,29 @@ impl CKVIndex {
             schema.upsert_schema(document)?;
         }
 
+        // new column-group fields get their ids on first write
+        if needs_update
+            && document
+                .keys()
+                .any(|field_name| self.column_group_fields.contains(field_name))
+        {
+            let column_field_ids = self.column_field_ids();
+            for segment in self.segments.iter() {
+                segment
+                    .write()
+                    .unwrap()
+                    .set_column_field_ids(column_field_ids.clone());
+            }
+        }
+
         Ok(())
     }
 
+    /// Ids of (known) column-group fields.
+    fn column_field_ids(&self) -> HashSet<FieldId> {
+        column_field_ids(&self.schema.read().unwrap(), &self.column_group_fields)
+    }
+
     fn extract_primary_key(
         &self,
         document: &HashMap<String, FieldValue>,
@@ -474,3 +1773,442 @@ impl CKVIndex {
         Ok(Some(serialized_primary_key))
     }
 }
+
+/// Ids of segments with problems in a verification report.
+fn broken_segment_ids(
+    mount_directory: &str,
+    num_segments: usize,
+    report: &VerificationReport,
+) -> Vec<usize> {
+    (0..num_segments)
+        .filter(|index_id| {
+            let segment_mount_directory = format!("{}/index/segment_{}", mount_directory, index_id);
+            report
+                .problems
+                .iter()
+                .any(|p| p.segment() == Some(segment_mount_directory.as_str()))
+        })
+        .collect()
+}
+
+/// Switches in the staged index of a committed resharding, and saves its header.
+/// Each step can be redone, so this is safe to call again after a crash at any point.
+fn complete_reshard(mount_directory: &str) -> anyhow::Result<()> {
+    let reshard_file = MetadataFile::new(mount_directory, RESHARD_FILENAME);
+    let header = match reshard_file.read()? {
+        None => return Ok(()),
+        Some(bytes) => CKVIndexHeader::parse_from_bytes(&bytes)?,
+    };
+
+    let index_directory = format!("{}/index", mount_directory);
+    let resharded_index_directory = format!("{}/{}", mount_directory, RESHARDED_INDEX_DIRECTORY);
+    let pre_reshard_index_directory =
+        format!("{}/{}", mount_directory, PRE_RESHARD_INDEX_DIRECTORY);
+    if Path::new(&resharded_index_directory).exists() {
+        if Path::new(&index_directory).exists() {
+            fs::rename(&index_directory, &pre_reshard_index_directory)?;
+        }
+        fs::rename(&resharded_index_directory, &index_directory)?;
+    }
+
+    // syncs the mount directory as well, persisting the renames
+    HeaderStore::open_or_create(mount_directory)?.write_header(&header)?;
+
+    if Path::new(&pre_reshard_index_directory).exists() {
+        fs::remove_dir_all(&pre_reshard_index_directory)?;
+    }
+    reshard_file.delete()?;
+
+    info!(
+        "Switched in resharded index with {} segments",
+        header.num_segments
+    );
+    Ok(())
+}
+
+/// Opens (or creates) all index segments concurrently, since each open replays
+/// the segment's offset table. Errors from all segments are reported together.
+fn open_or_create_segments(
+    mount_directory: &str,
+    num_segments: usize,
+) -> anyhow::Result<Vec<RwLock<CKVIndexSegment>>> {
+    let start = Instant::now();
+    let num_threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(num_segments);
+
+    // segment-id -> open result, populated by worker threads
+    let next_index_id = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<anyhow::Result<CKVIndexSegment>>>> =
+        Mutex::new((0..num_segments).map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..num_threads {
+            scope.spawn(|| loop {
+                let index_id = next_index_id.fetch_add(1, Ordering::SeqCst);
+                if index_id >= num_segments {
+                    break;
+                }
+
+                let segment_start = Instant::now();
+                let result = open_or_create_segment(mount_directory, index_id);
+                info!(
+                    "Opened index segment: {} in {} ms",
+                    index_id,
+                    segment_start.elapsed().as_millis()
+                );
+                results.lock().unwrap()[index_id] = Some(result);
+            });
+        }
+    });
+
+    let mut segments = Vec::with_capacity(num_segments);
+    let mut errors = vec![];
+    for (index_id, result) in results.into_inner().unwrap().into_iter().enumerate() {
+        match result {
+            Some(Ok(segment)) => segments.push(RwLock::new(segment)),
+            Some(Err(e)) => errors.push(format!("segment_{}: {}", index_id, e)),
+            None => errors.push(format!("segment_{}: not opened", index_id)),
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!(
+            "Cannot open {} of {} index segments, errors: [{}]",
+            errors.len(),
+            num_segments,
+            errors.join(", ")
+        );
+    }
+
+    info!(
+        "Opened {} index segments in {} ms",
+        num_segments,
+        start.elapsed().as_millis()
+    );
+    Ok(segments)
+}
+
+fn open_or_create_segment(
+    mount_directory: &str,
+    index_id: usize,
+) -> anyhow::Result<CKVIndexSegment> {
+    let segment_mount_directory = format!("{}/index/segment_{}", mount_directory, index_id);
+
+    // recover from a crash while swapping in a compacted segment: the compacted
+    // segment is complete once the live segment is moved away, else it is partial.
+    let compacted_segment_mount_directory =
+        format!("{}/index/compacted_segment_{}", mount_directory, index_id);
+    let stale_segment_mount_directory =
+        format!("{}/index/stale_segment_{}", mount_directory, index_id);
+    if !Path::new(&segment_mount_directory).exists()
+        && Path::new(&compacted_segment_mount_directory).exists()
+    {
+        std::fs::rename(&compacted_segment_mount_directory, &segment_mount_directory)?;
+    }
+    for leftover_directory in [
+        &compacted_segment_mount_directory,
+        &stale_segment_mount_directory,
+    ] {
+        if Path::new(leftover_directory).exists() {
+            std::fs::remove_dir_all(leftover_directory)?;
+        }
+    }
+
+    if Path::new(&segment_mount_directory).exists() {
+        CKVIndexSegment::is_valid_segment(&segment_mount_directory)?;
+    }
+    CKVIndexSegment::open_or_create(&segment_mount_directory)
+}
+
+/// Index segment which holds the provided primary-key.
+pub fn segment_id(primary_key: &[u8], num_segments: usize) -> usize {
+    fxhash::hash(primary_key) % num_segments
+}
+
+/// Segment count of an index, as saved in its header.
+fn saved_num_segments(header: &CKVIndexHeader) -> usize {
+    if header.num_segments == 0 {
+        return DEFAULT_NUM_SEGMENTS;
+    }
+    header.num_segments as usize
+}
+
+/// Handling of written values whose type differs from the declared
+/// type of their field, see "field_type_policy" config.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FieldTypePolicy {
+    /// Fail the write.
+    Reject,
+
+    /// Convert values to the declared type, fail the write if not convertible.
+    Coerce,
+
+    /// Write values as-is, warn on the first mismatch of a field and count others.
+    #[default]
+    Warn,
+}
+
+/// Attributes of a write, from the header of its event.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WriteOptions {
+    // epoch millis of the write at source, 0 if unknown
+    pub source_timestamp_millis: u64,
+
+    // time-to-live of written values, overrides per-field ttl, 0 if not set
+    pub ttl_millis: u64,
+
+    // (kafka partition, offset) of the event, None if not consumed from the stream
+    pub event_offset: Option<(i32, i64)>,
+}
+
+impl WriteOptions {
+    pub fn from_event_header(header: &EventHeader) -> Self {
+        let source_timestamp_millis = header
+            .sourceTimestamp
+            .as_ref()
+            .map(|ts| (ts.seconds.max(0) as u64) * 1000 + (ts.nanos.max(0) as u64) / 1_000_000)
+            .unwrap_or(0);
+
+        WriteOptions {
+            source_timestamp_millis,
+            ttl_millis: header.ttlMillis,
+            event_offset: None,
+        }
+    }
+
+    /// Expiry (epoch millis) of a written value, 0 if it does not expire.
+    /// Relative to the source timestamp if known, so that all replicas agree.
+    fn expires_at_millis(&self, field_ttl_millis: u64) -> u64 {
+        let ttl_millis = if self.ttl_millis != 0 {
+            self.ttl_millis
+        } else {
+            field_ttl_millis
+        };
+        if ttl_millis == 0 {
+            return 0;
+        }
+
+        let written_at_millis = if self.source_timestamp_millis != 0 {
+            self.source_timestamp_millis
+        } else {
+            now_millis()
+        };
+        written_at_millis.saturating_add(ttl_millis)
+    }
+}
+
+/// Parses a per-field client config, format: "field1:value1,field2:value2"
+pub(crate) fn configured_field_values(
+    config: &IKVStoreConfig,
+    config_name: &str,
+) -> anyhow::Result<Option<Vec<(String, String)>>> {
+    let field_values = match config.stringConfigs.get(config_name) {
+        None => return Ok(None),
+        Some(v) => v,
+    };
+
+    let mut result = vec![];
+    for entry in field_values.split(',') {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+
+        let (field_name, value) =
+            entry
+                .split_once(':')
+                .ok_or(anyhow!("{} bad entry: {}", config_name, entry))?;
+        result.push((field_name.trim().to_string(), value.trim().to_string()));
+    }
+
+    Ok(Some(result))
+}
+
+/// Client-specified compression codecs for field values, if any.
+/// Format: "field1:lz4,field2:lz4"
+fn configured_compression_codecs(
+    config: &IKVStoreConfig,
+) -> anyhow::Result<Option<HashMap<String, CompressionCodec>>> {
+    let field_codecs = match configured_field_values(config, "field_compression_codecs")? {
+        None => return Ok(None),
+        Some(v) => v,
+    };
+
+    let mut field_name_to_codec = HashMap::new();
+    for (field_name, codec) in field_codecs {
+        let codec = match codec.to_lowercase().as_str() {
+            "lz4" => CompressionCodec::LZ4,
+            "none" => CompressionCodec::UNCOMPRESSED,
+            _ => bail!("field_compression_codecs unsupported codec: {}", codec),
+        };
+        field_name_to_codec.insert(field_name, codec);
+    }
+
+    Ok(Some(field_name_to_codec))
+}
+
+/// Client-specified time-to-live for field values, if any.
+/// Format: "field1:3600,field2:60" (seconds)
+fn configured_ttls(config: &IKVStoreConfig) -> anyhow::Result<Option<HashMap<String, u64>>> {
+    let field_ttls = match configured_field_values(config, "field_ttl_secs")? {
+        None => return Ok(None),
+        Some(v) => v,
+    };
+
+    let mut field_name_to_ttl_millis = HashMap::new();
+    for (field_name, ttl_secs) in field_ttls {
+        let ttl_secs: u64 = ttl_secs
+            .parse()
+            .map_err(|_| anyhow!("field_ttl_secs bad value: {}", ttl_secs))?;
+        if ttl_secs > 0 {
+            field_name_to_ttl_millis.insert(field_name, ttl_secs.saturating_mul(1000));
+        }
+    }
+
+    Ok(Some(field_name_to_ttl_millis))
+}
+
+/// Client-specified ann indexes for vector fields, if any.
+/// Format: "field1:cosine,field2:l2" (metrics: l2, cosine, dot)
+fn configured_ann_metrics(
+    config: &IKVStoreConfig,
+) -> anyhow::Result<Option<HashMap<String, AnnMetric>>> {
+    let field_metrics = match configured_field_values(config, "field_ann_indexes")? {
+        None => return Ok(None),
+        Some(v) => v,
+    };
+
+    let mut field_name_to_metric = HashMap::new();
+    for (field_name, metric) in field_metrics {
+        let metric = match metric.to_lowercase().as_str() {
+            "l2" => AnnMetric::L2,
+            "cosine" => AnnMetric::COSINE,
+            "dot" => AnnMetric::DOT_PRODUCT,
+            _ => bail!("field_ann_indexes unsupported metric: {}", metric),
+        };
+        field_name_to_metric.insert(field_name, metric);
+    }
+
+    Ok(Some(field_name_to_metric))
+}
+
+/// Client-specified handling of field type mismatches, defaults to "warn".
+/// Values: "reject", "coerce", "warn"
+fn configured_field_type_policy(config: &IKVStoreConfig) -> anyhow::Result<FieldTypePolicy> {
+    let policy = match config.stringConfigs.get("field_type_policy") {
+        None => return Ok(FieldTypePolicy::default()),
+        Some(policy) => policy,
+    };
+
+    match policy.trim().to_lowercase().as_str() {
+        "reject" => Ok(FieldTypePolicy::Reject),
+        "coerce" => Ok(FieldTypePolicy::Coerce),
+        "warn" => Ok(FieldTypePolicy::Warn),
+        _ => bail!("field_type_policy unsupported value: {}", policy),
+    }
+}
+
+/// Client-specified page-cache handling of segment mmap files.
+/// Format: "random,willneed,hugepage" (any subset), and boolean "mlock_index_segments"
+fn configured_page_cache_policy(config: &IKVStoreConfig) -> anyhow::Result<PageCachePolicy> {
+    let mut policy = PageCachePolicy {
+        lock: config
+            .booleanConfigs
+            .get("mlock_index_segments")
+            .copied()
+            .unwrap_or(false),
+        ..Default::default()
+    };
+
+    let advices = config
+        .stringConfigs
+        .get("mmap_advice")
+        .map(|advices| advices.as_str())
+        .unwrap_or_default();
+    for advice in advices.split(',') {
+        match advice.trim().to_lowercase().as_str() {
+            "" | "normal" => {}
+            "random" => policy.random = true,
+            "willneed" => policy.will_need = true,
+            "hugepage" => policy.huge_pages = true,
+            _ => bail!("mmap_advice unsupported value: {}", advice),
+        }
+    }
+
+    Ok(policy)
+}
+
+/// Client-specified fields to fault into the page cache on startup, see CKVIndex::warmup().
+/// Format: "field1,field2"
+pub fn configured_warmup_field_names(config: &IKVStoreConfig) -> Vec<String> {
+    config
+        .stringConfigs
+        .get("warmup_field_names")
+        .map(|field_names| {
+            field_names
+                .split(',')
+                .map(|field_name| field_name.trim())
+                .filter(|field_name| !field_name.is_empty())
+                .map(|field_name| field_name.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Client-specified size of the decoded-value cache (see CKVIndexSegment::enable_value_cache())
+/// and its lock shards per segment. None (cache disabled) if the size is absent or 0.
+fn configured_value_cache(config: &IKVStoreConfig) -> anyhow::Result<Option<(u64, usize)>> {
+    let capacity_bytes = match config.intConfigs.get("value_cache_size_bytes").copied() {
+        None | Some(0) => return Ok(None),
+        Some(n) if n < 0 => bail!("value_cache_size_bytes bad value: {}", n),
+        Some(n) => n as u64,
+    };
+    let num_shards = match config.intConfigs.get("value_cache_num_shards").copied() {
+        None => DEFAULT_VALUE_CACHE_NUM_SHARDS,
+        Some(n) if n <= 0 || n > u16::MAX as i64 => {
+            bail!("value_cache_num_shards bad value: {}", n)
+        }
+        Some(n) => n as usize,
+    };
+    Ok(Some((capacity_bytes, num_shards)))
+}
+
+/// Client-specified fields to store in their own column files, for scans of single fields.
+/// Format: "field1,field2"
+fn configured_column_group_fields(config: &IKVStoreConfig) -> HashSet<String> {
+    config
+        .stringConfigs
+        .get("column_group_fields")
+        .map(|field_names| {
+            field_names
+                .split(',')
+                .map(|field_name| field_name.trim())
+                .filter(|field_name| !field_name.is_empty())
+                .map(|field_name| field_name.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn column_field_ids(
+    schema: &CKVIndexSchema,
+    column_group_fields: &HashSet<String>,
+) -> HashSet<FieldId> {
+    column_group_fields
+        .iter()
+        .filter_map(|field_name| schema.fetch_id_by_name(field_name))
+        .collect()
+}
+
+/// Client-specified segment count for the index, if any.
+pub fn configured_num_segments(config: &IKVStoreConfig) -> anyhow::Result<Option<usize>> {
+    match config.intConfigs.get("num_index_segments").copied() {
+        None => Ok(None),
+        Some(n) if n <= 0 || n > u16::MAX as i64 => {
+            bail!("num_index_segments bad value: {}", n)
+        }
+        Some(n) => Ok(Some(n as usize)),
+    }
+}
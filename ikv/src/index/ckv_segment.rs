@@ -1,17 +1,16 @@
 use std::{
-    borrow::Borrow,
-    collections::HashMap,
+    borrow::{Borrow, Cow},
+    collections::{hash_map::Entry, BTreeSet, HashMap, HashSet},
     fs::{File, OpenOptions},
     io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, Write},
-    iter,
-    ops::DerefMut,
     path::Path,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use integer_encoding::VarInt;
-use log::debug;
-use memmap2::MmapMut;
+use log::{debug, info, warn};
+use memmap2::{Advice, Mmap, MmapMut};
 use protobuf::{Enum, Message};
 
 use crate::{
@@ -20,34 +19,88 @@ use crate::{
         generated_proto::{
             common::{FieldType, FieldValue},
             index::{
-                offset_table_entry, CKVIndexSegmentMetadata, DeleteDoc, DeleteDocFields,
-                OffsetTableEntry, UpdateDocFields,
+                offset_table_entry, AnnLogEntry, AnnMetric, CKVIndexSegmentMetadata,
+                CompressionCodec, DeleteDoc, DeleteDocFields, OffsetTableEntry, SavedAnnIndexes,
+                UpdateDocFields,
             },
         },
     },
-    schema::field::FieldId,
+    schema::{field::FieldId, list, vector},
 };
 
-use super::stats::CompactionStats;
+use super::{
+    ann::{self, HnswIndex},
+    ckv::{segment_id, TypedValue},
+    metadata_file::MetadataFile,
+    stats::{CompactionStats, SegmentDocumentStats, SegmentValueCacheStats},
+    value_cache::{CachedValue, ValueCache},
+    verification::{VerificationProblem, VerificationReport},
+};
+
+#[cfg(test)]
+#[path = "ckv_segment_test.rs"]
+mod ckv_segment_test;
 
 const CHUNK_SIZE: usize = 8 * 1024 * 1024; // 8M
+
+// Offset table format used for new segments.
+// 0: [i32 size][OffsetTableEntry], 1: [i32 size][u32 crc32][OffsetTableEntry]
+const OFFSET_TABLE_FORMAT_VERSION: u32 = 1;
+
+// Offset table log size past which it is folded into a snapshot.
+// Snapshots are also written no more often than their own size, to bound write amplification.
+const OFFSET_TABLE_SNAPSHOT_THRESHOLD_BYTES: u64 = 64 * 1024 * 1024; // 64M
+
+// Snapshots are always written with checksummed entries.
+const OFFSET_TABLE_SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+// Ann index log size past which ann indexes are saved in full (and the log dropped).
+// As with offset table snapshots, saves are also bounded by their own size.
+const ANN_LOG_SAVE_THRESHOLD_BYTES: u64 = 64 * 1024 * 1024; // 64M
 const EMPTY_BYTE_SLICE: &[u8] = &[];
+
+// Documents with only tombstones (last-writer-wins deletes) are dropped by compaction
+// once their latest delete is older than this, until then older writes are still skipped.
+const TOMBSTONE_RETENTION_MILLIS: u64 = 24 * 60 * 60 * 1000; // 1 day
+
+// Bound on problems reported per segment by verify_segment(), for badly corrupt segments.
+const MAX_VERIFICATION_PROBLEMS_PER_SEGMENT: usize = 100;
+
+// Offsets of values in column files are tagged with (field id + 1) in their
+// high bits, untagged offsets point into the mmap file. Payload (mmap and column)
+// files are bounded so that their offsets fit below the tag.
+const COLUMN_TAG_SHIFT: u32 = 40;
+const MAX_PAYLOAD_FILE_SIZE: u64 = 1 << COLUMN_TAG_SHIFT;
+const MAX_COLUMN_FIELD_ID: FieldId = (1 << (u64::BITS - COLUMN_TAG_SHIFT)) - 2;
+
+// document_id bytes -> vector of offsets into the memory map
+type OffsetTable = HashMap<Vec<u8>, Vec<usize>>;
 const NONE_SIZE: [u8; 4] = (-1 as i32).to_le_bytes();
 
 #[derive(Debug)]
 pub struct CKVIndexSegment {
+    // Format: usr-mount-dir/store/partition/index/segment-N
+    mount_directory: String,
+
     /**
      * file references
      */
     // offset-table-file: persists changes to offset table
     offset_table_file_writer: BufWriter<File>,
 
-    // metadata-file: persists information about this segment like next available offset into mmap file
-    metadata_file_writer: BufWriter<File>,
+    // size of the offset table snapshot on disk, 0 if not present
+    offset_table_snapshot_size: u64,
+
+    // metadata-file: persists information about this segment like next available offset into mmap file,
+    // rewritten as a whole on flush
+    metadata_file: MetadataFile,
 
     // mmap file, stores payloads
     mmap_file: File,
 
+    // on-disk format of offset-table-file entries
+    offset_table_format_version: u32,
+
     /**
      * Core index structures.
      */
@@ -58,6 +111,126 @@ pub struct CKVIndexSegment {
     offset_table: HashMap<Vec<u8>, Vec<usize>>,
     mmap: MmapMut,
     write_offset: u64,
+
+    // kafka partition -> offset following the last applied increment/append event,
+    // persisted in the metadata file, see mark_event_applied()
+    applied_event_offsets: HashMap<i32, i64>,
+
+    // primary keys of documents written to since track_written_keys()
+    written_keys: Option<HashSet<Vec<u8>>>,
+
+    // approximate nearest-neighbor indexes of vector fields, see enable_ann_index()
+    ann_indexes: HashMap<FieldId, HnswIndex>,
+
+    // ann index changes not yet appended to the ann index log, see flush_writes()
+    ann_log_entries: Vec<AnnLogEntry>,
+
+    // ann indexes must be saved in full on the next flush (ex. after a build)
+    ann_indexes_save_needed: bool,
+
+    // size of the last full save of ann indexes
+    ann_indexes_saved_size: u64,
+
+    // madvise/mlock settings of the mmap, re-applied whenever it is re-mapped
+    page_cache_policy: PageCachePolicy,
+
+    // field-id -> payload file of column-group fields, see set_column_field_ids()
+    columns: HashMap<FieldId, ColumnFile>,
+
+    // fields whose new values are appended to their column file
+    column_field_ids: HashSet<FieldId>,
+
+    // decoded values of recently read fields, see enable_value_cache()
+    value_cache: Option<ValueCache>,
+}
+
+/// Payload file (column_N) holding values of a single column-group field,
+/// so that scanning the field does not touch pages of other fields.
+#[derive(Debug)]
+struct ColumnFile {
+    file: File,
+    mmap: MmapMut,
+    write_offset: u64,
+}
+
+impl ColumnFile {
+    fn open(
+        segment_mount_directory: &str,
+        field_id: FieldId,
+        write_offset: u64,
+    ) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(false)
+            .open(column_filename(segment_mount_directory, field_id))?;
+        file.seek(io::SeekFrom::Start(0))?;
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+        Ok(ColumnFile {
+            file,
+            mmap,
+            write_offset,
+        })
+    }
+
+    /// Creates an empty column file, dropping leftovers of writes which were never flushed.
+    fn create(segment_mount_directory: &str, field_id: FieldId) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(column_filename(segment_mount_directory, field_id))?;
+        file.set_len(0)?;
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+        Ok(ColumnFile {
+            file,
+            mmap,
+            write_offset: 0,
+        })
+    }
+}
+
+/// Persisted write offsets of the mmap file and of column files (field-id -> write offset).
+/// Values at or past them were not flushed, and offsets pointing to them are invalid.
+#[derive(Debug, Default)]
+struct WriteOffsets {
+    mmap: u64,
+    columns: HashMap<FieldId, u64>,
+}
+
+impl WriteOffsets {
+    fn from_metadata(metadata: &CKVIndexSegmentMetadata) -> Self {
+        WriteOffsets {
+            mmap: metadata.mmap_write_offset,
+            columns: metadata.column_write_offsets.clone(),
+        }
+    }
+
+    fn contains(&self, offset: u64) -> bool {
+        match split_offset(offset as usize) {
+            (None, offset) => (offset as u64) < self.mmap,
+            (Some(field_id), offset) => self
+                .columns
+                .get(&field_id)
+                .is_some_and(|write_offset| (offset as u64) < *write_offset),
+        }
+    }
+}
+
+/// Page-cache handling of a segment's mmap file, defaults to kernel defaults.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PageCachePolicy {
+    // MADV_RANDOM: disables readahead, for point lookups on datasets larger than memory
+    pub random: bool,
+
+    // MADV_WILLNEED: reads ahead the whole file, for datasets which fit in memory
+    pub will_need: bool,
+
+    // MADV_HUGEPAGE: backs the mapping with transparent huge pages (linux only)
+    pub huge_pages: bool,
+
+    // mlock: keeps the whole file resident, subject to RLIMIT_MEMLOCK
+    pub lock: bool,
 }
 
 impl CKVIndexSegment {
@@ -69,101 +242,88 @@ impl CKVIndexSegment {
             return CKVIndexSegment::new(segment_mount_directory);
         }
 
-        // offset-table exists on disk...
-        let offset_table_file = open_offset_table_file(segment_mount_directory)?;
-
-        // build offset-table by replaying offset_table_file
-        let mut offset_table = HashMap::new();
-        let mut reader = BufReader::new(&offset_table_file);
-
-        let mut entry_buffer = vec![];
-        loop {
-            // read size of serialized `OffsetTableEntry.proto`
-            let entry_size: usize;
-            let mut entry_size_buffer = vec![0u8; 4];
-            match reader.read_exact(&mut entry_size_buffer) {
-                Ok(_) => {
-                    entry_size = i32::from_le_bytes(entry_size_buffer.try_into().unwrap()) as usize
-                }
-                Err(e) => {
-                    match e.kind() {
-                        ErrorKind::UnexpectedEof => {
-                            // EOF reached
-                            break;
-                        }
-                        _ => return Err(e.into()),
-                    }
-                }
-            }
+        // metadata file
+        let metadata_file = MetadataFile::new(segment_mount_directory, "metadata");
+        let metadata = match metadata_file.read()? {
+            Some(bytes) => CKVIndexSegmentMetadata::parse_from_bytes(&bytes)?,
+            None => bail!(
+                "CKVIndexSegment metadata file does not exist: {}/metadata",
+                segment_mount_directory
+            ),
+        };
+        let write_offset = metadata.mmap_write_offset;
+        let write_offsets = WriteOffsets::from_metadata(&metadata);
+        let applied_event_offsets = metadata.applied_event_offsets.clone();
+        let offset_table_format_version = metadata.offset_table_format_version;
+        if offset_table_format_version > OFFSET_TABLE_FORMAT_VERSION {
+            bail!(
+                "Unsupported offset table format version: {}",
+                offset_table_format_version
+            );
+        }
 
-            // deserialize `OffsetTableEntry.proto` into offset_table
-            if entry_buffer.len() < entry_size {
-                let x = entry_size - entry_buffer.len();
-                entry_buffer.extend(iter::repeat(0u8).take(x));
-            }
-            reader.read_exact(&mut entry_buffer[..entry_size])?;
+        // leftover from an incomplete snapshot attempt
+        let tmp_filename = format!("{}/offset_table_snapshot.tmp", segment_mount_directory);
+        if Path::new(&tmp_filename).exists() {
+            std::fs::remove_file(&tmp_filename)?;
+        }
 
-            let entry = OffsetTableEntry::parse_from_bytes(&entry_buffer[..entry_size])?;
-            if let Some(operation) = entry.operation {
-                match operation {
-                    offset_table_entry::Operation::UpdateDocFields(e) => {
-                        let primary_key = &e.primary_key;
-                        let offsets: &mut Vec<usize> =
-                            offset_table.entry(primary_key.clone()).or_default();
-                        for i in 0..e.field_ids.len() {
-                            let field_id = e.field_ids[i] as usize;
-                            let offset = e.offsets[i] as usize;
+        // load offset-table snapshot if present
+        let mut offset_table = HashMap::new();
+        let offset_table_snapshot_size =
+            load_offset_table_snapshot(segment_mount_directory, &write_offsets, &mut offset_table)?;
 
-                            if field_id >= offsets.len() {
-                                // needs expansion
-                                offsets.resize(field_id + 1, usize::MAX);
-                            }
-                            offsets[field_id] = offset;
-                        }
-                    }
-                    offset_table_entry::Operation::DeleteDocFields(e) => {
-                        let primary_key = &e.primary_key;
-                        if let Some(offsets) = offset_table.get_mut(primary_key) {
-                            for field_id in e.field_ids.iter() {
-                                let i = *field_id as usize;
-                                if offsets.get(i).is_some() {
-                                    offsets[i] = usize::MAX;
-                                }
-                            }
-                        }
-                    }
-                    offset_table_entry::Operation::DeleteDoc(e) => {
-                        let primary_key = &e.primary_key;
-                        offset_table.remove(primary_key);
-                    }
-                }
-            }
+        // offset-table exists on disk...
+        // replay offset_table_file (log entries after the snapshot) on top
+        let offset_table_file = open_offset_table_file(segment_mount_directory)?;
+        let valid_len = replay_offset_table(
+            &offset_table_file,
+            offset_table_format_version,
+            &write_offsets,
+            &mut offset_table,
+        )?;
+
+        // drop torn or corrupt tail, following writes are appended to the last good entry
+        let file_len = offset_table_file.metadata()?.len();
+        if valid_len < file_len {
+            warn!(
+                "Truncating offset table of segment: {} from {} to {} bytes, dropping corrupt tail",
+                segment_mount_directory, file_len, valid_len
+            );
+            offset_table_file.set_len(valid_len)?;
         }
 
         // mmap file
         let mmap_file = open_mmap_file(segment_mount_directory)?;
         let mmap = unsafe { MmapMut::map_mut(&mmap_file)? };
 
-        // metadata file
-        let metadata_file = open_metadata_file(segment_mount_directory)?;
-
-        let write_offset: u64;
-        {
-            let mut metadata_file_reader = BufReader::new(metadata_file.try_clone()?);
-            let mut buffer = Vec::new();
-            metadata_file_reader.read_to_end(&mut buffer)?;
-            let metadata = CKVIndexSegmentMetadata::parse_from_bytes(&buffer)?;
-            write_offset = metadata.mmap_write_offset;
-            metadata_file_reader.rewind()?;
+        // column files
+        let mut columns = HashMap::with_capacity(write_offsets.columns.len());
+        for (field_id, write_offset) in write_offsets.columns {
+            let column = ColumnFile::open(segment_mount_directory, field_id, write_offset)?;
+            columns.insert(field_id, column);
         }
 
         Ok(CKVIndexSegment {
+            mount_directory: segment_mount_directory.to_string(),
             offset_table_file_writer: BufWriter::new(offset_table_file),
-            metadata_file_writer: BufWriter::new(metadata_file),
+            offset_table_snapshot_size,
+            metadata_file,
             mmap_file,
+            offset_table_format_version,
             offset_table,
             mmap,
             write_offset,
+            applied_event_offsets,
+            written_keys: None,
+            ann_indexes: HashMap::new(),
+            ann_log_entries: vec![],
+            ann_indexes_save_needed: false,
+            ann_indexes_saved_size: 0,
+            page_cache_policy: PageCachePolicy::default(),
+            columns,
+            column_field_ids: HashSet::new(),
+            value_cache: None,
         })
     }
 
@@ -177,17 +337,34 @@ impl CKVIndexSegment {
         let mmap = unsafe { MmapMut::map_mut(&mmap_file)? };
 
         // metadata file
-        let mut metadata_file_writer =
-            BufWriter::new(create_new_metadata_file(segment_mount_directory)?);
-        write_metadata(&mut metadata_file_writer, 0u64)?;
+        let metadata_file = MetadataFile::new(segment_mount_directory, "metadata");
+        write_metadata(
+            &metadata_file,
+            &WriteOffsets::default(),
+            &HashMap::new(),
+            OFFSET_TABLE_FORMAT_VERSION,
+        )?;
 
         Ok(CKVIndexSegment {
+            mount_directory: segment_mount_directory.to_string(),
             offset_table_file_writer: BufWriter::new(offset_table_file),
-            metadata_file_writer,
+            offset_table_snapshot_size: 0,
+            metadata_file,
             mmap_file,
+            offset_table_format_version: OFFSET_TABLE_FORMAT_VERSION,
             offset_table: HashMap::new(),
             mmap,
             write_offset: 0,
+            applied_event_offsets: HashMap::new(),
+            written_keys: None,
+            ann_indexes: HashMap::new(),
+            ann_log_entries: vec![],
+            ann_indexes_save_needed: false,
+            ann_indexes_saved_size: 0,
+            page_cache_policy: PageCachePolicy::default(),
+            columns: HashMap::new(),
+            column_field_ids: HashSet::new(),
+            value_cache: None,
         })
     }
 
@@ -216,78 +393,691 @@ impl CKVIndexSegment {
         Ok(())
     }
 
+    /// Deep verification of a segment on disk, without modifying it. Checks that segment
+    /// files load, and that all values referenced by the offset table decode and end
+    /// within the mmap write offset. Field ids at or past `field_id_counter` are unknown,
+    /// other ids not in `field_ids` belong to dropped fields.
+    /// Without `verify_values`, only checks metadata and sizes of segment files.
+    pub fn verify_segment(
+        segment_mount_directory: &str,
+        index_id: usize,
+        num_segments: usize,
+        field_id_counter: u64,
+        field_ids: &HashSet<FieldId>,
+        verify_values: bool,
+        report: &mut VerificationReport,
+    ) {
+        report.num_segments += 1;
+        if let Err(e) = Self::verify_segment_values(
+            segment_mount_directory,
+            index_id,
+            num_segments,
+            field_id_counter,
+            field_ids,
+            verify_values,
+            report,
+        ) {
+            report.problems.push(VerificationProblem::BadSegmentFile {
+                segment: segment_mount_directory.to_string(),
+                error: e.to_string(),
+            });
+        }
+    }
+
+    fn verify_segment_values(
+        segment_mount_directory: &str,
+        index_id: usize,
+        num_segments: usize,
+        field_id_counter: u64,
+        field_ids: &HashSet<FieldId>,
+        verify_values: bool,
+        report: &mut VerificationReport,
+    ) -> anyhow::Result<()> {
+        Self::is_valid_segment(segment_mount_directory)?;
+        let segment = segment_mount_directory.to_string();
+
+        let metadata = CKVIndexSegmentMetadata::parse_from_bytes(&std::fs::read(format!(
+            "{}/metadata",
+            segment_mount_directory
+        ))?)?;
+        let write_offsets = WriteOffsets::from_metadata(&metadata);
+        if metadata.offset_table_format_version > OFFSET_TABLE_FORMAT_VERSION {
+            bail!(
+                "Unsupported offset table format version: {}",
+                metadata.offset_table_format_version
+            );
+        }
+
+        // mmap and column files, must extend to their write offsets
+        let mut payload_files = vec![(None, format!("{}/mmap", segment_mount_directory))];
+        for field_id in write_offsets.columns.keys() {
+            payload_files.push((
+                Some(*field_id),
+                column_filename(segment_mount_directory, *field_id),
+            ));
+        }
+        let mut payload_file_handles = Vec::with_capacity(payload_files.len());
+        for (field_id, filename) in payload_files {
+            let file = File::open(&filename)?;
+            let write_offset = match field_id {
+                None => write_offsets.mmap,
+                Some(field_id) => write_offsets.columns[&field_id],
+            };
+            let mmap_file_size = file.metadata()?.len();
+            if write_offset > mmap_file_size {
+                report
+                    .problems
+                    .push(VerificationProblem::WriteOffsetPastEnd {
+                        segment: filename,
+                        write_offset,
+                        mmap_file_size,
+                    });
+            }
+            payload_file_handles.push((field_id, file));
+        }
+        if !verify_values {
+            return Ok(());
+        }
+
+        // offset table, loaded as by open_or_create()
+        let mut offset_table = HashMap::new();
+        load_offset_table_snapshot(segment_mount_directory, &write_offsets, &mut offset_table)?;
+        replay_offset_table(
+            &File::open(format!("{}/offset_table", segment_mount_directory))?,
+            metadata.offset_table_format_version,
+            &write_offsets,
+            &mut offset_table,
+        )?;
+
+        // payloads trimmed to their write offsets
+        let mut payloads: HashMap<Option<FieldId>, Mmap> = HashMap::new();
+        for (field_id, file) in payload_file_handles {
+            payloads.insert(field_id, unsafe { Mmap::map(&file)? });
+        }
+        let payloads: HashMap<Option<FieldId>, &[u8]> = payloads
+            .iter()
+            .map(|(field_id, mmap)| {
+                let write_offset = match field_id {
+                    None => write_offsets.mmap,
+                    Some(field_id) => write_offsets.columns[field_id],
+                };
+                (
+                    *field_id,
+                    &mmap[..std::cmp::min(write_offset, mmap.len() as u64) as usize],
+                )
+            })
+            .collect();
+
+        let mut num_problems = 0;
+        let mut num_misplaced_documents = 0;
+        let mut unknown_field_ids = BTreeSet::new();
+        for (primary_key, offsets) in offset_table.iter() {
+            report.num_documents += 1;
+            if segment_id(primary_key, num_segments) != index_id {
+                num_misplaced_documents += 1;
+            }
+
+            for (field_id, offset) in offsets.iter().copied().enumerate() {
+                if offset == usize::MAX {
+                    continue;
+                }
+                report.num_values += 1;
+
+                let field_id = field_id as FieldId;
+                if field_id as u64 >= field_id_counter {
+                    unknown_field_ids.insert(field_id);
+                    continue;
+                }
+                if !field_ids.contains(&field_id) {
+                    report.num_dropped_field_values += 1;
+                }
+
+                let (column_field_id, payload_offset) = split_offset(offset);
+                let payload = payloads.get(&column_field_id).copied().unwrap_or_default();
+                let problem = if payload_offset >= payload.len() {
+                    VerificationProblem::OffsetPastWriteOffset {
+                        segment: segment.clone(),
+                        field_id,
+                        offset: offset as u64,
+                    }
+                } else {
+                    match verify_mmap_value(payload, payload_offset) {
+                        Ok(_) => continue,
+                        Err(e) => VerificationProblem::CorruptValue {
+                            segment: segment.clone(),
+                            field_id,
+                            offset: offset as u64,
+                            error: e.to_string(),
+                        },
+                    }
+                };
+
+                // bound report size for badly corrupt segments
+                num_problems += 1;
+                if num_problems <= MAX_VERIFICATION_PROBLEMS_PER_SEGMENT {
+                    report.problems.push(problem);
+                }
+            }
+        }
+
+        if num_misplaced_documents > 0 {
+            report
+                .problems
+                .push(VerificationProblem::MisplacedDocuments {
+                    segment: segment.clone(),
+                    count: num_misplaced_documents,
+                });
+        }
+        for field_id in unknown_field_ids {
+            report.problems.push(VerificationProblem::UnknownFieldId {
+                segment: segment.clone(),
+                field_id,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Repairs a segment on disk in place: regenerates missing or corrupt metadata from the
+    /// highest valid offsets of the mmap and column files, and truncates the offset table at
+    /// the first entry past them.
+    /// Only drops writes which were not flushed (see flush_writes()), others must be intact,
+    /// so the segment can still fail verification after repair.
+    pub fn repair_segment(segment_mount_directory: &str) -> anyhow::Result<()> {
+        let mmap = unsafe { Mmap::map(&File::open(format!("{}/mmap", segment_mount_directory))?)? };
+        let offset_table_file = open_offset_table_file(segment_mount_directory)?;
+
+        let metadata_filename = format!("{}/metadata", segment_mount_directory);
+        let metadata = std::fs::read(&metadata_filename)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(CKVIndexSegmentMetadata::parse_from_bytes(&bytes)?))
+            .ok()
+            .filter(|metadata| metadata.offset_table_format_version <= OFFSET_TABLE_FORMAT_VERSION);
+        let applied_event_offsets = metadata
+            .as_ref()
+            .map(|metadata| metadata.applied_event_offsets.clone())
+            .unwrap_or_default();
+
+        // column files, as recorded in metadata or else as found on disk
+        let column_field_ids: Vec<FieldId> = match metadata.as_ref() {
+            Some(metadata) => metadata.column_write_offsets.keys().copied().collect(),
+            None => list_column_field_ids(segment_mount_directory)?,
+        };
+        let mut columns = HashMap::with_capacity(column_field_ids.len());
+        for field_id in column_field_ids {
+            let file = File::open(column_filename(segment_mount_directory, field_id))?;
+            columns.insert(field_id, unsafe { Mmap::map(&file)? });
+        }
+
+        let (write_offsets, format_version) = match metadata {
+            Some(metadata) => {
+                let write_offsets = WriteOffsets::from_metadata(&metadata);
+                if write_offsets.mmap > mmap.len() as u64 {
+                    // flushed values are lost
+                    bail!(
+                        "mmap file of size: {} is truncated below write offset: {}",
+                        mmap.len(),
+                        write_offsets.mmap
+                    );
+                }
+                for (field_id, write_offset) in write_offsets.columns.iter() {
+                    if *write_offset > columns[field_id].len() as u64 {
+                        bail!(
+                            "column file of field id: {} of size: {} is truncated below write offset: {}",
+                            field_id,
+                            columns[field_id].len(),
+                            write_offset
+                        );
+                    }
+                }
+                (write_offsets, metadata.offset_table_format_version)
+            }
+            None => {
+                let file_sizes = WriteOffsets {
+                    mmap: mmap.len() as u64,
+                    columns: columns
+                        .iter()
+                        .map(|(field_id, column)| (*field_id, column.len() as u64))
+                        .collect(),
+                };
+
+                // offset table format is unknown, pick the one with the longest valid log
+                let mut best = (0, OFFSET_TABLE_FORMAT_VERSION);
+                for format_version in (0..=OFFSET_TABLE_FORMAT_VERSION).rev() {
+                    let mut offset_table = HashMap::new();
+                    let valid_len = replay_offset_table(
+                        &offset_table_file,
+                        format_version,
+                        &file_sizes,
+                        &mut offset_table,
+                    )?;
+                    if valid_len > best.0 {
+                        best = (valid_len, format_version);
+                    }
+                }
+                let format_version = best.1;
+
+                // highest end of values referenced by the offset table, per payload file
+                let mut offset_table = HashMap::new();
+                load_offset_table_snapshot(
+                    segment_mount_directory,
+                    &file_sizes,
+                    &mut offset_table,
+                )?;
+                replay_offset_table(
+                    &offset_table_file,
+                    format_version,
+                    &file_sizes,
+                    &mut offset_table,
+                )?;
+                let mut write_offsets = WriteOffsets {
+                    mmap: 0,
+                    columns: columns.keys().map(|field_id| (*field_id, 0)).collect(),
+                };
+                for offset in offset_table
+                    .values()
+                    .flat_map(|offsets| offsets.iter().copied())
+                    .filter(|offset| *offset != usize::MAX)
+                {
+                    let (field_id, offset) = split_offset(offset);
+                    let (payload, write_offset): (&[u8], &mut u64) = match field_id {
+                        None => (&mmap, &mut write_offsets.mmap),
+                        Some(field_id) => match write_offsets.columns.get_mut(&field_id) {
+                            None => continue,
+                            Some(write_offset) => (&columns[&field_id], write_offset),
+                        },
+                    };
+                    if let Ok(end_offset) = verify_mmap_value(payload, offset) {
+                        *write_offset = std::cmp::max(*write_offset, end_offset as u64);
+                    }
+                }
+
+                warn!(
+                    "Regenerating metadata of segment: {} with write offsets: {:?}",
+                    segment_mount_directory, write_offsets
+                );
+                (write_offsets, format_version)
+            }
+        };
+
+        // truncate offset table at the first entry past the write offsets
+        let mut offset_table = HashMap::new();
+        let valid_len = replay_offset_table(
+            &offset_table_file,
+            format_version,
+            &write_offsets,
+            &mut offset_table,
+        )?;
+        let file_len = offset_table_file.metadata()?.len();
+        if valid_len < file_len {
+            warn!(
+                "Truncating offset table of segment: {} from {} to {} bytes",
+                segment_mount_directory, file_len, valid_len
+            );
+            offset_table_file.set_len(valid_len)?;
+            offset_table_file.sync_all()?;
+        }
+
+        // replaced atomically, a crash during repair leaves the previous metadata
+        let metadata = segment_metadata(&write_offsets, &applied_event_offsets, format_version);
+        MetadataFile::new(segment_mount_directory, "metadata")
+            .write(&metadata.write_to_bytes()?)?;
+
+        Ok(())
+    }
+
     pub fn copy_to_compact(
         &mut self,
         destination: &mut CKVIndexSegment,
         new_fid_to_old_fid: &[FieldId],
+    ) -> anyhow::Result<()> {
+        self.copy_to_reshard(std::slice::from_mut(destination), new_fid_to_old_fid)
+    }
+
+    /// Copy all documents to `destinations`, routing each document
+    /// to a destination segment by its primary-key.
+    pub fn copy_to_reshard(
+        &mut self,
+        destinations: &mut [CKVIndexSegment],
+        new_fid_to_old_fid: &[FieldId],
     ) -> anyhow::Result<()> {
         self.flush_writes()?;
+        self.copy_documents(destinations, new_fid_to_old_fid)
+    }
 
+    /// Same as `copy_to_reshard` but does not flush this segment,
+    /// so it can run under a shared reference while reads continue.
+    pub fn copy_documents(
+        &self,
+        destinations: &mut [CKVIndexSegment],
+        new_fid_to_old_fid: &[FieldId],
+    ) -> anyhow::Result<()> {
         for (primary_key, offsets) in self.offset_table.iter() {
-            // construct document to copy
-            let capacity = std::cmp::min(offsets.len(), new_fid_to_old_fid.len());
-            let mut field_ids = Vec::with_capacity(capacity);
-            let mut field_values = Vec::with_capacity(capacity);
-
-            for new_fid in 0..new_fid_to_old_fid.len() {
-                let old_fid = new_fid_to_old_fid[new_fid];
-                if let Some(offset) = offsets.get(old_fid as usize).copied() {
-                    if let Some((field_type, value)) = self.read_from_mmap(offset) {
-                        if field_type == FieldType::UNKNOWN {
-                            // either write event in kafka stream was missing type info, or
-                            // this node is behind on symbol list
-                            bail!(
-                                "Found unknown fieldType for primary-key: {}",
-                                format!("{:?}", primary_key.as_slice())
-                            );
+            self.copy_document(primary_key, offsets, destinations, new_fid_to_old_fid)?;
+        }
+
+        for destination in destinations.iter_mut() {
+            destination.merge_applied_event_offsets(&self.applied_event_offsets);
+            destination.flush_writes()?;
+        }
+        Ok(())
+    }
+
+    /// Copies documents of `primary_keys` to `destinations`, replacing any previous copies
+    /// (documents missing from this segment are deleted from destinations).
+    /// Destinations are not flushed, so that documents can be copied in batches.
+    pub fn copy_documents_by_key<'a>(
+        &self,
+        primary_keys: impl IntoIterator<Item = &'a Vec<u8>>,
+        destinations: &mut [CKVIndexSegment],
+        new_fid_to_old_fid: &[FieldId],
+    ) -> anyhow::Result<()> {
+        for primary_key in primary_keys {
+            let destination_id = segment_id(primary_key, destinations.len());
+            if destinations[destination_id]
+                .offset_table
+                .contains_key(primary_key)
+            {
+                destinations[destination_id].delete_document(primary_key)?;
+            }
+            if let Some(offsets) = self.offset_table.get(primary_key) {
+                self.copy_document(primary_key, offsets, destinations, new_fid_to_old_fid)?;
+            }
+        }
+
+        for destination in destinations.iter_mut() {
+            destination.merge_applied_event_offsets(&self.applied_event_offsets);
+        }
+        Ok(())
+    }
+
+    fn copy_document(
+        &self,
+        primary_key: &[u8],
+        offsets: &[usize],
+        destinations: &mut [CKVIndexSegment],
+        new_fid_to_old_fid: &[FieldId],
+    ) -> anyhow::Result<()> {
+        // construct document to copy, values are copied as stored (ex. compressed)
+        let capacity = std::cmp::min(offsets.len(), new_fid_to_old_fid.len());
+        let mut field_ids = Vec::with_capacity(capacity);
+        let mut values = Vec::with_capacity(capacity);
+
+        for new_fid in 0..new_fid_to_old_fid.len() {
+            let old_fid = new_fid_to_old_fid[new_fid];
+            if let Some(offset) = offsets.get(old_fid as usize).copied() {
+                if let Some(mut value) = self.read_from_mmap(offset) {
+                    if value.is_expired() {
+                        if value.timestamp_millis == 0 {
+                            // purged
+                            continue;
                         }
 
-                        let mut field_value = FieldValue::new();
-                        field_value.fieldType = field_type.into();
-                        field_value.value = value.to_vec();
-                        field_values.push(field_value);
+                        // keep write timestamp, for last-writer-wins
+                        value = MmapValue::tombstone(value.timestamp_millis);
+                    }
 
-                        field_ids.push(new_fid as FieldId);
+                    if value.field_type == FieldType::UNKNOWN && !value.is_tombstone() {
+                        // either write event in kafka stream was missing type info, or
+                        // this node is behind on symbol list
+                        bail!(
+                            "Found unknown fieldType for primary-key: {}",
+                            format!("{:?}", primary_key)
+                        );
                     }
+
+                    values.push(value);
+                    field_ids.push(new_fid as FieldId);
                 }
             }
+        }
 
-            // write to destination segment
-            destination.upsert_document(primary_key, &field_ids, &field_values)?;
+        // drop deleted documents, once late writes they would skip are unlikely
+        if !values.is_empty() && values.iter().all(|value| value.is_tombstone()) {
+            let deleted_at_millis = values.iter().map(|v| v.timestamp_millis).max();
+            if deleted_at_millis.unwrap_or(0) + TOMBSTONE_RETENTION_MILLIS <= now_millis() {
+                return Ok(());
+            }
         }
 
-        destination.flush_writes()?;
+        // write to destination segment
+        let destination = &mut destinations[segment_id(primary_key, destinations.len())];
+        destination.upsert_mmap_values(primary_key, &field_ids, &values)?;
         Ok(())
     }
 
     pub fn compaction_stats(&self) -> anyhow::Result<CompactionStats> {
+        // bytes of mmap entries still referenced from the offset table
+        let mut mmap_live_bytes = 0;
+        for offsets in self.offset_table.values() {
+            for offset in offsets.iter().copied() {
+                if let Some(value) = self.read_from_mmap(offset) {
+                    if !value.is_expired() {
+                        mmap_live_bytes += Self::size_of_mmap_entry(&value)? as u64;
+                    }
+                }
+            }
+        }
+
+        let mut mmap_file_size_bytes = self.mmap_file.metadata()?.len();
+        for column in self.columns.values() {
+            mmap_file_size_bytes += column.file.metadata()?.len();
+        }
+
         return Ok(CompactionStats {
-            offset_table_size_bytes: self.offset_table_file_writer.get_ref().metadata()?.len(),
-            mmap_file_size_bytes: self.mmap_file.metadata()?.len(),
+            offset_table_size_bytes: self.offset_table_file_writer.get_ref().metadata()?.len()
+                + self.offset_table_snapshot_size,
+            mmap_file_size_bytes,
+            mmap_used_bytes: self.used_bytes(),
+            mmap_live_bytes,
         });
     }
 
+    /// Document and value statistics, counting values of `field_ids` only
+    /// (values of other, ie. dropped, fields are garbage).
+    pub fn document_stats(
+        &self,
+        field_ids: &HashSet<FieldId>,
+    ) -> anyhow::Result<SegmentDocumentStats> {
+        let num_field_ids = field_ids
+            .iter()
+            .map(|field_id| *field_id as usize + 1)
+            .max()
+            .unwrap_or(0);
+        let mut stats = SegmentDocumentStats {
+            field_num_values: vec![0; num_field_ids],
+            field_value_bytes: vec![0; num_field_ids],
+            mmap_used_bytes: self.used_bytes(),
+            ..Default::default()
+        };
+
+        for offsets in self.offset_table.values() {
+            let mut has_values = false;
+            for (field_id, offset) in offsets.iter().copied().enumerate() {
+                if !field_ids.contains(&(field_id as FieldId)) {
+                    continue;
+                }
+                let value = match self.read_from_mmap(offset) {
+                    None => continue,
+                    Some(value) => value,
+                };
+                if !value.is_expired() {
+                    stats.mmap_live_bytes += Self::size_of_mmap_entry(&value)? as u64;
+                }
+                if value.is_readable() {
+                    has_values = true;
+                    stats.field_num_values[field_id] += 1;
+                    stats.field_value_bytes[field_id] += value.value_len() as u64;
+                }
+            }
+            if has_values {
+                stats.num_documents += 1;
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Applies madvise/mlock settings to the mmap file, also after it is re-mapped.
+    pub fn set_page_cache_policy(&mut self, policy: PageCachePolicy) {
+        self.page_cache_policy = policy;
+        self.apply_page_cache_policy();
+    }
+
+    /// Failures are logged and ignored, the policy only affects performance.
+    fn apply_page_cache_policy(&self) {
+        advise_payload(&self.mmap, self.page_cache_policy, &self.mount_directory);
+        for column in self.columns.values() {
+            advise_payload(&column.mmap, self.page_cache_policy, &self.mount_directory);
+        }
+    }
+
+    /// Reads one byte per page of the values of `field_ids`, to fault them
+    /// into the page cache. Returns the number of value bytes touched.
+    pub fn warmup(&self, field_ids: &HashSet<FieldId>) -> u64 {
+        let page_size = 4096;
+        let mut touched_bytes = 0;
+        for offsets in self.offset_table.values() {
+            for (field_id, offset) in offsets.iter().copied().enumerate() {
+                if !field_ids.contains(&(field_id as FieldId)) {
+                    continue;
+                }
+                if let Some(value) = self.read_from_mmap(offset) {
+                    for i in (0..value.data.len()).step_by(page_size) {
+                        std::hint::black_box(value.data[i]);
+                    }
+                    touched_bytes += value.data.len() as u64;
+                }
+            }
+        }
+        touched_bytes
+    }
+
+    /// Fields whose values are appended to their own column file (column_N) instead
+    /// of the mmap file, so that scanning them touches fewer pages. Existing values
+    /// are read from wherever they were written, and move on compaction.
+    pub fn set_column_field_ids(&mut self, field_ids: HashSet<FieldId>) {
+        self.column_field_ids = field_ids
+            .into_iter()
+            .filter(|field_id| {
+                if *field_id > MAX_COLUMN_FIELD_ID {
+                    warn!(
+                        "Field id: {} cannot be stored in a column file, using the mmap file",
+                        field_id
+                    );
+                }
+                *field_id <= MAX_COLUMN_FIELD_ID
+            })
+            .collect();
+    }
+
+    /// Cache up to `capacity_bytes` of decoded values (keyed on primary key and field id)
+    /// of read_field() and read_fields(), so that hot values are not decompressed on
+    /// every read. Cached values are dropped on writes to their document.
+    pub fn enable_value_cache(&mut self, capacity_bytes: u64, num_shards: usize) {
+        self.value_cache = Some(ValueCache::new(capacity_bytes, num_shards));
+    }
+
+    /// Moves the value cache of `other`, ex. when swapping in an online compacted
+    /// segment. Compaction does not change values, cached ones remain valid.
+    pub fn take_value_cache(&mut self, other: &mut CKVIndexSegment) {
+        self.value_cache = other.value_cache.take();
+    }
+
+    /// Hit/miss counters and size of the value cache, None if it is not enabled.
+    pub fn value_cache_stats(&self) -> Option<SegmentValueCacheStats> {
+        self.value_cache.as_ref().map(|cache| cache.stats())
+    }
+
+    /// Bytes appended to the mmap file and column files.
+    fn used_bytes(&self) -> u64 {
+        self.write_offset
+            + self
+                .columns
+                .values()
+                .map(|column| column.write_offset)
+                .sum::<u64>()
+    }
+
+    fn write_offsets(&self) -> WriteOffsets {
+        WriteOffsets {
+            mmap: self.write_offset,
+            columns: self
+                .columns
+                .iter()
+                .map(|(field_id, column)| (*field_id, column.write_offset))
+                .collect(),
+        }
+    }
+
+    /// Starts remembering primary keys of documents written to, ex. to copy them again
+    /// after an online compaction which ran concurrently, see take_written_keys().
+    pub fn track_written_keys(&mut self) {
+        self.written_keys = Some(HashSet::new());
+    }
+
+    /// Primary keys of documents written to since track_written_keys(), stops tracking.
+    pub fn take_written_keys(&mut self) -> HashSet<Vec<u8>> {
+        self.written_keys.take().unwrap_or_default()
+    }
+
+    /// Whether the event at `offset` of a kafka partition was already applied,
+    /// see mark_event_applied().
+    pub fn is_event_applied(&self, partition: i32, offset: i64) -> bool {
+        self.applied_event_offsets
+            .get(&partition)
+            .is_some_and(|end_offset| offset < *end_offset)
+    }
+
+    /// Remembers that the event at `offset` of a kafka partition was applied, so that
+    /// non-idempotent writes (increments, appends) are skipped when events are replayed
+    /// after a restart. Persisted along with the writes, on flush.
+    pub fn mark_event_applied(&mut self, partition: i32, offset: i64) {
+        let end_offset = self.applied_event_offsets.entry(partition).or_insert(0);
+        *end_offset = std::cmp::max(*end_offset, offset + 1);
+    }
+
+    fn merge_applied_event_offsets(&mut self, applied_event_offsets: &HashMap<i32, i64>) {
+        for (partition, end_offset) in applied_event_offsets.iter() {
+            self.mark_event_applied(*partition, *end_offset - 1);
+        }
+    }
+
+    /// Moves this segment to a new mount directory, ex. when swapping in
+    /// an online compacted segment. Open file handles remain valid,
+    /// files which are rewritten as a whole (metadata) follow the new directory.
+    pub fn rename(&mut self, segment_mount_directory: &str) -> io::Result<()> {
+        self.flush_writes()?;
+        std::fs::rename(&self.mount_directory, segment_mount_directory)?;
+        self.mount_directory = segment_mount_directory.to_string();
+        self.metadata_file = MetadataFile::new(segment_mount_directory, "metadata");
+        Ok(())
+    }
+
     pub fn read_field(&self, primary_key: &[u8], field_id: FieldId) -> Option<Vec<u8>> {
         let offsets = self.offset_table.get(primary_key)?;
-        let maybe_offset = offsets.get(field_id as usize).copied();
-        if let Some(offset) = maybe_offset {
-            let (field_type, result) = self.read_from_mmap(offset)?;
-            if field_type != FieldType::UNKNOWN {
-                return Some(result.to_vec());
+        let offset = offsets.get(field_id as usize).copied()?;
+        if let Some(value_cache) = self.value_cache.as_ref() {
+            if let Some(data) = value_cache.get(primary_key, field_id, |value| value.data.clone()) {
+                return Some(data);
             }
         }
 
-        None
+        self.read_value(primary_key, field_id, offset)
+            .map(|(_, data)| data.into_owned())
     }
 
     /// Read all fields for a given primary-key and push the values at the end of `dest` vector.
     /// Values are size/length prefixed with i32 values. Size=-1 for missing values, Size=0 for empty values
     ///
     /// Format of dest: [(size)field1][(size)field2]...[(size)fieldn]
+    ///
+    /// List values are trimmed to their last `last_k` elements, if provided.
     pub fn read_fields(
         &self,
         primary_key: &[u8],
         field_ids: &[Option<FieldId>],
+        last_k: Option<usize>,
         dest: &mut Vec<u8>,
     ) {
         let maybe_offsets = self.offset_table.get(primary_key);
@@ -305,69 +1095,100 @@ impl CKVIndexSegment {
                 continue;
             }
 
-            let maybe_offset = offsets.get(field_id.unwrap() as usize).copied();
+            let field_id = field_id.unwrap();
+            let maybe_offset = offsets.get(field_id as usize).copied();
             if maybe_offset.is_none() {
                 dest.extend(NONE_SIZE);
                 continue;
             }
 
-            match self.read_from_mmap(maybe_offset.unwrap()) {
+            if let Some(value_cache) = self.value_cache.as_ref() {
+                let hit = value_cache.get(primary_key, field_id, |value| {
+                    extend_with_value(dest, value.field_type, &value.data, last_k)
+                });
+                if hit.is_some() {
+                    continue;
+                }
+            }
+
+            match self.read_value(primary_key, field_id, maybe_offset.unwrap()) {
                 None => {
                     dest.extend(NONE_SIZE);
                 }
-                Some((field_type, value)) => {
-                    if field_type == FieldType::UNKNOWN {
-                        dest.extend(NONE_SIZE);
-                    } else {
-                        dest.extend((value.len() as i32).to_le_bytes());
-                        dest.extend_from_slice(value);
-                    }
-                }
+                Some((field_type, data)) => extend_with_value(dest, field_type, &data, last_k),
             };
         }
     }
 
-    fn read_from_mmap(&self, mmap_offset: usize) -> Option<(FieldType, &[u8])> {
-        if mmap_offset == usize::MAX {
-            return None;
+    /// Decoded value at `offset` with its type, None if it is not readable.
+    /// Added to the value cache, if enabled.
+    fn read_value(
+        &self,
+        primary_key: &[u8],
+        field_id: FieldId,
+        offset: usize,
+    ) -> Option<(FieldType, Cow<'_, [u8]>)> {
+        let value = self
+            .read_from_mmap(offset)
+            .filter(|value| value.is_readable())?;
+        let field_type = value.field_type;
+        let expires_at_millis = value.expires_at_millis;
+        let data = value.decompress()?;
+
+        if let Some(value_cache) = self.value_cache.as_ref() {
+            let cached_value = CachedValue {
+                field_type,
+                expires_at_millis,
+                data: data.to_vec(),
+            };
+            value_cache.insert(primary_key, field_id, cached_value);
         }
+        Some((field_type, data))
+    }
 
-        // mmap_offset points to a bytes section where:
-        // [2 bytes for field-type][data]
-        // where data can be prefixed with vbytes for variable length types
+    /// Values (with their types) of `field_ids` of a document, None if it does not exist.
+    pub fn read_document(
+        &self,
+        primary_key: &[u8],
+        field_ids: &[Option<FieldId>],
+    ) -> Option<Vec<Option<TypedValue>>> {
+        let offsets = self.offset_table.get(primary_key)?;
+        Some(self.read_document_values(offsets, field_ids))
+    }
 
-        let field_type_bytes = &self.mmap[mmap_offset..mmap_offset + 2];
-        let field_type: u16 = u16::from_le_bytes(
-            field_type_bytes
-                .try_into()
-                .expect("mmap value must be prefixed with 2 byte field types"),
-        );
-        let field_type: FieldType = FieldType::from_i32(i32::from(field_type))?;
+    /// Primary keys of all documents.
+    pub fn primary_keys(&self) -> Vec<Vec<u8>> {
+        self.offset_table.keys().cloned().collect()
+    }
 
-        let mmap_offset = mmap_offset + 2;
-        match field_type {
-            FieldType::UNKNOWN => {
-                // Some unknown field-type was written to the mmap files
-                // can occur when this reader is behind on the FieldType.proto symbol list.
-                // Can be okay to ignore this when doing live reads on the index, but this
-                // should not be ignored during data copy (ex. compaction).
-                Some((field_type, EMPTY_BYTE_SLICE))
-            }
-            FieldType::INT32 | FieldType::FLOAT32 => {
-                Some((field_type, &self.mmap[mmap_offset..mmap_offset + 4]))
-            }
-            FieldType::INT64 | FieldType::FLOAT64 => {
-                Some((field_type, &self.mmap[mmap_offset..mmap_offset + 8]))
-            }
-            FieldType::BOOLEAN => Some((field_type, &self.mmap[mmap_offset..mmap_offset + 1])),
-            FieldType::STRING | FieldType::BYTES => {
-                // extract size (varint decoding)
-                let (size, bytes_read) = u32::decode_var(&self.mmap[mmap_offset..])?;
-                let mmap_offset = mmap_offset + bytes_read;
-                Some((
-                    field_type,
-                    &self.mmap[mmap_offset..mmap_offset + size as usize],
-                ))
+    fn read_document_values(
+        &self,
+        offsets: &[usize],
+        field_ids: &[Option<FieldId>],
+    ) -> Vec<Option<TypedValue>> {
+        field_ids
+            .iter()
+            .map(|field_id| {
+                let offset = offsets.get((*field_id)? as usize).copied()?;
+                let value = self
+                    .read_from_mmap(offset)
+                    .filter(|value| value.is_readable())?;
+                let field_type = value.field_type;
+                value
+                    .decompress()
+                    .map(|data| (field_type, data.into_owned()))
+            })
+            .collect()
+    }
+
+    fn read_from_mmap(&self, mmap_offset: usize) -> Option<MmapValue<'_>> {
+        if mmap_offset == usize::MAX {
+            return None;
+        }
+        match split_offset(mmap_offset) {
+            (None, offset) => decode_mmap_value(&self.mmap, offset).ok(),
+            (Some(field_id), offset) => {
+                decode_mmap_value(&self.columns.get(&field_id)?.mmap, offset).ok()
             }
         }
     }
@@ -387,10 +1208,119 @@ impl CKVIndexSegment {
         // self.mmap.flush()
 
         // persists valid offset within the mmap-file to disk
-        write_metadata(&mut self.metadata_file_writer, self.write_offset as u64)?;
+        let write_offsets = self.write_offsets();
+        write_metadata(
+            &self.metadata_file,
+            &write_offsets,
+            &self.applied_event_offsets,
+            self.offset_table_format_version,
+        )?;
+
+        self.offset_table_file_writer.flush()?;
+
+        // fold offset table log into a snapshot, once it grows large
+        let offset_table_log_size = self.offset_table_file_writer.get_ref().metadata()?.len();
+        if offset_table_log_size
+            >= std::cmp::max(
+                OFFSET_TABLE_SNAPSHOT_THRESHOLD_BYTES,
+                self.offset_table_snapshot_size,
+            )
+        {
+            self.snapshot_offset_table()?;
+        }
+
+        // ann indexes are saved in full after (re)builds and once their log grows large,
+        // otherwise only their changes are appended to the log
+        if self.ann_indexes_save_needed
+            || self
+                .ann_indexes
+                .values()
+                .any(|ann_index| ann_index.needs_rebuild())
+        {
+            self.save_ann_indexes()?;
+        } else if !self.ann_log_entries.is_empty() {
+            let ann_log_size = ann::append_log(
+                &self.mount_directory,
+                &self.ann_log_entries,
+                self.used_bytes(),
+            )?;
+            self.ann_log_entries.clear();
+            if ann_log_size
+                >= std::cmp::max(ANN_LOG_SAVE_THRESHOLD_BYTES, self.ann_indexes_saved_size)
+            {
+                self.save_ann_indexes()?;
+            }
+        }
 
+        Ok(())
+    }
+
+    /// Writes the in-memory offset table to a snapshot file and truncates the
+    /// offset table log, so that opening the segment only needs to load the
+    /// snapshot and replay writes made after it.
+    pub fn snapshot_offset_table(&mut self) -> io::Result<()> {
+        // persist pending writes, snapshot entries must point below the saved write offset
+        let write_offsets = self.write_offsets();
+        write_metadata(
+            &self.metadata_file,
+            &write_offsets,
+            &self.applied_event_offsets,
+            self.offset_table_format_version,
+        )?;
         self.offset_table_file_writer.flush()?;
 
+        // write snapshot to a temporary file, then atomically move it in place
+        let tmp_filename = format!("{}/offset_table_snapshot.tmp", &self.mount_directory);
+        let filename = format!("{}/offset_table_snapshot", &self.mount_directory);
+        {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&tmp_filename)?;
+            let mut writer = BufWriter::new(file);
+            for (primary_key, offsets) in self.offset_table.iter() {
+                let mut update_doc_fields = UpdateDocFields::new();
+                update_doc_fields.primary_key = primary_key.clone();
+                for (field_id, offset) in offsets.iter().enumerate() {
+                    if *offset != usize::MAX {
+                        update_doc_fields.field_ids.push(field_id as FieldId);
+                        update_doc_fields.offsets.push(*offset as u64);
+                    }
+                }
+                if update_doc_fields.field_ids.is_empty() {
+                    continue;
+                }
+
+                let mut offset_table_entry = OffsetTableEntry::new();
+                offset_table_entry.operation = Some(
+                    proto::generated_proto::index::offset_table_entry::Operation::UpdateDocFields(
+                        update_doc_fields,
+                    ),
+                );
+                write_offset_table_entry(
+                    &mut writer,
+                    &offset_table_entry,
+                    OFFSET_TABLE_SNAPSHOT_FORMAT_VERSION,
+                )?;
+            }
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        std::fs::rename(&tmp_filename, &filename)?;
+        File::open(&self.mount_directory)?.sync_all()?;
+        self.offset_table_snapshot_size = std::fs::metadata(&filename)?.len();
+
+        // truncate log, a crash before this point replays the (idempotent)
+        // log entries on top of the snapshot
+        self.offset_table_file_writer.get_ref().set_len(0)?;
+        self.offset_table_file_writer.rewind()?;
+
+        debug!(
+            "Wrote offset table snapshot of {} bytes for segment: {}",
+            self.offset_table_snapshot_size, &self.mount_directory
+        );
+
         Ok(())
     }
 
@@ -398,29 +1328,43 @@ impl CKVIndexSegment {
         self.flush_writes()
     }
 
-    fn size_of_mmap_entry(field_value: &FieldValue) -> anyhow::Result<usize> {
-        if field_value.fieldType.value() > u16::MAX as i32 {
-            bail!("Cannot store field type in 2 bytes");
+    fn size_of_mmap_entry(value: &MmapValue) -> anyhow::Result<usize> {
+        let mut size: usize = 2; // for storing header
+        if value.flags & VALUE_FLAG_HAS_EXPIRY != 0 {
+            size += 8;
+        }
+        if value.flags & VALUE_FLAG_HAS_TIMESTAMP != 0 {
+            size += 8;
         }
-        let mut size: usize = 2; // for storing type
 
-        match field_value.fieldType.enum_value_or_default() {
+        match value.field_type {
             FieldType::UNKNOWN => {
-                // unknown types are serialized by just saving the type (=0)
+                // unknown types are serialized by just saving the header (type=0)
                 size += 0
             }
             FieldType::INT32 | FieldType::FLOAT32 => size += 4,
             FieldType::INT64 | FieldType::FLOAT64 => size += 8,
             FieldType::BOOLEAN => size += 1,
-            FieldType::STRING | FieldType::BYTES => {
-                // length varint + actual content
-                let value_len = field_value.value.len();
+            FieldType::STRING
+            | FieldType::BYTES
+            | FieldType::INT32_LIST
+            | FieldType::INT64_LIST
+            | FieldType::FLOAT32_LIST
+            | FieldType::FLOAT64_LIST
+            | FieldType::STRING_LIST
+            | FieldType::BYTES_LIST
+            | FieldType::FLOAT32_VECTOR
+            | FieldType::FLOAT16_VECTOR
+            | FieldType::INT8_VECTOR => {
+                // length varint + padding for alignment (see write_to_mmap()) + actual content
+                let value_len = value.data.len();
                 if value_len > u32::MAX as usize {
                     bail!("size of value cannot exceed 4GB");
                 }
 
                 let value_len_prefix = u32::required_space(value_len as u32);
-                size += value_len_prefix + value_len
+                let max_padding = vector::alignment(value.field_type) - 1;
+                size += value_len_prefix + max_padding + value_len
             }
         };
 
@@ -431,61 +1375,82 @@ impl CKVIndexSegment {
     fn write_to_mmap(
         mmap: &mut [u8],
         write_offset: usize,
-        field_value: &FieldValue,
+        value: &MmapValue,
     ) -> anyhow::Result<usize> {
-        if field_value.fieldType.value() > u16::MAX as i32 {
-            bail!("Cannot store field type in 2 bytes");
-        }
-
         // TODO: copy_from_slice() panics when src/dest slice lenghts are different.
         // Prevalidate lengths match and handle errors accordingly.
 
         // TODO: consider being more robust by not gettings stuck on incorrect input
         // events - limit blast radius of bad serialization schemes in producer.
 
-        // serialize field_type
-        if CKVIndexSegment::is_unknown_field_type(field_value) {
-            // we save unknown types as sentinels
-            // they are ignored during normal read ops but caught during compaction
-            let field_type = (FieldType::UNKNOWN.value() as u16).to_le_bytes();
-            mmap[write_offset..write_offset + 2].copy_from_slice(&field_type[..]);
-        } else {
-            let field_type = (field_value.fieldType.value() as u16).to_le_bytes();
-            mmap[write_offset..write_offset + 2].copy_from_slice(&field_type[..]);
-        }
+        // serialize header: field_type and flags
+        // unknown types are saved as sentinels (type=0), they are
+        // ignored during normal read ops but caught during compaction
+        let header = (value.field_type.value() as u16) | ((value.flags as u16) << 8);
+        mmap[write_offset..write_offset + 2].copy_from_slice(&header.to_le_bytes()[..]);
 
         let mut num_bytes = 2;
-        let write_offset = write_offset + 2;
+        let mut write_offset = write_offset + 2;
+
+        // serialize expiry
+        if value.flags & VALUE_FLAG_HAS_EXPIRY != 0 {
+            mmap[write_offset..write_offset + 8]
+                .copy_from_slice(&value.expires_at_millis.to_le_bytes()[..]);
+            num_bytes += 8;
+            write_offset += 8;
+        }
+
+        // serialize write timestamp
+        if value.flags & VALUE_FLAG_HAS_TIMESTAMP != 0 {
+            mmap[write_offset..write_offset + 8]
+                .copy_from_slice(&value.timestamp_millis.to_le_bytes()[..]);
+            num_bytes += 8;
+            write_offset += 8;
+        }
 
         // write value
-        num_bytes += match field_value.fieldType.enum_value_or_default() {
+        num_bytes += match value.field_type {
             FieldType::UNKNOWN => {
                 // no field value data required
                 0
             }
             FieldType::INT32 | FieldType::FLOAT32 => {
-                mmap[write_offset..write_offset + 4].copy_from_slice(&field_value.value[..]);
+                mmap[write_offset..write_offset + 4].copy_from_slice(&value.data[..]);
                 4
             }
             FieldType::INT64 | FieldType::FLOAT64 => {
-                mmap[write_offset..write_offset + 8].copy_from_slice(&field_value.value[..]);
+                mmap[write_offset..write_offset + 8].copy_from_slice(&value.data[..]);
                 8
             }
             FieldType::BOOLEAN => {
-                mmap[write_offset..write_offset + 1].copy_from_slice(&field_value.value[..]);
+                mmap[write_offset..write_offset + 1].copy_from_slice(&value.data[..]);
                 1
             }
-            FieldType::STRING | FieldType::BYTES => {
-                let value_len = field_value.value.len();
+            FieldType::STRING
+            | FieldType::BYTES
+            | FieldType::INT32_LIST
+            | FieldType::INT64_LIST
+            | FieldType::FLOAT32_LIST
+            | FieldType::FLOAT64_LIST
+            | FieldType::STRING_LIST
+            | FieldType::BYTES_LIST
+            | FieldType::FLOAT32_VECTOR
+            | FieldType::FLOAT16_VECTOR
+            | FieldType::INT8_VECTOR => {
+                let value_len = value.data.len();
 
                 // value length prefix
                 let x = u32::encode_var(value_len as u32, &mut mmap[write_offset..]);
 
-                // value
-                mmap[write_offset + x..write_offset + x + value_len]
-                    .copy_from_slice(&field_value.value[..]);
+                // value, aligned within the mmap-file (mapped at page boundaries).
+                // Space for the largest padding is used whatever the offset, so that
+                // the size of values does not change when they are copied.
+                let alignment = vector::alignment(value.field_type);
+                let data_offset = aligned_offset(write_offset + x, alignment);
+                mmap[write_offset + x..data_offset].fill(0);
+                mmap[data_offset..data_offset + value_len].copy_from_slice(&value.data[..]);
 
-                x + value_len
+                x + (alignment - 1) + value_len
             }
         };
 
@@ -493,11 +1458,13 @@ impl CKVIndexSegment {
     }
 
     /// Upsert field values for a document.
+    /// Values are stored as per the corresponding entry in `options`.
     pub fn upsert_document<T>(
         &mut self,
         primary_key: &[u8],
         field_ids: &[FieldId],
         field_values: &[T],
+        options: &[ValueOptions],
     ) -> anyhow::Result<()>
     where
         T: Borrow<FieldValue>,
@@ -506,6 +1473,28 @@ impl CKVIndexSegment {
             return Ok(());
         }
 
+        let mut values = Vec::with_capacity(field_values.len());
+        for (field_value, options) in field_values.iter().zip(options.iter()) {
+            values.push(MmapValue::encode(field_value.borrow(), options)?);
+        }
+        if values.len() != field_ids.len() {
+            bail!("Upsert requires one value and one set of options per field");
+        }
+
+        self.upsert_mmap_values(primary_key, field_ids, &values)
+    }
+
+    /// Upsert encoded values (as stored in mmap) for a document.
+    fn upsert_mmap_values(
+        &mut self,
+        primary_key: &[u8],
+        field_ids: &[FieldId],
+        values: &[MmapValue],
+    ) -> anyhow::Result<()> {
+        if primary_key.is_empty() || field_ids.is_empty() {
+            return Ok(());
+        }
+
         // Unknown field types (i.e. FieldType::UNKNOWN) handling.
         // They can occur when we are behind on symbol list or upstream ingestion path didn't propelry construct the write event
         // We save a sentinel which is 2 bytes for the field-type (=0), and no additonal data.
@@ -513,18 +1502,14 @@ impl CKVIndexSegment {
         //
         // TODO: we should have cfg flags to block kafka stream for such events (catch compaction errors earlier).
 
-        // mmap resizing?
-        let mmap;
-        {
-            // mmap instantiation
-            let mut mmap_entry_size = 0;
-            for field_value in field_values.iter().map(|fv| fv.borrow()) {
-                mmap_entry_size += Self::size_of_mmap_entry(field_value)?;
-            }
+        if let Some(value_cache) = self.value_cache.as_ref() {
+            value_cache.invalidate_fields(primary_key, field_ids);
+        }
 
-            // mmap instantiation
-            self.expand_mmap_if_required(self.write_offset, mmap_entry_size)?;
-            mmap = self.mmap.deref_mut();
+        // write values to mmap (or column) files
+        let mut value_offsets = Vec::with_capacity(field_ids.len());
+        for (field_id, value) in field_ids.iter().zip(values.iter()) {
+            value_offsets.push(self.append_value(*field_id, value)?);
         }
 
         // propagate to disk (OffsetTableEntry.proto)
@@ -535,27 +1520,16 @@ impl CKVIndexSegment {
 
         let offsets = self.offset_table.entry(primary_key.to_vec()).or_default();
 
-        for i in 0..field_ids.len() {
-            let field_id = field_ids[i];
-            let field_value = field_values[i].borrow();
-
-            let write_offset = self.write_offset;
-
-            // write value to mmap
-            let num_bytes = Self::write_to_mmap(mmap, write_offset as usize, field_value)?;
-
+        for (field_id, offset) in field_ids.iter().copied().zip(value_offsets) {
             // write to in-memory index
             if field_id >= offsets.len() as u32 {
                 offsets.resize(field_id as usize + 1, usize::MAX);
             }
-            offsets[field_id as usize] = write_offset as usize;
+            offsets[field_id as usize] = offset;
 
             // propagate to disk (OffsetTableEntry.proto)
             update_doc_fields.field_ids.push(field_id);
-            update_doc_fields.offsets.push(write_offset as u64);
-
-            // update global offset
-            self.write_offset += num_bytes as u64;
+            update_doc_fields.offsets.push(offset as u64);
         }
 
         // propagate to disk (OffsetTableEntry.proto)
@@ -567,11 +1541,46 @@ impl CKVIndexSegment {
         );
         self.persist_offset_table_update(offset_table_entry)?;
 
+        self.update_ann_indexes(primary_key, field_ids, values)?;
         Ok(())
     }
 
-    fn is_unknown_field_type(value: &FieldValue) -> bool {
-        return value.fieldType.enum_value_or_default() == FieldType::UNKNOWN;
+    /// Appends a value to the mmap file, or to the column file of column-group fields.
+    /// Returns its offset, tagged for column files.
+    fn append_value(&mut self, field_id: FieldId, value: &MmapValue) -> anyhow::Result<usize> {
+        let num_bytes = Self::size_of_mmap_entry(value)?;
+
+        if !self.column_field_ids.contains(&field_id) {
+            let write_offset = self.write_offset;
+            if write_offset + num_bytes as u64 > MAX_PAYLOAD_FILE_SIZE {
+                bail!(
+                    "mmap file of segment: {} cannot exceed 1TB",
+                    self.mount_directory
+                );
+            }
+            self.expand_mmap_if_required(write_offset, num_bytes)?;
+            Self::write_to_mmap(&mut self.mmap, write_offset as usize, value)?;
+            self.write_offset += num_bytes as u64;
+            return Ok(write_offset as usize);
+        }
+
+        let column = match self.columns.entry(field_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                entry.insert(ColumnFile::create(&self.mount_directory, field_id)?)
+            }
+        };
+        let write_offset = column.write_offset;
+        if write_offset + num_bytes as u64 > MAX_PAYLOAD_FILE_SIZE {
+            bail!("column file of field id: {} cannot exceed 1TB", field_id);
+        }
+        if expand_payload_if_required(&mut column.file, &mut column.mmap, write_offset, num_bytes)?
+        {
+            advise_payload(&column.mmap, self.page_cache_policy, &self.mount_directory);
+        }
+        Self::write_to_mmap(&mut column.mmap, write_offset as usize, value)?;
+        column.write_offset += num_bytes as u64;
+        Ok(column_offset(field_id, write_offset))
     }
 
     /// Delete field values for a document.
@@ -599,6 +1608,18 @@ impl CKVIndexSegment {
         );
         self.persist_offset_table_update(offset_table_entry)?;
 
+        if let Some(value_cache) = self.value_cache.as_ref() {
+            value_cache.invalidate_fields(primary_key, field_ids);
+        }
+
+        for field_id in field_ids.iter() {
+            if let Some(ann_index) = self.ann_indexes.get_mut(field_id) {
+                ann_index.delete(primary_key);
+                self.ann_log_entries
+                    .push(ann::delete_log_entry(*field_id, primary_key));
+            }
+        }
+
         // remove from in-memory offset_table
         let maybe_offsets = self.offset_table.get_mut(primary_key);
         if maybe_offsets.is_none() {
@@ -615,6 +1636,113 @@ impl CKVIndexSegment {
         Ok(())
     }
 
+    /// Value of a numeric field after adding `delta`, along with the storage options
+    /// of the current value (expiry and write timestamp are kept).
+    /// None if the field has no (live) value to increment.
+    pub fn incremented_value(
+        &self,
+        primary_key: &[u8],
+        field_id: FieldId,
+        delta: &FieldValue,
+    ) -> anyhow::Result<Option<(FieldValue, ValueOptions)>> {
+        let value = match self
+            .offset_table
+            .get(primary_key)
+            .and_then(|offsets| offsets.get(field_id as usize).copied())
+            .and_then(|offset| self.read_from_mmap(offset))
+        {
+            Some(value) if value.is_readable() => value,
+            _ => return Ok(None),
+        };
+
+        if value.field_type != delta.fieldType.enum_value_or_default() {
+            bail!(
+                "Cannot increment field of type: {:?} with delta of type: {:?}",
+                value.field_type,
+                delta.fieldType.enum_value_or_default()
+            );
+        }
+
+        let mut new_value = FieldValue::new();
+        new_value.fieldType = delta.fieldType;
+        new_value.value = add_numeric(value.field_type, &value.data, &delta.value)?;
+        let options = ValueOptions {
+            expires_at_millis: value.expires_at_millis,
+            timestamp_millis: value.timestamp_millis,
+            ..Default::default()
+        };
+        Ok(Some((new_value, options)))
+    }
+
+    /// List field value after appending elements (an encoded list), keeping the last
+    /// `max_length` elements (unbounded if 0). Missing values are initialized to the
+    /// appended elements.
+    pub fn appended_value(
+        &self,
+        primary_key: &[u8],
+        field_id: FieldId,
+        elements: &FieldValue,
+        max_length: usize,
+    ) -> anyhow::Result<FieldValue> {
+        let field_type = elements.fieldType.enum_value_or_default();
+        if !list::is_list_type(field_type) {
+            bail!("Cannot append to field of type: {:?}", field_type);
+        }
+
+        let existing = self
+            .offset_table
+            .get(primary_key)
+            .and_then(|offsets| offsets.get(field_id as usize).copied())
+            .and_then(|offset| self.read_from_mmap(offset))
+            .filter(|value| value.is_readable());
+
+        let mut list_value = FieldValue::new();
+        list_value.fieldType = elements.fieldType;
+        list_value.value = match existing {
+            None => list::append(field_type, &[], &elements.value, max_length)?,
+            Some(value) => {
+                if value.field_type != field_type {
+                    bail!(
+                        "Cannot append elements of type: {:?} to field of type: {:?}",
+                        field_type,
+                        value.field_type
+                    );
+                }
+                let data = value
+                    .decompress()
+                    .ok_or(anyhow!("Cannot decompress list field value"))?;
+                list::append(field_type, &data, &elements.value, max_length)?
+            }
+        };
+        Ok(list_value)
+    }
+
+    /// Write timestamp (epoch millis) saved with a field value or its delete,
+    /// 0 if the field has none.
+    pub fn write_timestamp_millis(&self, primary_key: &[u8], field_id: FieldId) -> u64 {
+        self.offset_table
+            .get(primary_key)
+            .and_then(|offsets| offsets.get(field_id as usize).copied())
+            .and_then(|offset| self.read_from_mmap(offset))
+            .map(|value| value.timestamp_millis)
+            .unwrap_or(0)
+    }
+
+    /// Delete field values for a document, remembering the timestamp of the delete
+    /// (for last-writer-wins) instead of dropping the values from the offset table.
+    pub fn tombstone_field_values(
+        &mut self,
+        primary_key: &[u8],
+        field_ids: &[FieldId],
+        timestamp_millis: u64,
+    ) -> anyhow::Result<()> {
+        let values: Vec<MmapValue> = field_ids
+            .iter()
+            .map(|_| MmapValue::tombstone(timestamp_millis))
+            .collect();
+        self.upsert_mmap_values(primary_key, field_ids, &values)
+    }
+
     /// Delete document (soft delete).
     pub fn delete_document(&mut self, primary_key: &[u8]) -> io::Result<()> {
         if primary_key.is_empty() {
@@ -630,6 +1758,16 @@ impl CKVIndexSegment {
         );
         self.persist_offset_table_update(offset_table_entry)?;
 
+        for (field_id, ann_index) in self.ann_indexes.iter_mut() {
+            ann_index.delete(primary_key);
+            self.ann_log_entries
+                .push(ann::delete_log_entry(*field_id, primary_key));
+        }
+
+        if let Some(value_cache) = self.value_cache.as_ref() {
+            value_cache.invalidate_document(primary_key);
+        }
+
         // remove from in-memory offset_table
         self.offset_table.remove(primary_key);
         Ok(())
@@ -644,74 +1782,879 @@ impl CKVIndexSegment {
         self.offset_table_file_writer.flush()?;
         self.offset_table_file_writer.get_ref().set_len(0)?;
         self.offset_table_file_writer.rewind()?;
-        self.offset_table = HashMap::new();
+        let offset_table = std::mem::take(&mut self.offset_table);
+        if let Some(written_keys) = self.written_keys.as_mut() {
+            written_keys.extend(offset_table.into_keys());
+        }
+        if let Some(value_cache) = self.value_cache.as_ref() {
+            value_cache.clear();
+        }
+
+        let snapshot_filename = format!("{}/offset_table_snapshot", &self.mount_directory);
+        if Path::new(&snapshot_filename).exists() {
+            std::fs::remove_file(&snapshot_filename)?;
+        }
+        self.offset_table_snapshot_size = 0;
 
         // clear mmap
         self.mmap_file.set_len(0)?;
         self.mmap_file.rewind()?;
         self.mmap = unsafe { MmapMut::map_mut(&self.mmap_file)? };
+        self.apply_page_cache_policy();
         self.write_offset = 0;
-        write_metadata(&mut self.metadata_file_writer, 0u64)?;
+        for field_id in std::mem::take(&mut self.columns).into_keys() {
+            std::fs::remove_file(column_filename(&self.mount_directory, field_id))?;
+        }
+        write_metadata(
+            &self.metadata_file,
+            &WriteOffsets::default(),
+            &self.applied_event_offsets,
+            self.offset_table_format_version,
+        )?;
+
+        for ann_index in self.ann_indexes.values_mut() {
+            ann_index.clear();
+        }
+        self.save_ann_indexes()?;
 
         Ok(())
     }
 
-    fn persist_offset_table_update(&mut self, entry: OffsetTableEntry) -> io::Result<()> {
-        let bytes = entry.write_to_bytes()?;
-        let size = bytes.len() as i32;
-        self.offset_table_file_writer
-            .write_all(&size.to_le_bytes())?;
-        self.offset_table_file_writer.write_all(&bytes)?;
+    /// Maintain an approximate nearest-neighbor index over vectors of a field.
+    /// The index is loaded from disk if it is up to date, else (re)built from stored values.
+    pub fn enable_ann_index(
+        &mut self,
+        field_id: FieldId,
+        field_name: &str,
+        metric: AnnMetric,
+    ) -> anyhow::Result<()> {
+        if let Some(ann_index) = self.ann_indexes.get(&field_id) {
+            if ann_index.field_name() == field_name && ann_index.metric() == metric {
+                return Ok(());
+            }
+        }
+
+        let ann_index = match self.load_ann_index(field_id, field_name, metric) {
+            Ok(Some(ann_index)) => ann_index,
+            Ok(None) => self.build_ann_index(field_id, field_name, metric)?,
+            Err(e) => {
+                warn!(
+                    "Cannot load ann index of field: {} in segment: {}, rebuilding. Error: {}",
+                    field_name, &self.mount_directory, e
+                );
+                self.build_ann_index(field_id, field_name, metric)?
+            }
+        };
+
+        self.ann_indexes.insert(field_id, ann_index);
+        Ok(())
+    }
+
+    /// Approximate `k` nearest neighbors of `query` among values of a field,
+    /// as (primary-key, distance) closest first. Empty if the field has no ann index.
+    pub fn ann_search(
+        &self,
+        field_id: FieldId,
+        query: &[f32],
+        k: usize,
+    ) -> anyhow::Result<Vec<(Vec<u8>, f32)>> {
+        let ann_index = match self.ann_indexes.get(&field_id) {
+            None => return Ok(vec![]),
+            Some(ann_index) => ann_index,
+        };
+
+        let mut result = Vec::with_capacity(k);
+        for (primary_key, distance) in ann_index.search(query, k)? {
+            if result.len() == k {
+                break;
+            }
+
+            // values can expire after being indexed
+            let readable = self
+                .offset_table
+                .get(primary_key)
+                .and_then(|offsets| offsets.get(field_id as usize).copied())
+                .and_then(|offset| self.read_from_mmap(offset))
+                .is_some_and(|value| value.is_readable());
+            if readable {
+                result.push((primary_key.to_vec(), distance));
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Moves ann indexes from `other`, which holds the same documents
+    /// (ex. when swapping in an online compacted segment).
+    pub fn take_ann_indexes(&mut self, other: &mut CKVIndexSegment) {
+        self.ann_indexes = std::mem::take(&mut other.ann_indexes);
+        self.ann_log_entries.clear();
+        self.ann_indexes_save_needed = true;
+    }
+
+    fn update_ann_indexes(
+        &mut self,
+        primary_key: &[u8],
+        field_ids: &[FieldId],
+        values: &[MmapValue],
+    ) -> anyhow::Result<()> {
+        if self.ann_indexes.is_empty() {
+            return Ok(());
+        }
+
+        for (field_id, value) in field_ids.iter().zip(values.iter()) {
+            if let Some(ann_index) = self.ann_indexes.get_mut(field_id) {
+                // vectors are never compressed
+                if value.is_readable() && vector::is_vector_type(value.field_type) {
+                    let vector = vector::to_f32_elements(value.field_type, &value.data)?;
+                    self.ann_log_entries.push(ann::upsert_log_entry(
+                        *field_id,
+                        primary_key,
+                        &vector,
+                    ));
+                    ann_index.insert(primary_key, vector);
+                } else {
+                    ann_index.delete(primary_key);
+                    self.ann_log_entries
+                        .push(ann::delete_log_entry(*field_id, primary_key));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load_ann_index(
+        &mut self,
+        field_id: FieldId,
+        field_name: &str,
+        metric: AnnMetric,
+    ) -> anyhow::Result<Option<HnswIndex>> {
+        let contents = match MetadataFile::new(&self.mount_directory, "ann_indexes").read()? {
+            None => return Ok(None),
+            Some(contents) => contents,
+        };
+
+        let saved_ann_indexes = SavedAnnIndexes::parse_from_bytes(&contents)?;
+        let saved_ann_index = match saved_ann_indexes.indexes.iter().find(|saved_ann_index| {
+            saved_ann_index.field_id == field_id
+                && saved_ann_index.field_name == field_name
+                && saved_ann_index.metric.enum_value_or_default() == metric
+        }) {
+            None => return Ok(None),
+            Some(saved_ann_index) => saved_ann_index,
+        };
+
+        // apply changes made after the save
+        let mut ann_index = HnswIndex::from_saved(saved_ann_index)?;
+        let mmap_write_offset = ann::replay_log(&self.mount_directory, |entry| {
+            ann_index.apply_log_entry(entry)
+        })?
+        .unwrap_or(saved_ann_indexes.mmap_write_offset);
+        if mmap_write_offset != self.used_bytes() {
+            // written to after the last save or log commit
+            return Ok(None);
+        }
+
+        self.ann_indexes_saved_size = contents.len() as u64;
+        Ok(Some(ann_index))
+    }
+
+    fn build_ann_index(
+        &mut self,
+        field_id: FieldId,
+        field_name: &str,
+        metric: AnnMetric,
+    ) -> anyhow::Result<HnswIndex> {
+        let mut ann_index = HnswIndex::new(field_id, field_name, metric);
+        for (primary_key, offsets) in self.offset_table.iter() {
+            let maybe_value = offsets
+                .get(field_id as usize)
+                .copied()
+                .and_then(|offset| self.read_from_mmap(offset))
+                .filter(|value| value.is_readable() && vector::is_vector_type(value.field_type));
+            if let Some(value) = maybe_value {
+                let vector = vector::to_f32_elements(value.field_type, &value.data)?;
+                ann_index.insert(primary_key, vector);
+            }
+        }
+
+        info!(
+            "Built ann index of field: {} in segment: {}",
+            field_name, &self.mount_directory
+        );
+        self.ann_indexes_save_needed = true;
+        Ok(ann_index)
+    }
+
+    /// Saves ann indexes in full along with the current mmap write offset,
+    /// which must be persisted (see flush_writes()).
+    fn save_ann_indexes(&mut self) -> io::Result<()> {
+        // the log only holds changes after the save, a crash before the save completes
+        // leaves the previous save without its changes, which is rebuilt on load
+        ann::remove_log(&self.mount_directory)?;
+        self.ann_log_entries.clear();
+
+        let mut saved_ann_indexes = SavedAnnIndexes::new();
+        saved_ann_indexes.mmap_write_offset = self.used_bytes();
+        for ann_index in self.ann_indexes.values_mut() {
+            if ann_index.needs_rebuild() {
+                ann_index.rebuild();
+            }
+            saved_ann_indexes.indexes.push(ann_index.to_saved());
+        }
+
+        let contents = saved_ann_indexes.write_to_bytes()?;
+        MetadataFile::new(&self.mount_directory, "ann_indexes").write(&contents)?;
 
+        self.ann_indexes_saved_size = contents.len() as u64;
+        self.ann_indexes_save_needed = false;
         Ok(())
     }
 
-    // See: https://stackoverflow.com/questions/28516996/how-to-create-and-write-to-memory-mapped-files
+    fn persist_offset_table_update(&mut self, entry: OffsetTableEntry) -> io::Result<()> {
+        if let (Some(written_keys), Some(operation)) =
+            (self.written_keys.as_mut(), entry.operation.as_ref())
+        {
+            let primary_key = match operation {
+                offset_table_entry::Operation::UpdateDocFields(e) => &e.primary_key,
+                offset_table_entry::Operation::DeleteDocFields(e) => &e.primary_key,
+                offset_table_entry::Operation::DeleteDoc(e) => &e.primary_key,
+            };
+            written_keys.insert(primary_key.clone());
+        }
+        write_offset_table_entry(
+            &mut self.offset_table_file_writer,
+            &entry,
+            self.offset_table_format_version,
+        )
+    }
+
     fn expand_mmap_if_required(
         &mut self,
         write_offset: u64,
         num_bytes_to_write: usize,
     ) -> io::Result<()> {
-        let end_offset = write_offset as usize + num_bytes_to_write; // non-inclusive
-                                                                     // space [write_offset, end_offset) should be available
-
-        if self.mmap.len() >= end_offset {
-            return Ok(());
+        if expand_payload_if_required(
+            &mut self.mmap_file,
+            &mut self.mmap,
+            write_offset,
+            num_bytes_to_write,
+        )? {
+            self.apply_page_cache_policy();
         }
+        Ok(())
+    }
+}
 
-        let num_chunks =
-            (1.0 + ((end_offset - self.mmap.len()) as f64 / CHUNK_SIZE as f64)) as usize;
-        assert!(num_chunks >= 1);
+// See: https://stackoverflow.com/questions/28516996/how-to-create-and-write-to-memory-mapped-files
+/// Extends a payload (mmap or column) file by whole chunks and re-maps it,
+/// if [write_offset, write_offset + num_bytes_to_write) does not fit.
+/// Returns true if the file was re-mapped.
+fn expand_payload_if_required(
+    file: &mut File,
+    mmap: &mut MmapMut,
+    write_offset: u64,
+    num_bytes_to_write: usize,
+) -> io::Result<bool> {
+    let end_offset = write_offset as usize + num_bytes_to_write; // non-inclusive
+                                                                 // space [write_offset, end_offset) should be available
 
-        debug!(
-            "Need to resize the mmap. curr_len: {} write_offset: {} end_offset: {} num_chunks: {}",
-            self.mmap.len(),
-            write_offset,
-            end_offset,
-            num_chunks
+    if mmap.len() >= end_offset {
+        return Ok(false);
+    }
+
+    let num_chunks = (1.0 + ((end_offset - mmap.len()) as f64 / CHUNK_SIZE as f64)) as usize;
+    assert!(num_chunks >= 1);
+
+    debug!(
+        "Need to resize the mmap. curr_len: {} write_offset: {} end_offset: {} num_chunks: {}",
+        mmap.len(),
+        write_offset,
+        end_offset,
+        num_chunks
+    );
+
+    // expand (and flush) underlying file
+    file.write_all(&vec![0_u8; CHUNK_SIZE * num_chunks])?;
+    file.flush()?;
+
+    // re-map the whole file
+    *mmap = unsafe { MmapMut::map_mut(&*file)? };
+    Ok(true)
+}
+
+/// Applies madvise/mlock settings to a payload (mmap or column) file mapping.
+/// Failures are logged and ignored, the policy only affects performance.
+fn advise_payload(mmap: &MmapMut, policy: PageCachePolicy, segment_mount_directory: &str) {
+    if policy == PageCachePolicy::default() || mmap.is_empty() {
+        return;
+    }
+
+    let mut advices = vec![];
+    if policy.random {
+        advices.push(Advice::Random);
+    }
+    if policy.will_need {
+        advices.push(Advice::WillNeed);
+    }
+    if policy.huge_pages {
+        #[cfg(target_os = "linux")]
+        advices.push(Advice::HugePage);
+        #[cfg(not(target_os = "linux"))]
+        warn!(
+            "Huge pages are only supported on linux, ignoring for segment: {}",
+            segment_mount_directory
         );
+    }
+    for advice in advices {
+        if let Err(e) = mmap.advise(advice) {
+            warn!(
+                "Cannot apply {:?} advice to segment: {}, error: {}",
+                advice, segment_mount_directory, e
+            );
+        }
+    }
 
-        // expand (and flush) underlying file
-        self.mmap_file
-            .write_all(&vec![0_u8; CHUNK_SIZE * num_chunks])?;
-        self.mmap_file.flush()?;
+    if policy.lock {
+        if let Err(e) = mmap.lock() {
+            warn!(
+                "Cannot lock segment: {} in memory, error: {}",
+                segment_mount_directory, e
+            );
+        }
+    }
+}
 
-        // re-map the whole file
-        self.mmap = unsafe { MmapMut::map_mut(&self.mmap_file)? };
-        Ok(())
+/// Offset of a value in the column file of a field, see COLUMN_TAG_SHIFT.
+fn column_offset(field_id: FieldId, offset: u64) -> usize {
+    (((field_id as u64 + 1) << COLUMN_TAG_SHIFT) | offset) as usize
+}
+
+/// Splits an offset from the offset table into the field id of its column file
+/// (None for the mmap file) and the offset within that file.
+fn split_offset(offset: usize) -> (Option<FieldId>, usize) {
+    let tag = offset as u64 >> COLUMN_TAG_SHIFT;
+    if tag == 0 {
+        return (None, offset);
     }
+    let offset = offset as u64 & (MAX_PAYLOAD_FILE_SIZE - 1);
+    (Some((tag - 1) as FieldId), offset as usize)
 }
 
-fn write_metadata(writer: &mut BufWriter<File>, write_offset: u64) -> io::Result<()> {
-    let mut metadata = CKVIndexSegmentMetadata::new();
-    metadata.mmap_write_offset = write_offset;
-    let bytes = metadata.write_to_bytes()?;
-    writer.rewind()?;
+// Flags saved in the high byte of a value's mmap header.
+// Value data is LZ4 compressed (with prepended uncompressed size).
+const VALUE_FLAG_LZ4_COMPRESSED: u8 = 0x01;
+// Value is prefixed with its expiry, u64 epoch millis.
+const VALUE_FLAG_HAS_EXPIRY: u8 = 0x02;
+// Value is prefixed with its write (source) timestamp, u64 epoch millis.
+const VALUE_FLAG_HAS_TIMESTAMP: u8 = 0x04;
+// Deleted value (no data), kept to remember the delete's timestamp.
+const VALUE_FLAG_TOMBSTONE: u8 = 0x08;
+const SUPPORTED_VALUE_FLAGS: u8 = VALUE_FLAG_LZ4_COMPRESSED
+    | VALUE_FLAG_HAS_EXPIRY
+    | VALUE_FLAG_HAS_TIMESTAMP
+    | VALUE_FLAG_TOMBSTONE;
+
+/// Storage options for a field value being written.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ValueOptions {
+    pub codec: CompressionCodec,
+
+    // epoch millis after which the value is treated as missing, 0 for no expiry
+    pub expires_at_millis: u64,
+
+    // write timestamp (epoch millis) saved with the value, 0 to not save one
+    pub timestamp_millis: u64,
+}
+
+/// A field value as stored in the mmap file.
+#[derive(Debug)]
+struct MmapValue<'a> {
+    field_type: FieldType,
+    flags: u8,
+    expires_at_millis: u64,
+    timestamp_millis: u64,
+    data: Cow<'a, [u8]>,
+}
+
+impl<'a> MmapValue<'a> {
+    /// Encodes a field value for storage, compressing it with the provided
+    /// codec if that makes it smaller.
+    fn encode(field_value: &'a FieldValue, options: &ValueOptions) -> anyhow::Result<Self> {
+        if field_value.fieldType.value() > u8::MAX as i32 {
+            bail!("Cannot store field type in 1 byte");
+        }
+
+        let field_type = field_value.fieldType.enum_value_or_default();
+        if field_type == FieldType::UNKNOWN {
+            return Ok(MmapValue {
+                field_type,
+                flags: 0,
+                expires_at_millis: 0,
+                timestamp_millis: 0,
+                data: Cow::Borrowed(EMPTY_BYTE_SLICE),
+            });
+        }
+
+        let mut value = MmapValue {
+            field_type,
+            flags: 0,
+            expires_at_millis: options.expires_at_millis,
+            timestamp_millis: options.timestamp_millis,
+            data: Cow::Borrowed(&field_value.value),
+        };
+        if options.expires_at_millis != 0 {
+            value.flags |= VALUE_FLAG_HAS_EXPIRY;
+        }
+        if options.timestamp_millis != 0 {
+            value.flags |= VALUE_FLAG_HAS_TIMESTAMP;
+        }
+
+        if list::is_list_type(field_type) {
+            list::validate(field_type, &field_value.value)?;
+        }
+
+        if options.codec == CompressionCodec::LZ4
+            && (field_type == FieldType::STRING
+                || field_type == FieldType::BYTES
+                || list::is_list_type(field_type))
+        {
+            let compressed = lz4_flex::compress_prepend_size(&field_value.value);
+            if compressed.len() < field_value.value.len() {
+                value.flags |= VALUE_FLAG_LZ4_COMPRESSED;
+                value.data = Cow::Owned(compressed);
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Marker for a value deleted at the provided timestamp.
+    fn tombstone(timestamp_millis: u64) -> Self {
+        MmapValue {
+            field_type: FieldType::UNKNOWN,
+            flags: VALUE_FLAG_TOMBSTONE | VALUE_FLAG_HAS_TIMESTAMP,
+            expires_at_millis: 0,
+            timestamp_millis,
+            data: Cow::Borrowed(EMPTY_BYTE_SLICE),
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.flags & VALUE_FLAG_HAS_EXPIRY != 0 && self.expires_at_millis <= now_millis()
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.flags & VALUE_FLAG_TOMBSTONE != 0
+    }
+
+    /// Can be returned to readers (not unknown, expired or deleted).
+    fn is_readable(&self) -> bool {
+        self.field_type != FieldType::UNKNOWN && !self.is_tombstone() && !self.is_expired()
+    }
+
+    /// Length of the original value bytes, without decompressing them.
+    fn value_len(&self) -> usize {
+        if self.flags & VALUE_FLAG_LZ4_COMPRESSED == 0 {
+            return self.data.len();
+        }
+
+        // see lz4_flex::compress_prepend_size()
+        match self.data.get(..4) {
+            Some(size) => u32::from_le_bytes(size.try_into().unwrap()) as usize,
+            None => 0,
+        }
+    }
+
+    /// Original value bytes, None if they cannot be decompressed.
+    fn decompress(self) -> Option<Cow<'a, [u8]>> {
+        if self.flags & VALUE_FLAG_LZ4_COMPRESSED == 0 {
+            return Some(self.data);
+        }
+
+        match lz4_flex::decompress_size_prepended(&self.data) {
+            Ok(value) => Some(Cow::Owned(value)),
+            Err(e) => {
+                warn!("Cannot decompress LZ4 value, error: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Pushes a size prefixed value at the end of `dest`, see CKVIndexSegment::read_fields().
+/// List values are trimmed to their last `last_k` elements, if provided.
+fn extend_with_value(
+    dest: &mut Vec<u8>,
+    field_type: FieldType,
+    data: &[u8],
+    last_k: Option<usize>,
+) {
+    let value = match last_k {
+        Some(k) if list::is_list_type(field_type) => {
+            list::last_elements(field_type, data, k).unwrap_or(data)
+        }
+        _ => data,
+    };
+    dest.extend((value.len() as i32).to_le_bytes());
+    dest.extend_from_slice(value);
+}
+
+/// Decodes the value at `mmap_offset`, errors if it has an unknown type or flags,
+/// or does not fit in `mmap`.
+fn decode_mmap_value(mmap: &[u8], mmap_offset: usize) -> anyhow::Result<MmapValue<'_>> {
+    // mmap_offset points to a bytes section where:
+    // [1 byte for field-type][1 byte for value flags]
+    // [8 bytes expiry, if flagged][8 bytes write timestamp, if flagged][data]
+    // where data can be prefixed with vbytes for variable length types
+    let slice = |from: usize, len: usize| {
+        from.checked_add(len)
+            .and_then(|to| mmap.get(from..to))
+            .ok_or(anyhow!(
+                "value at offset: {} extends past end of mmap",
+                mmap_offset
+            ))
+    };
+
+    let header: u16 = u16::from_le_bytes(slice(mmap_offset, 2)?.try_into()?);
+    let field_type: FieldType = FieldType::from_i32(i32::from(header & 0xFF))
+        .ok_or(anyhow!("unknown field type tag: {}", header & 0xFF))?;
+    let flags = (header >> 8) as u8;
+    if flags & !SUPPORTED_VALUE_FLAGS != 0 {
+        // written by a newer version, layout is unknown
+        bail!("unsupported value flags: {:#x}", flags);
+    }
+
+    let mut mmap_offset = mmap_offset + 2;
+    let mut expires_at_millis = 0;
+    if flags & VALUE_FLAG_HAS_EXPIRY != 0 {
+        expires_at_millis = u64::from_le_bytes(slice(mmap_offset, 8)?.try_into()?);
+        mmap_offset += 8;
+    }
+    let mut timestamp_millis = 0;
+    if flags & VALUE_FLAG_HAS_TIMESTAMP != 0 {
+        timestamp_millis = u64::from_le_bytes(slice(mmap_offset, 8)?.try_into()?);
+        mmap_offset += 8;
+    }
+
+    let data = match field_type {
+        FieldType::UNKNOWN => {
+            // Some unknown field-type was written to the mmap files
+            // can occur when this reader is behind on the FieldType.proto symbol list.
+            // Can be okay to ignore this when doing live reads on the index, but this
+            // should not be ignored during data copy (ex. compaction).
+            EMPTY_BYTE_SLICE
+        }
+        FieldType::INT32 | FieldType::FLOAT32 => slice(mmap_offset, 4)?,
+        FieldType::INT64 | FieldType::FLOAT64 => slice(mmap_offset, 8)?,
+        FieldType::BOOLEAN => slice(mmap_offset, 1)?,
+        FieldType::STRING
+        | FieldType::BYTES
+        | FieldType::INT32_LIST
+        | FieldType::INT64_LIST
+        | FieldType::FLOAT32_LIST
+        | FieldType::FLOAT64_LIST
+        | FieldType::STRING_LIST
+        | FieldType::BYTES_LIST
+        | FieldType::FLOAT32_VECTOR
+        | FieldType::FLOAT16_VECTOR
+        | FieldType::INT8_VECTOR => {
+            // extract size (varint decoding)
+            let (size, bytes_read) = mmap
+                .get(mmap_offset..)
+                .and_then(u32::decode_var)
+                .ok_or(anyhow!("truncated value length"))?;
+            let alignment = vector::alignment(field_type);
+            slice(
+                aligned_offset(mmap_offset + bytes_read, alignment),
+                size as usize,
+            )?
+        }
+    };
+
+    Ok(MmapValue {
+        field_type,
+        flags,
+        expires_at_millis,
+        timestamp_millis,
+        data: Cow::Borrowed(data),
+    })
+}
+
+/// Smallest offset at or after `offset` which is a multiple of `alignment`.
+fn aligned_offset(offset: usize, alignment: usize) -> usize {
+    offset.div_ceil(alignment) * alignment
+}
+
+/// Checks that the value at `mmap_offset` decodes and fits in `mmap`,
+/// including its decompressed data and list elements. Returns the end offset of the value.
+fn verify_mmap_value(mmap: &[u8], mmap_offset: usize) -> anyhow::Result<usize> {
+    let value = decode_mmap_value(mmap, mmap_offset)?;
+    let field_type = value.field_type;
+    let end_offset = mmap_offset + CKVIndexSegment::size_of_mmap_entry(&value)?;
+
+    let data = if value.flags & VALUE_FLAG_LZ4_COMPRESSED != 0 {
+        Cow::Owned(lz4_flex::decompress_size_prepended(&value.data)?)
+    } else {
+        value.data
+    };
+    if list::is_list_type(field_type) {
+        list::validate(field_type, &data)?;
+    }
+
+    Ok(end_offset)
+}
+
+/// Applies offset table entries from an offset table log (or snapshot) to `offset_table`.
+///
+/// Replay stops at the first entry which is partially written, fails its checksum,
+/// cannot be deserialized, or points past `write_offsets` in the mmap or column files
+/// (written out before the metadata file was last persisted). Returns the length of
+/// the valid prefix of the log.
+fn replay_offset_table(
+    offset_table_file: &File,
+    format_version: u32,
+    write_offsets: &WriteOffsets,
+    offset_table: &mut OffsetTable,
+) -> anyhow::Result<u64> {
+    let mut reader = BufReader::new(offset_table_file);
+    reader.rewind()?;
+
+    // length of log replayed so far, i.e. ending at the last good entry
+    let mut valid_len: u64 = 0;
+    let header_size: u64 = if format_version >= 1 { 8 } else { 4 };
+
+    let mut entry_buffer = vec![];
+    loop {
+        // read size (and checksum) of serialized `OffsetTableEntry.proto`
+        let mut entry_header_buffer = [0u8; 8];
+        if let Err(e) = reader.read_exact(&mut entry_header_buffer[..header_size as usize]) {
+            match e.kind() {
+                // EOF reached, or torn entry header
+                ErrorKind::UnexpectedEof => break,
+                _ => return Err(e.into()),
+            }
+        }
+        let entry_size = i32::from_le_bytes(entry_header_buffer[..4].try_into().unwrap());
+        if entry_size < 0 {
+            warn!("Found offset table entry with negative size: {}", entry_size);
+            break;
+        }
+        let entry_size = entry_size as usize;
+
+        // deserialize `OffsetTableEntry.proto` into offset_table
+        if entry_buffer.len() < entry_size {
+            entry_buffer.resize(entry_size, 0u8);
+        }
+        if let Err(e) = reader.read_exact(&mut entry_buffer[..entry_size]) {
+            match e.kind() {
+                // torn entry
+                ErrorKind::UnexpectedEof => break,
+                _ => return Err(e.into()),
+            }
+        }
+        let entry_bytes = &entry_buffer[..entry_size];
+
+        if format_version >= 1 {
+            let checksum = u32::from_le_bytes(entry_header_buffer[4..8].try_into().unwrap());
+            if checksum != crc32fast::hash(entry_bytes) {
+                warn!("Found offset table entry with checksum mismatch");
+                break;
+            }
+        }
+
+        let entry = match OffsetTableEntry::parse_from_bytes(entry_bytes) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Cannot deserialize offset table entry, error: {}", e);
+                break;
+            }
+        };
+
+        if let Some(operation) = entry.operation {
+            match operation {
+                offset_table_entry::Operation::UpdateDocFields(e) => {
+                    if e.field_ids.len() != e.offsets.len()
+                        || e.offsets
+                            .iter()
+                            .any(|offset| !write_offsets.contains(*offset))
+                    {
+                        warn!("Found offset table entry pointing past mmap write offset");
+                        break;
+                    }
+
+                    let primary_key = &e.primary_key;
+                    let offsets: &mut Vec<usize> =
+                        offset_table.entry(primary_key.clone()).or_default();
+                    for i in 0..e.field_ids.len() {
+                        let field_id = e.field_ids[i] as usize;
+                        let offset = e.offsets[i] as usize;
+
+                        if field_id >= offsets.len() {
+                            // needs expansion
+                            offsets.resize(field_id + 1, usize::MAX);
+                        }
+                        offsets[field_id] = offset;
+                    }
+                }
+                offset_table_entry::Operation::DeleteDocFields(e) => {
+                    let primary_key = &e.primary_key;
+                    if let Some(offsets) = offset_table.get_mut(primary_key) {
+                        for field_id in e.field_ids.iter() {
+                            let i = *field_id as usize;
+                            if offsets.get(i).is_some() {
+                                offsets[i] = usize::MAX;
+                            }
+                        }
+                    }
+                }
+                offset_table_entry::Operation::DeleteDoc(e) => {
+                    let primary_key = &e.primary_key;
+                    offset_table.remove(primary_key);
+                }
+            }
+        }
+
+        valid_len += header_size + entry_size as u64;
+    }
+
+    Ok(valid_len)
+}
+
+/// Loads the offset table snapshot of a segment (if present) into `offset_table`.
+/// Returns size of the snapshot, 0 if not present.
+fn load_offset_table_snapshot(
+    segment_mount_directory: &str,
+    write_offsets: &WriteOffsets,
+    offset_table: &mut OffsetTable,
+) -> anyhow::Result<u64> {
+    let filename = format!("{}/offset_table_snapshot", segment_mount_directory);
+    if !Path::new(&filename).exists() {
+        return Ok(0);
+    }
+
+    // snapshots are written atomically, unlike the log they cannot have a torn tail
+    let file = File::open(&filename)?;
+    let file_len = file.metadata()?.len();
+    let valid_len = replay_offset_table(
+        &file,
+        OFFSET_TABLE_SNAPSHOT_FORMAT_VERSION,
+        write_offsets,
+        offset_table,
+    )?;
+    if valid_len != file_len {
+        bail!(
+            "Corrupt offset table snapshot: {}, valid length: {} file length: {}",
+            &filename,
+            valid_len,
+            file_len
+        );
+    }
+
+    Ok(file_len)
+}
+
+/// Appends a single entry to an offset table log (or snapshot).
+fn write_offset_table_entry<W: Write>(
+    writer: &mut W,
+    entry: &OffsetTableEntry,
+    format_version: u32,
+) -> io::Result<()> {
+    let bytes = entry.write_to_bytes()?;
+    let size = bytes.len() as i32;
+    writer.write_all(&size.to_le_bytes())?;
+    if format_version >= 1 {
+        let checksum = crc32fast::hash(&bytes);
+        writer.write_all(&checksum.to_le_bytes())?;
+    }
     writer.write_all(&bytes)?;
-    writer.flush()?;
+
     Ok(())
 }
 
+/// Sum of two numeric values, serialized as per their field type (little endian).
+fn add_numeric(field_type: FieldType, value: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let result = match field_type {
+        FieldType::INT32 => {
+            let (value, delta) = (value.try_into(), delta.try_into());
+            match (value, delta) {
+                (Ok(value), Ok(delta)) => i32::from_le_bytes(value)
+                    .wrapping_add(i32::from_le_bytes(delta))
+                    .to_le_bytes()
+                    .to_vec(),
+                _ => bail!("INT32 values must be 4 bytes"),
+            }
+        }
+        FieldType::INT64 => {
+            let (value, delta) = (value.try_into(), delta.try_into());
+            match (value, delta) {
+                (Ok(value), Ok(delta)) => i64::from_le_bytes(value)
+                    .wrapping_add(i64::from_le_bytes(delta))
+                    .to_le_bytes()
+                    .to_vec(),
+                _ => bail!("INT64 values must be 8 bytes"),
+            }
+        }
+        FieldType::FLOAT32 => {
+            let (value, delta) = (value.try_into(), delta.try_into());
+            match (value, delta) {
+                (Ok(value), Ok(delta)) => (f32::from_le_bytes(value) + f32::from_le_bytes(delta))
+                    .to_le_bytes()
+                    .to_vec(),
+                _ => bail!("FLOAT32 values must be 4 bytes"),
+            }
+        }
+        FieldType::FLOAT64 => {
+            let (value, delta) = (value.try_into(), delta.try_into());
+            match (value, delta) {
+                (Ok(value), Ok(delta)) => (f64::from_le_bytes(value) + f64::from_le_bytes(delta))
+                    .to_le_bytes()
+                    .to_vec(),
+                _ => bail!("FLOAT64 values must be 8 bytes"),
+            }
+        }
+        _ => bail!("Cannot increment field of type: {:?}", field_type),
+    };
+
+    Ok(result)
+}
+
+/// Current time as epoch millis.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn segment_metadata(
+    write_offsets: &WriteOffsets,
+    applied_event_offsets: &HashMap<i32, i64>,
+    offset_table_format_version: u32,
+) -> CKVIndexSegmentMetadata {
+    let mut metadata = CKVIndexSegmentMetadata::new();
+    metadata.mmap_write_offset = write_offsets.mmap;
+    metadata.column_write_offsets = write_offsets.columns.clone();
+    metadata.applied_event_offsets = applied_event_offsets.clone();
+    metadata.offset_table_format_version = offset_table_format_version;
+    metadata
+}
+
+fn write_metadata(
+    metadata_file: &MetadataFile,
+    write_offsets: &WriteOffsets,
+    applied_event_offsets: &HashMap<i32, i64>,
+    offset_table_format_version: u32,
+) -> io::Result<()> {
+    let metadata = segment_metadata(
+        write_offsets,
+        applied_event_offsets,
+        offset_table_format_version,
+    );
+    metadata_file.write(&metadata.write_to_bytes()?)
+}
+
 fn create_new_offset_table_file(segment_mount_directory: &str) -> io::Result<File> {
     let filename = format!("{}/offset_table", segment_mount_directory);
     let file = OpenOptions::new()
@@ -754,23 +2697,22 @@ fn open_mmap_file(segment_mount_directory: &str) -> io::Result<File> {
     Ok(file)
 }
 
-fn create_new_metadata_file(segment_mount_directory: &str) -> io::Result<File> {
-    let filename = format!("{}/metadata", segment_mount_directory);
-    let file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create_new(true)
-        .open(filename)?;
-    Ok(file)
+fn column_filename(segment_mount_directory: &str, field_id: FieldId) -> String {
+    format!("{}/column_{}", segment_mount_directory, field_id)
 }
 
-fn open_metadata_file(segment_mount_directory: &str) -> io::Result<File> {
-    let filename = format!("{}/metadata", segment_mount_directory);
-    let mut file = OpenOptions::new()
-        .read(true)
-        .append(true)
-        .create(false)
-        .open(filename)?;
-    file.seek(io::SeekFrom::Start(0))?;
-    Ok(file)
+/// Field ids of column files present in a segment directory.
+fn list_column_field_ids(segment_mount_directory: &str) -> io::Result<Vec<FieldId>> {
+    let mut field_ids = vec![];
+    for entry in std::fs::read_dir(segment_mount_directory)? {
+        let filename = entry?.file_name();
+        let maybe_field_id = filename
+            .to_str()
+            .and_then(|filename| filename.strip_prefix("column_"))
+            .and_then(|field_id| field_id.parse::<FieldId>().ok());
+        if let Some(field_id) = maybe_field_id {
+            field_ids.push(field_id);
+        }
+    }
+    Ok(field_ids)
 }
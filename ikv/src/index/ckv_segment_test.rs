@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use crate::index::ckv_segment::{
+    decode_mmap_value, verify_mmap_value, CKVIndexSegment, ValueOptions, MAX_PAYLOAD_FILE_SIZE,
+};
+use crate::proto::generated_proto::common::{FieldType, FieldValue};
+use crate::schema::vector;
+use crate::utils::testing::string_to_field_value;
+
+fn upsert(segment: &mut CKVIndexSegment, primary_key: &str) {
+    let value = string_to_field_value(&format!("value:{}", primary_key));
+    segment
+        .upsert_document(
+            primary_key.as_bytes(),
+            &[1],
+            &[value],
+            &[ValueOptions::default()],
+        )
+        .unwrap();
+}
+
+fn read(segment: &CKVIndexSegment, primary_key: &str) -> Option<Vec<u8>> {
+    segment.read_field(primary_key.as_bytes(), 1)
+}
+
+fn offset_table_len(segment_mount_directory: &str) -> u64 {
+    std::fs::metadata(format!("{}/offset_table", segment_mount_directory))
+        .unwrap()
+        .len()
+}
+
+#[test]
+pub fn torn_offset_table_tail() {
+    let segment_mount_directory = "/tmp/ckv_segment_test_torn_offset_table_tail";
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+
+    let mut segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    upsert(&mut segment, "pkey0");
+    upsert(&mut segment, "pkey1");
+    segment.close().unwrap();
+    let valid_len = offset_table_len(segment_mount_directory);
+
+    // simulate crash in the middle of writing an entry
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(format!("{}/offset_table", segment_mount_directory))
+        .unwrap();
+    file.write_all(&[42, 0, 0, 0, 7, 7]).unwrap();
+    drop(file);
+
+    // tail is dropped, previous entries are intact
+    let mut segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    assert_eq!(offset_table_len(segment_mount_directory), valid_len);
+    assert_eq!(read(&segment, "pkey0").unwrap(), b"value:pkey0");
+    assert_eq!(read(&segment, "pkey1").unwrap(), b"value:pkey1");
+
+    // new writes are appended after the last good entry
+    upsert(&mut segment, "pkey2");
+    segment.close().unwrap();
+
+    let segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    assert_eq!(read(&segment, "pkey0").unwrap(), b"value:pkey0");
+    assert_eq!(read(&segment, "pkey2").unwrap(), b"value:pkey2");
+    segment.close().unwrap();
+
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+}
+
+#[test]
+pub fn corrupt_offset_table_entry() {
+    let segment_mount_directory = "/tmp/ckv_segment_test_corrupt_offset_table_entry";
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+
+    let mut segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    upsert(&mut segment, "pkey0");
+    segment.flush_writes().unwrap();
+    let valid_len = offset_table_len(segment_mount_directory);
+    upsert(&mut segment, "pkey1");
+    segment.close().unwrap();
+
+    // flip last byte of the last entry
+    let filename = format!("{}/offset_table", segment_mount_directory);
+    let mut bytes = std::fs::read(&filename).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xFF;
+    std::fs::write(&filename, &bytes).unwrap();
+
+    let segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    assert_eq!(offset_table_len(segment_mount_directory), valid_len);
+    assert_eq!(read(&segment, "pkey0").unwrap(), b"value:pkey0");
+    assert!(read(&segment, "pkey1").is_none());
+    segment.close().unwrap();
+
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+}
+
+#[test]
+pub fn offset_table_snapshot() {
+    let segment_mount_directory = "/tmp/ckv_segment_test_offset_table_snapshot";
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+
+    let mut segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    for i in 0..10 {
+        upsert(&mut segment, &format!("pkey{}", i));
+    }
+    segment.delete_document(b"pkey3").unwrap();
+    segment.delete_field_values(b"pkey4", &[1]).unwrap();
+
+    // snapshot folds the log
+    segment.snapshot_offset_table().unwrap();
+    assert_eq!(offset_table_len(segment_mount_directory), 0);
+    assert!(
+        std::path::Path::new(&format!("{}/offset_table_snapshot", segment_mount_directory))
+            .exists()
+    );
+
+    // writes after the snapshot go to the log
+    upsert(&mut segment, "pkey3");
+    segment.delete_document(b"pkey5").unwrap();
+    segment.close().unwrap();
+
+    let segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    for i in [0, 1, 2, 3, 6, 7, 8, 9] {
+        let primary_key = format!("pkey{}", i);
+        assert_eq!(
+            read(&segment, &primary_key).unwrap(),
+            format!("value:{}", primary_key).as_bytes()
+        );
+    }
+    assert!(read(&segment, "pkey4").is_none());
+    assert!(read(&segment, "pkey5").is_none());
+    segment.close().unwrap();
+
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+}
+
+#[test]
+pub fn offset_table_snapshot_crash_before_log_truncation() {
+    let segment_mount_directory =
+        "/tmp/ckv_segment_test_offset_table_snapshot_crash_before_log_truncation";
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+
+    let mut segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    upsert(&mut segment, "pkey0");
+    upsert(&mut segment, "pkey1");
+    segment.delete_document(b"pkey1").unwrap();
+    segment.flush_writes().unwrap();
+
+    let filename = format!("{}/offset_table", segment_mount_directory);
+    let log = std::fs::read(&filename).unwrap();
+    segment.snapshot_offset_table().unwrap();
+    segment.close().unwrap();
+
+    // log was not truncated after the snapshot was written, replay is idempotent
+    std::fs::write(&filename, &log).unwrap();
+
+    let segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    assert_eq!(read(&segment, "pkey0").unwrap(), b"value:pkey0");
+    assert!(read(&segment, "pkey1").is_none());
+    segment.close().unwrap();
+
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+}
+
+#[test]
+pub fn column_files() {
+    let segment_mount_directory = "/tmp/ckv_segment_test_column_files";
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+    let column_filename = format!("{}/column_1", segment_mount_directory);
+
+    let mut segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    segment.set_column_field_ids(HashSet::from([1]));
+    upsert(&mut segment, "pkey0");
+    segment.snapshot_offset_table().unwrap();
+    assert!(std::path::Path::new(&column_filename).exists());
+
+    // crash before the column write offset is persisted
+    upsert(&mut segment, "pkey1");
+    drop(segment);
+
+    // layout of existing values is persisted, unflushed values are dropped
+    let mut segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    assert_eq!(read(&segment, "pkey0").unwrap(), b"value:pkey0");
+    assert!(read(&segment, "pkey1").is_none());
+
+    // values of both layouts are readable
+    upsert(&mut segment, "pkey2");
+    segment.close().unwrap();
+
+    let mut segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    assert_eq!(read(&segment, "pkey0").unwrap(), b"value:pkey0");
+    assert_eq!(read(&segment, "pkey2").unwrap(), b"value:pkey2");
+
+    segment.delete_all_documents().unwrap();
+    assert!(!std::path::Path::new(&column_filename).exists());
+    assert!(read(&segment, "pkey0").is_none());
+    segment.close().unwrap();
+
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+}
+
+#[test]
+pub fn payload_file_size_bound() {
+    let segment_mount_directory = "/tmp/ckv_segment_test_payload_file_size_bound";
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+
+    let mut segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    upsert(&mut segment, "pkey0");
+
+    // mmap offsets past the bound would be read as column offsets
+    segment.write_offset = MAX_PAYLOAD_FILE_SIZE - 4;
+    let value = string_to_field_value("value:pkey1");
+    assert!(segment
+        .upsert_document(b"pkey1", &[1], &[value], &[ValueOptions::default()])
+        .is_err());
+    assert!(read(&segment, "pkey1").is_none());
+    assert_eq!(read(&segment, "pkey0").unwrap(), b"value:pkey0");
+    drop(segment);
+
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+}
+
+#[test]
+pub fn aligned_vectors() {
+    let segment_mount_directory = "/tmp/ckv_segment_test_aligned_vectors";
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+
+    let vector = |field_type: FieldType, num_bytes: usize| {
+        let mut field_value = FieldValue::new();
+        field_value.fieldType = field_type.into();
+        field_value.value = (1..=num_bytes as u8).collect();
+        field_value
+    };
+
+    // vectors after values of odd sizes
+    let mut segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    let documents = [
+        ("pkey0", vector(FieldType::FLOAT32_VECTOR, 12)),
+        ("pkey1", vector(FieldType::FLOAT16_VECTOR, 6)),
+        ("pkey2", vector(FieldType::INT8_VECTOR, 3)),
+        ("pkey3", vector(FieldType::FLOAT32_VECTOR, 8)),
+    ];
+    for (primary_key, field_value) in documents.iter() {
+        upsert(&mut segment, primary_key);
+        segment
+            .upsert_document(
+                primary_key.as_bytes(),
+                &[2],
+                &[field_value],
+                &[ValueOptions::default()],
+            )
+            .unwrap();
+    }
+    segment.close().unwrap();
+
+    // stored aligned to their element size, and read back as written
+    let segment = CKVIndexSegment::open_or_create(segment_mount_directory).unwrap();
+    for (primary_key, field_value) in documents.iter() {
+        let offset = segment.offset_table[primary_key.as_bytes()][2];
+        let value = decode_mmap_value(&segment.mmap, offset).unwrap();
+        let alignment = vector::alignment(field_value.fieldType.enum_value_or_default());
+        assert_eq!(value.data.as_ptr() as usize % alignment, 0);
+        assert_eq!(
+            segment.read_field(primary_key.as_bytes(), 2).unwrap(),
+            field_value.value
+        );
+        assert!(verify_mmap_value(&segment.mmap, offset).is_ok());
+    }
+    segment.close().unwrap();
+
+    let _ = std::fs::remove_dir_all(segment_mount_directory);
+}
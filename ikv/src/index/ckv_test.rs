@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 
-use crate::index::ckv::CKVIndex;
+use crate::index::ckv::{configured_page_cache_policy, CKVIndex, WriteOptions};
+use crate::index::ckv_segment::{now_millis, PageCachePolicy};
+use crate::proto::generated_proto::common::{FieldType, FieldValue, IKVStoreConfig};
+use crate::proto::generated_proto::index::{FieldStats, IndexStats, KafkaOffsetStoreEntry};
 use crate::utils;
 use crate::utils::testing::{bytes_to_field_value, i32_to_field_value, string_to_field_value};
 
@@ -93,7 +96,9 @@ pub fn test_full_document_operations() {
     assert!(index.get_field_value(b"foo", DOCFIELD1).is_none());
 
     // delete doc1
-    assert!(index.delete_document(&doc1).is_ok());
+    assert!(index
+        .delete_document_with_options(&doc1, &WriteOptions::default())
+        .is_ok());
     assert!(index
         .get_field_value(&pkey1, PRIMARY_KEY_FIELD_NAME)
         .is_none()); // doc1 access returns empty
@@ -107,9 +112,10 @@ pub fn test_full_document_operations() {
 
     // delete pkey and DOCFIELD1 from doc2
     assert!(index
-        .delete_field_values(
+        .delete_field_values_with_options(
             &doc2,
-            &[PRIMARY_KEY_FIELD_NAME.to_string(), DOCFIELD1.to_string()]
+            &[PRIMARY_KEY_FIELD_NAME.to_string(), DOCFIELD1.to_string()],
+            &WriteOptions::default()
         )
         .is_ok());
     assert!(index
@@ -265,3 +271,1370 @@ pub fn test_projected_document_operations() {
 }
 
 // TODO: close and reopen for reads
+
+#[test]
+pub fn test_open_reports_segment_errors() {
+    let mount_directory: &str = "/tmp/ckv_test_test_open_reports_segment_errors";
+    let ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index
+        .upsert_field_values(&utils::testing::create_document(0))
+        .unwrap();
+    index.close().unwrap();
+
+    // break two segments
+    for index_id in [2, 5] {
+        let filename = format!(
+            "{}/ckv_test_store/0/index/segment_{}/metadata",
+            mount_directory, index_id
+        );
+        std::fs::remove_file(&filename).unwrap();
+    }
+
+    let error = CKVIndex::open_or_create(&ikv_config)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("segment_2"));
+    assert!(error.contains("segment_5"));
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_compressed_fields() {
+    let mount_directory: &str = "/tmp/ckv_test_test_compressed_fields";
+    let mut ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    ikv_config.stringConfigs.insert(
+        "field_compression_codecs".to_string(),
+        format!("{}:lz4,{}:lz4", DOCFIELD1, DOCFIELD2),
+    );
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    // field1 compresses well, field2 is too small to benefit and is stored as is
+    let value = "feature-blob:".repeat(100);
+    let mut document = HashMap::new();
+    document.insert(
+        PRIMARY_KEY_FIELD_NAME.to_string(),
+        string_to_field_value("pkey"),
+    );
+    document.insert(DOCFIELD1.to_string(), string_to_field_value(&value));
+    document.insert(DOCFIELD2.to_string(), bytes_to_field_value(b"x"));
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index.upsert_field_values(&document).unwrap();
+    assert_eq!(
+        index.get_field_value(b"pkey", DOCFIELD1).unwrap(),
+        value.as_bytes()
+    );
+    assert_eq!(index.get_field_value(b"pkey", DOCFIELD2).unwrap(), b"x");
+
+    let (pre_stats, _) = index.compact_and_close().unwrap();
+    assert!(pre_stats.mmap_used_bytes < value.len() as u64);
+
+    // compressed values survive compaction, and are read without codec config
+    let ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    let result = index.batch_get_field_values(vec![b"pkey"], vec![DOCFIELD1, DOCFIELD2]);
+    let mut expected = vec![];
+    expected.extend((value.len() as i32).to_le_bytes());
+    expected.extend(value.as_bytes());
+    expected.extend(1i32.to_le_bytes());
+    expected.extend(b"x");
+    assert_eq!(result, expected);
+
+    // cleanup mount dir
+    index.close().unwrap();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_field_ttl() {
+    let mount_directory: &str = "/tmp/ckv_test_test_field_ttl";
+    let mut ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    ikv_config
+        .stringConfigs
+        .insert("field_ttl_secs".to_string(), format!("{}:60", DOCFIELD1));
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    let doc0 = utils::testing::create_document(0);
+    let pkey0 = doc0.get(PRIMARY_KEY_FIELD_NAME).unwrap().value.clone();
+    let doc1 = utils::testing::create_document(1);
+    let pkey1 = doc1.get(PRIMARY_KEY_FIELD_NAME).unwrap().value.clone();
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+
+    // written 2 minutes ago at source: field1 (60s ttl) has expired
+    let two_minutes_ago = WriteOptions {
+        source_timestamp_millis: now_millis() - 120_000,
+        ttl_millis: 0,
+        event_offset: None,
+    };
+    index
+        .upsert_field_values_with_options(&doc0, &two_minutes_ago)
+        .unwrap();
+    assert!(index.get_field_value(&pkey0, DOCFIELD1).is_none());
+    assert!(index.get_field_value(&pkey0, DOCFIELD2).is_some());
+
+    // per-write ttl overrides field ttl, and applies to all fields
+    let write_ttl = WriteOptions {
+        source_timestamp_millis: now_millis() - 120_000,
+        ttl_millis: 3_600_000,
+        event_offset: None,
+    };
+    index
+        .upsert_field_values_with_options(&doc1, &write_ttl)
+        .unwrap();
+    assert!(index.get_field_value(&pkey1, DOCFIELD1).is_some());
+
+    let expired = WriteOptions {
+        source_timestamp_millis: 0,
+        ttl_millis: 1,
+        event_offset: None,
+    };
+    index
+        .upsert_field_values_with_options(&doc1, &expired)
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    let result = index.batch_get_field_values(vec![&pkey1], vec![DOCFIELD1, DOCFIELD2]);
+    let mut expected = vec![];
+    expected.extend((-1i32).to_le_bytes());
+    expected.extend((-1i32).to_le_bytes());
+    assert_eq!(result, expected);
+
+    // expired values are purged by compaction, and count as garbage
+    let (pre_stats, post_stats) = index.compact_and_close().unwrap();
+    assert!(post_stats.mmap_used_bytes < pre_stats.mmap_used_bytes);
+    assert_eq!(post_stats.mmap_used_bytes, pre_stats.mmap_live_bytes);
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert!(index.get_field_value(&pkey0, DOCFIELD1).is_none());
+    assert!(index.get_field_value(&pkey0, DOCFIELD2).is_some());
+    assert!(index.get_field_value(&pkey1, DOCFIELD1).is_none());
+    assert!(index.get_field_value(&pkey1, DOCFIELD3).is_none());
+
+    // cleanup mount dir
+    index.close().unwrap();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_last_writer_wins() {
+    let mount_directory: &str = "/tmp/ckv_test_test_last_writer_wins";
+    let mut ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    ikv_config
+        .booleanConfigs
+        .insert("last_writer_wins".to_string(), true);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    // recent timestamps, deleted documents are kept by compaction
+    let t0 = now_millis();
+    let at = |source_timestamp_millis: u64| WriteOptions {
+        source_timestamp_millis: t0 + source_timestamp_millis,
+        ttl_millis: 0,
+        event_offset: None,
+    };
+    let document = |field1: &str| {
+        let mut document = utils::testing::create_document(0);
+        document.insert(DOCFIELD1.to_string(), string_to_field_value(field1));
+        document
+    };
+    let pkey = string_to_field_value("field0:0").value;
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+
+    // older upserts and deletes are skipped
+    index
+        .upsert_field_values_with_options(&document("t2000"), &at(2000))
+        .unwrap();
+    index
+        .upsert_field_values_with_options(&document("t1000"), &at(1000))
+        .unwrap();
+    index
+        .delete_field_values_with_options(&document(""), &[DOCFIELD1.to_string()], &at(1500))
+        .unwrap();
+    assert_eq!(index.get_field_value(&pkey, DOCFIELD1).unwrap(), b"t2000");
+
+    // deletes are remembered
+    index
+        .delete_field_values_with_options(&document(""), &[DOCFIELD1.to_string()], &at(3000))
+        .unwrap();
+    index
+        .upsert_field_values_with_options(&document("t2500"), &at(2500))
+        .unwrap();
+    assert!(index.get_field_value(&pkey, DOCFIELD1).is_none());
+    assert!(index.get_field_value(&pkey, DOCFIELD3).is_some());
+
+    index
+        .delete_document_with_options(&document(""), &at(4000))
+        .unwrap();
+    index
+        .upsert_field_values_with_options(&document("t3500"), &at(3500))
+        .unwrap();
+    assert!(index.get_field_value(&pkey, DOCFIELD1).is_none());
+    assert!(index.get_field_value(&pkey, DOCFIELD3).is_none());
+
+    // timestamps survive compaction
+    index.compact_and_close().unwrap();
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index
+        .upsert_field_values_with_options(&document("t3600"), &at(3600))
+        .unwrap();
+    assert!(index.get_field_value(&pkey, DOCFIELD1).is_none());
+
+    index
+        .upsert_field_values_with_options(&document("t5000"), &at(5000))
+        .unwrap();
+    assert_eq!(index.get_field_value(&pkey, DOCFIELD1).unwrap(), b"t5000");
+
+    // writes without source timestamp are always applied
+    index.upsert_field_values(&document("untimed")).unwrap();
+    assert_eq!(index.get_field_value(&pkey, DOCFIELD1).unwrap(), b"untimed");
+
+    // documents deleted long ago are dropped by compaction, along with their timestamps
+    let old = |source_timestamp_millis: u64| WriteOptions {
+        source_timestamp_millis,
+        ..Default::default()
+    };
+    let document1 = utils::testing::create_document(1);
+    let pkey1 = string_to_field_value("field0:1").value;
+    index
+        .upsert_field_values_with_options(&document1, &old(1000))
+        .unwrap();
+    index
+        .delete_document_with_options(&document1, &old(2000))
+        .unwrap();
+    index.compact_and_close().unwrap();
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert_eq!(index.get_field_value(&pkey, DOCFIELD1).unwrap(), b"untimed");
+    index
+        .upsert_field_values_with_options(&document1, &old(1500))
+        .unwrap();
+    assert!(index.get_field_value(&pkey1, DOCFIELD1).is_some());
+
+    // cleanup mount dir
+    index.close().unwrap();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_increment_fields() {
+    let mount_directory: &str = "/tmp/ckv_test_test_increment_fields";
+    let ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    let no_options = WriteOptions::default();
+    let increment = |primary_key: &str, field_name: &str, delta: FieldValue| {
+        let mut document = HashMap::new();
+        document.insert(
+            PRIMARY_KEY_FIELD_NAME.to_string(),
+            string_to_field_value(primary_key),
+        );
+        document.insert(field_name.to_string(), delta);
+        document
+    };
+    let f64_to_field_value = |value: f64| {
+        let mut fv = FieldValue::new();
+        fv.fieldType = FieldType::FLOAT64.into();
+        fv.value = value.to_le_bytes().to_vec();
+        fv
+    };
+
+    let doc0 = utils::testing::create_document(0);
+    let pkey0 = doc0.get(PRIMARY_KEY_FIELD_NAME).unwrap().value.clone();
+    let pkey1 = string_to_field_value("field0:1").value;
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index.upsert_field_values(&doc0).unwrap();
+
+    // existing values are incremented
+    index
+        .increment_field_values_with_options(
+            &increment("field0:0", DOCFIELD3, i32_to_field_value(5)),
+            &no_options,
+        )
+        .unwrap();
+    index
+        .increment_field_values_with_options(
+            &increment("field0:0", DOCFIELD3, i32_to_field_value(-2)),
+            &no_options,
+        )
+        .unwrap();
+    assert_eq!(
+        index.get_field_value(&pkey0, DOCFIELD3).unwrap(),
+        3i32.to_le_bytes()
+    );
+
+    // missing values (and documents) are initialized to the delta
+    index
+        .increment_field_values_with_options(
+            &increment("field0:1", "score", f64_to_field_value(1.5)),
+            &no_options,
+        )
+        .unwrap();
+    index
+        .increment_field_values_with_options(
+            &increment("field0:1", "score", f64_to_field_value(1.0)),
+            &no_options,
+        )
+        .unwrap();
+    assert_eq!(
+        index.get_field_value(&pkey1, "score").unwrap(),
+        2.5f64.to_le_bytes()
+    );
+    assert_eq!(
+        index
+            .get_field_value(&pkey1, PRIMARY_KEY_FIELD_NAME)
+            .unwrap(),
+        pkey1
+    );
+
+    // non-numeric deltas, and deltas of a different type are rejected
+    assert!(index
+        .increment_field_values_with_options(
+            &increment("field0:0", DOCFIELD1, string_to_field_value("1")),
+            &no_options,
+        )
+        .is_err());
+    assert!(index
+        .increment_field_values_with_options(
+            &increment("field0:1", "score", i32_to_field_value(1)),
+            &no_options,
+        )
+        .is_err());
+    assert_eq!(
+        index.get_field_value(&pkey1, "score").unwrap(),
+        2.5f64.to_le_bytes()
+    );
+
+    // a rejected field fails the whole increment, other fields are not incremented
+    let mut document = increment("field0:1", "score", i32_to_field_value(1));
+    document.insert("count".to_string(), i32_to_field_value(1));
+    assert!(index
+        .increment_field_values_with_options(&document, &no_options)
+        .is_err());
+    assert_eq!(
+        index.get_field_value(&pkey1, "score").unwrap(),
+        2.5f64.to_le_bytes()
+    );
+    assert!(index.get_field_value(&pkey1, "count").is_none());
+
+    // events replayed from the stream (at or below an applied offset) are skipped
+    let at_offset = |offset: i64| WriteOptions {
+        event_offset: Some((0, offset)),
+        ..Default::default()
+    };
+    for offset in [10, 10, 9, 11] {
+        index
+            .increment_field_values_with_options(
+                &increment("field0:1", "score", f64_to_field_value(1.0)),
+                &at_offset(offset),
+            )
+            .unwrap();
+    }
+    assert_eq!(
+        index.get_field_value(&pkey1, "score").unwrap(),
+        4.5f64.to_le_bytes()
+    );
+
+    // also after a restart
+    index.close().unwrap();
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index
+        .increment_field_values_with_options(
+            &increment("field0:1", "score", f64_to_field_value(1.0)),
+            &at_offset(11),
+        )
+        .unwrap();
+    assert_eq!(
+        index.get_field_value(&pkey1, "score").unwrap(),
+        4.5f64.to_le_bytes()
+    );
+
+    // applied offsets are rewritten with the segment metadata, which does not grow on flush
+    let index_directory = format!(
+        "{}/index",
+        utils::paths::get_index_mount_directory_fqn(&ikv_config).unwrap()
+    );
+    let metadata_size = || -> u64 {
+        std::fs::read_dir(&index_directory)
+            .unwrap()
+            .map(|segment| {
+                let filename = segment.unwrap().path().join("metadata");
+                std::fs::metadata(filename).unwrap().len()
+            })
+            .sum()
+    };
+    let size = metadata_size();
+    for _ in 0..10 {
+        index.flush_writes().unwrap();
+    }
+    assert_eq!(metadata_size(), size);
+
+    // increments and applied offsets survive compaction
+    index.compact_and_close().unwrap();
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index
+        .increment_field_values_with_options(
+            &increment("field0:1", "score", f64_to_field_value(1.0)),
+            &at_offset(11),
+        )
+        .unwrap();
+    assert_eq!(
+        index.get_field_value(&pkey0, DOCFIELD3).unwrap(),
+        3i32.to_le_bytes()
+    );
+    assert_eq!(
+        index.get_field_value(&pkey1, "score").unwrap(),
+        4.5f64.to_le_bytes()
+    );
+
+    // cleanup mount dir
+    index.close().unwrap();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_append_list_fields() {
+    let mount_directory: &str = "/tmp/ckv_test_test_append_list_fields";
+    let ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    let no_options = WriteOptions::default();
+    let string_list = |elements: &[&str]| {
+        let mut value = vec![];
+        for element in elements {
+            value.extend((element.len() as u32).to_le_bytes());
+            value.extend(element.as_bytes());
+        }
+        value
+    };
+    let append = |elements: &[&str]| {
+        let mut fv = FieldValue::new();
+        fv.fieldType = FieldType::STRING_LIST.into();
+        fv.value = string_list(elements);
+
+        let mut document = HashMap::new();
+        document.insert(
+            PRIMARY_KEY_FIELD_NAME.to_string(),
+            string_to_field_value("field0:0"),
+        );
+        document.insert("viewed".to_string(), fv);
+        document
+    };
+    let pkey = string_to_field_value("field0:0").value;
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+
+    // missing lists (and documents) are initialized, then trimmed to max length
+    index
+        .append_field_values_with_options(&append(&["a", "b"]), 3, &no_options)
+        .unwrap();
+    index
+        .append_field_values_with_options(&append(&["c", "d"]), 3, &no_options)
+        .unwrap();
+    assert_eq!(
+        index.get_field_value(&pkey, "viewed").unwrap(),
+        string_list(&["b", "c", "d"])
+    );
+    assert_eq!(
+        index
+            .get_field_value(&pkey, PRIMARY_KEY_FIELD_NAME)
+            .unwrap(),
+        pkey
+    );
+
+    // last k elements, in batch read format
+    let result =
+        index.batch_get_last_list_elements(vec![&pkey], vec!["viewed", PRIMARY_KEY_FIELD_NAME], 2);
+    let mut expected = vec![];
+    expected.extend((string_list(&["c", "d"]).len() as i32).to_le_bytes());
+    expected.extend(string_list(&["c", "d"]));
+    expected.extend((pkey.len() as i32).to_le_bytes());
+    expected.extend(&pkey);
+    assert_eq!(result, expected);
+
+    // non-list and malformed values are rejected
+    let mut document = append(&[]);
+    document.insert("viewed".to_string(), string_to_field_value("e"));
+    assert!(index
+        .append_field_values_with_options(&document, 3, &no_options)
+        .is_err());
+    let mut document = append(&[]);
+    document.get_mut("viewed").unwrap().value = vec![1, 0, 0];
+    assert!(index
+        .append_field_values_with_options(&document, 3, &no_options)
+        .is_err());
+    assert!(index.upsert_field_values(&document).is_err());
+
+    // a rejected field fails the whole append, other fields are not appended to
+    let mut document = append(&[]);
+    document.get_mut("viewed").unwrap().fieldType = FieldType::BYTES_LIST.into();
+    let mut tags = FieldValue::new();
+    tags.fieldType = FieldType::STRING_LIST.into();
+    tags.value = string_list(&["x"]);
+    document.insert("tags".to_string(), tags);
+    assert!(index
+        .append_field_values_with_options(&document, 3, &no_options)
+        .is_err());
+    assert!(index.get_field_value(&pkey, "tags").is_none());
+
+    // events replayed from the stream (at or below an applied offset) are skipped
+    let at_offset = |offset: i64| WriteOptions {
+        event_offset: Some((0, offset)),
+        ..Default::default()
+    };
+    index
+        .append_field_values_with_options(&append(&["e"]), 0, &at_offset(20))
+        .unwrap();
+    index
+        .append_field_values_with_options(&append(&["e"]), 0, &at_offset(20))
+        .unwrap();
+    assert_eq!(
+        index.get_field_value(&pkey, "viewed").unwrap(),
+        string_list(&["b", "c", "d", "e"])
+    );
+
+    // lists and applied offsets survive compaction
+    index.compact_and_close().unwrap();
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index
+        .append_field_values_with_options(&append(&["e"]), 0, &at_offset(20))
+        .unwrap();
+    index
+        .append_field_values_with_options(&append(&["f"]), 0, &no_options)
+        .unwrap();
+    assert_eq!(
+        index.get_field_value(&pkey, "viewed").unwrap(),
+        string_list(&["b", "c", "d", "e", "f"])
+    );
+
+    // cleanup mount dir
+    index.close().unwrap();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_vector_fields() {
+    let mount_directory: &str = "/tmp/ckv_test_test_vector_fields";
+    let ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    let embedding = [0.5f32, -1.0, 2.25];
+    let f32_vector = |elements: &[f32]| {
+        let mut fv = FieldValue::new();
+        fv.fieldType = FieldType::FLOAT32_VECTOR.into();
+        fv.value = elements.iter().flat_map(|e| e.to_le_bytes()).collect();
+        fv
+    };
+
+    let mut doc0 = utils::testing::create_document(0);
+    doc0.insert("embedding".to_string(), f32_vector(&embedding));
+    let pkey0 = doc0.get(PRIMARY_KEY_FIELD_NAME).unwrap().value.clone();
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index.upsert_field_values(&doc0).unwrap();
+
+    // read back as little-endian elements
+    let value = index.get_field_value(&pkey0, "embedding").unwrap();
+    let elements: Vec<f32> = value
+        .chunks_exact(4)
+        .map(|e| f32::from_le_bytes(e.try_into().unwrap()))
+        .collect();
+    assert_eq!(elements, embedding);
+
+    // writes with wrong dimension are rejected
+    let mut doc1 = utils::testing::create_document(1);
+    doc1.insert("embedding".to_string(), f32_vector(&[1.0, 2.0]));
+    let pkey1 = doc1.get(PRIMARY_KEY_FIELD_NAME).unwrap().value.clone();
+    assert!(index.upsert_field_values(&doc1).is_err());
+    assert!(index.get_field_value(&pkey1, DOCFIELD1).is_none());
+
+    // cleanup mount dir
+    index.close().unwrap();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_knn() {
+    let mount_directory: &str = "/tmp/ckv_test_test_knn";
+    let mut ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    ikv_config
+        .stringConfigs
+        .insert("field_ann_indexes".to_string(), "embedding:l2".to_string());
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    // points on a line: doc i has embedding [i, 0]
+    let document = |i: u32| {
+        let mut embedding = FieldValue::new();
+        embedding.fieldType = FieldType::FLOAT32_VECTOR.into();
+        embedding.value = [i as f32, 0.0]
+            .iter()
+            .flat_map(|e| e.to_le_bytes())
+            .collect();
+
+        let mut document = utils::testing::create_document(i);
+        document.insert("embedding".to_string(), embedding);
+        document
+    };
+    let pkey = |i: u32| string_to_field_value(&format!("field0:{}", i)).value;
+    let nearest = |index: &CKVIndex, query: f32, k: usize| -> Vec<Vec<u8>> {
+        index
+            .knn("embedding", &[query, 0.0], k)
+            .unwrap()
+            .into_iter()
+            .map(|(primary_key, _)| primary_key)
+            .collect()
+    };
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert!(index.knn("embedding", &[0.0, 0.0], 3).unwrap().is_empty());
+    assert!(index.knn(DOCFIELD1, &[0.0, 0.0], 3).is_err());
+
+    for i in 0..100 {
+        index.upsert_field_values(&document(i)).unwrap();
+    }
+    let result = index.knn("embedding", &[10.2, 0.0], 3).unwrap();
+    let expected = vec![(pkey(10), 0.04), (pkey(11), 0.64), (pkey(9), 1.44)];
+    assert_eq!(result.len(), expected.len());
+    for ((primary_key, distance), (expected_primary_key, expected_distance)) in
+        result.iter().zip(expected.iter())
+    {
+        assert_eq!(primary_key, expected_primary_key);
+        assert!((distance - expected_distance).abs() < 1e-4);
+    }
+
+    // deletes are not returned
+    index
+        .delete_document_with_options(&document(10), &WriteOptions::default())
+        .unwrap();
+    index
+        .delete_field_values_with_options(
+            &document(11),
+            &vec!["embedding".to_string()],
+            &WriteOptions::default(),
+        )
+        .unwrap();
+    assert_eq!(nearest(&index, 10.2, 2), vec![pkey(9), pkey(12)]);
+
+    // upserts replace vectors
+    let mut moved = document(50);
+    moved.insert(
+        "embedding".to_string(),
+        document(10).remove("embedding").unwrap(),
+    );
+    index.upsert_field_values(&moved).unwrap();
+    assert_eq!(nearest(&index, 10.2, 1), vec![pkey(50)]);
+    assert_eq!(nearest(&index, 50.0, 1), vec![pkey(49)]);
+
+    // persisted with the segments
+    index.close().unwrap();
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert_eq!(nearest(&index, 10.2, 2), vec![pkey(50), pkey(9)]);
+
+    // rebuilt after offline compaction
+    index.compact_and_close().unwrap();
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert_eq!(nearest(&index, 10.2, 2), vec![pkey(50), pkey(9)]);
+
+    // cleanup mount dir
+    index.close().unwrap();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_field_type_policy() {
+    let mount_directory: &str = "/tmp/ckv_test_test_field_type_policy";
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    // field3 is declared INT32 by the first write
+    let doc0 = utils::testing::create_document(0);
+    let pkey0 = doc0.get(PRIMARY_KEY_FIELD_NAME).unwrap().value.clone();
+    let ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index.upsert_field_values(&doc0).unwrap();
+    assert_eq!(index.get_field_type(DOCFIELD3), Some(FieldType::INT32));
+    assert_eq!(index.get_field_type("unknown"), None);
+
+    let mut doc1 = utils::testing::create_document(1);
+    doc1.insert(DOCFIELD3.to_string(), string_to_field_value("17"));
+    let pkey1 = doc1.get(PRIMARY_KEY_FIELD_NAME).unwrap().value.clone();
+    let mut doc2 = utils::testing::create_document(2);
+    doc2.insert(DOCFIELD3.to_string(), string_to_field_value("abc"));
+
+    // warn (default): written as-is, and counted
+    index.upsert_field_values(&doc1).unwrap();
+    index.upsert_field_values(&doc1).unwrap();
+    assert_eq!(index.get_field_value(&pkey1, DOCFIELD3).unwrap(), b"17");
+    assert_eq!(index.get_field_type(DOCFIELD3), Some(FieldType::INT32));
+    assert_eq!(index.index_stats().unwrap().num_mismatched_type_writes, 2);
+    index.close().unwrap();
+
+    // reject
+    let mut ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    ikv_config
+        .stringConfigs
+        .insert("field_type_policy".to_string(), "reject".to_string());
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert!(index.upsert_field_values(&doc1).is_err());
+    index.upsert_field_values(&doc0).unwrap();
+    index.close().unwrap();
+
+    // coerce: converted to declared type, fails if not convertible
+    ikv_config
+        .stringConfigs
+        .insert("field_type_policy".to_string(), "coerce".to_string());
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index.upsert_field_values(&doc1).unwrap();
+    assert_eq!(
+        index.get_field_value(&pkey1, DOCFIELD3).unwrap(),
+        17i32.to_le_bytes()
+    );
+    assert!(index.upsert_field_values(&doc2).is_err());
+    assert_eq!(
+        index.get_field_value(&pkey0, DOCFIELD3).unwrap(),
+        0i32.to_le_bytes()
+    );
+    index.close().unwrap();
+
+    // bad policy
+    ikv_config
+        .stringConfigs
+        .insert("field_type_policy".to_string(), "ignore".to_string());
+    assert!(CKVIndex::open_or_create(&ikv_config).is_err());
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_verify_index() {
+    let mount_directory: &str = "/tmp/ckv_test_test_verify_index";
+    let ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    // missing index
+    let report = CKVIndex::verify_index(&ikv_config).unwrap();
+    assert!(!report.is_valid());
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    for i in 0..20 {
+        index
+            .upsert_field_values(&utils::testing::create_document(i))
+            .unwrap();
+    }
+    index.drop_fields(&[DOCFIELD2.to_string()], &[]).unwrap();
+    index.close().unwrap();
+
+    // healthy, values of dropped fields are expected
+    let report = CKVIndex::verify_index(&ikv_config).unwrap();
+    assert!(report.is_valid(), "{}", report.problems_summary());
+    assert_eq!(report.num_segments, 16);
+    assert_eq!(report.num_documents, 20);
+    assert_eq!(report.num_values, 80);
+    assert_eq!(report.num_dropped_field_values, 20);
+
+    // segment holding document 0
+    let pkey0 = utils::testing::create_document(0)
+        .get(PRIMARY_KEY_FIELD_NAME)
+        .unwrap()
+        .value
+        .clone();
+    let segment_directory = format!(
+        "{}/ckv_test_store/0/index/segment_{}",
+        mount_directory,
+        crate::index::ckv::segment_id(&pkey0, 16)
+    );
+
+    // bad type tag of the first value
+    let mmap_filename = format!("{}/mmap", segment_directory);
+    let mut mmap = std::fs::read(&mmap_filename).unwrap();
+    let header = mmap[0];
+    mmap[0] = 0xEE;
+    std::fs::write(&mmap_filename, &mmap).unwrap();
+    let report = CKVIndex::verify_index(&ikv_config).unwrap();
+    assert_eq!(report.problems.len(), 1);
+    assert!(report.problems_summary().contains("unknown field type tag"));
+
+    // values are not read by structural verification
+    let report = CKVIndex::verify_index_structure(&ikv_config).unwrap();
+    assert!(report.is_valid(), "{}", report.problems_summary());
+    assert_eq!(report.num_segments, 16);
+    assert_eq!(report.num_documents, 0);
+
+    // truncated mmap file, write offset past its end
+    mmap[0] = header;
+    mmap.truncate(3);
+    std::fs::write(&mmap_filename, &mmap).unwrap();
+    let report = CKVIndex::verify_index(&ikv_config).unwrap();
+    assert!(report
+        .problems_summary()
+        .contains("past end of mmap file of size 3"));
+    assert!(report.problems_summary().contains("past write offset"));
+    let report = CKVIndex::verify_index_structure(&ikv_config).unwrap();
+    assert_eq!(report.problems.len(), 1);
+    assert!(report
+        .problems_summary()
+        .contains("past end of mmap file of size 3"));
+
+    // missing segment file
+    std::fs::remove_file(&mmap_filename).unwrap();
+    let report = CKVIndex::verify_index(&ikv_config).unwrap();
+    assert!(!report.is_valid());
+    let report = CKVIndex::verify_index_structure(&ikv_config).unwrap();
+    assert!(!report.is_valid());
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_repair_index() {
+    let mount_directory: &str = "/tmp/ckv_test_test_repair_index";
+    let ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    let index_directory = format!("{}/ckv_test_store/0", mount_directory);
+    let base_index_directory = format!("{}/base_index", mount_directory);
+
+    // base index at offset 10
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    for i in 0..20 {
+        index
+            .upsert_field_values(&utils::testing::create_document(i))
+            .unwrap();
+    }
+    index.close().unwrap();
+    write_offset(&index_directory, 10);
+    copy_directory(&index_directory, &base_index_directory);
+
+    // index at offset 20
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    for i in 20..30 {
+        index
+            .upsert_field_values(&utils::testing::create_document(i))
+            .unwrap();
+    }
+    index.close().unwrap();
+    write_offset(&index_directory, 20);
+
+    // segment holding document 0, and a document of another segment
+    let document0 = utils::testing::create_document(0);
+    let pkey0 = document0.get(PRIMARY_KEY_FIELD_NAME).unwrap().value.clone();
+    let index_id = crate::index::ckv::segment_id(&pkey0, 16);
+    let other_document = (1..30)
+        .map(utils::testing::create_document)
+        .find(|document| {
+            let pkey = &document.get(PRIMARY_KEY_FIELD_NAME).unwrap().value;
+            crate::index::ckv::segment_id(pkey, 16) != index_id
+        })
+        .unwrap();
+    let segment_directory = format!("{}/index/segment_{}", index_directory, index_id);
+
+    // lost metadata, regenerated from the offset table
+    std::fs::remove_file(format!("{}/metadata", segment_directory)).unwrap();
+    assert!(!CKVIndex::verify_index(&ikv_config).unwrap().is_valid());
+    assert!(CKVIndex::repair_segments(&ikv_config).unwrap().is_empty());
+    let report = CKVIndex::verify_index(&ikv_config).unwrap();
+    assert!(report.is_valid(), "{}", report.problems_summary());
+    assert_eq!(report.num_documents, 30);
+
+    // truncated mmap file cannot be repaired in place
+    let mmap_filename = format!("{}/mmap", segment_directory);
+    let mut mmap = std::fs::read(&mmap_filename).unwrap();
+    mmap.truncate(3);
+    std::fs::write(&mmap_filename, &mmap).unwrap();
+    assert_eq!(
+        CKVIndex::repair_segments(&ikv_config).unwrap(),
+        vec![index_id]
+    );
+
+    // restore from base index, offsets are rewound
+    CKVIndex::restore_segments(&ikv_config, &base_index_directory, &[index_id]).unwrap();
+    let report = CKVIndex::verify_index(&ikv_config).unwrap();
+    assert!(report.is_valid(), "{}", report.problems_summary());
+    let offsets = crate::index::offset_store::OffsetStore::open_or_create(index_directory.clone())
+        .unwrap()
+        .read_all_offsets()
+        .unwrap();
+    assert_eq!(offsets[0].offset, 10);
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert_eq!(
+        index.get_field_value(&pkey0, DOCFIELD1).unwrap(),
+        document0.get(DOCFIELD1).unwrap().value
+    );
+
+    // replayed events only apply to the restored segment, across restarts
+    assert!(index
+        .applies_replayed_event("topic", 0, 12, Some(&document0))
+        .unwrap());
+    assert!(!index
+        .applies_replayed_event("topic", 0, 12, Some(&other_document))
+        .unwrap());
+    assert!(!index.applies_replayed_event("topic", 0, 12, None).unwrap());
+    index.close().unwrap();
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert!(!index
+        .applies_replayed_event("topic", 0, 19, Some(&other_document))
+        .unwrap());
+
+    // caught up
+    assert!(index
+        .applies_replayed_event("topic", 0, 20, Some(&other_document))
+        .unwrap());
+    index.close().unwrap();
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert!(index.applies_replayed_event("topic", 0, 15, None).unwrap());
+    index.close().unwrap();
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+fn write_offset(index_directory: &str, offset: i64) {
+    let mut entry = KafkaOffsetStoreEntry::new();
+    entry.topic = "topic".to_string();
+    entry.partition = 0;
+    entry.offset = offset;
+    crate::index::offset_store::OffsetStore::open_or_create(index_directory.to_string())
+        .unwrap()
+        .write_entries(vec![entry])
+        .unwrap();
+}
+
+fn copy_directory(source: &str, destination: &str) {
+    std::fs::create_dir_all(destination).unwrap();
+    for entry in std::fs::read_dir(source).unwrap() {
+        let entry = entry.unwrap();
+        let source_path = entry.path().to_string_lossy().to_string();
+        let destination_path = format!("{}/{}", destination, entry.file_name().to_string_lossy());
+        if entry.file_type().unwrap().is_dir() {
+            copy_directory(&source_path, &destination_path);
+        } else {
+            std::fs::copy(&source_path, &destination_path).unwrap();
+        }
+    }
+}
+
+fn field_stats<'a>(stats: &'a IndexStats, field_name: &str) -> &'a FieldStats {
+    stats
+        .field_stats
+        .iter()
+        .find(|field_stats| field_stats.field_name == field_name)
+        .unwrap()
+}
+
+#[test]
+pub fn test_index_stats() {
+    let mount_directory = "/tmp/ckv_test_test_index_stats";
+    let ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    let stats = index.index_stats().unwrap();
+    assert_eq!(stats.segment_num_documents.iter().sum::<u64>(), 0);
+    assert_eq!(stats.mmap_dead_bytes, 0);
+
+    for i in 0..3 {
+        index
+            .upsert_field_values(&utils::testing::create_document(i))
+            .unwrap();
+    }
+    let stats = index.index_stats().unwrap();
+    assert_eq!(stats.segment_num_documents.iter().sum::<u64>(), 3);
+    assert_eq!(stats.mmap_dead_bytes, 0);
+    let mut field_names: Vec<&str> = stats
+        .field_stats
+        .iter()
+        .map(|field_stats| field_stats.field_name.as_str())
+        .collect();
+    assert_eq!(field_names[0], PRIMARY_KEY_FIELD_NAME);
+    field_names.sort();
+    assert_eq!(field_names, vec!["field0", "field1", "field2", "field3"]);
+    assert_eq!(field_stats(&stats, DOCFIELD3).num_values, 3);
+    assert_eq!(field_stats(&stats, DOCFIELD3).value_bytes, 12);
+    assert_eq!(field_stats(&stats, DOCFIELD3).avg_value_bytes, 4.0);
+
+    // deleted values are not present, and are garbage
+    index
+        .delete_field_values_with_options(
+            &utils::testing::create_document(2),
+            &[DOCFIELD1.to_string()],
+            &WriteOptions::default(),
+        )
+        .unwrap();
+    let stats = index.index_stats().unwrap();
+    assert_eq!(stats.segment_num_documents.iter().sum::<u64>(), 3);
+    assert_eq!(field_stats(&stats, DOCFIELD1).num_values, 2);
+    assert_eq!(field_stats(&stats, DOCFIELD1).value_bytes, 16);
+    assert_eq!(field_stats(&stats, DOCFIELD1).avg_value_bytes, 8.0);
+    let dead_bytes = stats.mmap_dead_bytes;
+    assert!(dead_bytes > 0);
+
+    // dropped fields are not reported, their values are garbage
+    index.drop_fields(&[DOCFIELD2.to_string()], &[]).unwrap();
+    let stats = index.index_stats().unwrap();
+    assert_eq!(stats.field_stats.len(), 3);
+    assert!(stats
+        .field_stats
+        .iter()
+        .all(|field_stats| field_stats.field_name != DOCFIELD2));
+    assert!(stats.mmap_dead_bytes > dead_bytes);
+
+    // compaction reclaims garbage
+    for index_id in 0..stats.segment_num_documents.len() {
+        index.compact_segment(index_id).unwrap();
+    }
+    let stats = index.index_stats().unwrap();
+    assert_eq!(stats.segment_num_documents.iter().sum::<u64>(), 3);
+    assert_eq!(field_stats(&stats, DOCFIELD1).num_values, 2);
+    assert_eq!(stats.mmap_dead_bytes, 0);
+    index.close().unwrap();
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_page_cache_policy() {
+    let mount_directory = "/tmp/ckv_test_test_page_cache_policy";
+    let mut ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    // defaults
+    assert_eq!(
+        configured_page_cache_policy(&ikv_config).unwrap(),
+        PageCachePolicy::default()
+    );
+
+    ikv_config
+        .stringConfigs
+        .insert("mmap_advice".to_string(), "sequential".to_string());
+    assert!(configured_page_cache_policy(&ikv_config).is_err());
+    assert!(CKVIndex::open_or_create(&ikv_config).is_err());
+
+    ikv_config.stringConfigs.insert(
+        "mmap_advice".to_string(),
+        "Random, willneed,hugepage".to_string(),
+    );
+    ikv_config
+        .booleanConfigs
+        .insert("mlock_index_segments".to_string(), true);
+    let expected_policy = PageCachePolicy {
+        random: true,
+        will_need: true,
+        huge_pages: true,
+        lock: true,
+    };
+    assert_eq!(
+        configured_page_cache_policy(&ikv_config).unwrap(),
+        expected_policy
+    );
+
+    // policies only affect performance, failures to apply them (ex. mlock limits) are ignored
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert_eq!(index.page_cache_policy, expected_policy);
+
+    // mmap files are empty (unmapped) on creation, the policy is applied once they are extended
+    let mut expected_warmup_bytes = 0;
+    for i in 0..100 {
+        let doc = utils::testing::create_document(i);
+        index.upsert_field_values(&doc).unwrap();
+        expected_warmup_bytes += doc.get(DOCFIELD1).unwrap().value.len() as u64;
+    }
+    let doc = utils::testing::create_document(0);
+    let pkey = doc.get(PRIMARY_KEY_FIELD_NAME).unwrap().value.clone();
+    assert_eq!(
+        index.get_field_value(&pkey, DOCFIELD1).unwrap(),
+        doc.get(DOCFIELD1).unwrap().value
+    );
+    assert_random_advice(&ikv_config);
+
+    // warmup touches values of known fields only
+    ikv_config.stringConfigs.insert(
+        "warmup_field_names".to_string(),
+        format!("{}, unknown,", DOCFIELD1),
+    );
+    let field_names = crate::index::ckv::configured_warmup_field_names(&ikv_config);
+    assert_eq!(
+        field_names,
+        vec![DOCFIELD1.to_string(), "unknown".to_string()]
+    );
+    assert_eq!(index.warmup(&field_names).unwrap(), expected_warmup_bytes);
+    assert_eq!(index.warmup(&["unknown".to_string()]).unwrap(), 0);
+    assert_eq!(index.warmup(&[]).unwrap(), 0);
+
+    index.close().unwrap();
+
+    // policy is applied to existing mmap files on open
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert_random_advice(&ikv_config);
+    assert_eq!(index.warmup(&field_names).unwrap(), expected_warmup_bytes);
+    index.close().unwrap();
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+/// Checks that all mapped mmap files of the index are advised MADV_RANDOM ("rr" VmFlag).
+/// Other advices have no reliable trace (willneed) or depend on the host (hugepage, mlock).
+#[cfg(target_os = "linux")]
+fn assert_random_advice(config: &IKVStoreConfig) {
+    let index_directory = format!(
+        "{}/index/",
+        utils::paths::get_index_mount_directory_fqn(config).unwrap()
+    );
+    let smaps = std::fs::read_to_string("/proc/self/smaps").unwrap();
+
+    let mut num_mappings = 0;
+    let mut mapping = None;
+    for line in smaps.lines() {
+        if let Some(flags) = line.strip_prefix("VmFlags:") {
+            if let Some(path) = mapping.take() {
+                assert!(
+                    flags.split_whitespace().any(|flag| flag == "rr"),
+                    "mapping of {} is not advised random: {}",
+                    path,
+                    flags
+                );
+                num_mappings += 1;
+            }
+        } else if let Some(path) = line.split_whitespace().nth(5) {
+            if path.starts_with(&index_directory) && path.ends_with("/mmap") {
+                mapping = Some(path.to_string());
+            }
+        }
+    }
+    assert!(num_mappings > 0);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn assert_random_advice(_config: &IKVStoreConfig) {}
+
+#[test]
+pub fn test_column_group_fields() {
+    let mount_directory = "/tmp/ckv_test_test_column_group_fields";
+    let mut ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    ikv_config.stringConfigs.insert(
+        "column_group_fields".to_string(),
+        format!("{}, {}", DOCFIELD2, DOCFIELD3),
+    );
+
+    // column files of written segments
+    let index_directory = format!(
+        "{}/index",
+        utils::paths::get_index_mount_directory_fqn(&ikv_config).unwrap()
+    );
+    let column_files = || -> Vec<String> {
+        let mut column_files = vec![];
+        for segment in std::fs::read_dir(&index_directory).unwrap() {
+            for file in std::fs::read_dir(segment.unwrap().path()).unwrap() {
+                let file_name = file.unwrap().file_name().into_string().unwrap();
+                if file_name.starts_with("column_") && !column_files.contains(&file_name) {
+                    column_files.push(file_name);
+                }
+            }
+        }
+        column_files.sort();
+        column_files
+    };
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    for i in 0..3 {
+        index
+            .upsert_field_values(&utils::testing::create_document(i))
+            .unwrap();
+    }
+    let field_names = index.field_names();
+    let mut expected_column_files: Vec<String> = [DOCFIELD2, DOCFIELD3]
+        .iter()
+        .map(|field_name| {
+            let field_id = field_names.iter().position(|f| f == field_name).unwrap();
+            format!("column_{}", field_id)
+        })
+        .collect();
+    expected_column_files.sort();
+    assert_eq!(column_files(), expected_column_files);
+
+    // increments of column values
+    let mut increment = HashMap::new();
+    increment.insert(
+        PRIMARY_KEY_FIELD_NAME.to_string(),
+        string_to_field_value("field0:1"),
+    );
+    increment.insert(DOCFIELD3.to_string(), i32_to_field_value(5));
+    index
+        .increment_field_values_with_options(&increment, &WriteOptions::default())
+        .unwrap();
+    index.close().unwrap();
+
+    let report = CKVIndex::verify_index(&ikv_config).unwrap();
+    assert!(report.is_valid(), "{}", report.problems_summary());
+
+    // layout change applies to new writes, existing values stay readable
+    ikv_config.stringConfigs.remove("column_group_fields");
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index
+        .upsert_field_values(&utils::testing::create_document(3))
+        .unwrap();
+    for i in 0..4 {
+        let doc = utils::testing::create_document(i);
+        let pkey = doc.get(PRIMARY_KEY_FIELD_NAME).unwrap().value.clone();
+        assert_eq!(
+            index.get_field_value(&pkey, DOCFIELD2).unwrap(),
+            doc.get(DOCFIELD2).unwrap().value
+        );
+    }
+    assert_eq!(
+        index.get_field_value(b"field0:1", DOCFIELD3).unwrap(),
+        6i32.to_le_bytes()
+    );
+    let stats = index.index_stats().unwrap();
+    assert_eq!(stats.segment_num_documents.iter().sum::<u64>(), 4);
+    // value replaced by the increment
+    assert!(stats.mmap_dead_bytes > 0);
+
+    // compaction moves values to the configured layout
+    index.compact_and_close().unwrap();
+    assert!(column_files().is_empty());
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert_eq!(
+        index.get_field_value(b"field0:2", DOCFIELD2).unwrap(),
+        b"field2:2"
+    );
+    assert_eq!(
+        index.get_field_value(b"field0:1", DOCFIELD3).unwrap(),
+        6i32.to_le_bytes()
+    );
+    index.close().unwrap();
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_drop_all_column_group_fields() {
+    let mount_directory = "/tmp/ckv_test_test_drop_all_column_group_fields";
+    let mut ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    ikv_config.stringConfigs.insert(
+        "column_group_fields".to_string(),
+        format!("{}, {}", DOCFIELD2, DOCFIELD3),
+    );
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    for i in 0..10 {
+        index
+            .upsert_field_values(&utils::testing::create_document(i))
+            .unwrap();
+    }
+    index.flush_writes().unwrap();
+
+    // deletes column files, which must not be expected on reopen
+    index.drop_all_documents().unwrap();
+    index.flush_writes().unwrap();
+    drop(index);
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    let stats = index.index_stats().unwrap();
+    assert_eq!(stats.segment_num_documents.iter().sum::<u64>(), 0);
+
+    // new writes recreate column files
+    let doc = utils::testing::create_document(0);
+    index.upsert_field_values(&doc).unwrap();
+    index.close().unwrap();
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert_eq!(
+        index.get_field_value(b"field0:0", DOCFIELD2).unwrap(),
+        doc.get(DOCFIELD2).unwrap().value
+    );
+    index.close().unwrap();
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_value_cache() {
+    let mount_directory = "/tmp/ckv_test_test_value_cache";
+    let mut ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    ikv_config
+        .intConfigs
+        .insert("value_cache_size_bytes".to_string(), 1024 * 1024);
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index
+        .upsert_field_values(&utils::testing::create_document(0))
+        .unwrap();
+
+    // first read populates the cache
+    for _ in 0..2 {
+        assert_eq!(
+            index.get_field_value(b"field0:0", DOCFIELD1).unwrap(),
+            b"field1:0"
+        );
+    }
+    let stats = index.value_cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.num_entries), (1, 1, 1));
+    assert_eq!(stats.hit_rate, 0.5);
+
+    // upserts invalidate cached values
+    let mut document = HashMap::new();
+    document.insert(
+        PRIMARY_KEY_FIELD_NAME.to_string(),
+        string_to_field_value("field0:0"),
+    );
+    document.insert(DOCFIELD1.to_string(), string_to_field_value("updated"));
+    index.upsert_field_values(&document).unwrap();
+    assert_eq!(
+        index.get_field_value(b"field0:0", DOCFIELD1).unwrap(),
+        b"updated"
+    );
+
+    // batch reads are served from the cache as well
+    let result = index.batch_get_field_values(vec![b"field0:0"], vec![DOCFIELD1, DOCFIELD2]);
+    let mut expected = vec![];
+    for value in [b"updated".as_slice(), b"field2:0"] {
+        expected.extend((value.len() as i32).to_le_bytes());
+        expected.extend(value);
+    }
+    assert_eq!(result, expected);
+    assert_eq!(index.value_cache_stats().hits, 2);
+
+    // deletes invalidate cached values
+    index
+        .delete_field_values_with_options(
+            &document,
+            &[DOCFIELD1.to_string()],
+            &WriteOptions::default(),
+        )
+        .unwrap();
+    assert!(index.get_field_value(b"field0:0", DOCFIELD1).is_none());
+    assert!(index.get_field_value(b"field0:0", DOCFIELD2).is_some());
+    index
+        .delete_document_with_options(&document, &WriteOptions::default())
+        .unwrap();
+    assert!(index.get_field_value(b"field0:0", DOCFIELD2).is_none());
+    assert_eq!(index.value_cache_stats().num_entries, 0);
+    index.close().unwrap();
+
+    // disabled by default
+    ikv_config.intConfigs.remove("value_cache_size_bytes");
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    index
+        .upsert_field_values(&utils::testing::create_document(1))
+        .unwrap();
+    assert!(index.get_field_value(b"field0:1", DOCFIELD1).is_some());
+    let stats = index.value_cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.num_entries), (0, 0, 0));
+    index.close().unwrap();
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
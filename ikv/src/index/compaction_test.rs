@@ -1,11 +1,14 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
+use protobuf::Message;
+
+use crate::proto::generated_proto::index::CKVIndexHeader;
 use crate::utils::{
     self,
     testing::{i32_to_field_value, string_to_field_value, DOCFIELD1, DOCFIELD3},
 };
 
-use super::CKVIndex;
+use super::{CKVIndex, WriteOptions};
 
 #[test]
 pub fn drop_all_documents() {
@@ -25,7 +28,9 @@ pub fn drop_all_documents() {
     let index = CKVIndex::open_or_create(&ikv_config).unwrap();
     for docid in 0..1000 {
         let doc = utils::testing::create_document(docid);
-        index.delete_document(&doc).unwrap();
+        index
+            .delete_document_with_options(&doc, &WriteOptions::default())
+            .unwrap();
     }
 
     let (pre_stats, post_stats) = index.compact_and_close().unwrap();
@@ -124,3 +129,228 @@ pub fn change_fields() {
     index.close().unwrap();
     let _ = std::fs::remove_dir_all(&mount_directory);
 }
+
+#[test]
+pub fn reshard() {
+    let mount_directory: &str = "/tmp/compactions_test_reshard";
+    let mut ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    ikv_config
+        .intConfigs
+        .insert("num_index_segments".to_string(), 4);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+
+    // new index picks up configured segment count
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert_eq!(index.num_segments(), 4);
+    assert_eq!(index.read_index_header().unwrap().num_segments, 4);
+
+    for docid in 0..100 {
+        let doc = utils::testing::create_document(docid);
+        index.upsert_field_values(&doc).unwrap();
+    }
+    let (pre_stats, post_stats) = index.reshard_and_close(7).unwrap();
+    assert!(pre_stats.mmap_file_size_bytes > 0);
+    assert!(post_stats.mmap_file_size_bytes > 0);
+
+    // re open, saved segment count wins over configured one
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert_eq!(index.num_segments(), 7);
+    assert!(CKVIndex::is_valid_index(&ikv_config).is_ok());
+
+    for docid in 0..100 {
+        let doc = utils::testing::create_document(docid);
+        let pkey = string_to_field_value(&format!("field0:{}", docid)).value;
+        assert_eq!(
+            index.get_field_value(&pkey, DOCFIELD1).unwrap(),
+            doc.get(DOCFIELD1).unwrap().value.clone()
+        );
+        assert_eq!(
+            index.get_field_value(&pkey, DOCFIELD3).unwrap(),
+            doc.get(DOCFIELD3).unwrap().value.clone()
+        );
+    }
+
+    // cleanup mount dir
+    index.close().unwrap();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn interrupted_reshard() {
+    let mount_directory: &str = "/tmp/compactions_test_interrupted_reshard";
+    let mut ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    ikv_config
+        .intConfigs
+        .insert("num_index_segments".to_string(), 4);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    let index_mount_directory = utils::paths::get_index_mount_directory_fqn(&ikv_config).unwrap();
+
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    for docid in 0..100 {
+        let doc = utils::testing::create_document(docid);
+        index.upsert_field_values(&doc).unwrap();
+    }
+    index.reshard_and_close(7).unwrap();
+
+    // crash state: resharding committed, old index moved aside but new one not switched in
+    let header_filepath = format!("{}/header", &index_mount_directory);
+    std::fs::copy(
+        &header_filepath,
+        format!("{}/reshard", &index_mount_directory),
+    )
+    .unwrap();
+    let mut header =
+        CKVIndexHeader::parse_from_bytes(&std::fs::read(&header_filepath).unwrap()).unwrap();
+    header.num_segments = 4;
+    std::fs::write(&header_filepath, header.write_to_bytes().unwrap()).unwrap();
+    std::fs::rename(
+        format!("{}/index", &index_mount_directory),
+        format!("{}/resharded_index", &index_mount_directory),
+    )
+    .unwrap();
+    std::fs::create_dir_all(format!(
+        "{}/pre_reshard_index/segment_0",
+        &index_mount_directory
+    ))
+    .unwrap();
+
+    // re open completes resharding
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    assert_eq!(index.num_segments(), 7);
+    assert_eq!(index.read_index_header().unwrap().num_segments, 7);
+    for filename in ["reshard", "resharded_index", "pre_reshard_index"] {
+        assert!(!Path::new(&format!("{}/{}", &index_mount_directory, filename)).exists());
+    }
+    for docid in 0..100 {
+        let doc = utils::testing::create_document(docid);
+        let pkey = string_to_field_value(&format!("field0:{}", docid)).value;
+        assert_eq!(
+            index.get_field_value(&pkey, DOCFIELD1).unwrap(),
+            doc.get(DOCFIELD1).unwrap().value.clone()
+        );
+    }
+
+    // cleanup mount dir
+    index.close().unwrap();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn online_compaction() {
+    let mount_directory: &str = "/tmp/compactions_test_online_compaction";
+    let ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+
+    // insert 100 docs, overwrite them 4 times, drop field1
+    for _ in 0..5 {
+        for docid in 0..100 {
+            let doc = utils::testing::create_document(docid);
+            index.upsert_field_values(&doc).unwrap();
+        }
+    }
+    index
+        .drop_fields(&vec![DOCFIELD1.to_string()], &vec![])
+        .unwrap();
+
+    for index_id in 0..index.num_segments() {
+        let pre_stats = index.segment_compaction_stats(index_id).unwrap();
+        assert!(pre_stats.garbage_ratio() > 0.75);
+
+        index.compact_segment(index_id).unwrap();
+
+        let post_stats = index.segment_compaction_stats(index_id).unwrap();
+        assert_eq!(post_stats.garbage_ratio(), 0.0);
+        assert!(post_stats.mmap_used_bytes < pre_stats.mmap_used_bytes);
+    }
+
+    // compacted segments accept writes
+    let mut doc = utils::testing::create_document(100);
+    doc.remove(DOCFIELD1);
+    index.upsert_field_values(&doc).unwrap();
+    index.close().unwrap();
+
+    // re open and read
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    for docid in 0..101 {
+        let doc = utils::testing::create_document(docid);
+        let pkey = string_to_field_value(&format!("field0:{}", docid)).value;
+        assert!(index.get_field_value(&pkey, DOCFIELD1).is_none());
+        assert_eq!(
+            index.get_field_value(&pkey, DOCFIELD3).unwrap(),
+            doc.get(DOCFIELD3).unwrap().value.clone()
+        );
+    }
+
+    // cleanup mount dir
+    index.close().unwrap();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn online_compaction_with_concurrent_writes() {
+    let mount_directory: &str = "/tmp/compactions_test_online_compaction_with_concurrent_writes";
+    let ikv_config = utils::testing::setup_index_cfg(&mount_directory);
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+
+    for _ in 0..3 {
+        for docid in 0..1000 {
+            let doc = utils::testing::create_document(docid);
+            index.upsert_field_values(&doc).unwrap();
+        }
+    }
+
+    // overwrite, delete and write new fields while segments are compacted
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for docid in 0..1000 {
+                let mut doc = utils::testing::create_document(docid);
+                doc.insert(
+                    "new_field".to_string(),
+                    string_to_field_value(&format!("new:{}", docid)),
+                );
+                index.upsert_field_values(&doc).unwrap();
+            }
+            for docid in 0..100 {
+                let doc = utils::testing::create_document(docid);
+                index
+                    .delete_document_with_options(&doc, &WriteOptions::default())
+                    .unwrap();
+            }
+        });
+        for _ in 0..3 {
+            for index_id in 0..index.num_segments() {
+                index.compact_segment(index_id).unwrap();
+            }
+        }
+    });
+
+    for index_id in 0..index.num_segments() {
+        index.compact_segment(index_id).unwrap();
+    }
+    index.close().unwrap();
+
+    // re open and read
+    let index = CKVIndex::open_or_create(&ikv_config).unwrap();
+    for docid in 0..1000 {
+        let doc = utils::testing::create_document(docid);
+        let pkey = string_to_field_value(&format!("field0:{}", docid)).value;
+        if docid < 100 {
+            assert!(index.get_field_value(&pkey, DOCFIELD3).is_none());
+            continue;
+        }
+        assert_eq!(
+            index.get_field_value(&pkey, DOCFIELD3).unwrap(),
+            doc.get(DOCFIELD3).unwrap().value.clone()
+        );
+        assert_eq!(
+            index.get_field_value(&pkey, "new_field").unwrap(),
+            format!("new:{}", docid).into_bytes()
+        );
+    }
+
+    // cleanup mount dir
+    index.close().unwrap();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
@@ -1,6 +1,4 @@
 use std::{
-    fs::{File, OpenOptions},
-    io::{BufReader, BufWriter, Read, Seek, Write},
     path::Path,
     sync::RwLock,
     time::{SystemTime, UNIX_EPOCH},
@@ -11,27 +9,20 @@ use protobuf::Message;
 
 use crate::proto::generated_proto::index::CKVIndexHeader;
 
+use super::metadata_file::MetadataFile;
+
 /// Manages index level headers
 #[derive(Debug)]
 pub struct HeaderStore {
     lock: RwLock<()>,
-    file: File,
+    file: MetadataFile,
 }
 
-// TODO: implementation is very similar to offset_store.rs
-// Reuser.
 impl HeaderStore {
     fn new(mount_directory: &str) -> anyhow::Result<Self> {
-        let filename = format!("{}/header", mount_directory);
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create_new(true)
-            .open(filename)?;
-
         let store = HeaderStore {
             lock: RwLock::new(()),
-            file,
+            file: MetadataFile::new(mount_directory, "header"),
         };
 
         let mut header = CKVIndexHeader::new();
@@ -43,19 +34,12 @@ impl HeaderStore {
     }
 
     pub fn open_or_create(mount_directory: &str) -> anyhow::Result<Self> {
-        let filename = format!("{}/header", mount_directory);
-        if !Path::new(&filename).exists() {
+        let file = MetadataFile::new(mount_directory, "header");
+        if !file.exists() {
             // does not exist on disk, create new
             return HeaderStore::new(mount_directory);
         }
 
-        // Exists on disk
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(false)
-            .open(filename)?;
-
         Ok(Self {
             lock: RwLock::new(()),
             file,
@@ -68,10 +52,7 @@ impl HeaderStore {
     }
 
     pub fn delete_all(mount_directory: &str) -> anyhow::Result<()> {
-        let filepath = format!("{}/header", mount_directory);
-        if Path::new(&filepath).exists() {
-            std::fs::remove_file(&filepath)?;
-        }
+        MetadataFile::new(mount_directory, "header").delete()?;
         Ok(())
     }
 
@@ -86,13 +67,10 @@ impl HeaderStore {
     }
 
     pub fn read_header(&self) -> anyhow::Result<CKVIndexHeader> {
-        let mut bytes = Vec::new();
-        {
+        let bytes = {
             let _guard = self.lock.read().unwrap();
-            let mut reader = BufReader::new(&self.file);
-            reader.rewind()?;
-            reader.read_to_end(&mut bytes)?;
-        }
+            self.file.read()?.unwrap_or_default()
+        };
 
         if bytes.len() == 0 {
             bail!("ckv index header not present on disk.");
@@ -105,13 +83,8 @@ impl HeaderStore {
     pub fn write_header(&self, header: &CKVIndexHeader) -> anyhow::Result<()> {
         let bytes = header.write_to_bytes()?;
 
-        let _guard = self.lock.write();
-
-        let mut writer = BufWriter::new(&self.file);
-        writer.rewind()?;
-        writer.write_all(&bytes)?;
-        writer.flush()?;
-
+        let _guard = self.lock.write().unwrap();
+        self.file.write(&bytes)?;
         Ok(())
     }
 }
@@ -0,0 +1,80 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+#[cfg(test)]
+#[path = "metadata_file_test.rs"]
+mod metadata_file_test;
+
+/// Small metadata file (schema, headers, offsets..) which is rewritten as a whole.
+///
+/// Writes are crash-safe: contents are written to a temporary file which is
+/// synced and then renamed over the file, so that readers see either the old
+/// or the new contents, never a truncated or partial file.
+#[derive(Debug)]
+pub struct MetadataFile {
+    directory: PathBuf,
+    path: PathBuf,
+    tmp_path: PathBuf,
+}
+
+impl MetadataFile {
+    pub fn new(mount_directory: &str, filename: &str) -> Self {
+        let directory = PathBuf::from(mount_directory);
+        let path = directory.join(filename);
+        let tmp_path = directory.join(format!("{}.tmp", filename));
+        Self {
+            directory,
+            path,
+            tmp_path,
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Contents of the file, None if it does not exist.
+    /// Leftovers of interrupted writes are ignored.
+    pub fn read(&self) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Atomically replaces contents of the file (or creates it).
+    pub fn write(&self, contents: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+
+        fs::rename(&self.tmp_path, &self.path)?;
+
+        // persist the rename
+        sync_directory(&self.directory)
+    }
+
+    /// Deletes the file, along with leftovers of interrupted writes.
+    pub fn delete(&self) -> io::Result<()> {
+        for path in [&self.path, &self.tmp_path] {
+            match fs::remove_file(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn sync_directory(directory: &Path) -> io::Result<()> {
+    File::open(directory)?.sync_all()
+}
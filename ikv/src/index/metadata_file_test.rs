@@ -0,0 +1,96 @@
+use crate::index::metadata_file::MetadataFile;
+
+#[test]
+pub fn test_lifecycle() {
+    // create mount dir
+    let mount_directory = "/tmp/metadata_file_test_test_lifecycle";
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    std::fs::create_dir_all(&mount_directory).unwrap();
+
+    let file = MetadataFile::new(mount_directory, "header");
+    assert!(!file.exists());
+    assert_eq!(file.read().unwrap(), None);
+
+    file.write(b"first").unwrap();
+    assert!(file.exists());
+    assert_eq!(file.read().unwrap().unwrap(), b"first");
+
+    // shorter contents replace longer ones entirely
+    file.write(b"2nd").unwrap();
+    assert_eq!(file.read().unwrap().unwrap(), b"2nd");
+    file.write(b"").unwrap();
+    assert_eq!(file.read().unwrap().unwrap(), b"");
+
+    file.delete().unwrap();
+    assert!(!file.exists());
+    file.delete().unwrap();
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+// Crash states of a write: temporary file partially or fully written, before the rename.
+const TEMP_CONTENTS: [Option<&[u8]>; 4] =
+    [None, Some(b""), Some(b"new con"), Some(b"new contents")];
+
+#[test]
+pub fn test_crash_during_overwrite() {
+    // create mount dir
+    let mount_directory = "/tmp/metadata_file_test_test_crash_during_overwrite";
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    std::fs::create_dir_all(&mount_directory).unwrap();
+    let tmp_filename = format!("{}/schema.tmp", mount_directory);
+
+    for temp_contents in TEMP_CONTENTS {
+        let file = MetadataFile::new(mount_directory, "schema");
+        file.write(b"old contents").unwrap();
+        if let Some(temp_contents) = temp_contents {
+            std::fs::write(&tmp_filename, temp_contents).unwrap();
+        }
+
+        // old contents until renamed, never partial
+        let file = MetadataFile::new(mount_directory, "schema");
+        assert_eq!(
+            file.read().unwrap().unwrap(),
+            b"old contents",
+            "{:?}",
+            temp_contents
+        );
+
+        // recovers with next write
+        file.write(b"next contents").unwrap();
+        assert_eq!(file.read().unwrap().unwrap(), b"next contents");
+        assert!(!std::path::Path::new(&tmp_filename).exists());
+        file.delete().unwrap();
+    }
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+pub fn test_crash_during_create() {
+    // create mount dir
+    let mount_directory = "/tmp/metadata_file_test_test_crash_during_create";
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    std::fs::create_dir_all(&mount_directory).unwrap();
+    let tmp_filename = format!("{}/kafka_offsets.tmp", mount_directory);
+
+    for temp_contents in TEMP_CONTENTS {
+        if let Some(temp_contents) = temp_contents {
+            std::fs::write(&tmp_filename, temp_contents).unwrap();
+        }
+
+        // missing until renamed
+        let file = MetadataFile::new(mount_directory, "kafka_offsets");
+        assert!(!file.exists(), "{:?}", temp_contents);
+        assert_eq!(file.read().unwrap(), None);
+
+        // no leftovers after delete
+        file.delete().unwrap();
+        assert_eq!(std::fs::read_dir(mount_directory).unwrap().count(), 0);
+    }
+
+    // cleanup mount dir
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
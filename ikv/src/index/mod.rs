@@ -1,6 +1,11 @@
+mod ann;
 pub mod ckv;
 mod ckv_segment;
 mod header;
+mod metadata_file;
 pub mod offset_store;
+pub mod online_compactor;
 mod schema_store;
 mod stats;
+mod value_cache;
+pub mod verification;
@@ -1,9 +1,4 @@
-use std::{
-    fs::{File, OpenOptions},
-    io::{self, BufReader, BufWriter, Read, Seek, Write},
-    path::Path,
-    sync::RwLock,
-};
+use std::{io, path::Path, sync::RwLock};
 
 use anyhow::{anyhow, bail};
 use protobuf::Message;
@@ -11,13 +6,15 @@ use rdkafka::TopicPartitionList;
 
 use crate::proto::generated_proto::index::{KafkaOffsetStore, KafkaOffsetStoreEntry};
 
+use super::metadata_file::MetadataFile;
+
 #[cfg(test)]
 #[path = "offset_store_test.rs"]
 mod offset_store_test;
 
 pub struct OffsetStore {
     lock: RwLock<()>,
-    file: File,
+    file: MetadataFile,
 }
 
 /// NOTE - it is okay to store raw kafka offsets
@@ -25,23 +22,10 @@ pub struct OffsetStore {
 /// with time/size based retention in play (auto expiry by kafka).
 impl OffsetStore {
     pub fn open_or_create(mount_directory: String) -> io::Result<Self> {
-        let filename = format!("{}/kafka_offsets", mount_directory);
-        let file;
-
-        if !Path::new(&filename).exists() {
-            // does not exist on disk
-            file = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create_new(true)
-                .open(filename)?;
-        } else {
-            // Exists on disk
-            file = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(false)
-                .open(filename)?;
+        let file = MetadataFile::new(&mount_directory, "kafka_offsets");
+        if !file.exists() {
+            // does not exist on disk, create empty
+            file.write(&[])?;
         }
 
         Ok(Self {
@@ -56,10 +40,7 @@ impl OffsetStore {
     }
 
     pub fn delete_all(mount_directory: &str) -> anyhow::Result<()> {
-        let filepath = format!("{}/kafka_offsets", mount_directory);
-        if Path::new(&filepath).exists() {
-            std::fs::remove_file(&filepath)?;
-        }
+        MetadataFile::new(mount_directory, "kafka_offsets").delete()?;
         Ok(())
     }
 
@@ -77,11 +58,7 @@ impl OffsetStore {
         let _guard = self.lock.read().unwrap();
 
         let mut entries = Vec::with_capacity(1);
-        let mut bytes = Vec::new();
-
-        let mut reader = BufReader::new(&self.file);
-        reader.rewind()?;
-        reader.read_to_end(&mut bytes)?;
+        let bytes = self.file.read()?.unwrap_or_default();
 
         if bytes.len() == 0 {
             return Ok(entries);
@@ -99,8 +76,6 @@ impl OffsetStore {
         &self,
         topic_partition_list: &TopicPartitionList,
     ) -> anyhow::Result<()> {
-        let _guard = self.lock.write().unwrap();
-
         let mut entries = Vec::new();
         for elt in topic_partition_list.elements() {
             let mut entry = KafkaOffsetStoreEntry::new();
@@ -113,15 +88,18 @@ impl OffsetStore {
             entries.push(entry);
         }
 
+        self.write_entries(entries)
+    }
+
+    /// Replaces all stored offsets.
+    pub fn write_entries(&self, entries: Vec<KafkaOffsetStoreEntry>) -> anyhow::Result<()> {
+        let _guard = self.lock.write().unwrap();
+
         let mut kafka_offset_store = KafkaOffsetStore::new();
         kafka_offset_store.entries = entries;
 
         let bytes = kafka_offset_store.write_to_bytes()?;
-
-        let mut writer = BufWriter::new(&self.file);
-        writer.rewind()?;
-        writer.write_all(&bytes)?;
-        writer.flush()?;
+        self.file.write(&bytes)?;
 
         Ok(())
     }
@@ -0,0 +1,102 @@
+use std::{
+    sync::{mpsc, Arc},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use anyhow::bail;
+use log::{error, info};
+
+use crate::proto::generated_proto::common::IKVStoreConfig;
+
+use super::ckv::CKVIndex;
+
+// Interval between garbage checks over all segments, when not configured.
+const DEFAULT_INTERVAL_SECS: i64 = 600;
+
+// Segments with fewer used mmap bytes are not worth compacting.
+const MIN_MMAP_USED_BYTES: u64 = 8 * 1024 * 1024; // 8M
+
+/// Background compactor for a live index. Periodically computes the garbage ratio
+/// of each segment, and compacts segments (one at a time) crossing the configured ratio.
+pub struct OnlineCompactor {
+    // dropped to stop the background thread
+    stop_sender: mpsc::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl OnlineCompactor {
+    /// Starts background compaction, if enabled with "online_compaction_garbage_ratio".
+    pub fn start(config: &IKVStoreConfig, index: Arc<CKVIndex>) -> anyhow::Result<Option<Self>> {
+        let garbage_ratio = match config
+            .floatConfigs
+            .get("online_compaction_garbage_ratio")
+            .copied()
+        {
+            None => return Ok(None),
+            Some(ratio) if ratio <= 0.0 || ratio > 1.0 => {
+                bail!("online_compaction_garbage_ratio bad value: {}", ratio)
+            }
+            Some(ratio) => ratio as f64,
+        };
+
+        let interval_secs = config
+            .intConfigs
+            .get("online_compaction_interval_secs")
+            .copied()
+            .unwrap_or(DEFAULT_INTERVAL_SECS);
+        if interval_secs <= 0 {
+            bail!(
+                "online_compaction_interval_secs bad value: {}",
+                interval_secs
+            );
+        }
+        let interval = Duration::from_secs(interval_secs as u64);
+
+        info!(
+            "Starting online compaction, garbage ratio: {} interval: {}s",
+            garbage_ratio, interval_secs
+        );
+
+        let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+        let handle = std::thread::spawn(move || {
+            // wakes up every interval, till the sender is dropped
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                if let Err(e) = compact_segments(&index, garbage_ratio) {
+                    error!("Online compaction failed, error: {}", e);
+                }
+            }
+        });
+
+        Ok(Some(OnlineCompactor {
+            stop_sender,
+            handle,
+        }))
+    }
+
+    /// Stops background compaction, waits for in-progress segment compaction to finish.
+    pub fn stop(self) {
+        drop(self.stop_sender);
+        if self.handle.join().is_err() {
+            error!("Online compaction thread panicked");
+        }
+    }
+}
+
+fn compact_segments(index: &CKVIndex, garbage_ratio: f64) -> anyhow::Result<()> {
+    for index_id in 0..index.num_segments() {
+        let stats = index.segment_compaction_stats(index_id)?;
+        if stats.mmap_used_bytes < MIN_MMAP_USED_BYTES || stats.garbage_ratio() < garbage_ratio {
+            continue;
+        }
+
+        info!(
+            "Index segment: {} has garbage ratio: {:.3}, compacting",
+            index_id,
+            stats.garbage_ratio()
+        );
+        index.compact_segment(index_id)?;
+    }
+
+    Ok(())
+}
@@ -1,15 +1,18 @@
 use std::{
-    collections::HashMap,
-    fs::OpenOptions,
-    io::{BufReader, BufWriter, Read, Write},
+    collections::{HashMap, HashSet},
     path::Path,
 };
 
 use anyhow::bail;
 use protobuf::Message;
 
-use crate::proto::generated_proto::{common::FieldValue, index::SavedCKVIndexSchema};
-use crate::schema::field::FieldId;
+use crate::proto::generated_proto::{
+    common::{FieldType, FieldValue},
+    index::{CompressionCodec, SavedCKVIndexSchema},
+};
+use crate::schema::{field::FieldId, vector};
+
+use super::metadata_file::MetadataFile;
 
 #[cfg(test)]
 #[path = "schema_store_test.rs"]
@@ -27,6 +30,21 @@ pub struct CKVIndexSchema {
     // Current value denotes an available id, i.e. [0, current-1] are already taken.
     // It's value always increases, and can only go down if compacted.
     field_id_counter: u64,
+
+    // field-name -> compression codec, for fields with compressed values
+    field_name_to_codec: HashMap<String, CompressionCodec>,
+
+    // field-name -> time-to-live (millis), for fields with expiring values
+    field_name_to_ttl_millis: HashMap<String, u64>,
+
+    // field-name -> vector dimension, for vector fields
+    field_name_to_vector_dims: HashMap<String, u32>,
+
+    // field-name -> vector type (element type), for vector fields
+    field_name_to_vector_type: HashMap<String, FieldType>,
+
+    // field-name -> declared value type, recorded on first write
+    field_name_to_type: HashMap<String, FieldType>,
 }
 
 impl CKVIndexSchema {
@@ -41,6 +59,11 @@ impl CKVIndexSchema {
             primary_key_field_name: primary_key,
             field_name_to_id,
             field_id_counter: 1,
+            field_name_to_codec: HashMap::new(),
+            field_name_to_ttl_millis: HashMap::new(),
+            field_name_to_vector_dims: HashMap::new(),
+            field_name_to_vector_type: HashMap::new(),
+            field_name_to_type: HashMap::new(),
         };
 
         index.save()?;
@@ -48,19 +71,14 @@ impl CKVIndexSchema {
     }
 
     pub fn open_or_create(mount_directory: &str, primary_key: String) -> anyhow::Result<Self> {
-        let file_path = format!("{}/schema", mount_directory);
-        if !Path::new(&file_path).exists() {
+        let contents = match MetadataFile::new(mount_directory, "schema").read()? {
             // no schema file, assume new store for host
-            return CKVIndexSchema::new(mount_directory, primary_key);
-        }
-
-        let file = OpenOptions::new().read(true).open(file_path)?;
-        let mut reader = BufReader::new(file);
-
-        let mut contents: Vec<u8> = vec![];
-        reader.read_to_end(&mut contents)?;
-        if contents.len() == 0 {
-            // empty schema file, unreachable
+            None => return CKVIndexSchema::new(mount_directory, primary_key),
+            Some(contents) => contents,
+        };
+        if contents.is_empty() {
+            // unreachable with atomic writes (see MetadataFile), but
+            // possible for schemas saved in place by older versions
             // TODO: add this to is_valid_index check
             bail!("Index schema store has no content and no primary key, invalid state");
         }
@@ -72,11 +90,38 @@ impl CKVIndexSchema {
             field_id_counter = saved_schema.field_id_counter;
         }
 
+        let field_name_to_codec = saved_schema
+            .field_compression_codecs
+            .iter()
+            .map(|(field_name, codec)| (field_name.clone(), codec.enum_value_or_default()))
+            .collect();
+
+        let field_name_to_vector_type = saved_schema
+            .field_vector_types
+            .iter()
+            .map(|(field_name, field_type)| {
+                (field_name.clone(), field_type.enum_value_or_default())
+            })
+            .collect();
+
+        let field_name_to_type = saved_schema
+            .field_types
+            .iter()
+            .map(|(field_name, field_type)| {
+                (field_name.clone(), field_type.enum_value_or_default())
+            })
+            .collect();
+
         Ok(CKVIndexSchema {
             mount_directory: mount_directory.to_string(),
             primary_key_field_name: saved_schema.primary_key_field_name,
             field_name_to_id: saved_schema.field_ids,
             field_id_counter,
+            field_name_to_codec,
+            field_name_to_ttl_millis: saved_schema.field_ttl_millis,
+            field_name_to_vector_dims: saved_schema.field_vector_dims,
+            field_name_to_vector_type,
+            field_name_to_type,
         })
     }
 
@@ -101,10 +146,7 @@ impl CKVIndexSchema {
     }
 
     pub fn delete_all(mount_directory: &str) -> anyhow::Result<()> {
-        let filepath = format!("{}/schema", mount_directory);
-        if Path::new(&filepath).exists() {
-            std::fs::remove_file(&filepath)?;
-        }
+        MetadataFile::new(mount_directory, "schema").delete()?;
         Ok(())
     }
 
@@ -135,10 +177,144 @@ impl CKVIndexSchema {
         Ok(new_fid_to_old_fid)
     }
 
+    /// Field id mapping (new-field-id -> old-field-id) which keeps field ids unchanged,
+    /// for compacting segments without compacting the schema. Dropped fields map to FieldId::MAX.
+    pub fn uncompacted_fid_mapping(&self) -> Vec<FieldId> {
+        let mut new_fid_to_old_fid = vec![FieldId::MAX; self.field_id_counter as usize];
+        for field_id in self.field_name_to_id.values().copied() {
+            if let Some(old_fid) = new_fid_to_old_fid.get_mut(field_id as usize) {
+                *old_fid = field_id;
+            }
+        }
+        new_fid_to_old_fid
+    }
+
+    /// Ids of all (not dropped) fields.
+    pub fn field_ids(&self) -> HashSet<FieldId> {
+        self.field_name_to_id.values().copied().collect()
+    }
+
+    pub fn primary_key_field_name(&self) -> &str {
+        &self.primary_key_field_name
+    }
+
+    /// Names of all (not dropped) fields, ordered by field id (primary key first).
+    pub fn field_names(&self) -> Vec<String> {
+        let mut field_names: Vec<(&String, &FieldId)> = self.field_name_to_id.iter().collect();
+        field_names.sort_by_key(|(_, field_id)| **field_id);
+        field_names
+            .into_iter()
+            .map(|(field_name, _)| field_name.clone())
+            .collect()
+    }
+
+    /// Next id to be assigned, all assigned ids are smaller.
+    pub fn field_id_counter(&self) -> u64 {
+        self.field_id_counter
+    }
+
+    /// Checks consistency of field id assignments.
+    pub fn verify(&self) -> anyhow::Result<()> {
+        if self.fetch_id_by_name(&self.primary_key_field_name) != Some(0) {
+            bail!("primary-key field does not have id 0");
+        }
+        if self.field_ids().len() != self.field_name_to_id.len() {
+            bail!("same id is assigned to multiple fields");
+        }
+        if let Some(field_id) = self
+            .field_name_to_id
+            .values()
+            .find(|field_id| **field_id as u64 >= self.field_id_counter)
+        {
+            bail!(
+                "field id: {} is not below field id counter: {}",
+                field_id,
+                self.field_id_counter
+            );
+        }
+        Ok(())
+    }
+
+    /// True if field ids of `other` (ex. schema of an older copy of the index) identify
+    /// the same fields here, i.e. its fields have the same ids, or are dropped here
+    /// without their ids being reused.
+    pub fn has_compatible_field_ids(&self, other: &CKVIndexSchema) -> bool {
+        if self.primary_key_field_name != other.primary_key_field_name {
+            return false;
+        }
+
+        let field_ids = self.field_ids();
+        other.field_name_to_id.iter().all(|(field_name, field_id)| {
+            match self.field_name_to_id.get(field_name) {
+                Some(id) => id == field_id,
+                None => !field_ids.contains(field_id) && (*field_id as u64) < self.field_id_counter,
+            }
+        })
+    }
+
     pub fn fetch_id_by_name(&self, field_name: &str) -> Option<FieldId> {
         self.field_name_to_id.get(field_name).copied()
     }
 
+    /// Declared type of a field, None if unknown or not written yet.
+    pub fn fetch_type_by_name(&self, field_name: &str) -> Option<FieldType> {
+        self.field_name_to_type.get(field_name).copied()
+    }
+
+    /// Returns true if some fields of the document don't have a declared type yet.
+    pub fn has_undeclared_types(&self, document: &HashMap<String, FieldValue>) -> bool {
+        document.iter().any(|(field_name, field_value)| {
+            field_value.fieldType.enum_value_or_default() != FieldType::UNKNOWN
+                && !self.field_name_to_type.contains_key(field_name)
+        })
+    }
+
+    /// Compression codec for values of a field.
+    pub fn fetch_codec_by_name(&self, field_name: &str) -> CompressionCodec {
+        self.field_name_to_codec
+            .get(field_name)
+            .copied()
+            .unwrap_or(CompressionCodec::UNCOMPRESSED)
+    }
+
+    /// Replaces per-field compression codecs. Applies to new writes only,
+    /// since each stored value records whether it is compressed.
+    pub fn set_compression_codecs(
+        &mut self,
+        field_name_to_codec: HashMap<String, CompressionCodec>,
+    ) -> anyhow::Result<()> {
+        if self.field_name_to_codec == field_name_to_codec {
+            return Ok(());
+        }
+
+        self.field_name_to_codec = field_name_to_codec;
+        self.save()?;
+        Ok(())
+    }
+
+    /// Time-to-live (millis) of values of a field, 0 if they do not expire.
+    pub fn fetch_ttl_millis_by_name(&self, field_name: &str) -> u64 {
+        self.field_name_to_ttl_millis
+            .get(field_name)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Replaces per-field time-to-live. Applies to new writes only,
+    /// since each stored value records its own expiry.
+    pub fn set_ttls(
+        &mut self,
+        field_name_to_ttl_millis: HashMap<String, u64>,
+    ) -> anyhow::Result<()> {
+        if self.field_name_to_ttl_millis == field_name_to_ttl_millis {
+            return Ok(());
+        }
+
+        self.field_name_to_ttl_millis = field_name_to_ttl_millis;
+        self.save()?;
+        Ok(())
+    }
+
     pub fn extract_primary_key_value<'a>(
         &self,
         document: &'a HashMap<String, FieldValue>,
@@ -146,13 +322,90 @@ impl CKVIndexSchema {
         document.get(&self.primary_key_field_name)
     }
 
+    /// Checks type and dimension of vector field values against the recorded ones of their
+    /// fields, values of other types cannot be written to vector fields.
+    /// Returns true if some vector fields don't have a recorded type or dimension yet.
+    pub fn check_vector_fields(
+        &self,
+        document: &HashMap<String, FieldValue>,
+    ) -> anyhow::Result<bool> {
+        let mut has_new_vector_fields = false;
+        for (field_name, field_value) in document.iter() {
+            let field_type = field_value.fieldType.enum_value_or_default();
+            match self.field_name_to_vector_type.get(field_name) {
+                // vector fields saved before types were recorded, get the type of this write
+                None if vector::is_vector_type(field_type)
+                    && self.field_name_to_vector_dims.contains_key(field_name) =>
+                {
+                    has_new_vector_fields = true
+                }
+                Some(expected) if *expected != field_type => bail!(
+                    "Field: {} has vectors of type: {:?}, cannot write type: {:?}",
+                    field_name,
+                    expected,
+                    field_type
+                ),
+                _ => {}
+            }
+            if !vector::is_vector_type(field_type) {
+                continue;
+            }
+
+            let dimension = vector::dimension(field_type, &field_value.value)?;
+            match self.field_name_to_vector_dims.get(field_name) {
+                None => has_new_vector_fields = true,
+                Some(expected) if *expected != dimension => bail!(
+                    "Field: {} has vectors of dimension: {}, cannot write dimension: {}",
+                    field_name,
+                    expected,
+                    dimension
+                ),
+                Some(_) => {}
+            }
+        }
+
+        Ok(has_new_vector_fields)
+    }
+
     /// Update the internal fields table with new field-info if required.
     /// Known fields are skipped, new start getting tracked.
+    /// Vector types and dimensions are validated, and recorded for new vector fields.
+    /// Types are declared for fields written for the first time.
     ///
     /// TODO - This operation can fail partially - ie schema for only some fields gets updated.
     pub fn upsert_schema(&mut self, document: &HashMap<String, FieldValue>) -> anyhow::Result<()> {
         let mut updated = false;
 
+        if self.check_vector_fields(document)? {
+            for (field_name, field_value) in document.iter() {
+                let field_type = field_value.fieldType.enum_value_or_default();
+                if !vector::is_vector_type(field_type) {
+                    continue;
+                }
+                if !self.field_name_to_vector_dims.contains_key(field_name) {
+                    let dimension = vector::dimension(field_type, &field_value.value)?;
+                    self.field_name_to_vector_dims
+                        .insert(field_name.clone(), dimension);
+                    updated = true;
+                }
+                if !self.field_name_to_vector_type.contains_key(field_name) {
+                    self.field_name_to_vector_type
+                        .insert(field_name.clone(), field_type);
+                    updated = true;
+                }
+            }
+        }
+
+        for (field_name, field_value) in document.iter() {
+            let field_type = field_value.fieldType.enum_value_or_default();
+            if field_type != FieldType::UNKNOWN && !self.field_name_to_type.contains_key(field_name)
+            {
+                self.field_name_to_type
+                    .insert(field_name.clone(), field_type);
+                updated = true;
+            }
+        }
+
         let table = &mut self.field_name_to_id;
         for field_name in document.keys() {
             if !table.contains_key(field_name) {
@@ -200,6 +453,17 @@ impl CKVIndexSchema {
                 if self.field_name_to_id.remove(fieldname).is_some() {
                     updated = true;
                 }
+                // dropped vector fields can be recreated with a new dimension
+                if self.field_name_to_vector_dims.remove(fieldname).is_some() {
+                    updated = true;
+                }
+                if self.field_name_to_vector_type.remove(fieldname).is_some() {
+                    updated = true;
+                }
+                // and with a new type
+                if self.field_name_to_type.remove(fieldname).is_some() {
+                    updated = true;
+                }
             }
         }
 
@@ -219,6 +483,10 @@ impl CKVIndexSchema {
         let mut field_name_to_id = HashMap::new();
         field_name_to_id.insert(self.primary_key_field_name.clone(), 0 as FieldId);
         self.field_name_to_id = field_name_to_id;
+        self.field_name_to_vector_dims.clear();
+        self.field_name_to_vector_type.clear();
+        self.field_name_to_type
+            .retain(|field_name, _| *field_name == self.primary_key_field_name);
         self.save()?;
 
         Ok(())
@@ -236,18 +504,27 @@ impl CKVIndexSchema {
         saved_schema.primary_key_field_name = self.primary_key_field_name.clone();
         saved_schema.field_ids = field_ids;
         saved_schema.field_id_counter = self.field_id_counter;
+        for (fieldname, codec) in self.field_name_to_codec.iter() {
+            saved_schema
+                .field_compression_codecs
+                .insert(fieldname.clone(), (*codec).into());
+        }
+        saved_schema.field_ttl_millis = self.field_name_to_ttl_millis.clone();
+        saved_schema.field_vector_dims = self.field_name_to_vector_dims.clone();
+        for (fieldname, field_type) in self.field_name_to_vector_type.iter() {
+            saved_schema
+                .field_vector_types
+                .insert(fieldname.clone(), (*field_type).into());
+        }
+        for (fieldname, field_type) in self.field_name_to_type.iter() {
+            saved_schema
+                .field_types
+                .insert(fieldname.clone(), (*field_type).into());
+        }
 
         let contents = saved_schema.write_to_bytes()?;
 
-        // truncate existing schema file, write new version
-        let filepath = format!("{}/schema", self.mount_directory);
-        let file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(filepath)?;
-        let mut writer = BufWriter::new(file);
-        writer.write_all(&contents)?;
-        Ok(())
+        // atomically replace existing schema file
+        MetadataFile::new(&self.mount_directory, "schema").write(&contents)
     }
 }
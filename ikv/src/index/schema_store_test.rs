@@ -1,7 +1,8 @@
 use std::collections::HashMap;
 
 use crate::{
-    index::schema_store::CKVIndexSchema, proto::generated_proto::common::FieldValue,
+    index::schema_store::CKVIndexSchema,
+    proto::generated_proto::common::{FieldType, FieldValue},
     utils::testing::string_to_field_value,
 };
 
@@ -200,6 +201,9 @@ fn test_compact() {
     let new_fid_to_old_fid = schema_store.compact().unwrap();
     assert_eq!(new_fid_to_old_fid, vec![0, 1, 2]);
 
+    // ids of fields added together follow document (hashmap) order
+    let field2_id = schema_store.fetch_id_by_name("field2").unwrap();
+
     // close and reopen
     drop(schema_store);
     let mut schema_store =
@@ -214,9 +218,9 @@ fn test_compact() {
         .soft_delete_fields(&vec!["field1".to_string()], &Vec::new())
         .unwrap();
 
-    // compact (0->0, 1->2, 2->3)
+    // compact (0->0, 1->field2, 2->3)
     let new_fid_to_old_fid = schema_store.compact().unwrap();
-    assert_eq!(new_fid_to_old_fid, vec![0, 2, 3]);
+    assert_eq!(new_fid_to_old_fid, vec![0, field2_id, 3]);
 
     // drop all
     schema_store.hard_delete_all_fields().unwrap();
@@ -226,3 +230,124 @@ fn test_compact() {
     // cleanup mount dir
     let _ = std::fs::remove_dir_all(&mount_directory);
 }
+
+#[test]
+fn vector_dims() {
+    // create mount dir
+    let mount_directory = "/tmp/schema_store_test_vector_dims";
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    std::fs::create_dir_all(&mount_directory).unwrap();
+
+    let vector_document = |field_type: FieldType, num_bytes: usize| {
+        let mut embedding = FieldValue::new();
+        embedding.fieldType = field_type.into();
+        embedding.value = vec![0u8; num_bytes];
+
+        let mut document = create_document(0);
+        document.insert("embedding".to_string(), embedding);
+        document
+    };
+
+    let mut index =
+        CKVIndexSchema::open_or_create(&mount_directory, PRIMARY_KEY_FIELD_NAME.to_string())
+            .unwrap();
+
+    // dimension recorded on first write
+    let document = vector_document(FieldType::FLOAT32_VECTOR, 16);
+    assert!(index.check_vector_fields(&document).unwrap());
+    index.upsert_schema(&document).unwrap();
+    assert!(!index.check_vector_fields(&document).unwrap());
+
+    // same dimension, other element type
+    let document = vector_document(FieldType::INT8_VECTOR, 4);
+    assert!(index.check_vector_fields(&document).is_err());
+    assert!(index.upsert_schema(&document).is_err());
+
+    // not a vector
+    let document = vector_document(FieldType::BYTES, 16);
+    assert!(index.check_vector_fields(&document).is_err());
+
+    // wrong dimension, or partial elements
+    let document = vector_document(FieldType::FLOAT32_VECTOR, 20);
+    assert!(index.check_vector_fields(&document).is_err());
+    assert!(index.upsert_schema(&document).is_err());
+    let document = vector_document(FieldType::FLOAT16_VECTOR, 7);
+    assert!(index.check_vector_fields(&document).is_err());
+
+    // dimension survives reopen
+    index.close();
+    let mut index =
+        CKVIndexSchema::open_or_create(&mount_directory, PRIMARY_KEY_FIELD_NAME.to_string())
+            .unwrap();
+    let document = vector_document(FieldType::FLOAT32_VECTOR, 20);
+    assert!(index.upsert_schema(&document).is_err());
+    assert!(index
+        .upsert_schema(&vector_document(FieldType::INT8_VECTOR, 4))
+        .is_err());
+
+    // dropped fields can be recreated with a new dimension
+    index
+        .soft_delete_fields(&["embedding".to_string()], &[])
+        .unwrap();
+    index.upsert_schema(&document).unwrap();
+    assert!(!index.check_vector_fields(&document).unwrap());
+
+    // cleanup mount dir
+    index.close();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
+
+#[test]
+fn field_types() {
+    // create mount dir
+    let mount_directory = "/tmp/schema_store_test_field_types";
+    let _ = std::fs::remove_dir_all(&mount_directory);
+    std::fs::create_dir_all(&mount_directory).unwrap();
+
+    let mut index =
+        CKVIndexSchema::open_or_create(&mount_directory, PRIMARY_KEY_FIELD_NAME.to_string())
+            .unwrap();
+
+    // type declared on first write, unknown types are not declared
+    let mut document = create_document(0);
+    document.insert("unknown".to_string(), Default::default());
+    assert!(index.has_undeclared_types(&document));
+    index.upsert_schema(&document).unwrap();
+    assert!(!index.has_undeclared_types(&document));
+    assert_eq!(index.fetch_type_by_name("name"), Some(FieldType::STRING));
+    assert_eq!(index.fetch_type_by_name("unknown"), None);
+
+    // later writes do not change the declared type
+    let mut document = create_document(1);
+    document.insert("name".to_string(), Default::default());
+    document.get_mut("name").unwrap().fieldType = FieldType::INT32.into();
+    index.upsert_schema(&document).unwrap();
+    assert_eq!(index.fetch_type_by_name("name"), Some(FieldType::STRING));
+
+    // declared type survives reopen
+    index.close();
+    let mut index =
+        CKVIndexSchema::open_or_create(&mount_directory, PRIMARY_KEY_FIELD_NAME.to_string())
+            .unwrap();
+    assert_eq!(index.fetch_type_by_name("name"), Some(FieldType::STRING));
+
+    // dropped fields can be recreated with a new type
+    index
+        .soft_delete_fields(&["name".to_string()], &[])
+        .unwrap();
+    assert_eq!(index.fetch_type_by_name("name"), None);
+    index.upsert_schema(&document).unwrap();
+    assert_eq!(index.fetch_type_by_name("name"), Some(FieldType::INT32));
+
+    // primary-key keeps its type on hard delete
+    index.hard_delete_all_fields().unwrap();
+    assert_eq!(index.fetch_type_by_name("name"), None);
+    assert_eq!(
+        index.fetch_type_by_name(PRIMARY_KEY_FIELD_NAME),
+        Some(FieldType::STRING)
+    );
+
+    // cleanup mount dir
+    index.close();
+    let _ = std::fs::remove_dir_all(&mount_directory);
+}
@@ -2,21 +2,70 @@
 pub struct CompactionStats {
     pub offset_table_size_bytes: u64,
     pub mmap_file_size_bytes: u64,
+
+    // bytes appended to mmap file(s), live or overwritten
+    pub mmap_used_bytes: u64,
+
+    // bytes of mmap entries still referenced by the offset table
+    pub mmap_live_bytes: u64,
 }
 
 impl CompactionStats {
     pub fn aggregate(stats: &[CompactionStats]) -> CompactionStats {
         let mut offset_table_size_bytes = 0;
         let mut mmap_file_size_bytes = 0;
+        let mut mmap_used_bytes = 0;
+        let mut mmap_live_bytes = 0;
 
         for cs in stats.iter() {
             offset_table_size_bytes += cs.offset_table_size_bytes;
             mmap_file_size_bytes += cs.mmap_file_size_bytes;
+            mmap_used_bytes += cs.mmap_used_bytes;
+            mmap_live_bytes += cs.mmap_live_bytes;
         }
 
         return CompactionStats {
             offset_table_size_bytes,
             mmap_file_size_bytes,
+            mmap_used_bytes,
+            mmap_live_bytes,
         };
     }
+
+    /// Fraction of used mmap bytes which are no longer referenced,
+    /// ie. reclaimable by compaction.
+    pub fn garbage_ratio(&self) -> f64 {
+        if self.mmap_used_bytes == 0 {
+            return 0.0;
+        }
+        let live_bytes = std::cmp::min(self.mmap_live_bytes, self.mmap_used_bytes);
+        1.0 - (live_bytes as f64 / self.mmap_used_bytes as f64)
+    }
+}
+
+/// Document and value statistics of a single segment, see CKVIndex::index_stats().
+#[derive(Debug, Default)]
+pub struct SegmentDocumentStats {
+    // documents with at least one present value
+    pub num_documents: u64,
+
+    // present values and their (uncompressed) bytes, indexed by field id
+    pub field_num_values: Vec<u64>,
+    pub field_value_bytes: Vec<u64>,
+
+    // bytes appended to mmap file(s), live or garbage
+    pub mmap_used_bytes: u64,
+
+    // bytes of mmap entries which are referenced and not expired, of (not dropped) fields
+    pub mmap_live_bytes: u64,
+}
+
+/// Counters and size of the value cache of a single segment, see CKVIndex::value_cache_stats().
+#[derive(Debug, Default)]
+pub struct SegmentValueCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub num_entries: u64,
+    pub size_bytes: u64,
 }
@@ -0,0 +1,232 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{proto::generated_proto::common::FieldType, schema::field::FieldId};
+
+use super::{ckv_segment::now_millis, stats::SegmentValueCacheStats};
+
+#[cfg(test)]
+#[path = "value_cache_test.rs"]
+mod value_cache_test;
+
+// Approximate bookkeeping bytes of an entry (map slots, lru slot, key copy).
+const ENTRY_OVERHEAD_BYTES: u64 = 64;
+
+/// Size-bounded LRU cache of decoded (decompressed) field values, keyed on
+/// (primary_key, field_id). Split into independently locked shards to reduce
+/// contention between concurrent readers. Owned by a segment, which
+/// invalidates entries on every write to their document.
+#[derive(Debug)]
+pub struct ValueCache {
+    shards: Vec<Mutex<Shard>>,
+    shard_capacity_bytes: u64,
+
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// Cached value, along with its type and expiry (0 if none).
+#[derive(Debug)]
+pub struct CachedValue {
+    pub field_type: FieldType,
+    pub expires_at_millis: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct Shard {
+    // primary-key -> field-id -> (value, lru tick)
+    entries: HashMap<Vec<u8>, HashMap<FieldId, (CachedValue, u64)>>,
+
+    // lru tick -> key, least recently used first
+    lru: BTreeMap<u64, (Vec<u8>, FieldId)>,
+    next_tick: u64,
+    size_bytes: u64,
+}
+
+impl ValueCache {
+    /// Cache holding up to `capacity_bytes` (values and their keys), split into `num_shards`.
+    pub fn new(capacity_bytes: u64, num_shards: usize) -> Self {
+        let num_shards = num_shards.max(1);
+        let mut shards = Vec::with_capacity(num_shards);
+        for _ in 0..num_shards {
+            shards.push(Mutex::new(Shard::default()));
+        }
+
+        ValueCache {
+            shards,
+            shard_capacity_bytes: capacity_bytes / num_shards as u64,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Applies `f` to the cached value of a field, None on miss.
+    /// Expired values are dropped and count as misses.
+    pub fn get<F, R>(&self, primary_key: &[u8], field_id: FieldId, f: F) -> Option<R>
+    where
+        F: FnOnce(&CachedValue) -> R,
+    {
+        let result = self
+            .shard(primary_key)
+            .lock()
+            .unwrap()
+            .get(primary_key, field_id)
+            .map(f);
+        match result {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        result
+    }
+
+    /// Caches a value read from the segment. Values larger than a shard are not cached.
+    pub fn insert(&self, primary_key: &[u8], field_id: FieldId, value: CachedValue) {
+        let entry_size = entry_size(primary_key, &value);
+        if entry_size > self.shard_capacity_bytes {
+            return;
+        }
+
+        let mut shard = self.shard(primary_key).lock().unwrap();
+        shard.remove(primary_key, field_id);
+        let mut evictions = 0;
+        while shard.size_bytes + entry_size > self.shard_capacity_bytes && shard.evict_lru() {
+            evictions += 1;
+        }
+        shard.insert(primary_key, field_id, value);
+        drop(shard);
+
+        if evictions > 0 {
+            self.evictions.fetch_add(evictions, Ordering::Relaxed);
+        }
+    }
+
+    /// Drops cached values of the provided fields of a document.
+    pub fn invalidate_fields(&self, primary_key: &[u8], field_ids: &[FieldId]) {
+        let mut shard = self.shard(primary_key).lock().unwrap();
+        for field_id in field_ids {
+            shard.remove(primary_key, *field_id);
+        }
+    }
+
+    /// Drops all cached values of a document.
+    pub fn invalidate_document(&self, primary_key: &[u8]) {
+        self.shard(primary_key)
+            .lock()
+            .unwrap()
+            .remove_document(primary_key);
+    }
+
+    /// Drops all cached values, counters are kept.
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            *shard.lock().unwrap() = Shard::default();
+        }
+    }
+
+    pub fn stats(&self) -> SegmentValueCacheStats {
+        let mut stats = SegmentValueCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            ..Default::default()
+        };
+        for shard in self.shards.iter() {
+            let shard = shard.lock().unwrap();
+            stats.num_entries += shard.lru.len() as u64;
+            stats.size_bytes += shard.size_bytes;
+        }
+        stats
+    }
+
+    fn shard(&self, primary_key: &[u8]) -> &Mutex<Shard> {
+        let mut hasher = DefaultHasher::new();
+        primary_key.hash(&mut hasher);
+        &self.shards[(hasher.finish() % self.shards.len() as u64) as usize]
+    }
+}
+
+impl Shard {
+    fn get(&mut self, primary_key: &[u8], field_id: FieldId) -> Option<&CachedValue> {
+        let (value, tick) = self.entries.get(primary_key)?.get(&field_id)?;
+        if value.expires_at_millis != 0 && value.expires_at_millis <= now_millis() {
+            self.remove(primary_key, field_id);
+            return None;
+        }
+
+        // move to the back of the lru
+        let old_tick = *tick;
+        let new_tick = self.next_tick;
+        self.next_tick += 1;
+        let key = self.lru.remove(&old_tick).unwrap();
+        self.lru.insert(new_tick, key);
+        let (value, tick) = self
+            .entries
+            .get_mut(primary_key)
+            .unwrap()
+            .get_mut(&field_id)
+            .unwrap();
+        *tick = new_tick;
+
+        Some(value)
+    }
+
+    fn insert(&mut self, primary_key: &[u8], field_id: FieldId, value: CachedValue) {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.size_bytes += entry_size(primary_key, &value);
+        self.lru.insert(tick, (primary_key.to_vec(), field_id));
+        self.entries
+            .entry(primary_key.to_vec())
+            .or_default()
+            .insert(field_id, (value, tick));
+    }
+
+    fn remove(&mut self, primary_key: &[u8], field_id: FieldId) {
+        let fields = match self.entries.get_mut(primary_key) {
+            None => return,
+            Some(fields) => fields,
+        };
+        if let Some((value, tick)) = fields.remove(&field_id) {
+            self.size_bytes -= entry_size(primary_key, &value);
+            self.lru.remove(&tick);
+        }
+        if fields.is_empty() {
+            self.entries.remove(primary_key);
+        }
+    }
+
+    fn remove_document(&mut self, primary_key: &[u8]) {
+        if let Some(fields) = self.entries.remove(primary_key) {
+            for (value, tick) in fields.into_values() {
+                self.size_bytes -= entry_size(primary_key, &value);
+                self.lru.remove(&tick);
+            }
+        }
+    }
+
+    /// Drops the least recently used value, false if the shard is empty.
+    fn evict_lru(&mut self) -> bool {
+        match self.lru.first_key_value() {
+            None => false,
+            Some((_, (primary_key, field_id))) => {
+                let (primary_key, field_id) = (primary_key.clone(), *field_id);
+                self.remove(&primary_key, field_id);
+                true
+            }
+        }
+    }
+}
+
+fn entry_size(primary_key: &[u8], value: &CachedValue) -> u64 {
+    // primary key is held twice, in entries and lru
+    (2 * primary_key.len() + value.data.len()) as u64 + ENTRY_OVERHEAD_BYTES
+}
@@ -0,0 +1,65 @@
+use crate::proto::generated_proto::common::FieldType;
+
+use super::{CachedValue, ValueCache};
+
+fn cached_value(data: &str, expires_at_millis: u64) -> CachedValue {
+    CachedValue {
+        field_type: FieldType::STRING,
+        expires_at_millis,
+        data: data.as_bytes().to_vec(),
+    }
+}
+
+fn get(cache: &ValueCache, primary_key: &str, field_id: u32) -> Option<Vec<u8>> {
+    cache.get(primary_key.as_bytes(), field_id, |value| value.data.clone())
+}
+
+#[test]
+pub fn lru_eviction() {
+    // room for two entries of a single shard
+    let cache = ValueCache::new(2 * (2 * 4 + 6 + 64), 1);
+    cache.insert(b"pkey", 1, cached_value("value1", 0));
+    cache.insert(b"pkey", 2, cached_value("value2", 0));
+
+    // read of field 1 makes field 2 the least recently used
+    assert_eq!(get(&cache, "pkey", 1).unwrap(), b"value1");
+    cache.insert(b"pkey", 3, cached_value("value3", 0));
+    assert!(get(&cache, "pkey", 2).is_none());
+    assert_eq!(get(&cache, "pkey", 1).unwrap(), b"value1");
+    assert_eq!(get(&cache, "pkey", 3).unwrap(), b"value3");
+
+    // values larger than a shard are not cached
+    cache.insert(b"pkey", 4, cached_value(&"x".repeat(1024), 0));
+    assert!(get(&cache, "pkey", 4).is_none());
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.evictions), (3, 2, 1));
+    assert_eq!(stats.num_entries, 2);
+    assert_eq!(stats.size_bytes, 2 * (2 * 4 + 6 + 64));
+}
+
+#[test]
+pub fn invalidation() {
+    let cache = ValueCache::new(1024 * 1024, 4);
+    for pkey in ["pkey0", "pkey1"] {
+        cache.insert(pkey.as_bytes(), 1, cached_value("value1", 0));
+        cache.insert(pkey.as_bytes(), 2, cached_value("value2", 0));
+    }
+
+    cache.invalidate_fields(b"pkey0", &[1]);
+    assert!(get(&cache, "pkey0", 1).is_none());
+    assert!(get(&cache, "pkey0", 2).is_some());
+
+    cache.invalidate_document(b"pkey1");
+    assert!(get(&cache, "pkey1", 1).is_none());
+    assert!(get(&cache, "pkey1", 2).is_none());
+    assert_eq!(cache.stats().num_entries, 1);
+
+    // expired values are dropped on read
+    cache.insert(b"pkey2", 1, cached_value("value1", 1));
+    assert!(get(&cache, "pkey2", 1).is_none());
+
+    cache.clear();
+    let stats = cache.stats();
+    assert_eq!((stats.num_entries, stats.size_bytes), (0, 0));
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+
+use crate::schema::field::FieldId;
+
+/// Problem found by deep verification of an index on disk.
+/// Segments are identified by their mount directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerificationProblem {
+    /// Index level structure (header, schema, offsets..) is missing or cannot be read.
+    BadIndexFile { name: String, error: String },
+
+    /// Segment files are missing or cannot be read.
+    BadSegmentFile { segment: String, error: String },
+
+    /// Segment metadata points past the end of the mmap file.
+    WriteOffsetPastEnd {
+        segment: String,
+        write_offset: u64,
+        mmap_file_size: u64,
+    },
+
+    /// Offset table entry points past the mmap write offset.
+    OffsetPastWriteOffset {
+        segment: String,
+        field_id: FieldId,
+        offset: u64,
+    },
+
+    /// Value referenced by the offset table cannot be decoded,
+    /// or does not end before the mmap write offset.
+    CorruptValue {
+        segment: String,
+        field_id: FieldId,
+        offset: u64,
+        error: String,
+    },
+
+    /// Offset table has values for a field id never assigned by the schema.
+    UnknownFieldId { segment: String, field_id: FieldId },
+
+    /// Documents stored in a segment other than the one their primary key hashes to.
+    MisplacedDocuments { segment: String, count: u64 },
+}
+
+impl VerificationProblem {
+    /// Mount directory of the segment with the problem, None for index level problems.
+    pub fn segment(&self) -> Option<&str> {
+        match self {
+            VerificationProblem::BadIndexFile { .. } => None,
+            VerificationProblem::BadSegmentFile { segment, .. }
+            | VerificationProblem::WriteOffsetPastEnd { segment, .. }
+            | VerificationProblem::OffsetPastWriteOffset { segment, .. }
+            | VerificationProblem::CorruptValue { segment, .. }
+            | VerificationProblem::UnknownFieldId { segment, .. }
+            | VerificationProblem::MisplacedDocuments { segment, .. } => Some(segment),
+        }
+    }
+}
+
+impl fmt::Display for VerificationProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerificationProblem::BadIndexFile { name, error } => {
+                write!(f, "bad index {}: {}", name, error)
+            }
+            VerificationProblem::BadSegmentFile { segment, error } => {
+                write!(f, "bad segment {}: {}", segment, error)
+            }
+            VerificationProblem::WriteOffsetPastEnd {
+                segment,
+                write_offset,
+                mmap_file_size,
+            } => write!(
+                f,
+                "segment {}: write offset {} past end of mmap file of size {}",
+                segment, write_offset, mmap_file_size
+            ),
+            VerificationProblem::OffsetPastWriteOffset {
+                segment,
+                field_id,
+                offset,
+            } => write!(
+                f,
+                "segment {}: field id {} offset {} past write offset",
+                segment, field_id, offset
+            ),
+            VerificationProblem::CorruptValue {
+                segment,
+                field_id,
+                offset,
+                error,
+            } => write!(
+                f,
+                "segment {}: corrupt value of field id {} at offset {}: {}",
+                segment, field_id, offset, error
+            ),
+            VerificationProblem::UnknownFieldId { segment, field_id } => {
+                write!(f, "segment {}: unknown field id {}", segment, field_id)
+            }
+            VerificationProblem::MisplacedDocuments { segment, count } => {
+                write!(
+                    f,
+                    "segment {}: {} documents of other segments",
+                    segment, count
+                )
+            }
+        }
+    }
+}
+
+/// Result of deep verification of an index on disk, see CKVIndex::verify_index().
+#[derive(Debug, Default)]
+pub struct VerificationReport {
+    pub num_segments: usize,
+    pub num_documents: u64,
+
+    // values referenced by the offset table, including ones of dropped fields
+    pub num_values: u64,
+
+    // values of fields dropped from the schema, reclaimed by compaction
+    pub num_dropped_field_values: u64,
+
+    pub problems: Vec<VerificationProblem>,
+}
+
+impl VerificationReport {
+    /// True if no problems were found.
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Problems as a single line, for logs and errors.
+    pub fn problems_summary(&self) -> String {
+        self.problems
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<String>>()
+            .join("; ")
+    }
+}
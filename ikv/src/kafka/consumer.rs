@@ -1,10 +1,10 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use anyhow::bail;
 use log::{debug, error, info, warn};
-use rdkafka::consumer::DefaultConsumerContext;
-use rdkafka::message::Message;
+use rdkafka::consumer::{BaseConsumer, DefaultConsumerContext};
+use rdkafka::message::{BorrowedMessage, Message};
 use rdkafka::util::Timeout;
 use rdkafka::Offset;
 use rdkafka::{
@@ -12,9 +12,11 @@ use rdkafka::{
     ClientConfig, TopicPartitionList,
 };
 use tokio::runtime::{Builder, Runtime};
+use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
 use crate::index::offset_store::OffsetStore;
+use crate::proto::generated_proto::index::KafkaOffsetStoreEntry;
 use crate::proto::generated_proto::{common::IKVStoreConfig, streaming::IKVDataEvent};
 
 use super::offset_committer::OffsetCommitter;
@@ -27,11 +29,13 @@ mod consumer_test;
 pub struct IKVKafkaConsumer {
     mount_directory: String,
 
-    tokio_runtime: Runtime,
+    // owned, or shared with consumers of other stores (see with_runtime())
+    tokio_runtime: Arc<Runtime>,
     writes_processor: Arc<WritesProcessor>,
 
     // consumer thread
     cancellation_token: CancellationToken,
+    consume_task: Mutex<Option<JoinHandle<anyhow::Result<()>>>>,
 
     // Consumer configuration - created in constructor
     client_config: ClientConfig,
@@ -43,95 +47,30 @@ pub struct IKVKafkaConsumer {
 impl IKVKafkaConsumer {
     /// Create a new consumer.
     pub fn new(config: &IKVStoreConfig, processor: Arc<WritesProcessor>) -> anyhow::Result<Self> {
-        let mount_directory = crate::utils::paths::get_index_mount_directory_fqn(&config)?;
-
-        let account_id = config.stringConfigs.get("account_id").ok_or(
-            rdkafka::error::KafkaError::ClientCreation(
-                "account_id is a required client-specified config".to_string(),
-            ),
-        )?;
-
-        let account_passkey = config.stringConfigs.get("account_passkey").ok_or(
-            rdkafka::error::KafkaError::ClientCreation(
-                "account_passkey is a required client-specified config".to_string(),
-            ),
-        )?;
-
-        let kafka_consumer_bootstrap_server = config
-            .stringConfigs
-            .get("kafka_bootstrap_server")
-            .ok_or(rdkafka::error::KafkaError::ClientCreation(
-                "kafka_bootstrap_server is a required gateway-specified config".to_string(),
-            ))?;
-
-        // Ref:
-        // https://docs.confluent.io/platform/current/installation/configuration/consumer-configs.html
-        // https://github.com/confluentinc/librdkafka/blob/master/CONFIGURATION.md
-        let mut client_config = ClientConfig::new();
-        client_config
-            .set("group.id", "ikv-default-consumer") // we don't use offset management or automatic partition assignment
-            .set("bootstrap.servers", kafka_consumer_bootstrap_server)
-            // This should be true to allow app level eof handler to be invoked. Can result in noisy ERROR logs.
-            // Also, if set to false, kafka consumer can wrap around (auto.offset.reset behavior)
-            .set("enable.partition.eof", "true")
-            .set("session.timeout.ms", "3600000")
-            .set("max.poll.interval.ms", "3600000")
-            .set("enable.auto.commit", "false")
-            .set("auto.offset.reset", "earliest")
-            .set("security.protocol", "SASL_SSL")
-            .set("sasl.mechanisms", "SCRAM-SHA-512")
-            .set("sasl.username", account_id)
-            .set("sasl.password", account_passkey)
-            .set("enable.ssl.certificate.verification", "false");
-
-        // Apply kafka overrides
-        // "kafkaprop_{}": "value_string"
-        // ex. "kafkaprop_ssl.ca.location": "/etc/ssl/certs" -> "ssl.ca.location": "/etc/ssl/certs"
-        for (cfg_key, cfg_val) in config.stringConfigs.iter() {
-            if cfg_key.starts_with("kafkaprop_") {
-                let parts: Vec<&str> = cfg_key.split("_").collect();
-                if parts.len() != 2 {
-                    bail!(
-                        "Malformed kafka override property in supplied cfg: {}",
-                        cfg_key
-                    );
-                }
-
-                // example .set("ssl.ca.location", "/etc/ssl/certs") <- required for ubuntu.
-                client_config.set(parts[1], cfg_val);
-            }
-        }
+        let runtime = Arc::new(consumer_runtime(1)?);
+        IKVKafkaConsumer::with_runtime(config, processor, runtime)
+    }
 
-        // topic and parition
-        let topic = config.stringConfigs.get("kafka_topic").ok_or(
-            rdkafka::error::KafkaError::ClientCreation(
-                "kafka_topic is a required gateway-specified config".to_string(),
-            ),
-        )?;
-        let partition = config.intConfigs.get("partition").ok_or(
-            rdkafka::error::KafkaError::ClientCreation(
-                "partition is a required user-specified config".to_string(),
-            ),
-        )?;
-        let partition = if (*partition > i32::MAX as i64) || (*partition < 0) {
-            bail!("partition bad value: {}", partition);
-        } else {
-            *partition as i32
-        };
+    /// Create a new consumer, which runs on a (possibly shared) tokio runtime.
+    /// See consumer_runtime().
+    pub fn with_runtime(
+        config: &IKVStoreConfig,
+        processor: Arc<WritesProcessor>,
+        runtime: Arc<Runtime>,
+    ) -> anyhow::Result<Self> {
+        let mount_directory = crate::utils::paths::get_index_mount_directory_fqn(&config)?;
 
-        let runtime = Builder::new_multi_thread()
-            .worker_threads(1)
-            .thread_name("kafka-consumer-thread")
-            .enable_time()
-            .build()?;
+        let client_config = kafka_client_config(config)?;
+        let (topic, partition) = kafka_topic_partition(config)?;
 
         Ok(IKVKafkaConsumer {
             mount_directory,
             tokio_runtime: runtime,
             writes_processor: processor,
             cancellation_token: CancellationToken::new(),
-            client_config: client_config.clone(),
-            topic: topic.to_string(),
+            consume_task: Mutex::new(None),
+            client_config,
+            topic,
             partition,
         })
     }
@@ -160,7 +99,8 @@ impl IKVKafkaConsumer {
         self.tokio_runtime.block_on(handle)??;
 
         // consume new writes in background
-        self.tokio_runtime
+        let consume_task = self
+            .tokio_runtime
             .spawn(IKVKafkaConsumer::run_consume_forever(
                 offset_store.clone(),
                 self.writes_processor.clone(),
@@ -170,6 +110,7 @@ impl IKVKafkaConsumer {
                 self.partition,
                 self.cancellation_token.clone(),
             ));
+        *self.consume_task.lock().unwrap() = Some(consume_task);
 
         Ok(())
     }
@@ -177,7 +118,14 @@ impl IKVKafkaConsumer {
     /// Stop run_in_background() message consumption.
     pub fn stop(self) {
         self.cancellation_token.cancel();
-        self.tokio_runtime.shutdown_timeout(Duration::from_secs(60));
+
+        // recv() is cancellation safe, a shared runtime keeps running
+        if let Some(consume_task) = self.consume_task.into_inner().unwrap() {
+            consume_task.abort();
+        }
+        if let Ok(runtime) = Arc::try_unwrap(self.tokio_runtime) {
+            runtime.shutdown_timeout(Duration::from_secs(60));
+        }
     }
 
     /// Consumes all pending events (usually for index build).
@@ -261,6 +209,116 @@ impl IKVKafkaConsumer {
     }
 }
 
+/// Kafka client configuration of a store, with "kafkaprop_" overrides applied.
+fn kafka_client_config(config: &IKVStoreConfig) -> anyhow::Result<ClientConfig> {
+    let account_id = config.stringConfigs.get("account_id").ok_or(
+        rdkafka::error::KafkaError::ClientCreation(
+            "account_id is a required client-specified config".to_string(),
+        ),
+    )?;
+
+    let account_passkey = config.stringConfigs.get("account_passkey").ok_or(
+        rdkafka::error::KafkaError::ClientCreation(
+            "account_passkey is a required client-specified config".to_string(),
+        ),
+    )?;
+
+    let kafka_consumer_bootstrap_server = config
+        .stringConfigs
+        .get("kafka_bootstrap_server")
+        .ok_or(rdkafka::error::KafkaError::ClientCreation(
+            "kafka_bootstrap_server is a required gateway-specified config".to_string(),
+        ))?;
+
+    // Ref:
+    // https://docs.confluent.io/platform/current/installation/configuration/consumer-configs.html
+    // https://github.com/confluentinc/librdkafka/blob/master/CONFIGURATION.md
+    let mut client_config = ClientConfig::new();
+    client_config
+        .set("group.id", "ikv-default-consumer") // we don't use offset management or automatic partition assignment
+        .set("bootstrap.servers", kafka_consumer_bootstrap_server)
+        // This should be true to allow app level eof handler to be invoked. Can result in noisy ERROR logs.
+        // Also, if set to false, kafka consumer can wrap around (auto.offset.reset behavior)
+        .set("enable.partition.eof", "true")
+        .set("session.timeout.ms", "3600000")
+        .set("max.poll.interval.ms", "3600000")
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "earliest")
+        .set("security.protocol", "SASL_SSL")
+        .set("sasl.mechanisms", "SCRAM-SHA-512")
+        .set("sasl.username", account_id)
+        .set("sasl.password", account_passkey)
+        .set("enable.ssl.certificate.verification", "false");
+
+    // Apply kafka overrides
+    // "kafkaprop_{}": "value_string"
+    // ex. "kafkaprop_ssl.ca.location": "/etc/ssl/certs" -> "ssl.ca.location": "/etc/ssl/certs"
+    for (cfg_key, cfg_val) in config.stringConfigs.iter() {
+        if cfg_key.starts_with("kafkaprop_") {
+            let parts: Vec<&str> = cfg_key.split("_").collect();
+            if parts.len() != 2 {
+                bail!(
+                    "Malformed kafka override property in supplied cfg: {}",
+                    cfg_key
+                );
+            }
+
+            // example .set("ssl.ca.location", "/etc/ssl/certs") <- required for ubuntu.
+            client_config.set(parts[1], cfg_val);
+        }
+    }
+
+    Ok(client_config)
+}
+
+/// Topic and partition consumed by a store.
+fn kafka_topic_partition(config: &IKVStoreConfig) -> anyhow::Result<(String, i32)> {
+    let topic = config.stringConfigs.get("kafka_topic").ok_or(
+        rdkafka::error::KafkaError::ClientCreation(
+            "kafka_topic is a required gateway-specified config".to_string(),
+        ),
+    )?;
+    let partition =
+        config
+            .intConfigs
+            .get("partition")
+            .ok_or(rdkafka::error::KafkaError::ClientCreation(
+                "partition is a required user-specified config".to_string(),
+            ))?;
+    let partition = if (*partition > i32::MAX as i64) || (*partition < 0) {
+        bail!("partition bad value: {}", partition);
+    } else {
+        *partition as i32
+    };
+
+    Ok((topic.to_string(), partition))
+}
+
+/// Offset entry of the current end (high watermark) of the configured topic-partition,
+/// consuming from it skips all events written so far.
+pub fn fetch_high_watermark_offset(
+    config: &IKVStoreConfig,
+) -> anyhow::Result<KafkaOffsetStoreEntry> {
+    let (topic, partition) = kafka_topic_partition(config)?;
+    let consumer: BaseConsumer<DefaultConsumerContext> = kafka_client_config(config)?.create()?;
+    let (_, high_w) = fetch_watermarks(&consumer, &topic, partition)?;
+
+    let mut entry = KafkaOffsetStoreEntry::new();
+    entry.topic = topic;
+    entry.partition = partition;
+    entry.offset = high_w;
+    Ok(entry)
+}
+
+/// Runtime for consuming write events, can be shared by consumers of several stores.
+pub fn consumer_runtime(worker_threads: usize) -> anyhow::Result<Runtime> {
+    Ok(Builder::new_multi_thread()
+        .worker_threads(worker_threads)
+        .thread_name("kafka-consumer-thread")
+        .enable_time()
+        .build()?)
+}
+
 async fn initialize_stream_consumer(
     offset_store: Arc<OffsetStore>,
     client_config: &ClientConfig,
@@ -314,7 +372,7 @@ fn seek_consumer(
 
 // Returns low and high offsets for the topic and partition.
 fn fetch_watermarks(
-    consumer: &StreamConsumer<DefaultConsumerContext>,
+    consumer: &impl Consumer<DefaultConsumerContext>,
     topic: &str,
     partition: i32,
 ) -> anyhow::Result<(i64, i64)> {
@@ -367,24 +425,14 @@ async fn consume_till_high_watermark(
                 e => return Err(e.into()),
             },
             Ok(curr_message) => {
-                if let Some(bytes) = rdkafka::Message::payload(&curr_message) {
-                    let event = <IKVDataEvent as protobuf::Message>::parse_from_bytes(bytes)?;
-                    writes_processor.process(&event)?;
-
+                if rdkafka::Message::payload(&curr_message).is_some() {
                     // this exit condition may or may not be met if end-offset is "exclusive",
                     // but we also exit on encountering EOF.
                     let exit = curr_message.offset() == end_offset;
 
                     // flush index and commit offset in batches
                     // we do this for startup pending event catchup as well to store incremental progress
-                    if exit || offset_committer.should_commit() {
-                        writes_processor.flush_all()?;
-                        offset_committer.commit(
-                            curr_message.topic(),
-                            curr_message.partition(),
-                            curr_message.offset(),
-                        )?;
-                    }
+                    process_message(&curr_message, &writes_processor, &offset_committer, exit)?;
 
                     if exit {
                         return Ok(());
@@ -418,25 +466,45 @@ async fn consume_till_cancelled(
                     ),
                 }
 
-                // 100ms sleep and try again
-                std::thread::sleep(Duration::from_millis(100));
+                // 100ms sleep and try again, without blocking consumers
+                // of other stores on a shared runtime
+                tokio::time::sleep(Duration::from_millis(100)).await;
             }
             Ok(curr_message) => {
-                if let Some(bytes) = rdkafka::Message::payload(&curr_message) {
-                    let event = <IKVDataEvent as protobuf::Message>::parse_from_bytes(bytes)?;
-                    writes_processor.process(&event)?;
-
-                    // flush index and commit offset in batches
-                    if offset_committer.should_commit() {
-                        writes_processor.flush_all()?;
-                        offset_committer.commit(
-                            curr_message.topic(),
-                            curr_message.partition(),
-                            curr_message.offset(),
-                        )?;
-                    }
-                }
+                // flush index and commit offset in batches
+                process_message(&curr_message, &writes_processor, &offset_committer, false)?;
             }
         };
     }
 }
+
+/// Applies the write event of a message, then flushes the index and commits the
+/// offset if `force_commit` is set or a batch is complete. Runs as blocking code
+/// (processing, flush fsyncs) so that other tasks of a shared runtime keep running.
+fn process_message(
+    message: &BorrowedMessage<'_>,
+    writes_processor: &WritesProcessor,
+    offset_committer: &OffsetCommitter,
+    force_commit: bool,
+) -> anyhow::Result<()> {
+    let bytes = match rdkafka::Message::payload(message) {
+        None => return Ok(()),
+        Some(bytes) => bytes,
+    };
+
+    tokio::task::block_in_place(|| {
+        let event = <IKVDataEvent as protobuf::Message>::parse_from_bytes(bytes)?;
+        writes_processor.process_stream_event(
+            &event,
+            message.topic(),
+            message.partition(),
+            message.offset(),
+        )?;
+
+        if force_commit || offset_committer.should_commit() {
+            writes_processor.flush_all()?;
+            offset_committer.commit(message.topic(), message.partition(), message.offset())?;
+        }
+        Ok(())
+    })
+}
@@ -1,4 +1,5 @@
 pub mod consumer;
 mod offset_committer;
+pub mod partitioner;
 pub mod processor;
 pub mod producer;
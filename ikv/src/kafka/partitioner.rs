@@ -0,0 +1,79 @@
+use anyhow::bail;
+use protobuf::Message;
+
+use crate::proto::generated_proto::common::{FieldValue, IKVStoreConfig};
+
+#[cfg(test)]
+#[path = "partitioner_test.rs"]
+mod partitioner_test;
+
+/// Assignment of documents to partitions of a store's topic, shared by the producer
+/// (see IKVKafkaProducer::write_to_single_partition()) and readers.
+///
+/// Existing stores are written with the kafka default partitioner, which stays the
+/// default. Switching a store to the IKV partitioner moves existing documents to other
+/// partitions, and readers of their old partitions would keep serving stale copies.
+/// It is only safe for a new store, or when migrating to a new topic: set
+/// "partitioner" to "ikv" on all writers and readers of the store, rebuild the base
+/// index from the new topic and only then move readers over.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Partitioner {
+    /// partition() of the primary key bytes, computed by the producer.
+    Ikv,
+
+    /// librdkafka's default partitioner over the serialized primary key FieldValue.
+    KafkaDefault,
+}
+
+impl Partitioner {
+    /// Client-specified "partitioner": "kafka_default" (default) or "ikv".
+    /// Must match across writers and readers of a store.
+    pub fn from_config(config: &IKVStoreConfig) -> anyhow::Result<Self> {
+        match config.stringConfigs.get("partitioner").map(|p| p.as_str()) {
+            None | Some("kafka_default") => Ok(Partitioner::KafkaDefault),
+            Some("ikv") => Ok(Partitioner::Ikv),
+            Some(other) => bail!("partitioner unsupported value: {}", other),
+        }
+    }
+
+    /// Partition holding the writes of a document.
+    pub fn document_partition(
+        &self,
+        primary_key: &FieldValue,
+        num_partitions: i32,
+    ) -> anyhow::Result<i32> {
+        match self {
+            Partitioner::Ikv => partition(&primary_key.value, num_partitions),
+            Partitioner::KafkaDefault => {
+                match default_partition(&primary_key.write_to_bytes()?, num_partitions) {
+                    Some(partition) => Ok(partition),
+                    None => bail!(
+                        "Cannot partition primary key: {:?} over {} partitions",
+                        primary_key,
+                        num_partitions
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// IKV partition function: crc32 (IEEE, as in java.util.zip.CRC32, Go's
+/// hash/crc32.ChecksumIEEE or Python's zlib.crc32) of the primary key bytes,
+/// modulo the partition count.
+pub fn partition(primary_key: &[u8], num_partitions: i32) -> anyhow::Result<i32> {
+    if num_partitions <= 0 {
+        bail!("num_partitions bad value: {}", num_partitions);
+    }
+    Ok((crc32fast::hash(primary_key) % num_partitions as u32) as i32)
+}
+
+/// Partition picked by librdkafka's default ("consistent_random") partitioner for a
+/// message key: crc32 of the key modulo the partition count. Messages with empty keys
+/// are spread randomly, and have no fixed partition.
+pub fn default_partition(key: &[u8], num_partitions: i32) -> Option<i32> {
+    if key.is_empty() || num_partitions <= 0 {
+        return None;
+    }
+    Some((crc32fast::hash(key) % num_partitions as u32) as i32)
+}
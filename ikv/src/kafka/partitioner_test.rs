@@ -0,0 +1,60 @@
+use protobuf::Message;
+
+use crate::kafka::partitioner::{default_partition, partition, Partitioner};
+use crate::proto::generated_proto::common::IKVStoreConfig;
+use crate::utils::testing::string_to_field_value;
+
+#[test]
+pub fn test_partition() {
+    // crc32 check value
+    assert_eq!(
+        partition(b"123456789", 1000).unwrap(),
+        (3421780262u32 % 1000) as i32
+    );
+    assert_eq!(partition(b"", 8).unwrap(), 0);
+    assert!(partition(b"key", 0).is_err());
+}
+
+#[test]
+pub fn test_default_partition() {
+    // crc32 check value, as computed by librdkafka's rd_crc32()
+    assert_eq!(
+        default_partition(b"123456789", 1000),
+        Some((3421780262u32 % 1000) as i32)
+    );
+    assert_eq!(default_partition(b"123456789", 1), Some(0));
+
+    assert!(default_partition(b"", 8).is_none());
+    assert!(default_partition(b"key", 0).is_none());
+}
+
+#[test]
+pub fn test_document_partition() {
+    let mut config = IKVStoreConfig::new();
+    let partitioner = Partitioner::from_config(&config).unwrap();
+    assert_eq!(partitioner, Partitioner::KafkaDefault);
+
+    // kafka default partitioner hashes the serialized primary key
+    let primary_key = string_to_field_value("pkey");
+    assert_eq!(
+        partitioner.document_partition(&primary_key, 16).unwrap(),
+        default_partition(&primary_key.write_to_bytes().unwrap(), 16).unwrap()
+    );
+    assert!(partitioner.document_partition(&primary_key, 0).is_err());
+
+    // ikv partitioner only depends on the primary key bytes
+    config
+        .stringConfigs
+        .insert("partitioner".to_string(), "ikv".to_string());
+    let partitioner = Partitioner::from_config(&config).unwrap();
+    assert_eq!(partitioner, Partitioner::Ikv);
+    assert_eq!(
+        partitioner.document_partition(&primary_key, 16).unwrap(),
+        partition(b"pkey", 16).unwrap()
+    );
+
+    config
+        .stringConfigs
+        .insert("partitioner".to_string(), "murmur2".to_string());
+    assert!(Partitioner::from_config(&config).is_err());
+}
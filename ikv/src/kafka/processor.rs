@@ -2,11 +2,11 @@ use std::sync::Arc;
 
 use anyhow::Ok;
 
-use crate::index::ckv::CKVIndex;
+use crate::index::ckv::{CKVIndex, WriteOptions};
 use crate::proto::generated_proto::streaming::ikvdata_event::Event;
 use crate::proto::generated_proto::streaming::{
-    DeleteDocumentEvent, DeleteDocumentFieldsEvent, DropFieldEvent, IKVDataEvent,
-    UpsertDocumentFieldsEvent,
+    AppendDocumentFieldsEvent, DeleteDocumentEvent, DeleteDocumentFieldsEvent, DropFieldEvent,
+    IKVDataEvent, IncrementDocumentFieldsEvent, UpsertDocumentFieldsEvent,
 };
 
 pub struct WritesProcessor {
@@ -26,30 +26,121 @@ impl WritesProcessor {
     }
 
     pub fn process(&self, event: &IKVDataEvent) -> anyhow::Result<()> {
+        self.process_with_options(event, &WriteOptions::from_event_header(&event.eventHeader))
+    }
+
+    fn process_with_options(
+        &self,
+        event: &IKVDataEvent,
+        write_options: &WriteOptions,
+    ) -> anyhow::Result<()> {
         // dispatch to inner event processors
         if let Some(inner_event) = event.event.as_ref() {
             match inner_event {
-                Event::UpsertDocumentFieldsEvent(e) => return self.process_upsert(e),
-                Event::DeleteDocumentFieldsEvent(e) => return self.process_field_delete(e),
-                Event::DeleteDocumentEvent(e) => return self.process_document_delete(e),
+                Event::UpsertDocumentFieldsEvent(e) => {
+                    return self.process_upsert(e, write_options)
+                }
+                Event::DeleteDocumentFieldsEvent(e) => {
+                    return self.process_field_delete(e, write_options)
+                }
+                Event::DeleteDocumentEvent(e) => {
+                    return self.process_document_delete(e, write_options)
+                }
                 Event::DropFieldEvent(e) => return self.process_drop_fields(e),
+                Event::IncrementDocumentFieldsEvent(e) => {
+                    return self.process_increment(e, write_options)
+                }
+                Event::AppendDocumentFieldsEvent(e) => {
+                    return self.process_append(e, write_options)
+                }
             };
         }
 
         Ok(())
     }
 
-    fn process_upsert(&self, event: &UpsertDocumentFieldsEvent) -> anyhow::Result<()> {
+    /// Processes an event consumed from the stream at `offset`. Events replayed to catch up
+    /// segments restored by index repair skip documents of other segments, and schema changes.
+    pub fn process_stream_event(
+        &self,
+        event: &IKVDataEvent,
+        topic: &str,
+        partition: i32,
+        offset: i64,
+    ) -> anyhow::Result<()> {
+        let document_on_wire = match event.event.as_ref() {
+            Some(Event::UpsertDocumentFieldsEvent(e)) => e.document.as_ref(),
+            Some(Event::DeleteDocumentFieldsEvent(e)) => e.documentId.as_ref(),
+            Some(Event::DeleteDocumentEvent(e)) => e.documentId.as_ref(),
+            Some(Event::IncrementDocumentFieldsEvent(e)) => e.document.as_ref(),
+            Some(Event::AppendDocumentFieldsEvent(e)) => e.document.as_ref(),
+            Some(Event::DropFieldEvent(_)) | None => None,
+        };
+
+        let document = document_on_wire.map(|d| &d.document);
+        if !self
+            .ckv_index
+            .applies_replayed_event(topic, partition, offset, document)?
+        {
+            return Ok(());
+        }
+
+        // lets non-idempotent writes (increments, appends) skip replayed events
+        let mut write_options = WriteOptions::from_event_header(&event.eventHeader);
+        write_options.event_offset = Some((partition, offset));
+        self.process_with_options(event, &write_options)
+    }
+
+    fn process_upsert(
+        &self,
+        event: &UpsertDocumentFieldsEvent,
+        write_options: &WriteOptions,
+    ) -> anyhow::Result<()> {
+        if event.document.is_none() {
+            return Ok(());
+        }
+
+        let document_on_wire = event.document.as_ref().unwrap();
+        self.ckv_index
+            .upsert_field_values_with_options(&document_on_wire.document, write_options)
+    }
+
+    fn process_increment(
+        &self,
+        event: &IncrementDocumentFieldsEvent,
+        write_options: &WriteOptions,
+    ) -> anyhow::Result<()> {
         if event.document.is_none() {
             return Ok(());
         }
 
         let document_on_wire = event.document.as_ref().unwrap();
         self.ckv_index
-            .upsert_field_values(&document_on_wire.document)
+            .increment_field_values_with_options(&document_on_wire.document, write_options)
+    }
+
+    fn process_append(
+        &self,
+        event: &AppendDocumentFieldsEvent,
+        write_options: &WriteOptions,
+    ) -> anyhow::Result<()> {
+        if event.document.is_none() {
+            return Ok(());
+        }
+
+        let document_on_wire = event.document.as_ref().unwrap();
+        self.ckv_index.append_field_values_with_options(
+            &document_on_wire.document,
+            event.maxLength as usize,
+            write_options,
+        )
     }
 
-    fn process_field_delete(&self, event: &DeleteDocumentFieldsEvent) -> anyhow::Result<()> {
+    fn process_field_delete(
+        &self,
+        event: &DeleteDocumentFieldsEvent,
+        write_options: &WriteOptions,
+    ) -> anyhow::Result<()> {
         if event.documentId.is_none() {
             return Ok(());
         }
@@ -60,17 +151,25 @@ impl WritesProcessor {
             return Ok(());
         }
 
-        self.ckv_index
-            .delete_field_values(&document_on_wire.document, field_names)
+        self.ckv_index.delete_field_values_with_options(
+            &document_on_wire.document,
+            field_names,
+            write_options,
+        )
     }
 
-    fn process_document_delete(&self, event: &DeleteDocumentEvent) -> anyhow::Result<()> {
+    fn process_document_delete(
+        &self,
+        event: &DeleteDocumentEvent,
+        write_options: &WriteOptions,
+    ) -> anyhow::Result<()> {
         if event.documentId.is_none() {
             return Ok(());
         }
 
         let document_on_wire = event.documentId.as_ref().unwrap();
-        self.ckv_index.delete_document(&document_on_wire.document)
+        self.ckv_index
+            .delete_document_with_options(&document_on_wire.document, write_options)
     }
 
     fn process_drop_fields(&self, event: &DropFieldEvent) -> anyhow::Result<()> {
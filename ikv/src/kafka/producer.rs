@@ -18,10 +18,13 @@ use crate::proto::generated_proto::common::FieldValue;
 use crate::proto::generated_proto::streaming::EventHeader;
 use crate::proto::generated_proto::{common::IKVStoreConfig, streaming::IKVDataEvent};
 
+use super::partitioner::Partitioner;
+
 pub struct IKVKafkaProducer {
     tokio_runtime: Runtime,
     sender: Sender<(KafkaMessage, KafkaSendCondVar)>,
     partitions: i32,
+    partitioner: Partitioner,
 }
 
 impl IKVKafkaProducer {
@@ -46,6 +49,7 @@ impl IKVKafkaProducer {
             ))
             .cloned()?
             .try_into()?;
+        let partitioner = Partitioner::from_config(config)?;
 
         // test kafka connection
         IKVKafkaProducer::check_kafka_connection(topic.clone(), &client_config)?;
@@ -70,6 +74,7 @@ impl IKVKafkaProducer {
             tokio_runtime: runtime,
             sender,
             partitions,
+            partitioner,
         })
     }
 
@@ -80,16 +85,26 @@ impl IKVKafkaProducer {
     }
 
     // for upsert, delete operations
+    // `field_value` is the primary key of the document, see Partitioner.
     pub fn write_to_single_partition(
         &self,
         field_value: &FieldValue,
         event: &IKVDataEvent,
     ) -> anyhow::Result<()> {
+        // kafka default partitioner is applied by librdkafka
+        let partition = match self.partitioner {
+            Partitioner::Ikv => Some(
+                self.partitioner
+                    .document_partition(field_value, self.partitions)?,
+            ),
+            Partitioner::KafkaDefault => None,
+        };
+
         // create message
         let serialized_field_value = field_value.write_to_bytes()?;
         let serialized_ikv_data_event = event.write_to_bytes()?;
         let kafka_message = KafkaMessage {
-            partition: None,
+            partition,
             serialized_field_value,
             serialized_ikv_data_event,
         };
@@ -128,7 +143,7 @@ impl IKVKafkaProducer {
         message_stream: Receiver<(KafkaMessage, KafkaSendCondVar)>,
     ) -> anyhow::Result<()> {
         // initialize producer
-        // note: use custom partitioner when store partitioning is implemented
+        // note: partitions are picked by the caller, see Partitioner
         let producer: FutureProducer = client_config.create()?;
 
         // loop and wait for events
@@ -164,7 +179,6 @@ impl IKVKafkaProducer {
         client_config: &ClientConfig,
     ) -> anyhow::Result<()> {
         // initialize producer
-        // note: use custom partitioner when store partitioning is implemented
         let producer: FutureProducer = client_config.create()?;
 
         // construct no-op message
@@ -0,0 +1,131 @@
+// Conversion of field values between field types, see "field_type_policy" config.
+//
+// Supported conversions:
+// numeric/boolean -> numeric/boolean: if the value is exactly representable in the target type
+// numeric/boolean -> STRING: decimal (or true/false) text
+// STRING -> numeric/boolean: parsed from text
+// STRING <-> BYTES: same bytes (utf8 required for STRING)
+
+use anyhow::{anyhow, bail};
+
+use crate::proto::generated_proto::common::{FieldType, FieldValue};
+
+#[cfg(test)]
+#[path = "coerce_test.rs"]
+mod coerce_test;
+
+/// Numeric (or boolean) value decoded from any numeric/boolean type.
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+/// Converts a value to `to` field type, errors if there is no lossless conversion.
+pub fn coerce(value: &FieldValue, to: FieldType) -> anyhow::Result<FieldValue> {
+    let from = value.fieldType.enum_value_or_default();
+    if from == to {
+        return Ok(value.clone());
+    }
+
+    let data = match (from, to) {
+        (FieldType::STRING, FieldType::BYTES) => value.value.clone(),
+        (FieldType::BYTES, FieldType::STRING) => {
+            std::str::from_utf8(&value.value)?;
+            value.value.clone()
+        }
+        (FieldType::STRING, to) => {
+            let text = std::str::from_utf8(&value.value)?.trim();
+            let number = if to == FieldType::BOOLEAN {
+                match text {
+                    "true" => Number::Int(1),
+                    "false" => Number::Int(0),
+                    _ => bail!("Cannot parse boolean from: {}", text),
+                }
+            } else if let Ok(i) = text.parse::<i64>() {
+                Number::Int(i)
+            } else if to == FieldType::FLOAT32 {
+                // decimal text is rounded to nearest, whatever the precision
+                Number::Float(
+                    text.parse::<f32>()
+                        .map_err(|_| anyhow!("Cannot parse number from: {}", text))?
+                        as f64,
+                )
+            } else {
+                Number::Float(
+                    text.parse::<f64>()
+                        .map_err(|_| anyhow!("Cannot parse number from: {}", text))?,
+                )
+            };
+            encode_number(number, to)?
+        }
+        (from, FieldType::STRING) => {
+            let text = match (from, decode_number(from, &value.value)?) {
+                (FieldType::BOOLEAN, Number::Int(i)) => (i != 0).to_string(),
+                (_, Number::Int(i)) => i.to_string(),
+                (_, Number::Float(f)) => f.to_string(),
+            };
+            text.into_bytes()
+        }
+        (from, to) => encode_number(decode_number(from, &value.value)?, to)?,
+    };
+
+    let mut coerced = FieldValue::new();
+    coerced.fieldType = to.into();
+    coerced.value = data;
+    Ok(coerced)
+}
+
+fn decode_number(field_type: FieldType, data: &[u8]) -> anyhow::Result<Number> {
+    let number = match field_type {
+        FieldType::INT32 => Number::Int(i32::from_le_bytes(data.try_into()?) as i64),
+        FieldType::INT64 => Number::Int(i64::from_le_bytes(data.try_into()?)),
+        FieldType::FLOAT32 => Number::Float(f32::from_le_bytes(data.try_into()?) as f64),
+        FieldType::FLOAT64 => Number::Float(f64::from_le_bytes(data.try_into()?)),
+        FieldType::BOOLEAN => {
+            let byte: [u8; 1] = data.try_into()?;
+            Number::Int((byte[0] != 0) as i64)
+        }
+        _ => bail!("Cannot convert from field type: {:?}", field_type),
+    };
+    Ok(number)
+}
+
+fn encode_number(number: Number, field_type: FieldType) -> anyhow::Result<Vec<u8>> {
+    let data = match (field_type, number) {
+        (FieldType::INT32, Number::Int(i)) => i32::try_from(i)?.to_le_bytes().to_vec(),
+        (FieldType::INT64, Number::Int(i)) => i.to_le_bytes().to_vec(),
+        (FieldType::INT32 | FieldType::INT64, Number::Float(f)) => {
+            if f.fract() != 0.0 || !f.is_finite() || f.abs() >= i64::MAX as f64 {
+                bail!("Cannot convert: {} to an integer", f);
+            }
+            return encode_number(Number::Int(f as i64), field_type);
+        }
+        // round-trip checks, i.e. integers beyond the float mantissa and
+        // doubles without an exact single precision value are not converted
+        (FieldType::FLOAT32, Number::Int(i)) => {
+            if i as f32 as i128 != i as i128 {
+                bail!("Cannot convert: {} to FLOAT32 without loss", i);
+            }
+            (i as f32).to_le_bytes().to_vec()
+        }
+        (FieldType::FLOAT32, Number::Float(f)) => {
+            if f as f32 as f64 != f && !f.is_nan() {
+                bail!("Cannot convert: {} to FLOAT32 without loss", f);
+            }
+            (f as f32).to_le_bytes().to_vec()
+        }
+        (FieldType::FLOAT64, Number::Int(i)) => {
+            if i as f64 as i128 != i as i128 {
+                bail!("Cannot convert: {} to FLOAT64 without loss", i);
+            }
+            (i as f64).to_le_bytes().to_vec()
+        }
+        (FieldType::FLOAT64, Number::Float(f)) => f.to_le_bytes().to_vec(),
+        (FieldType::BOOLEAN, Number::Int(i @ (0 | 1))) => vec![i as u8],
+        (FieldType::BOOLEAN, Number::Float(f)) if f == 0.0 || f == 1.0 => vec![f as u8],
+        (FieldType::BOOLEAN, _) => bail!("Cannot convert: {:?} to a boolean", number),
+        _ => bail!("Cannot convert to field type: {:?}", field_type),
+    };
+    Ok(data)
+}
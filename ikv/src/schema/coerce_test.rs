@@ -0,0 +1,106 @@
+use crate::proto::generated_proto::common::{FieldType, FieldValue};
+use crate::schema::coerce::coerce;
+use crate::utils::testing::{bytes_to_field_value, i32_to_field_value, string_to_field_value};
+
+fn field_value(field_type: FieldType, value: &[u8]) -> FieldValue {
+    let mut field_value = FieldValue::new();
+    field_value.fieldType = field_type.into();
+    field_value.value = value.to_vec();
+    field_value
+}
+
+#[test]
+pub fn test_numeric() {
+    // widening
+    let result = coerce(&i32_to_field_value(-7), FieldType::INT64).unwrap();
+    assert_eq!(
+        result,
+        field_value(FieldType::INT64, &(-7i64).to_le_bytes())
+    );
+    let result = coerce(&i32_to_field_value(3), FieldType::FLOAT64).unwrap();
+    assert_eq!(result, field_value(FieldType::FLOAT64, &3f64.to_le_bytes()));
+
+    // narrowing, only if representable
+    let value = field_value(FieldType::INT64, &5_000_000_000i64.to_le_bytes());
+    assert!(coerce(&value, FieldType::INT32).is_err());
+    let value = field_value(FieldType::FLOAT64, &2.0f64.to_le_bytes());
+    assert_eq!(
+        coerce(&value, FieldType::INT32).unwrap(),
+        i32_to_field_value(2)
+    );
+    let value = field_value(FieldType::FLOAT32, &2.5f32.to_le_bytes());
+    assert!(coerce(&value, FieldType::INT64).is_err());
+
+    // to floats, only if exact
+    let value = field_value(FieldType::INT64, &(1i64 << 40).to_le_bytes());
+    assert_eq!(
+        coerce(&value, FieldType::FLOAT32).unwrap(),
+        field_value(FieldType::FLOAT32, &((1i64 << 40) as f32).to_le_bytes())
+    );
+    let value = field_value(FieldType::INT64, &16_777_217i64.to_le_bytes());
+    assert!(coerce(&value, FieldType::FLOAT32).is_err());
+    let value = field_value(FieldType::INT64, &(i64::MAX - 1).to_le_bytes());
+    assert!(coerce(&value, FieldType::FLOAT64).is_err());
+    let value = field_value(FieldType::INT64, &i64::MAX.to_le_bytes());
+    assert!(coerce(&value, FieldType::FLOAT32).is_err());
+    let value = field_value(FieldType::FLOAT64, &0.25f64.to_le_bytes());
+    assert_eq!(
+        coerce(&value, FieldType::FLOAT32).unwrap(),
+        field_value(FieldType::FLOAT32, &0.25f32.to_le_bytes())
+    );
+    let value = field_value(FieldType::FLOAT64, &0.1f64.to_le_bytes());
+    assert!(coerce(&value, FieldType::FLOAT32).is_err());
+
+    // booleans
+    assert_eq!(
+        coerce(&i32_to_field_value(1), FieldType::BOOLEAN).unwrap(),
+        field_value(FieldType::BOOLEAN, &[1])
+    );
+    assert!(coerce(&i32_to_field_value(2), FieldType::BOOLEAN).is_err());
+
+    // malformed
+    let value = field_value(FieldType::INT32, &[1, 2]);
+    assert!(coerce(&value, FieldType::INT64).is_err());
+}
+
+#[test]
+pub fn test_text() {
+    // to and from strings
+    assert_eq!(
+        coerce(&string_to_field_value(" 42 "), FieldType::INT32).unwrap(),
+        i32_to_field_value(42)
+    );
+    assert_eq!(
+        coerce(&string_to_field_value("0.5"), FieldType::FLOAT32).unwrap(),
+        field_value(FieldType::FLOAT32, &0.5f32.to_le_bytes())
+    );
+    assert_eq!(
+        coerce(&string_to_field_value("0.1"), FieldType::FLOAT32).unwrap(),
+        field_value(FieldType::FLOAT32, &0.1f32.to_le_bytes())
+    );
+    assert_eq!(
+        coerce(&string_to_field_value("true"), FieldType::BOOLEAN).unwrap(),
+        field_value(FieldType::BOOLEAN, &[1])
+    );
+    assert!(coerce(&string_to_field_value("abc"), FieldType::INT64).is_err());
+    assert_eq!(
+        coerce(&i32_to_field_value(-12), FieldType::STRING).unwrap(),
+        string_to_field_value("-12")
+    );
+    assert_eq!(
+        coerce(&field_value(FieldType::BOOLEAN, &[0]), FieldType::STRING).unwrap(),
+        string_to_field_value("false")
+    );
+
+    // strings and bytes
+    assert_eq!(
+        coerce(&string_to_field_value("abc"), FieldType::BYTES).unwrap(),
+        bytes_to_field_value(b"abc")
+    );
+    assert!(coerce(&bytes_to_field_value(&[0xff, 0xfe]), FieldType::STRING).is_err());
+
+    // no conversion for lists and vectors
+    let value = field_value(FieldType::FLOAT32_VECTOR, &1f32.to_le_bytes());
+    assert!(coerce(&value, FieldType::FLOAT32).is_err());
+    assert!(coerce(&i32_to_field_value(1), FieldType::INT32_LIST).is_err());
+}
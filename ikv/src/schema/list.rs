@@ -0,0 +1,175 @@
+// Encoding of list field values (FieldType::*_LIST).
+//
+// Fixed width elements (ints and floats) are little-endian encoded and concatenated.
+// Variable width elements (strings and bytes) are each prefixed by their
+// length as u32 little-endian: [(len)element1][(len)element2]...[(len)elementn]
+// Elements are ordered oldest to newest, i.e. appends go at the end.
+
+use anyhow::bail;
+
+use crate::proto::generated_proto::common::FieldType;
+
+#[cfg(test)]
+#[path = "list_test.rs"]
+mod list_test;
+
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+pub fn is_list_type(field_type: FieldType) -> bool {
+    element_width(field_type).is_some()
+}
+
+/// Size in bytes of list elements, 0 for variable width (length prefixed) elements.
+/// None for non-list types.
+fn element_width(field_type: FieldType) -> Option<usize> {
+    match field_type {
+        FieldType::INT32_LIST | FieldType::FLOAT32_LIST => Some(4),
+        FieldType::INT64_LIST | FieldType::FLOAT64_LIST => Some(8),
+        FieldType::STRING_LIST | FieldType::BYTES_LIST => Some(0),
+        _ => None,
+    }
+}
+
+/// Type of list elements, None for non-list types.
+pub fn element_type(field_type: FieldType) -> Option<FieldType> {
+    match field_type {
+        FieldType::INT32_LIST => Some(FieldType::INT32),
+        FieldType::INT64_LIST => Some(FieldType::INT64),
+        FieldType::FLOAT32_LIST => Some(FieldType::FLOAT32),
+        FieldType::FLOAT64_LIST => Some(FieldType::FLOAT64),
+        FieldType::STRING_LIST => Some(FieldType::STRING),
+        FieldType::BYTES_LIST => Some(FieldType::BYTES),
+        _ => None,
+    }
+}
+
+/// Encodes `elements` (values of the element type) as a list of type `field_type`.
+pub fn encode(field_type: FieldType, elements: &[Vec<u8>]) -> anyhow::Result<Vec<u8>> {
+    let width = match element_width(field_type) {
+        None => bail!("Not a list type: {:?}", field_type),
+        Some(width) => width,
+    };
+
+    let mut result = vec![];
+    for element in elements {
+        if width == 0 {
+            if element.len() > u32::MAX as usize {
+                bail!(
+                    "{:?} element of {} bytes is too long",
+                    field_type,
+                    element.len()
+                );
+            }
+            result.extend((element.len() as u32).to_le_bytes());
+        } else if element.len() != width {
+            bail!(
+                "{:?} element of {} bytes does not match element size",
+                field_type,
+                element.len()
+            );
+        }
+        result.extend_from_slice(element);
+    }
+
+    Ok(result)
+}
+
+/// Byte offsets of each element of an encoded list.
+fn element_offsets(field_type: FieldType, data: &[u8]) -> anyhow::Result<Vec<usize>> {
+    let width = match element_width(field_type) {
+        None => bail!("Not a list type: {:?}", field_type),
+        Some(width) => width,
+    };
+
+    if width > 0 {
+        if !data.len().is_multiple_of(width) {
+            bail!(
+                "{:?} value of {} bytes is not a multiple of element size",
+                field_type,
+                data.len()
+            );
+        }
+        return Ok((0..data.len()).step_by(width).collect());
+    }
+
+    let mut offsets = vec![];
+    let mut offset = 0;
+    while offset < data.len() {
+        if offset + LENGTH_PREFIX_SIZE > data.len() {
+            bail!("{:?} value has truncated element length", field_type);
+        }
+        let element_len = u32::from_le_bytes(
+            data[offset..offset + LENGTH_PREFIX_SIZE]
+                .try_into()
+                .expect("length prefix must be 4 bytes"),
+        ) as usize;
+        if offset + LENGTH_PREFIX_SIZE + element_len > data.len() {
+            bail!("{:?} value has truncated element", field_type);
+        }
+
+        offsets.push(offset);
+        offset += LENGTH_PREFIX_SIZE + element_len;
+    }
+
+    Ok(offsets)
+}
+
+/// Elements of an encoded list, without length prefixes.
+pub fn elements(field_type: FieldType, data: &[u8]) -> anyhow::Result<Vec<&[u8]>> {
+    let width = element_width(field_type).unwrap_or_default();
+    let offsets = element_offsets(field_type, data)?;
+    Ok(offsets
+        .into_iter()
+        .map(|offset| match width {
+            0 => {
+                let element_len = u32::from_le_bytes(
+                    data[offset..offset + LENGTH_PREFIX_SIZE]
+                        .try_into()
+                        .expect("length prefix must be 4 bytes"),
+                ) as usize;
+                let start = offset + LENGTH_PREFIX_SIZE;
+                &data[start..start + element_len]
+            }
+            width => &data[offset..offset + width],
+        })
+        .collect())
+}
+
+/// Checks that `data` is a well-formed list of type `field_type`.
+pub fn validate(field_type: FieldType, data: &[u8]) -> anyhow::Result<()> {
+    element_offsets(field_type, data).map(|_| ())
+}
+
+/// Last (newest) `k` elements of an encoded list, as an encoded list.
+pub fn last_elements(field_type: FieldType, data: &[u8], k: usize) -> anyhow::Result<&[u8]> {
+    let offsets = element_offsets(field_type, data)?;
+    if k >= offsets.len() {
+        return Ok(data);
+    }
+    if k == 0 {
+        return Ok(&data[data.len()..]);
+    }
+    Ok(&data[offsets[offsets.len() - k]..])
+}
+
+/// Appends `elements` to `list` (both encoded lists), keeping only the last
+/// `max_length` elements. No limit on length if `max_length` is 0.
+pub fn append(
+    field_type: FieldType,
+    list: &[u8],
+    elements: &[u8],
+    max_length: usize,
+) -> anyhow::Result<Vec<u8>> {
+    validate(field_type, list)?;
+    validate(field_type, elements)?;
+
+    let mut result = Vec::with_capacity(list.len() + elements.len());
+    result.extend_from_slice(list);
+    result.extend_from_slice(elements);
+    if max_length == 0 {
+        return Ok(result);
+    }
+
+    let trimmed_len = last_elements(field_type, &result, max_length)?.len();
+    Ok(result.split_off(result.len() - trimmed_len))
+}
@@ -0,0 +1,104 @@
+use crate::proto::generated_proto::common::FieldType;
+use crate::schema::list;
+
+fn i32_list(elements: &[i32]) -> Vec<u8> {
+    elements.iter().flat_map(|e| e.to_le_bytes()).collect()
+}
+
+fn string_list(elements: &[&str]) -> Vec<u8> {
+    let mut result = vec![];
+    for element in elements {
+        result.extend((element.len() as u32).to_le_bytes());
+        result.extend(element.as_bytes());
+    }
+    result
+}
+
+#[test]
+pub fn test_fixed_width_lists() {
+    let data = i32_list(&[1, 2, 3]);
+    assert_eq!(
+        list::last_elements(FieldType::INT32_LIST, &data, 2).unwrap(),
+        i32_list(&[2, 3])
+    );
+    assert_eq!(
+        list::last_elements(FieldType::INT32_LIST, &data, 10).unwrap(),
+        data
+    );
+    assert!(list::last_elements(FieldType::INT32_LIST, &data, 0)
+        .unwrap()
+        .is_empty());
+
+    // trimmed to max length, oldest dropped
+    let appended = list::append(FieldType::INT32_LIST, &data, &i32_list(&[4, 5]), 4).unwrap();
+    assert_eq!(appended, i32_list(&[2, 3, 4, 5]));
+    let appended = list::append(FieldType::INT32_LIST, &data, &i32_list(&[4, 5]), 0).unwrap();
+    assert_eq!(appended, i32_list(&[1, 2, 3, 4, 5]));
+
+    // malformed
+    assert!(list::validate(FieldType::INT32_LIST, &[0u8; 5]).is_err());
+    assert!(list::validate(FieldType::INT64_LIST, &[0u8; 12]).is_err());
+    assert!(list::validate(FieldType::INT32, &[0u8; 4]).is_err());
+}
+
+#[test]
+pub fn test_variable_width_lists() {
+    let data = string_list(&["a", "", "bcd"]);
+    assert_eq!(
+        list::last_elements(FieldType::STRING_LIST, &data, 3).unwrap(),
+        data
+    );
+    assert_eq!(
+        list::last_elements(FieldType::STRING_LIST, &data, 2).unwrap(),
+        string_list(&["", "bcd"])
+    );
+
+    let appended =
+        list::append(FieldType::STRING_LIST, &data, &string_list(&["e", "fg"]), 3).unwrap();
+    assert_eq!(appended, string_list(&["bcd", "e", "fg"]));
+
+    // appending to an empty list
+    let appended = list::append(FieldType::BYTES_LIST, &[], &string_list(&["x"]), 3).unwrap();
+    assert_eq!(appended, string_list(&["x"]));
+
+    // malformed
+    assert!(list::validate(FieldType::STRING_LIST, &[1, 0, 0]).is_err());
+    assert!(list::validate(FieldType::STRING_LIST, &[2, 0, 0, 0, b'a']).is_err());
+}
+
+#[test]
+pub fn test_encode_lists() {
+    let elements: Vec<Vec<u8>> = [1i32, 2, 3]
+        .iter()
+        .map(|e| e.to_le_bytes().to_vec())
+        .collect();
+    assert_eq!(
+        list::encode(FieldType::INT32_LIST, &elements).unwrap(),
+        i32_list(&[1, 2, 3])
+    );
+    assert!(list::encode(FieldType::INT64_LIST, &elements).is_err());
+
+    let elements = vec![b"a".to_vec(), vec![], b"bcd".to_vec()];
+    assert_eq!(
+        list::encode(FieldType::STRING_LIST, &elements).unwrap(),
+        string_list(&["a", "", "bcd"])
+    );
+    assert!(list::encode(FieldType::STRING_LIST, &[])
+        .unwrap()
+        .is_empty());
+    assert!(list::encode(FieldType::STRING, &elements).is_err());
+    assert_eq!(
+        list::elements(FieldType::STRING_LIST, &string_list(&["a", "", "bcd"])).unwrap(),
+        vec![b"a".as_slice(), b"", b"bcd"]
+    );
+    assert_eq!(
+        list::elements(FieldType::INT32_LIST, &i32_list(&[1, 2])).unwrap(),
+        vec![1i32.to_le_bytes(), 2i32.to_le_bytes()]
+    );
+
+    assert_eq!(
+        list::element_type(FieldType::BYTES_LIST),
+        Some(FieldType::BYTES)
+    );
+    assert_eq!(list::element_type(FieldType::BYTES), None);
+}
@@ -1 +1,4 @@
+pub mod coerce;
 pub mod field;
+pub mod list;
+pub mod vector;
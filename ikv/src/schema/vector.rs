@@ -0,0 +1,165 @@
+// Encoding of dense vector field values (FieldType::*_VECTOR).
+//
+// Vectors are the little-endian encoded elements, concatenated:
+// FLOAT32_VECTOR: IEEE 754 single precision (4 bytes per element)
+// FLOAT16_VECTOR: IEEE 754 half precision (2 bytes per element)
+// INT8_VECTOR: signed bytes (1 byte per element)
+// All vectors of a field have the same type and dimension, see CKVIndexSchema.
+// Stored vectors are aligned to their element size, see alignment().
+
+use anyhow::bail;
+
+use crate::proto::generated_proto::common::FieldType;
+
+pub fn is_vector_type(field_type: FieldType) -> bool {
+    element_size(field_type).is_some()
+}
+
+/// Alignment in bytes of stored values of `field_type`, i.e. element size of vectors
+/// and 1 for other types. Aligned vectors can be viewed as typed arrays in place.
+pub fn alignment(field_type: FieldType) -> usize {
+    element_size(field_type).unwrap_or(1)
+}
+
+/// Size in bytes of vector elements, None for non-vector types.
+fn element_size(field_type: FieldType) -> Option<usize> {
+    match field_type {
+        FieldType::FLOAT32_VECTOR => Some(4),
+        FieldType::FLOAT16_VECTOR => Some(2),
+        FieldType::INT8_VECTOR => Some(1),
+        _ => None,
+    }
+}
+
+/// Number of elements in an encoded vector.
+pub fn dimension(field_type: FieldType, data: &[u8]) -> anyhow::Result<u32> {
+    let element_size = match element_size(field_type) {
+        None => bail!("Not a vector type: {:?}", field_type),
+        Some(element_size) => element_size,
+    };
+
+    if data.is_empty() || !data.len().is_multiple_of(element_size) {
+        bail!(
+            "{:?} value of {} bytes is not a non-empty multiple of element size",
+            field_type,
+            data.len()
+        );
+    }
+
+    let dimension = data.len() / element_size;
+    if dimension > u32::MAX as usize {
+        bail!("{:?} value has too many elements", field_type);
+    }
+    Ok(dimension as u32)
+}
+
+/// Decodes an encoded vector to f32 elements.
+pub fn to_f32_elements(field_type: FieldType, data: &[u8]) -> anyhow::Result<Vec<f32>> {
+    dimension(field_type, data)?;
+
+    let elements = match field_type {
+        FieldType::FLOAT32_VECTOR => data
+            .chunks_exact(4)
+            .map(|e| f32::from_le_bytes(e.try_into().expect("4 byte chunks")))
+            .collect(),
+        FieldType::FLOAT16_VECTOR => data
+            .chunks_exact(2)
+            .map(|e| f16_to_f32(u16::from_le_bytes(e.try_into().expect("2 byte chunks"))))
+            .collect(),
+        FieldType::INT8_VECTOR => data.iter().map(|e| *e as i8 as f32).collect(),
+        _ => bail!("Not a vector type: {:?}", field_type),
+    };
+
+    Ok(elements)
+}
+
+/// Encodes f32 elements as a vector of type `field_type`. Elements are rounded to
+/// nearest for FLOAT16_VECTOR, and must be integers in range for INT8_VECTOR.
+pub fn from_f32_elements(field_type: FieldType, elements: &[f32]) -> anyhow::Result<Vec<u8>> {
+    let data: Vec<u8> = match field_type {
+        FieldType::FLOAT32_VECTOR => elements.iter().flat_map(|e| e.to_le_bytes()).collect(),
+        FieldType::FLOAT16_VECTOR => elements
+            .iter()
+            .flat_map(|e| f32_to_f16(*e).to_le_bytes())
+            .collect(),
+        FieldType::INT8_VECTOR => {
+            let mut data = Vec::with_capacity(elements.len());
+            for e in elements {
+                if e.fract() != 0.0 || *e < i8::MIN as f32 || *e > i8::MAX as f32 {
+                    bail!("Cannot convert: {} to an INT8_VECTOR element", e);
+                }
+                data.push(*e as i8 as u8);
+            }
+            data
+        }
+        _ => bail!("Not a vector type: {:?}", field_type),
+    };
+
+    dimension(field_type, &data)?;
+    Ok(data)
+}
+
+/// IEEE 754 single precision to half precision conversion, rounding to nearest even.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    // infinity and NaN
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    // rebias exponent (127 -> 15)
+    let half_exponent = exponent - 112;
+    if half_exponent >= 0x1f {
+        // overflow to infinity
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        // subnormals, underflow to zero
+        if half_exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        return sign | round_shift_right(mantissa, (14 - half_exponent) as u32) as u16;
+    }
+
+    // narrow mantissa (23 -> 10 bits), a carry rounds up the exponent
+    sign | (((half_exponent as u32) << 10) + round_shift_right(mantissa, 13)) as u16
+}
+
+/// value >> shift, rounding to nearest even.
+fn round_shift_right(value: u32, shift: u32) -> u32 {
+    let truncated = value >> shift;
+    let remainder = value & ((1 << shift) - 1);
+    let half = 1 << (shift - 1);
+    if remainder > half || (remainder == half && truncated & 1 == 1) {
+        truncated + 1
+    } else {
+        truncated
+    }
+}
+
+/// IEEE 754 half precision to single precision conversion.
+fn f16_to_f32(bits: u16) -> f32 {
+    let exponent = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+
+    let magnitude = match exponent {
+        // zero and subnormals
+        0 => mantissa as f32 * 2f32.powi(-24),
+        // infinity and NaN
+        0x1f if mantissa == 0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        // rebias exponent (15 -> 127), widen mantissa (10 -> 23 bits)
+        _ => f32::from_bits(((exponent + 112) << 23) | (mantissa << 13)),
+    };
+
+    if bits & 0x8000 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}